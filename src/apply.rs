@@ -0,0 +1,916 @@
+//! `rig apply` and `rig export`: declarative machine setup from a `rig.toml`
+//! file.
+//!
+//! The file lists R versions, with their aliases, named libraries and
+//! packages, the default R version and the repository setup. `rig apply`
+//! compares it to the machine and makes the changes that are missing, with
+//! the same code the individual commands use. It only ever adds: an R
+//! version, library or package the file does not mention is left alone.
+//!
+//! The changes come in two parts. Installing R, aliases, the default version
+//! and the repositories change the R installations, which needs administrator
+//! rights in admin mode. Libraries and packages belong to the user. `sudo`
+//! would re-run all of rig as root, and put the packages in root's library, so
+//! in admin mode the first part runs in a child `rig apply`, and only that one
+//! escalates.
+
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use clap::ArgMatches;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use simple_error::*;
+
+#[cfg(target_os = "macos")]
+use crate::macos::*;
+
+#[cfg(target_os = "windows")]
+use crate::windows::*;
+
+#[cfg(target_os = "linux")]
+use crate::linux::*;
+
+use crate::alias::add_alias;
+use crate::common::sc_get_list_details;
+use crate::install::default_jobs;
+use crate::library::{
+    library_add, sc_library_get_default, sc_library_get_list, sc_library_set_default,
+};
+use crate::output::OUTPUT;
+use crate::pkg::install::{install_into, requested_deps, InstallOptions};
+use crate::pkg::list::{read_installed, ResolvedLibrary};
+use crate::repos::{repos_setup, repos_setup_is_current, ReposSetupArgs};
+use crate::rversion::InstalledVersion;
+use crate::shim::{pick_version, Request};
+
+// ------------------------------------------------------------------------
+// The file
+
+/// A `rig.toml` file.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MachineSpec {
+    /// The default R version, one of the `version`s of `r`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repos: Option<ReposSpec>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub r: Vec<RSpec>,
+}
+
+/// One R version of a `rig.toml` file.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct RSpec {
+    /// What `rig add` takes: a version number, `release`, `devel`, etc. An
+    /// installed version matches it the way it matches an `.R-version` file.
+    pub version: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    /// Named libraries, besides `main`, which always exists.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub libraries: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_library: Option<String>,
+    /// Packages for the default library.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub packages: Vec<String>,
+}
+
+/// The repository setup, as `--with-repos` and `--without-repos` give it.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReposSpec {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub with: Vec<String>,
+    /// `"*"` leaves out all the default repositories.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub without: Vec<String>,
+}
+
+impl ReposSpec {
+    fn setup_args(&self) -> ReposSetupArgs {
+        let lower = |x: &[String]| -> Vec<String> {
+            x.iter()
+                .filter(|r| *r != "*")
+                .map(|r| r.to_lowercase())
+                .collect()
+        };
+        if self.without.iter().any(|r| r == "*") {
+            ReposSetupArgs::Empty {
+                whitelist: lower(&self.with),
+            }
+        } else {
+            ReposSetupArgs::Default {
+                whitelist: lower(&self.with),
+                blacklist: lower(&self.without),
+            }
+        }
+    }
+}
+
+pub fn read_machine_spec(path: &Path) -> Result<MachineSpec, Box<dyn Error>> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(x) => x,
+        Err(err) => bail!("Cannot read {}: {}", path.display(), err),
+    };
+    let spec: MachineSpec = match toml::from_str(&text) {
+        Ok(x) => x,
+        Err(err) => bail!("Invalid {}: {}", path.display(), err),
+    };
+
+    if let Some(default) = &spec.default {
+        if !spec.r.iter().any(|r| &r.version == default) {
+            bail!(
+                "Invalid {}: the default R version, {}, is not one of the `[[r]]` versions",
+                path.display(),
+                default
+            );
+        }
+    }
+    for r in spec.r.iter() {
+        if spec.r.iter().filter(|x| x.version == r.version).count() > 1 {
+            bail!(
+                "Invalid {}: R {} is listed twice",
+                path.display(),
+                r.version
+            );
+        }
+        if let Some(deflib) = &r.default_library {
+            if deflib != "main" && !r.libraries.contains(deflib) {
+                bail!(
+                    "Invalid {}: the default library of R {}, {}, is not one of its `libraries`",
+                    path.display(),
+                    r.version,
+                    deflib
+                );
+            }
+        }
+    }
+
+    Ok(spec)
+}
+
+// ------------------------------------------------------------------------
+// What to change
+
+/// One change `rig apply` makes. `version` is always the `version` of the
+/// file, the installation it means is only known once it is installed.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+enum Action {
+    AddR {
+        version: String,
+    },
+    AddAlias {
+        version: String,
+        alias: String,
+        from: Option<String>,
+    },
+    SetDefault {
+        version: String,
+        from: Option<String>,
+    },
+    SetupRepos {
+        versions: Vec<String>,
+        with: Vec<String>,
+        without: Vec<String>,
+    },
+    AddLibrary {
+        version: String,
+        library: String,
+    },
+    SetDefaultLibrary {
+        version: String,
+        library: String,
+        from: Option<String>,
+    },
+    InstallPackages {
+        version: String,
+        packages: Vec<String>,
+    },
+}
+
+impl Action {
+    /// Whether this changes an R installation, rather than the user's
+    /// libraries.
+    fn is_system(&self) -> bool {
+        matches!(
+            self,
+            Action::AddR { .. }
+                | Action::AddAlias { .. }
+                | Action::SetDefault { .. }
+                | Action::SetupRepos { .. }
+        )
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::AddR { version } => write!(f, "+ R {}", version),
+            Action::AddAlias {
+                version,
+                alias,
+                from: None,
+            } => write!(f, "+ alias R-{} -> R {}", alias, version),
+            Action::AddAlias {
+                version,
+                alias,
+                from: Some(from),
+            } => write!(f, "~ alias R-{}: R {} -> R {}", alias, from, version),
+            Action::SetDefault { version, from } => write!(
+                f,
+                "~ default R version: {} -> {}",
+                from.as_deref().unwrap_or("none"),
+                version
+            ),
+            Action::SetupRepos {
+                versions,
+                with,
+                without,
+            } => {
+                write!(f, "~ repositories of R {}", versions.join(", "))?;
+                if !with.is_empty() {
+                    write!(f, ", with {}", with.join(", "))?;
+                }
+                if !without.is_empty() {
+                    write!(f, ", without {}", without.join(", "))?;
+                }
+                Ok(())
+            }
+            Action::AddLibrary { version, library } => {
+                write!(f, "+ library {} of R {}", library, version)
+            }
+            Action::SetDefaultLibrary {
+                version,
+                library,
+                from,
+            } => write!(
+                f,
+                "~ default library of R {}: {} -> {}",
+                version,
+                from.as_deref().unwrap_or("main"),
+                library
+            ),
+            Action::InstallPackages { version, packages } => {
+                write!(f, "+ packages of R {}: {}", version, packages.join(", "))
+            }
+        }
+    }
+}
+
+/// What the machine has, as far as `rig apply` is concerned.
+#[derive(Debug, Default)]
+struct MachineState {
+    installed: Vec<InstalledVersion>,
+    default: Option<String>,
+    libraries: Vec<Libraries>,
+    /// The installations whose repositories are set up as the file asks.
+    repos_current: Vec<String>,
+}
+
+/// The libraries of an installation, its default library, and the packages
+/// in that.
+#[derive(Debug)]
+struct Libraries {
+    rversion: String,
+    names: Vec<String>,
+    default: String,
+    packages: Vec<String>,
+}
+
+impl Libraries {
+    fn read(rver: &str) -> Result<Libraries, Box<dyn Error>> {
+        let libs = sc_library_get_list(Some(rver.to_string()), true)?;
+        let deflib = sc_library_get_default(rver)?;
+        let packages = if deflib.path.exists() {
+            read_installed(&deflib.path)?
+                .into_iter()
+                .map(|p| p.package)
+                .collect()
+        } else {
+            vec![]
+        };
+        Ok(Libraries {
+            rversion: rver.to_string(),
+            names: libs.into_iter().map(|l| l.name).collect(),
+            default: deflib.name,
+            packages,
+        })
+    }
+}
+
+impl MachineState {
+    fn read() -> Result<MachineState, Box<dyn Error>> {
+        let installed = sc_get_list_details()?;
+        let default = sc_get_default()?;
+        let mut libraries = vec![];
+        for v in installed.iter() {
+            match Libraries::read(&v.name) {
+                Ok(libs) => libraries.push(libs),
+                Err(err) => {
+                    debug!("Cannot read the libraries of R {}: {}", v.name, err);
+                }
+            }
+        }
+        Ok(MachineState {
+            installed,
+            default,
+            libraries,
+            repos_current: vec![],
+        })
+    }
+
+    /// Check which installations have their repositories set up as `repos`
+    /// asks.
+    fn read_repos(&mut self, repos: &ReposSpec) {
+        let setup = repos.setup_args();
+        for v in self.installed.iter() {
+            match repos_setup_is_current(&v.name, &setup) {
+                Ok(true) => self.repos_current.push(v.name.clone()),
+                Ok(false) => {}
+                Err(err) => {
+                    debug!("Cannot check the repositories of R {}: {}", v.name, err);
+                }
+            }
+        }
+    }
+
+    /// The installation a `version` of the file means.
+    fn find(&self, version: &str) -> Option<&InstalledVersion> {
+        pick_version(
+            &Request::Version(version.to_string()),
+            &self.installed,
+            None,
+        )
+    }
+
+    fn libraries(&self, rver: &str) -> Option<&Libraries> {
+        self.libraries.iter().find(|l| l.rversion == rver)
+    }
+}
+
+/// The changes that take `state` to `spec`.
+fn plan(spec: &MachineSpec, state: &MachineState) -> Vec<Action> {
+    let mut system: Vec<Action> = vec![];
+    let mut user: Vec<Action> = vec![];
+
+    for r in spec.r.iter() {
+        let inst = state.find(&r.version);
+        if inst.is_none() {
+            system.push(Action::AddR {
+                version: r.version.clone(),
+            });
+        }
+
+        for alias in r.aliases.iter() {
+            let owner = state
+                .installed
+                .iter()
+                .find(|v| v.aliases.contains(alias))
+                .map(|v| v.name.clone());
+            let wanted = inst.map(|v| &v.name);
+            if owner.is_none() || owner.as_ref() != wanted {
+                system.push(Action::AddAlias {
+                    version: r.version.clone(),
+                    alias: alias.clone(),
+                    from: owner,
+                });
+            }
+        }
+
+        let (libs, deflib, pkgs) = match inst.and_then(|v| state.libraries(&v.name)) {
+            Some(l) => (&l.names[..], l.default.as_str(), &l.packages[..]),
+            None => (&[][..], "main", &[][..]),
+        };
+        for lib in r.libraries.iter() {
+            if lib != "main" && !libs.contains(lib) {
+                user.push(Action::AddLibrary {
+                    version: r.version.clone(),
+                    library: lib.clone(),
+                });
+            }
+        }
+        if let Some(want) = &r.default_library {
+            if want != deflib {
+                user.push(Action::SetDefaultLibrary {
+                    version: r.version.clone(),
+                    library: want.clone(),
+                    from: Some(deflib.to_string()),
+                });
+            }
+        }
+        // A new default library starts out empty.
+        let have: &[String] = match &r.default_library {
+            Some(want) if want != deflib => &[],
+            _ => pkgs,
+        };
+        let missing: Vec<String> = r
+            .packages
+            .iter()
+            .filter(|p| !have.contains(p))
+            .cloned()
+            .collect();
+        if !missing.is_empty() {
+            user.push(Action::InstallPackages {
+                version: r.version.clone(),
+                packages: missing,
+            });
+        }
+    }
+
+    if let Some(default) = &spec.default {
+        let current = state.default.as_deref();
+        let wanted = state.find(default).map(|v| v.name.as_str());
+        if wanted.is_none() || wanted != current {
+            system.push(Action::SetDefault {
+                version: default.clone(),
+                from: state.default.clone(),
+            });
+        }
+    }
+
+    // Only the versions whose `repositories` file would change, a version
+    // that is not installed yet always needs the setup.
+    if let Some(repos) = &spec.repos {
+        let versions: Vec<String> = spec
+            .r
+            .iter()
+            .filter(|r| match state.find(&r.version) {
+                Some(v) => !state.repos_current.contains(&v.name),
+                None => true,
+            })
+            .map(|r| r.version.clone())
+            .collect();
+        if !versions.is_empty() {
+            system.push(Action::SetupRepos {
+                versions,
+                with: repos.with.clone(),
+                without: repos.without.clone(),
+            });
+        }
+    }
+
+    system.extend(user);
+    system
+}
+
+// ------------------------------------------------------------------------
+// rig apply
+
+pub fn sc_apply(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let json = args.get_flag("json") || mainargs.get_flag("json");
+    let dry_run = args.get_flag("dry-run");
+    let system_only = args.get_flag("system-only");
+    let path = PathBuf::from(args.get_one::<String>("file").unwrap());
+    let spec = read_machine_spec(&path)?;
+
+    let mut state = MachineState::read()?;
+    if let Some(repos) = &spec.repos {
+        state.read_repos(repos);
+    }
+    let actions = plan(&spec, &state);
+
+    if dry_run || !system_only {
+        print_plan(&path, &actions, json)?;
+    }
+    if dry_run || actions.is_empty() {
+        return Ok(());
+    }
+
+    let (system, user): (Vec<&Action>, Vec<&Action>) = actions.iter().partition(|a| a.is_system());
+    if !system.is_empty() {
+        if system_only || !system_part_in_child()? {
+            apply_system(&spec, &system)?;
+        } else {
+            let path = std::fs::canonicalize(&path)?;
+            let path = path.to_string_lossy();
+            run_rig(&["apply", &path, "--system-only"])?;
+        }
+    }
+    if system_only {
+        return Ok(());
+    }
+
+    if !user.is_empty() {
+        apply_user(&user)?;
+    }
+
+    OUTPUT.success(&format!("Applied {}", path.display()));
+    info!("Applied {} ({} changes)", path.display(), actions.len());
+    Ok(())
+}
+
+fn print_plan(path: &Path, actions: &[Action], json: bool) -> Result<(), Box<dyn Error>> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(actions)?);
+    } else if actions.is_empty() {
+        OUTPUT.success(&format!(
+            "Nothing to do, this machine matches {}",
+            path.display()
+        ));
+    } else {
+        for action in actions {
+            println!("{}", action);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+fn system_part_in_child() -> Result<bool, Box<dyn Error>> {
+    Ok(crate::utils::get_mode()? == crate::utils::Mode::Admin && !nix::unistd::geteuid().is_root())
+}
+
+#[cfg(target_os = "windows")]
+fn system_part_in_child() -> Result<bool, Box<dyn Error>> {
+    Ok(true)
+}
+
+fn run_rig(args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let exe = std::env::current_exe()?;
+    debug!("Running rig {}", args.join(" "));
+    let status = Command::new(&exe).args(args).status()?;
+    if !status.success() {
+        bail!("`rig {}` failed", args.join(" "));
+    }
+    Ok(())
+}
+
+/// The installation a `version` of the file means, once it is installed.
+fn installed_name(version: &str) -> Result<String, Box<dyn Error>> {
+    let installed = sc_get_list_details()?;
+    match pick_version(&Request::Version(version.to_string()), &installed, None) {
+        Some(v) => Ok(v.name.clone()),
+        None => bail!("R {} is not installed", version),
+    }
+}
+
+fn apply_system(spec: &MachineSpec, actions: &[&Action]) -> Result<(), Box<dyn Error>> {
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
+    if crate::utils::get_mode()? == crate::utils::Mode::Admin {
+        crate::escalate::escalate("setting up R versions")?;
+    }
+
+    for action in actions {
+        debug!("Applying: {}", action);
+        match action {
+            Action::AddR { version } => {
+                OUTPUT.status(&format!("Installing R {}", version));
+                info!("Installing R {}", version);
+                run_rig(&["add", version])?;
+            }
+            Action::AddAlias { version, alias, .. } => {
+                add_alias(&installed_name(version)?, alias)?;
+            }
+            Action::SetDefault { version, .. } => {
+                let name = installed_name(version)?;
+                sc_set_default(&name)?;
+                OUTPUT.success(&format!("Default R version is now {}", name));
+                info!("Default R version is now {}", name);
+            }
+            Action::SetupRepos { versions, .. } => {
+                let names = versions
+                    .iter()
+                    .map(|v| installed_name(v))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let setup = spec.repos.as_ref().map(|r| r.setup_args()).unwrap();
+                OUTPUT.status(&format!(
+                    "Setting up repositories for R {}",
+                    names.join(", ")
+                ));
+                info!("Setting up repositories for R {}", names.join(", "));
+                repos_setup(Some(names), setup)?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn apply_user(actions: &[&Action]) -> Result<(), Box<dyn Error>> {
+    // A failed package install does not stop the rest of the file.
+    let mut failed: Vec<String> = vec![];
+    for action in actions {
+        debug!("Applying: {}", action);
+        match action {
+            Action::AddLibrary { version, library } => {
+                let name = installed_name(version)?;
+                OUTPUT.status(&format!("Adding library {} of R {}", library, name));
+                info!("Adding library {} of R {}", library, name);
+                library_add(library, &name)?;
+            }
+            Action::SetDefaultLibrary {
+                version, library, ..
+            } => {
+                let name = installed_name(version)?;
+                OUTPUT.status(&format!(
+                    "Setting default library of R {} to {}",
+                    name, library
+                ));
+                info!("Setting default library of R {} to {}", name, library);
+                sc_library_set_default(library, Some(&name))?;
+            }
+            Action::InstallPackages { version, packages } => {
+                let name = installed_name(version)?;
+                let deflib = sc_library_get_default(&name)?;
+                let lib = ResolvedLibrary {
+                    name: Some(deflib.name),
+                    path: deflib.path,
+                    rversion: Some(name.clone()),
+                };
+                let deps = requested_deps(packages)?;
+                let options = InstallOptions {
+                    platform: None,
+                    prefer_binary: None,
+                    reinstall: false,
+                    rebuild: &[],
+                    dry_run: false,
+                    jobs: default_jobs(),
+                    json: false,
+                    print_plan: true,
+                };
+                if let Err(err) = install_into(&lib, &name, &deps, &options, None) {
+                    warn!("Could not install the packages of R {}: {}", name, err);
+                    failed.push(name);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if !failed.is_empty() {
+        bail!("Could not install the packages of R {}", failed.join(", "));
+    }
+    Ok(())
+}
+
+// ------------------------------------------------------------------------
+// rig export
+
+pub fn sc_export(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let json = args.get_flag("json") || mainargs.get_flag("json");
+    let state = MachineState::read()?;
+    let spec = export_spec(&state);
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&spec)?);
+    } else {
+        println!("# Written by `rig export`. The repository setup is not exported,");
+        println!("# add a [repos] table to set it.");
+        println!();
+        print!("{}", toml::to_string(&spec)?);
+    }
+    Ok(())
+}
+
+/// The `rig.toml` of a machine.
+fn export_spec(state: &MachineState) -> MachineSpec {
+    let mut r: Vec<RSpec> = vec![];
+    let mut default: Option<String> = None;
+
+    for v in state.installed.iter() {
+        // The version number is what `rig add` takes on every platform, the
+        // installation name is not, e.g. `4.5-arm64` on macOS. A build of an
+        // already listed version, e.g. a debug build, keeps its name.
+        let moving = v.name == "devel" || v.name == "next";
+        let version = match &v.version {
+            Some(ver) if !moving && !r.iter().any(|x| &x.version == ver) => ver.clone(),
+            _ => v.name.clone(),
+        };
+        if state.default.as_ref() == Some(&v.name) {
+            default = Some(version.clone());
+        }
+        let (libraries, default_library, packages) = match state.libraries(&v.name) {
+            Some(libs) => {
+                let mut pkgs = libs.packages.clone();
+                pkgs.sort_by_key(|p| p.to_lowercase());
+                (
+                    libs.names
+                        .iter()
+                        .filter(|l| *l != "main")
+                        .cloned()
+                        .collect(),
+                    if libs.default == "main" {
+                        None
+                    } else {
+                        Some(libs.default.clone())
+                    },
+                    pkgs,
+                )
+            }
+            None => (vec![], None, vec![]),
+        };
+        let mut aliases = v.aliases.clone();
+        aliases.sort();
+        r.push(RSpec {
+            version,
+            aliases,
+            libraries,
+            default_library,
+            packages,
+        });
+    }
+
+    MachineSpec {
+        default,
+        repos: None,
+        r,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn installed(name: &str, version: &str, aliases: &[&str]) -> InstalledVersion {
+        InstalledVersion {
+            name: name.to_string(),
+            version: Some(version.to_string()),
+            path: None,
+            binary: None,
+            aliases: aliases.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+
+    fn strings(x: &[&str]) -> Vec<String> {
+        x.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn libs(rversion: &str, names: &[&str], default: &str, packages: &[&str]) -> Libraries {
+        Libraries {
+            rversion: rversion.to_string(),
+            names: strings(names),
+            default: default.to_string(),
+            packages: strings(packages),
+        }
+    }
+
+    const SPEC: &str = r#"
+default = "4.5.1"
+
+[repos]
+with = ["bioc"]
+
+[[r]]
+version = "4.5.1"
+aliases = ["release"]
+libraries = ["dev"]
+default-library = "dev"
+packages = ["cli", "glue"]
+
+[[r]]
+version = "4.4.3"
+packages = ["cli"]
+"#;
+
+    fn spec() -> MachineSpec {
+        toml::from_str(SPEC).unwrap()
+    }
+
+    #[test]
+    fn a_spec_is_parsed() {
+        let spec = spec();
+        assert_eq!(spec.default.as_deref(), Some("4.5.1"));
+        assert_eq!(spec.r.len(), 2);
+        assert_eq!(spec.r[0].default_library.as_deref(), Some("dev"));
+        assert_eq!(
+            spec.repos.unwrap().setup_args(),
+            ReposSetupArgs::Default {
+                whitelist: strings(&["bioc"]),
+                blacklist: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn unknown_fields_are_an_error() {
+        assert!(toml::from_str::<MachineSpec>("[[r]]\nversion = \"4.5\"\npkgs = []\n").is_err());
+    }
+
+    #[test]
+    fn without_all_repos() {
+        let repos = ReposSpec {
+            with: strings(&["CRAN"]),
+            without: strings(&["*"]),
+        };
+        assert_eq!(
+            repos.setup_args(),
+            ReposSetupArgs::Empty {
+                whitelist: strings(&["cran"])
+            }
+        );
+    }
+
+    #[test]
+    fn an_empty_machine_gets_everything() {
+        let actions = plan(&spec(), &MachineState::default());
+        let lines: Vec<String> = actions.iter().map(|a| a.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "+ R 4.5.1",
+                "+ alias R-release -> R 4.5.1",
+                "+ R 4.4.3",
+                "~ default R version: none -> 4.5.1",
+                "~ repositories of R 4.5.1, 4.4.3, with bioc",
+                "+ library dev of R 4.5.1",
+                "~ default library of R 4.5.1: main -> dev",
+                "+ packages of R 4.5.1: cli, glue",
+                "+ packages of R 4.4.3: cli",
+            ]
+        );
+    }
+
+    #[test]
+    fn a_matching_machine_needs_no_changes() {
+        let spec = spec();
+        let state = MachineState {
+            installed: vec![
+                installed("4.5.1", "4.5.1", &["release"]),
+                installed("4.4.3", "4.4.3", &[]),
+            ],
+            default: Some("4.5.1".to_string()),
+            libraries: vec![
+                libs("4.5.1", &["main", "dev"], "dev", &["glue", "cli", "rlang"]),
+                libs("4.4.3", &["main"], "main", &["cli"]),
+            ],
+            repos_current: strings(&["4.5.1", "4.4.3"]),
+        };
+        assert!(plan(&spec, &state).is_empty());
+    }
+
+    #[test]
+    fn only_changed_repositories_are_set_up() {
+        let mut spec = spec();
+        spec.r[0].default_library = None;
+        spec.r[0].packages = vec![];
+        spec.r[1].packages = vec![];
+        spec.default = None;
+        let state = MachineState {
+            installed: vec![
+                installed("4.5.1", "4.5.1", &["release"]),
+                installed("4.4.3", "4.4.3", &[]),
+            ],
+            libraries: vec![
+                libs("4.5.1", &["main", "dev"], "main", &[]),
+                libs("4.4.3", &["main"], "main", &[]),
+            ],
+            repos_current: strings(&["4.5.1"]),
+            ..Default::default()
+        };
+        let lines: Vec<String> = plan(&spec, &state).iter().map(|a| a.to_string()).collect();
+        assert_eq!(lines, vec!["~ repositories of R 4.4.3, with bioc"]);
+    }
+
+    #[test]
+    fn a_moved_alias_and_missing_packages() {
+        let mut spec = spec();
+        spec.repos = None;
+        let state = MachineState {
+            installed: vec![
+                installed("4.5.1", "4.5.1", &[]),
+                installed("4.4.3", "4.4.3", &["release"]),
+            ],
+            default: Some("4.4.3".to_string()),
+            libraries: vec![libs("4.5.1", &["main", "dev"], "dev", &["cli"])],
+            repos_current: vec![],
+        };
+        let lines: Vec<String> = plan(&spec, &state).iter().map(|a| a.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "~ alias R-release: R 4.4.3 -> R 4.5.1",
+                "~ default R version: 4.4.3 -> 4.5.1",
+                "+ packages of R 4.5.1: glue",
+                "+ packages of R 4.4.3: cli",
+            ]
+        );
+    }
+
+    #[test]
+    fn export_round_trips() {
+        let state = MachineState {
+            installed: vec![
+                installed("4.5-arm64", "4.5.1", &["release"]),
+                installed("devel", "4.6.0", &[]),
+            ],
+            default: Some("4.5-arm64".to_string()),
+            libraries: vec![libs("4.5-arm64", &["main", "dev"], "dev", &["glue", "cli"])],
+            repos_current: vec![],
+        };
+        let spec = export_spec(&state);
+        assert_eq!(spec.default.as_deref(), Some("4.5.1"));
+        assert_eq!(spec.r[0].version, "4.5.1");
+        assert_eq!(spec.r[0].packages, strings(&["cli", "glue"]));
+        assert_eq!(spec.r[1].version, "devel");
+        let text = toml::to_string(&spec).unwrap();
+        let back: MachineSpec = toml::from_str(&text).unwrap();
+        assert_eq!(back, spec);
+    }
+}
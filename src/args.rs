@@ -259,6 +259,23 @@ pub fn rig_app() -> Command {
                 .required(false),
         );
 
+    let cmd_pin = Command::new("pin")
+        .about(ABOUT_PIN)
+        .display_order(0)
+        .long_about(HELP_PIN)
+        .arg(
+            Arg::new("version")
+                .help("R version to pin for the current directory")
+                .required(false),
+        )
+        .arg(
+            Arg::new("json")
+                .help("JSON output")
+                .long("json")
+                .num_args(0)
+                .required(false),
+        );
+
     let cmd_list = Command::new("list")
         .aliases(["ls"])
         .about(ABOUT_LIST)
@@ -288,8 +305,8 @@ pub fn rig_app() -> Command {
     cmd_add = cmd_add
         .arg(
             Arg::new("str")
-                .help("R version to install")
-                .default_value("release"),
+                .help("R version to install [default: the pinned one, or release]")
+                .required(false),
         )
         .arg(
             Arg::new("with-repos")
@@ -413,6 +430,40 @@ pub fn rig_app() -> Command {
                     .long("platform")
                     .required(false)
                     .platform("linux"),
+            )
+            .arg(
+                Arg::new("from-source")
+                    .help(
+                        "Build R from the source tarball, instead of installing a build.\n\
+                        The tarball is not verified, CRAN publishes no checksums for it.",
+                    )
+                    .long("from-source")
+                    .num_args(0)
+                    .required(false)
+                    .conflicts_with("platform")
+                    .platform("linux"),
+            )
+            .arg(
+                Arg::new("profile")
+                    .help(
+                        "Build profile for --from-source: `default`, `shlib`\n\
+                        (--enable-R-shlib), `debug` or `sanitizers` (ASAN and UBSAN).",
+                    )
+                    .long("profile")
+                    .required(false)
+                    .value_parser(["default", "shlib", "debug", "sanitizers"])
+                    .requires("from-source")
+                    .platform("linux"),
+            )
+            .arg(
+                Arg::new("configure-args")
+                    .help("Extra arguments for `configure`, with --from-source.")
+                    .long("configure-args")
+                    .require_equals(true)
+                    .allow_hyphen_values(true)
+                    .required(false)
+                    .requires("from-source")
+                    .platform("linux"),
             );
     }
 
@@ -467,6 +518,195 @@ pub fn rig_app() -> Command {
                 .required(false),
         );
 
+    let cmd_upgrade = Command::new("upgrade")
+        .about(ABOUT_UPGRADE)
+        .display_order(0)
+        .long_about(HELP_UPGRADE)
+        .arg(
+            Arg::new("from")
+                .help("R version to upgrade from [default: the default R version]")
+                .required(false),
+        )
+        .arg(
+            Arg::new("to")
+                .help("R version to upgrade to, installed if needed")
+                .default_value("release")
+                .required(false),
+        )
+        .arg(
+            Arg::new("remove-old")
+                .help("Remove the old R version if all of its packages were carried over")
+                .long("remove-old")
+                .num_args(0)
+                .required(false),
+        )
+        .arg(
+            Arg::new("exclude")
+                .help("Do not carry over these packages (comma separated list)")
+                .long("exclude")
+                .num_args(1)
+                .value_delimiter(',')
+                .action(clap::ArgAction::Append)
+                .required(false),
+        )
+        .arg(
+            Arg::new("dry-run")
+                .help("Show what would be done, change nothing")
+                .long("dry-run")
+                .num_args(0)
+                .required(false),
+        )
+        .arg(
+            Arg::new("prefer-binary")
+                .help(
+                    "How many of the newest versions of a package to look through\n\
+                    for a binary package, before compiling the newest one.",
+                )
+                .long("prefer-binary")
+                .num_args(1)
+                .require_equals(true)
+                .default_value("3")
+                .value_parser(clap::value_parser!(usize))
+                .required(false),
+        )
+        .arg(
+            Arg::new("jobs")
+                .help("Total number of compiler jobs for source packages (default: number of CPUs)")
+                .long("jobs")
+                .short('j')
+                .num_args(1)
+                .value_parser(clap::value_parser!(usize))
+                .required(false),
+        )
+        .arg(
+            Arg::new("json")
+                .help("JSON output")
+                .long("json")
+                .num_args(0)
+                .required(false),
+        );
+
+    let cmd_apply = Command::new("apply")
+        .about(ABOUT_APPLY)
+        .display_order(0)
+        .long_about(HELP_APPLY)
+        .arg(
+            Arg::new("file")
+                .help("Machine setup file")
+                .default_value("rig.toml")
+                .required(false),
+        )
+        .arg(
+            Arg::new("dry-run")
+                .help("Show the changes, make none")
+                .long("dry-run")
+                .num_args(0)
+                .required(false),
+        )
+        .arg(
+            Arg::new("system-only")
+                .help("Only change the R installations, not the libraries")
+                .long("system-only")
+                .num_args(0)
+                .hide(true)
+                .required(false),
+        )
+        .arg(
+            Arg::new("json")
+                .help("JSON output")
+                .long("json")
+                .num_args(0)
+                .required(false),
+        );
+
+    let cmd_export = Command::new("export")
+        .about(ABOUT_EXPORT)
+        .display_order(0)
+        .long_about(HELP_EXPORT)
+        .arg(
+            Arg::new("json")
+                .help("JSON output")
+                .long("json")
+                .num_args(0)
+                .required(false),
+        );
+
+    let cmd_doctor = Command::new("doctor")
+        .about(ABOUT_DOCTOR)
+        .display_order(0)
+        .long_about(HELP_DOCTOR)
+        .arg(
+            Arg::new("fix")
+                .help("Fix the problems that are safe to fix")
+                .long("fix")
+                .num_args(0)
+                .required(false),
+        )
+        .arg(
+            Arg::new("json")
+                .help("JSON output")
+                .long("json")
+                .num_args(0)
+                .required(false),
+        );
+
+    let cmd_audit = Command::new("audit")
+        .about(ABOUT_AUDIT)
+        .display_order(0)
+        .long_about(HELP_AUDIT)
+        .arg(
+            Arg::new("lockfile")
+                .help("Lockfile to check")
+                .long("lockfile")
+                .num_args(1)
+                .default_value("pkg.lock")
+                .required(false),
+        )
+        .arg(
+            Arg::new("library")
+                .help("Library name or path to check, instead of the lockfile")
+                .long("library")
+                .short('l')
+                .num_args(1)
+                .conflicts_with("lockfile")
+                .required(false),
+        )
+        .arg(
+            Arg::new("r-version")
+                .help("R version of the library, instead of the default")
+                .long("r-version")
+                .short('r')
+                .num_args(1)
+                .requires("library")
+                .required(false),
+        )
+        .arg(
+            Arg::new("db")
+                .help(
+                    "OSV advisory database: a directory, a .zip or .tar.gz\n\
+                    archive, or the URL of one.",
+                )
+                .long("db")
+                .num_args(1)
+                .required(false),
+        )
+        .arg(
+            Arg::new("ignore")
+                .help("Advisories to ignore, by ID or alias, comma separated")
+                .long("ignore")
+                .num_args(1)
+                .value_delimiter(',')
+                .action(clap::ArgAction::Append)
+                .required(false),
+        )
+        .arg(
+            Arg::new("json")
+                .help("JSON output")
+                .long("json")
+                .num_args(0)
+                .required(false),
+        );
+
     let mut cmd_available = Command::new("available")
         .about(ABOUT_AVAILABLE)
         .display_order(0)
@@ -1393,6 +1633,15 @@ pub fn rig_app() -> Command {
                         .long("json")
                         .num_args(0)
                         .required(false),
+                )
+                .arg(
+                    Arg::new("format")
+                        .help("Print the tree as a graph, in this format")
+                        .long("format")
+                        .num_args(1)
+                        .value_parser(["dot", "mermaid", "graphml"])
+                        .conflicts_with("json")
+                        .required(false),
                 ),
         )
         .subcommand(
@@ -1492,6 +1741,115 @@ pub fn rig_app() -> Command {
                         .num_args(1)
                         .value_parser(clap::value_parser!(usize))
                         .required(false),
+                )
+                .arg(
+                    Arg::new("jobs")
+                        .help("Total number of compiler jobs for source packages (default: number of CPUs)")
+                        .long("jobs")
+                        .short('j')
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(usize))
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("progress")
+                        .help("How to show progress: progress bars, or JSON events, one per line")
+                        .long("progress")
+                        .num_args(1)
+                        .require_equals(true)
+                        .value_parser(["bar", "jsonl"])
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("progress-fd")
+                        .help("Write the --progress=jsonl events to this file descriptor, instead of standard output")
+                        .long("progress-fd")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(i32))
+                        .requires("progress")
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("licenses")
+                .about(ABOUT_PROJ_LICENSES)
+                .long_about(HELP_PROJ_LICENSES)
+                .display_order(0)
+                .arg(
+                    Arg::new("lockfile")
+                        .help("Lockfile to read")
+                        .long("lockfile")
+                        .num_args(1)
+                        .default_value("pkg.lock")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("deny")
+                        .help(
+                            "Licenses that must not appear, comma separated, e.g.\n\
+                            GPL-3,AGPL. Fails if a package has one of them.",
+                        )
+                        .long("deny")
+                        .num_args(1)
+                        .value_delimiter(',')
+                        .action(clap::ArgAction::Append)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("json")
+                        .help("JSON output")
+                        .long("json")
+                        .num_args(0)
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("sbom")
+                .about(ABOUT_PROJ_SBOM)
+                .long_about(HELP_PROJ_SBOM)
+                .display_order(0)
+                .arg(
+                    Arg::new("format")
+                        .help("SBOM format")
+                        .long("format")
+                        .num_args(1)
+                        .value_parser(["cyclonedx", "spdx"])
+                        .default_value("cyclonedx")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("lockfile")
+                        .help("Lockfile to read")
+                        .long("lockfile")
+                        .num_args(1)
+                        .default_value("pkg.lock")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("library")
+                        .help("Library name or path to read, instead of the lockfile")
+                        .long("library")
+                        .short('l')
+                        .num_args(1)
+                        .conflicts_with("lockfile")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("r-version")
+                        .help("R version of the library, instead of the default")
+                        .long("r-version")
+                        .short('r')
+                        .num_args(1)
+                        .requires("library")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("output")
+                        .help("File to write the SBOM to, instead of the standard output")
+                        .long("output")
+                        .short('o')
+                        .num_args(1)
+                        .required(false),
                 ),
         );
     rig = rig.subcommand(cmd_proj);
@@ -1540,6 +1898,53 @@ pub fn rig_app() -> Command {
                         .long("pkg-type")
                         .num_args(1)
                         .required(false),
+                )
+                .arg(
+                    Arg::new("repo")
+                        .help(
+                            "Repository to list, a name or a URL, can be given several times \
+                            (default: the repositories of the R version)",
+                        )
+                        .long("repo")
+                        .num_args(1)
+                        .action(clap::ArgAction::Append)
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("check")
+                .about(ABOUT_PKG_CHECK)
+                .long_about(HELP_PKG_CHECK)
+                .display_order(0)
+                .arg(
+                    Arg::new("library")
+                        .help("Library name or path, instead of the default library")
+                        .long("library")
+                        .short('l')
+                        .num_args(1)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("r-version")
+                        .help("R version to operate on, instead of the default")
+                        .long("r-version")
+                        .short('r')
+                        .num_args(1)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("fix")
+                        .help("Reinstall the packages that have problems")
+                        .long("fix")
+                        .num_args(0)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("json")
+                        .help("JSON output")
+                        .long("json")
+                        .num_args(0)
+                        .required(false),
                 ),
         )
         .subcommand(
@@ -1575,8 +1980,16 @@ pub fn rig_app() -> Command {
                         .required(false),
                 )
                 .arg(
-                    Arg::new("json")
-                        .help("JSON output")
+                    Arg::new("reverse")
+                        .help("Show the packages on CRAN that depend on the package")
+                        .long("reverse")
+                        .num_args(0)
+                        .required(false)
+                        .conflicts_with("version"),
+                )
+                .arg(
+                    Arg::new("json")
+                        .help("JSON output")
                         .long("json")
                         .num_args(0)
                         .required(false),
@@ -1660,6 +2073,33 @@ pub fn rig_app() -> Command {
                         .num_args(0)
                         .required(false),
                 )
+                .arg(
+                    Arg::new("jobs")
+                        .help("Total number of compiler jobs for source packages (default: number of CPUs)")
+                        .long("jobs")
+                        .short('j')
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(usize))
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("progress")
+                        .help("How to show progress: progress bars, or JSON events, one per line")
+                        .long("progress")
+                        .num_args(1)
+                        .require_equals(true)
+                        .value_parser(["bar", "jsonl"])
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("progress-fd")
+                        .help("Write the --progress=jsonl events to this file descriptor, instead of standard output")
+                        .long("progress-fd")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(i32))
+                        .requires("progress")
+                        .required(false),
+                )
                 .arg(
                     Arg::new("platform")
                         .help(
@@ -1695,6 +2135,47 @@ pub fn rig_app() -> Command {
                         .required(false),
                 ),
         )
+        .subcommand(
+            Command::new("licenses")
+                .about(ABOUT_PKG_LICENSES)
+                .long_about(HELP_PKG_LICENSES)
+                .display_order(0)
+                .arg(
+                    Arg::new("library")
+                        .help("Library name or path, instead of the default library")
+                        .long("library")
+                        .short('l')
+                        .num_args(1)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("r-version")
+                        .help("R version to operate on, instead of the default")
+                        .long("r-version")
+                        .short('r')
+                        .num_args(1)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("deny")
+                        .help(
+                            "Licenses that must not appear, comma separated, e.g.\n\
+                            GPL-3,AGPL. Fails if a package has one of them.",
+                        )
+                        .long("deny")
+                        .num_args(1)
+                        .value_delimiter(',')
+                        .action(clap::ArgAction::Append)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("json")
+                        .help("JSON output")
+                        .long("json")
+                        .num_args(0)
+                        .required(false),
+                ),
+        )
         .subcommand(
             Command::new("list")
                 .aliases(["ls"])
@@ -1768,6 +2249,140 @@ pub fn rig_app() -> Command {
                         .required(false),
                 ),
         )
+        .subcommand(
+            Command::new("revdeps")
+                .about(ABOUT_PKG_REVDEPS)
+                .long_about(HELP_PKG_REVDEPS)
+                .display_order(0)
+                .arg(
+                    Arg::new("package")
+                        .help("package to show the reverse dependencies of")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("depth")
+                        .help("How many levels of reverse dependencies to show")
+                        .long("depth")
+                        .short('d')
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(u64).range(1..))
+                        .default_value("1")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("recursive")
+                        .help("Show all levels of reverse dependencies")
+                        .long("recursive")
+                        .num_args(0)
+                        .required(false)
+                        .conflicts_with("depth"),
+                )
+                .arg(
+                    Arg::new("dependencies")
+                        .help(
+                            "Dependency types to follow, comma separated, e.g.\n\
+                            Imports,LinkingTo (default: Depends, Imports, LinkingTo)",
+                        )
+                        .long("dependencies")
+                        .num_args(1)
+                        .value_delimiter(',')
+                        .action(clap::ArgAction::Append)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("dev")
+                        .help("Include the packages that suggest or enhance the package")
+                        .long("dev")
+                        .num_args(0)
+                        .required(false)
+                        .conflicts_with("dependencies"),
+                )
+                .arg(
+                    Arg::new("library")
+                        .help("Library name or path, search its packages instead of CRAN")
+                        .long("library")
+                        .short('l')
+                        .num_args(1)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("r-version")
+                        .help("R version of the library, instead of the default")
+                        .long("r-version")
+                        .short('r')
+                        .num_args(1)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("json")
+                        .help("JSON output")
+                        .long("json")
+                        .num_args(0)
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("search")
+                .about(ABOUT_PKG_SEARCH)
+                .long_about(HELP_PKG_SEARCH)
+                .display_order(0)
+                .arg(
+                    Arg::new("query")
+                        .help("words to search for")
+                        .num_args(1..)
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("limit")
+                        .help("Maximum number of packages to show")
+                        .long("limit")
+                        .short('n')
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("20"),
+                )
+                .arg(
+                    Arg::new("json")
+                        .help("JSON output")
+                        .long("json")
+                        .num_args(0)
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("size")
+                .about(ABOUT_PKG_SIZE)
+                .long_about(HELP_PKG_SIZE)
+                .display_order(0)
+                .arg(
+                    Arg::new("package")
+                        .help("Package to weigh, instead of all packages of the library")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("library")
+                        .help("Library name or path, instead of the default library")
+                        .long("library")
+                        .short('l')
+                        .num_args(1)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("r-version")
+                        .help("R version to operate on, instead of the default")
+                        .long("r-version")
+                        .short('r')
+                        .num_args(1)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("json")
+                        .help("JSON output")
+                        .long("json")
+                        .num_args(0)
+                        .required(false),
+                ),
+        )
         .subcommand(
             Command::new("tree")
                 .about(ABOUT_PKG_TREE)
@@ -1814,6 +2429,15 @@ pub fn rig_app() -> Command {
                         .long("json")
                         .num_args(0)
                         .required(false),
+                )
+                .arg(
+                    Arg::new("format")
+                        .help("Print the tree as a graph, in this format")
+                        .long("format")
+                        .num_args(1)
+                        .value_parser(["dot", "mermaid", "graphml"])
+                        .conflicts_with("json")
+                        .required(false),
                 ),
         );
 
@@ -1857,6 +2481,51 @@ pub fn rig_app() -> Command {
                         .required(false),
                 ),
         )
+        .subcommand(
+            Command::new("coverage")
+                .about(ABOUT_PPM_COVERAGE)
+                .long_about(HELP_PPM_COVERAGE)
+                .display_order(0)
+                .arg(
+                    Arg::new("lockfile")
+                        .help("Lockfile to check")
+                        .long("lockfile")
+                        .num_args(1)
+                        .default_value("pkg.lock")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("platform")
+                        .help(
+                            "P3M platforms to check, comma separated, e.g. jammy,noble-arm64\n\
+                            (default: this machine's)",
+                        )
+                        .long("platform")
+                        .num_args(1)
+                        .value_delimiter(',')
+                        .action(clap::ArgAction::Append)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("r-version")
+                        .help(
+                            "R versions to check, comma separated, e.g. 4.4,4.5\n\
+                            (default: the lockfile's)",
+                        )
+                        .long("r-version")
+                        .num_args(1)
+                        .value_delimiter(',')
+                        .action(clap::ArgAction::Append)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("json")
+                        .help("JSON output")
+                        .long("json")
+                        .num_args(0)
+                        .required(false),
+                ),
+        )
         .subcommand(
             Command::new("platforms")
                 .about(ABOUT_PPM_PLATFORMS)
@@ -1971,44 +2640,75 @@ pub fn rig_app() -> Command {
                 .num_args(0)
                 .required(false),
         )
-        // .subcommand(
-        //     Command::new("add")
-        //         .about("Add an R package repository")
-        //         .display_order(0)
-        //         .arg(
-        //             Arg::new("enable")
-        //                 .help("Enable the repository after adding it")
-        //                 .long("enable")
-        //                 .num_args(0)
-        //                 .required(false),
-        //         )
-        //         .arg(
-        //             Arg::new("name")
-        //                 .help("name of the repository, e.g. 'CRAN'")
-        //                 .required(true),
-        //         )
-        //         .arg(Arg::new("url").help("URL of the repository").required(true)),
-        // )
-        // .subcommand(
-        //     Command::new("disable")
-        //         .about("Disable an R package repository")
-        //         .display_order(0)
-        //         .arg(
-        //             Arg::new("name")
-        //                 .help("name of the repository, e.g. 'CRAN'")
-        //                 .required(true),
-        //         ),
-        // )
-        // .subcommand(
-        //     Command::new("enable")
-        //         .about("Enable an R package repository")
-        //         .display_order(0)
-        //         .arg(
-        //             Arg::new("name")
-        //                 .help("name of the repository, e.g. 'CRAN'")
-        //                 .required(true),
-        //         ),
-        // )
+        .subcommand(
+            Command::new("add")
+                .about(ABOUT_REPOS_ADD)
+                .long_about(HELP_REPOS_ADD)
+                .display_order(0)
+                .arg(
+                    Arg::new("enable")
+                        .help("Enable the repository after adding it")
+                        .long("enable")
+                        .num_args(0)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("r-version")
+                        .help("R version to add the repository to (default: all)")
+                        .long("r-version")
+                        .short('r')
+                        .num_args(1)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("name")
+                        .help("Name of the repository, e.g. 'RHUB', or a new name if a URL is given")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("url")
+                        .help("URL of the repository, not needed for the repositories rig knows about")
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("disable")
+                .about(ABOUT_REPOS_DISABLE)
+                .long_about(HELP_REPOS_DISABLE)
+                .display_order(0)
+                .arg(
+                    Arg::new("r-version")
+                        .help("R version to disable the repository for (default: all)")
+                        .long("r-version")
+                        .short('r')
+                        .num_args(1)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("name")
+                        .help("Name of the repository, e.g. 'CRAN'")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("enable")
+                .about(ABOUT_REPOS_ENABLE)
+                .long_about(HELP_REPOS_ENABLE)
+                .display_order(0)
+                .arg(
+                    Arg::new("r-version")
+                        .help("R version to enable the repository for (default: all)")
+                        .long("r-version")
+                        .short('r')
+                        .num_args(1)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("name")
+                        .help("Name of the repository, e.g. 'CRAN'")
+                        .required(true),
+                ),
+        )
         .subcommand(
             Command::new("list")
                 .about(ABOUT_REPOS_LIST)
@@ -2097,20 +2797,170 @@ pub fn rig_app() -> Command {
                         .required(false),
                 ),
         )
-        // .subcommand(
-        //     Command::new("reset")
-        //         .about("Reset R package repositories to rig or R default")
-        //         .display_order(0),
-        // )
-        // .subcommand(
-        //     Command::new("rm")
-        //         .about("Remove an R package repository")
-        //         .display_order(0),
-        // )
+        .subcommand(
+            Command::new("reset")
+                .about(ABOUT_REPOS_RESET)
+                .long_about(HELP_REPOS_RESET)
+                .display_order(0)
+                .arg(
+                    Arg::new("r-default")
+                        .help("Reset to the repositories of R, instead of the rig defaults")
+                        .long("r-default")
+                        .num_args(0)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("r-version")
+                        .help("R version to reset the repositories of (default: all)")
+                        .long("r-version")
+                        .short('r')
+                        .num_args(1)
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("rm")
+                .about(ABOUT_REPOS_RM)
+                .long_about(HELP_REPOS_RM)
+                .display_order(0)
+                .arg(
+                    Arg::new("r-version")
+                        .help("R version to remove the repository from (default: all)")
+                        .long("r-version")
+                        .short('r')
+                        .num_args(1)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("name")
+                        .help("Name of the repository, e.g. 'CRAN'")
+                        .required(true),
+                ),
+        )
         .subcommand(cmd_repos_setup);
 
     rig = rig.subcommand(cmd_repos);
 
+    let cache_category = || {
+        Arg::new("category")
+            .help("Cache categories to operate on (default: all)")
+            .value_parser(["packages", "binaries", "metadata", "installers"])
+            .action(clap::ArgAction::Append)
+            .required(false)
+    };
+    let cmd_cache = Command::new("cache")
+        .about(ABOUT_CACHE)
+        .long_about(HELP_CACHE)
+        .display_order(0)
+        .arg_required_else_help(true)
+        .arg(
+            Arg::new("json")
+                .help("JSON output")
+                .long("json")
+                .num_args(0)
+                .required(false),
+        )
+        .subcommand(
+            Command::new("info")
+                .about(ABOUT_CACHE_INFO)
+                .long_about(HELP_CACHE_INFO)
+                .display_order(0)
+                .arg(cache_category())
+                .arg(
+                    Arg::new("json")
+                        .help("JSON output")
+                        .long("json")
+                        .num_args(0)
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("prune")
+                .about(ABOUT_CACHE_PRUNE)
+                .long_about(HELP_CACHE_PRUNE)
+                .display_order(0)
+                .arg(cache_category())
+                .arg(
+                    Arg::new("older-than")
+                        .help("Remove files not modified for this long, e.g. 90d, 12h, 2w")
+                        .long("older-than")
+                        .value_name("AGE")
+                        .num_args(1)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("unused")
+                        .help("Remove package files no known lockfile or library uses")
+                        .long("unused")
+                        .num_args(0)
+                        .required(false),
+                )
+                .group(
+                    ArgGroup::new("prune-by")
+                        .args(["older-than", "unused"])
+                        .multiple(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("lockfile")
+                        .help(
+                            "Lockfile whose packages are in use (default: pkg.lock, if it exists)",
+                        )
+                        .long("lockfile")
+                        .num_args(1)
+                        .action(clap::ArgAction::Append)
+                        .requires("unused")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("library")
+                        .help("Extra library directory whose packages are in use")
+                        .long("library")
+                        .short('l')
+                        .num_args(1)
+                        .action(clap::ArgAction::Append)
+                        .requires("unused")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("dry-run")
+                        .help("Show what would be removed, remove nothing")
+                        .long("dry-run")
+                        .num_args(0)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("json")
+                        .help("JSON output")
+                        .long("json")
+                        .num_args(0)
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("clean")
+                .about(ABOUT_CACHE_CLEAN)
+                .long_about(HELP_CACHE_CLEAN)
+                .display_order(0)
+                .arg(cache_category())
+                .arg(
+                    Arg::new("dry-run")
+                        .help("Show what would be removed, remove nothing")
+                        .long("dry-run")
+                        .num_args(0)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("json")
+                        .help("JSON output")
+                        .long("json")
+                        .num_args(0)
+                        .required(false),
+                ),
+        );
+
+    rig = rig.subcommand(cmd_cache);
+
     #[cfg(debug_assertions)]
     {
         let cmd_test = Command::new("test")
@@ -2215,13 +3065,28 @@ pub fn rig_app() -> Command {
                 .long("admin")
                 .global(true)
                 .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("lock-timeout")
+                .help("Seconds to wait for other rig processes to release a lock")
+                .long("lock-timeout")
+                .global(true)
+                .num_args(1)
+                .value_parser(clap::value_parser!(u64))
+                .required(false),
         );
 
     rig = rig
         .subcommand(cmd_default)
+        .subcommand(cmd_pin)
         .subcommand(cmd_list)
         .subcommand(cmd_add)
         .subcommand(cmd_rm)
+        .subcommand(cmd_upgrade)
+        .subcommand(cmd_apply)
+        .subcommand(cmd_export)
+        .subcommand(cmd_doctor)
+        .subcommand(cmd_audit)
         .subcommand(cmd_system)
         .subcommand(cmd_rtools())
         .subcommand(cmd_resolve)
@@ -2623,4 +3488,96 @@ mod tests {
             .unwrap();
         assert!(m.get_flag("json"));
     }
+
+    #[test]
+    fn test_cache_args() {
+        let m = rig_app()
+            .try_get_matches_from(["rig", "cache", "info", "packages", "installers"])
+            .unwrap();
+        let info = m
+            .subcommand_matches("cache")
+            .unwrap()
+            .subcommand_matches("info")
+            .unwrap();
+        let cats: Vec<&String> = info.get_many::<String>("category").unwrap().collect();
+        assert_eq!(cats, ["packages", "installers"]);
+
+        assert!(rig_app()
+            .try_get_matches_from(["rig", "cache", "info", "logs"])
+            .is_err());
+
+        // `prune` needs something to prune by, and --lockfile only makes sense
+        // with --unused.
+        assert!(rig_app()
+            .try_get_matches_from(["rig", "cache", "prune"])
+            .is_err());
+        assert!(rig_app()
+            .try_get_matches_from([
+                "rig",
+                "cache",
+                "prune",
+                "--older-than",
+                "90d",
+                "--lockfile",
+                "x"
+            ])
+            .is_err());
+        let m = rig_app()
+            .try_get_matches_from([
+                "rig",
+                "cache",
+                "prune",
+                "--older-than",
+                "90d",
+                "--unused",
+                "--lockfile",
+                "a/pkg.lock",
+                "--lockfile",
+                "b/pkg.lock",
+            ])
+            .unwrap();
+        let prune = m
+            .subcommand_matches("cache")
+            .unwrap()
+            .subcommand_matches("prune")
+            .unwrap();
+        assert!(prune.get_flag("unused"));
+        assert_eq!(
+            prune.get_one::<String>("older-than"),
+            Some(&"90d".to_string())
+        );
+        assert_eq!(prune.get_many::<String>("lockfile").unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_progress_args() {
+        let m = rig_app()
+            .try_get_matches_from([
+                "rig",
+                "pkg",
+                "install",
+                "--progress=jsonl",
+                "--progress-fd",
+                "3",
+                "cli",
+            ])
+            .unwrap();
+        let install = m
+            .subcommand_matches("pkg")
+            .unwrap()
+            .subcommand_matches("install")
+            .unwrap();
+        assert_eq!(
+            install.get_one::<String>("progress"),
+            Some(&"jsonl".to_string())
+        );
+        assert_eq!(install.get_one::<i32>("progress-fd"), Some(&3));
+
+        assert!(rig_app()
+            .try_get_matches_from(["rig", "proj", "deploy", "-l", "lib", "--progress=json"])
+            .is_err());
+        assert!(rig_app()
+            .try_get_matches_from(["rig", "proj", "deploy", "-l", "lib", "--progress-fd", "3"])
+            .is_err());
+    }
 }
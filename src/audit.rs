@@ -0,0 +1,625 @@
+//! `rig audit`: check the packages of a lockfile or a library against the
+//! security advisories of an OSV database.
+//!
+//! The advisories are OSV JSON files (<https://ossf.github.io/osv-schema/>),
+//! either in a local directory, or in a `.zip` or `.tar.gz` archive, local or
+//! downloaded. By default rig downloads the CRAN advisories of osv.dev, and
+//! caches them for a day.
+//!
+//! An advisory lists the affected versions as ranges of `introduced`, `fixed`
+//! and `last_affected` events. Each range is turned into version intervals,
+//! i.e. [`DepVersionSpec`]s, so a version is matched the same way as the
+//! version requirements of the dependencies.
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::ArgMatches;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use simple_error::*;
+use tabular::*;
+
+use crate::cache::get_cache_dir;
+use crate::dcf::{DepVersionSpec, RPackageVersion, VersionConstraint, VersionConstraintType};
+use crate::download::download_if_newer_;
+use crate::output::OUTPUT;
+use crate::pak::PakLockfile;
+use crate::pkg::list::{read_installed, resolve_library};
+use crate::solver::is_base_package;
+use crate::utils::{calculate_hash, create_parent_dir_if_needed};
+
+/// How long a downloaded advisory database is used before checking for a new
+/// one.
+const ADVISORY_DB_TTL: Duration = Duration::from_hours(24);
+
+/// The OSV ecosystems of R packages.
+const ECOSYSTEMS: &[&str] = &["CRAN", "Bioconductor"];
+
+fn advisory_db_url() -> String {
+    std::env::var("RIG_ADVISORY_DB").unwrap_or_else(|_| {
+        "https://osv-vulnerabilities.storage.googleapis.com/CRAN/all.zip".to_string()
+    })
+}
+
+pub fn sc_audit(args: &ArgMatches, mainargs: &ArgMatches) -> Result<i32, Box<dyn Error>> {
+    let json = args.get_flag("json") || mainargs.get_flag("json");
+
+    let (packages, tag) = if args.contains_id("library") {
+        let lib = resolve_library(args)?;
+        // The base packages are part of R.
+        let packages: Vec<(String, String)> = read_installed(&lib.path)?
+            .into_iter()
+            .filter(|pkg| !is_base_package(&pkg.package))
+            .map(|pkg| (pkg.package, pkg.version))
+            .collect();
+        (packages, lib.tag())
+    } else {
+        let path = args.get_one::<String>("lockfile").unwrap();
+        let contents = match fs::read_to_string(path) {
+            Ok(x) => x,
+            Err(err) => bail!(
+                "Cannot read lockfile {}: {}. Create one with `rig proj solve`.",
+                path,
+                err
+            ),
+        };
+        let lockfile: PakLockfile = serde_json::from_str(&contents)?;
+        let packages: Vec<(String, String)> = lockfile
+            .packages
+            .into_iter()
+            .map(|pkg| (pkg.package, pkg.version))
+            .collect();
+        (packages, format!("({})", path))
+    };
+
+    let db = match args.get_one::<String>("db") {
+        Some(db) => db.to_string(),
+        None => advisory_db_url(),
+    };
+    let advisories = load_advisories(&db)?;
+    let ignore: HashSet<&str> = args
+        .get_many::<String>("ignore")
+        .map(|ids| ids.map(|id| id.as_str()).collect())
+        .unwrap_or_default();
+
+    let index = AdvisoryIndex::new(&advisories);
+    let findings: Vec<Finding> = index
+        .audit(&packages)
+        .into_iter()
+        .filter(|f| {
+            let ignored = ignore.contains(f.advisory.as_str())
+                || f.aliases.iter().any(|a| ignore.contains(a.as_str()));
+            if ignored {
+                info!("Ignoring advisory {} of {}", f.advisory, f.package);
+            }
+            !ignored
+        })
+        .collect();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&findings)?);
+    } else {
+        print_findings(&findings, packages.len(), advisories.len(), &tag);
+    }
+
+    Ok(if findings.is_empty() { 0 } else { 1 })
+}
+
+// ------------------------------------------------------------------------
+// The OSV format, the parts of it rig uses
+
+#[derive(Debug, Deserialize)]
+struct OsvAdvisory {
+    id: String,
+    #[serde(default)]
+    summary: String,
+    #[serde(default)]
+    aliases: Vec<String>,
+    /// When the advisory was withdrawn, e.g. because it was a mistake.
+    withdrawn: Option<String>,
+    #[serde(default)]
+    affected: Vec<OsvAffected>,
+    #[serde(default)]
+    references: Vec<OsvReference>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OsvAffected {
+    package: OsvPackage,
+    #[serde(default)]
+    ranges: Vec<OsvRange>,
+    /// Affected versions, listed one by one, in addition to the ranges.
+    #[serde(default)]
+    versions: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OsvPackage {
+    ecosystem: String,
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct OsvRange {
+    #[serde(rename = "type")]
+    range_type: String,
+    #[serde(default)]
+    events: Vec<OsvEvent>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OsvEvent {
+    introduced: Option<String>,
+    fixed: Option<String>,
+    last_affected: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OsvReference {
+    #[serde(rename = "type")]
+    ref_type: String,
+    url: String,
+}
+
+impl OsvAdvisory {
+    /// The advisory itself, or else the first reference.
+    fn url(&self) -> Option<String> {
+        self.references
+            .iter()
+            .find(|r| r.ref_type == "ADVISORY")
+            .or(self.references.first())
+            .map(|r| r.url.clone())
+    }
+}
+
+// ------------------------------------------------------------------------
+// Reading the database
+
+/// Read the advisories from a directory, an archive or a single JSON file, or
+/// download the archive first, if `db` is a URL.
+fn load_advisories(db: &str) -> Result<Vec<OsvAdvisory>, Box<dyn Error>> {
+    let path = if db.starts_with("https://") || db.starts_with("http://") {
+        download_advisory_db(db)?
+    } else {
+        PathBuf::from(db)
+    };
+
+    let mut files: Vec<(String, String)> = vec![];
+    if path.is_dir() {
+        read_json_dir(&path, &mut files)?;
+    } else {
+        let name = path.to_string_lossy().to_lowercase();
+        if name.ends_with(".zip") {
+            let mut ar = zip::ZipArchive::new(fs::File::open(&path)?)?;
+            for i in 0..ar.len() {
+                let mut entry = ar.by_index(i)?;
+                if entry.is_file() && entry.name().ends_with(".json") {
+                    let mut contents = String::new();
+                    entry.read_to_string(&mut contents)?;
+                    files.push((entry.name().to_string(), contents));
+                }
+            }
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            let decoder = flate2::read::GzDecoder::new(fs::File::open(&path)?);
+            let mut ar = tar::Archive::new(decoder);
+            for entry in ar.entries()? {
+                let mut entry = entry?;
+                let entry_path = entry.path()?.to_string_lossy().to_string();
+                if entry.header().entry_type().is_file() && entry_path.ends_with(".json") {
+                    let mut contents = String::new();
+                    entry.read_to_string(&mut contents)?;
+                    files.push((entry_path, contents));
+                }
+            }
+        } else if name.ends_with(".json") {
+            files.push((path.display().to_string(), fs::read_to_string(&path)?));
+        } else {
+            bail!(
+                "Advisory database must be a directory, a .zip, .tar.gz or .json file, \
+                 or a URL: {}",
+                db
+            );
+        }
+    }
+
+    let mut advisories: Vec<OsvAdvisory> = vec![];
+    let mut bad = 0;
+    for (name, contents) in files {
+        match serde_json::from_str::<OsvAdvisory>(&contents) {
+            Ok(adv) if adv.withdrawn.is_some() => debug!("Skipping withdrawn {}", adv.id),
+            Ok(adv) => advisories.push(adv),
+            Err(err) => {
+                debug!("Cannot parse advisory {}: {}", name, err);
+                bad += 1;
+            }
+        }
+    }
+    if bad > 0 {
+        OUTPUT.warn(&format!(
+            "Skipped {} advisories that are not valid OSV",
+            bad
+        ));
+        warn!("Skipped {} advisories that are not valid OSV", bad);
+    }
+    info!("Read {} advisories from {}", advisories.len(), db);
+
+    Ok(advisories)
+}
+
+fn read_json_dir(dir: &Path, files: &mut Vec<(String, String)>) -> Result<(), Box<dyn Error>> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            read_json_dir(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "json") {
+            files.push((path.display().to_string(), fs::read_to_string(&path)?));
+        }
+    }
+    Ok(())
+}
+
+/// Download the advisory archive at `url` into the cache, unless the cached
+/// copy is recent.
+fn download_advisory_db(url: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let basename = url.rsplit('/').next().unwrap_or("advisories.zip");
+    let mut local = get_cache_dir()?;
+    local.push("advisories");
+    local.push(format!("{}-{}", &calculate_hash(url)[..8], basename));
+    create_parent_dir_if_needed(&local)?;
+
+    if !local.exists() {
+        OUTPUT.status(&format!("Downloading advisories from {}", url));
+    }
+    download_if_newer_(url, &local, Some(ADVISORY_DB_TTL), None)?;
+    Ok(local)
+}
+
+// ------------------------------------------------------------------------
+// Matching
+
+/// Affected versions of a package, by one advisory.
+#[derive(Debug)]
+struct AffectedInterval {
+    spec: DepVersionSpec,
+    /// The first version without the problem, if there is one.
+    fixed: Option<String>,
+}
+
+/// The advisories of each package, with their affected versions.
+struct AdvisoryIndex<'a> {
+    packages: HashMap<&'a str, Vec<(&'a OsvAdvisory, &'a OsvAffected)>>,
+}
+
+#[derive(Debug, Serialize)]
+struct Finding {
+    package: String,
+    version: String,
+    advisory: String,
+    aliases: Vec<String>,
+    summary: String,
+    /// The version to upgrade to, if the advisory knows a fixed version.
+    fixed: Option<String>,
+    url: Option<String>,
+}
+
+impl<'a> AdvisoryIndex<'a> {
+    fn new(advisories: &'a [OsvAdvisory]) -> AdvisoryIndex<'a> {
+        let mut packages: HashMap<&str, Vec<(&OsvAdvisory, &OsvAffected)>> = HashMap::new();
+        for adv in advisories {
+            for aff in adv.affected.iter() {
+                if ECOSYSTEMS.contains(&aff.package.ecosystem.as_str()) {
+                    packages
+                        .entry(aff.package.name.as_str())
+                        .or_default()
+                        .push((adv, aff));
+                }
+            }
+        }
+        AdvisoryIndex { packages }
+    }
+
+    /// The advisories that affect `packages`, `(name, version)` pairs, by
+    /// package name.
+    fn audit(&self, packages: &[(String, String)]) -> Vec<Finding> {
+        let mut findings: Vec<Finding> = vec![];
+        for (name, version) in packages {
+            let Some(entries) = self.packages.get(name.as_str()) else {
+                continue;
+            };
+            let parsed = match RPackageVersion::from_str(version) {
+                Ok(v) => v,
+                Err(_) => {
+                    warn!("Cannot check {} {}, invalid version", name, version);
+                    continue;
+                }
+            };
+            for (adv, aff) in entries {
+                if findings
+                    .iter()
+                    .any(|f| f.package == *name && f.advisory == adv.id)
+                {
+                    continue;
+                }
+                if let Some(fixed) = affected(name, &parsed, aff) {
+                    findings.push(Finding {
+                        package: name.to_string(),
+                        version: version.to_string(),
+                        advisory: adv.id.clone(),
+                        aliases: adv.aliases.clone(),
+                        summary: adv.summary.clone(),
+                        fixed,
+                        url: adv.url(),
+                    });
+                }
+            }
+        }
+        findings.sort_by(|a, b| {
+            (a.package.to_lowercase(), &a.advisory).cmp(&(b.package.to_lowercase(), &b.advisory))
+        });
+        findings
+    }
+}
+
+/// Whether `version` of package `name` is affected. If it is, the result has
+/// the fixed version, if any.
+fn affected(name: &str, version: &RPackageVersion, aff: &OsvAffected) -> Option<Option<String>> {
+    let listed = aff
+        .versions
+        .iter()
+        .any(|v| RPackageVersion::from_str(v).is_ok_and(|v| v == *version));
+
+    for range in aff.ranges.iter() {
+        // GIT ranges are commits, not versions.
+        if range.range_type == "GIT" {
+            continue;
+        }
+        let intervals = match range_intervals(name, range) {
+            Ok(x) => x,
+            Err(err) => {
+                debug!("Ignoring invalid range of {}: {}", name, err);
+                continue;
+            }
+        };
+        for interval in intervals {
+            if interval.spec.satisfies(&version.original).unwrap_or(false) {
+                return Some(interval.fixed);
+            }
+        }
+    }
+
+    if listed {
+        Some(None)
+    } else {
+        None
+    }
+}
+
+/// Turn the events of a range into version intervals. An `introduced` event
+/// opens an interval, a `fixed` or `last_affected` event closes it. An
+/// `introduced` version of `0` means all versions before the first fix.
+fn range_intervals(name: &str, range: &OsvRange) -> Result<Vec<AffectedInterval>, Box<dyn Error>> {
+    let constraint = |constraint_type, version: &str| -> Result<_, Box<dyn Error>> {
+        Ok(VersionConstraint {
+            constraint_type,
+            version: RPackageVersion::from_str(version)?,
+        })
+    };
+
+    let mut events: Vec<(RPackageVersion, &OsvEvent)> = vec![];
+    for event in range.events.iter() {
+        let version = event
+            .introduced
+            .as_ref()
+            .or(event.fixed.as_ref())
+            .or(event.last_affected.as_ref());
+        if let Some(version) = version {
+            events.push((RPackageVersion::from_str(version)?, event));
+        }
+    }
+    events.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let interval = |constraints: Vec<VersionConstraint>, fixed: Option<String>| AffectedInterval {
+        spec: DepVersionSpec {
+            name: name.to_string(),
+            types: vec![],
+            constraints,
+        },
+        fixed,
+    };
+
+    let mut intervals: Vec<AffectedInterval> = vec![];
+    let mut open: Option<Vec<VersionConstraint>> = None;
+    for (_, event) in events {
+        if let Some(introduced) = &event.introduced {
+            if open.is_none() {
+                open = Some(if introduced == "0" {
+                    vec![]
+                } else {
+                    vec![constraint(
+                        VersionConstraintType::GreaterOrEqual,
+                        introduced,
+                    )?]
+                });
+            }
+        } else if let Some(mut constraints) = open.take() {
+            if let Some(fixed) = &event.fixed {
+                constraints.push(constraint(VersionConstraintType::Less, fixed)?);
+                intervals.push(interval(constraints, Some(fixed.clone())));
+            } else if let Some(last) = &event.last_affected {
+                constraints.push(constraint(VersionConstraintType::LessOrEqual, last)?);
+                intervals.push(interval(constraints, None));
+            }
+        }
+    }
+    if let Some(constraints) = open {
+        intervals.push(interval(constraints, None));
+    }
+
+    Ok(intervals)
+}
+
+// ------------------------------------------------------------------------
+// Output
+
+fn print_findings(findings: &[Finding], num_packages: usize, num_advisories: usize, tag: &str) {
+    if findings.is_empty() {
+        OUTPUT.success(&format!(
+            "No known vulnerabilities in {} packages, checked against {} advisories {}",
+            num_packages, num_advisories, tag
+        ));
+        return;
+    }
+
+    let mut tab = Table::new("{:<}  {:<}  {:<}  {:<}  {:<}");
+    tab.add_row(row!(
+        "Package", "Version", "Advisory", "Fixed in", "Summary"
+    ));
+    for f in findings {
+        let advisory = match f.aliases.iter().find(|a| a.starts_with("CVE-")) {
+            Some(cve) => format!("{} ({})", f.advisory, cve),
+            None => f.advisory.clone(),
+        };
+        tab.add_row(row!(
+            &f.package,
+            &f.version,
+            advisory,
+            f.fixed.as_deref().unwrap_or("-"),
+            &f.summary
+        ));
+    }
+    println!("{}", tab);
+
+    let affected: HashSet<&str> = findings.iter().map(|f| f.package.as_str()).collect();
+    OUTPUT.error(&format!(
+        "Found {} {} affecting {} of {} packages {}",
+        findings.len(),
+        if findings.len() == 1 {
+            "advisory"
+        } else {
+            "advisories"
+        },
+        affected.len(),
+        num_packages,
+        tag
+    ));
+    info!(
+        "Found {} advisories affecting {} packages",
+        findings.len(),
+        affected.len()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn advisory(json: &str) -> OsvAdvisory {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn pkgs(x: &[(&str, &str)]) -> Vec<(String, String)> {
+        x.iter()
+            .map(|(n, v)| (n.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn ranges_become_version_intervals() {
+        let adv = advisory(
+            r#"{"id": "RSEC-1", "affected": [{
+                 "package": {"ecosystem": "CRAN", "name": "x"},
+                 "ranges": [{"type": "ECOSYSTEM", "events": [
+                   {"fixed": "1.2.0"}, {"introduced": "0"},
+                   {"introduced": "2.0"}, {"last_affected": "2.1-3"},
+                   {"introduced": "3.0.0"}
+                 ]}]}]}"#,
+        );
+        let aff = &adv.affected[0];
+        let check = |v: &str| affected("x", &RPackageVersion::from_str(v).unwrap(), aff);
+
+        assert_eq!(check("1.1.9"), Some(Some("1.2.0".to_string())));
+        assert_eq!(check("1.2.0"), None);
+        assert_eq!(check("1.10.0"), None);
+        assert_eq!(check("2.1-3"), Some(None));
+        assert_eq!(check("2.1-4"), None);
+        assert_eq!(check("3.0.0"), Some(None));
+        assert_eq!(check("4.0"), Some(None));
+    }
+
+    #[test]
+    fn audit_matches_packages_and_versions() {
+        let advisories = vec![
+            advisory(
+                r#"{"id": "RSEC-2023-1", "summary": "Bad", "aliases": ["CVE-2023-1"],
+                    "affected": [{
+                      "package": {"ecosystem": "CRAN", "name": "cli"},
+                      "ranges": [{"type": "ECOSYSTEM",
+                                  "events": [{"introduced": "3.0.0"}, {"fixed": "3.6.3"}]}]}],
+                    "references": [{"type": "WEB", "url": "https://a"},
+                                   {"type": "ADVISORY", "url": "https://b"}]}"#,
+            ),
+            advisory(
+                r#"{"id": "RSEC-2023-2", "affected": [{
+                      "package": {"ecosystem": "CRAN", "name": "glue"},
+                      "versions": ["1.6.0", "1.6.1"]}]}"#,
+            ),
+            advisory(
+                r#"{"id": "PYSEC-1", "affected": [{
+                      "package": {"ecosystem": "PyPI", "name": "cli"},
+                      "ranges": [{"type": "ECOSYSTEM", "events": [{"introduced": "0"}]}]}]}"#,
+            ),
+        ];
+        let index = AdvisoryIndex::new(&advisories);
+
+        let findings = index.audit(&pkgs(&[
+            ("glue", "1.6.1"),
+            ("cli", "3.6.2"),
+            ("rlang", "1.0.0"),
+        ]));
+        assert_eq!(findings.len(), 2);
+        assert_eq!(findings[0].package, "cli");
+        assert_eq!(findings[0].advisory, "RSEC-2023-1");
+        assert_eq!(findings[0].fixed.as_deref(), Some("3.6.3"));
+        assert_eq!(findings[0].url.as_deref(), Some("https://b"));
+        assert_eq!(findings[1].package, "glue");
+        assert_eq!(findings[1].fixed, None);
+
+        let findings = index.audit(&pkgs(&[("glue", "1.7.0"), ("cli", "3.6.3")]));
+        assert!(findings.is_empty());
+    }
+
+    #[test]
+    fn advisories_are_read_from_directories_and_archives() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("advisories");
+        fs::create_dir_all(dir.join("cli")).unwrap();
+        let one = r#"{"id": "RSEC-1", "affected": []}"#;
+        fs::write(dir.join("cli").join("RSEC-1.json"), one).unwrap();
+        fs::write(
+            dir.join("RSEC-2.json"),
+            r#"{"id": "RSEC-2", "withdrawn": "2024-01-01T00:00:00Z"}"#,
+        )
+        .unwrap();
+        fs::write(dir.join("README.md"), "not an advisory").unwrap();
+
+        let advisories = load_advisories(&dir.display().to_string()).unwrap();
+        let ids: Vec<&str> = advisories.iter().map(|a| a.id.as_str()).collect();
+        assert_eq!(ids, vec!["RSEC-1"]);
+
+        let zip_path = tmp.path().join("all.zip");
+        let mut zip = zip::ZipWriter::new(fs::File::create(&zip_path).unwrap());
+        zip.start_file("RSEC-1.json", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        std::io::Write::write_all(&mut zip, one.as_bytes()).unwrap();
+        zip.finish().unwrap();
+        let advisories = load_advisories(&zip_path.display().to_string()).unwrap();
+        assert_eq!(advisories.len(), 1);
+
+        assert!(load_advisories(&tmp.path().join("x.txt").display().to_string()).is_err());
+    }
+}
@@ -91,7 +91,7 @@ fn default_download_dir() -> PathBuf {
 }
 
 /// Whether `get_download_dir()` was overridden by the user
-fn download_dir_is_overridden() -> Result<bool, Box<dyn Error>> {
+pub fn download_dir_is_overridden() -> Result<bool, Box<dyn Error>> {
     if std::env::var("RIG_DOWNLOAD_DIR").is_ok() {
         return Ok(true);
     }
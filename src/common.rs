@@ -149,9 +149,7 @@ pub fn get_default_r_version() -> Result<Option<String>, Box<dyn Error>> {
         None => Ok(None),
         Some(d) => {
             let name = check_installed(&d)?;
-            let desc = Path::new(&get_r_root_for(&name)?)
-                .join(get_r_syslibpath()?.replace("{}", &version_dir_key(&name)))
-                .join("base/DESCRIPTION");
+            let desc = get_r_system_library(&name)?.join("base/DESCRIPTION");
             let lines = read_lines(&desc).unwrap_or_default();
             let idx = grep_lines(&re, &lines);
             let version: Option<String> = if idx.is_empty() {
@@ -167,11 +165,16 @@ pub fn get_default_r_version() -> Result<Option<String>, Box<dyn Error>> {
 
 // -- rig list ------------------------------------------------------------
 
+/// The system library of an installed R version, the one holding the base and
+/// recommended packages.
+pub fn get_r_system_library(name: &str) -> Result<PathBuf, Box<dyn Error>> {
+    Ok(Path::new(&get_r_root_for(name)?)
+        .join(get_r_syslibpath()?.replace("{}", &version_dir_key(name))))
+}
+
 pub fn get_r_version_data_version(name: &str) -> Result<String, Box<dyn Error>> {
     let re = Regex::new("^Version:[ ]?").expect("Invalid regex pattern");
-    let desc = Path::new(&get_r_root_for(name)?)
-        .join(get_r_syslibpath()?.replace("{}", &version_dir_key(name)))
-        .join("base/DESCRIPTION");
+    let desc = get_r_system_library(name)?.join("base/DESCRIPTION");
     let lines = read_lines(&desc).unwrap_or_default();
     let idx = grep_lines(&re, &lines);
     if idx.is_empty() {
@@ -868,7 +871,12 @@ pub fn get_arch(platform: &str, args: &ArgMatches) -> String {
         None => env::consts::ARCH.to_string(),
     };
 
-    // Prefer 'arm64' on macos, but 'aarch64' on linux and windows
+    arch_name(platform, arch)
+}
+
+/// The name of `arch` for the R versions API: prefer `arm64` on macOS, but
+/// `aarch64` on Linux and Windows.
+pub fn arch_name(platform: &str, arch: String) -> String {
     if platform == "macos" && arch == "aarch64" {
         "arm64".to_string()
     } else if arch == "arm64" {
@@ -891,9 +899,7 @@ pub fn sc_available(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<
     let platform = get_platform(args)?;
     let arch = get_arch(&platform, args);
 
-    let url = "https://api.r-hub.io/rversions/available/".to_string() + &platform + "/" + &arch;
-    let resp = download_json_sync(vec![url])?;
-    let resp = resp[0].as_array().unwrap();
+    let resp = crate::resolve::get_available_table(&platform, &arch)?;
 
     let mut vers: Vec<Available> = vec![];
     for item in resp.iter().rev() {
@@ -987,7 +993,7 @@ struct Distro {
 fn get_distros() -> Result<Vec<Distro>, Box<dyn Error>> {
     let mut distros: Vec<Distro> = vec![];
 
-    let url = "https://api.r-hub.io/rversions/linux-distros".to_string();
+    let url = crate::resolve::rversions_api() + "linux-distros";
     let resp = download_json_sync(vec![url])?;
     let resp = resp[0].as_array().unwrap();
 
@@ -1085,7 +1091,7 @@ fn sc_available_rtools_versions(
     args: &ArgMatches,
     mainargs: &ArgMatches,
 ) -> Result<(), Box<dyn Error>> {
-    let mut url = "https://api.r-hub.io/rversions/rtools-versions".to_string();
+    let mut url = crate::resolve::rversions_api() + "rtools-versions";
     let arch = get_arch("windows", args);
     if arch != "x86_64" {
         url = url + "/" + &arch;
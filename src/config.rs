@@ -10,6 +10,7 @@ use serde_derive::Deserialize;
 use serde_derive::Serialize;
 
 use crate::cache::get_data_dir;
+use crate::lock::FileLock;
 use crate::utils::*;
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -34,6 +35,12 @@ fn rig_config_file() -> Result<PathBuf, Box<dyn Error>> {
     Ok(config_file)
 }
 
+// Held for a whole read-modify-write of the configuration file, so that two
+// rig processes that set different entries do not lose one of them.
+fn lock_config() -> Result<FileLock, Box<dyn Error>> {
+    FileLock::acquire(&rig_config_file()?)
+}
+
 // The path of the rig configuration file. It does not need to exist.
 // Used by `rig config config-file-path` and `rig system dirs`.
 pub fn config_file_path() -> Result<PathBuf, Box<dyn Error>> {
@@ -53,14 +60,12 @@ impl Config {
         Ok(config)
     }
 
+    // Readers do not take the lock, so the file is replaced, never
+    // rewritten in place.
     fn save(&self) -> Result<(), Box<dyn Error>> {
         let str = serde_json::to_string_pretty(self)?;
         let config_file = rig_config_file()?;
-        let parent = config_file
-            .parent()
-            .ok_or(SimpleError::new("Invalid config file directory"))?;
-        std::fs::create_dir_all(parent)?;
-        std::fs::write(config_file, str)?;
+        write_atomically(&config_file, str.as_bytes())?;
         Ok(())
     }
 
@@ -80,6 +85,7 @@ impl Config {
 }
 
 pub fn save_config(rver: &str, key: &str, value: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let _lock = lock_config()?;
     let mut config = Config::load()?;
     match key {
         "userlibrary" => config.set_userlibrary(rver, value)?,
@@ -165,11 +171,7 @@ fn load_raw_config() -> Result<serde_json::Map<String, serde_json::Value>, Box<d
 
 fn save_raw_config(map: &serde_json::Map<String, serde_json::Value>) -> Result<(), Box<dyn Error>> {
     let config_file = rig_config_file()?;
-    let parent = config_file
-        .parent()
-        .ok_or(SimpleError::new("Invalid config file directory"))?;
-    std::fs::create_dir_all(parent)?;
-    std::fs::write(config_file, serde_json::to_string_pretty(map)?)?;
+    write_atomically(&config_file, serde_json::to_string_pretty(map)?.as_bytes())?;
     Ok(())
 }
 
@@ -182,6 +184,7 @@ pub fn get_global_config_value(key: &str) -> Result<Option<String>, Box<dyn Erro
 }
 
 pub fn set_global_config_value(key: &str, value: &str) -> Result<(), Box<dyn Error>> {
+    let _lock = lock_config()?;
     let mut map = load_raw_config()?;
     map.insert(
         key.to_string(),
@@ -195,6 +198,7 @@ fn sc_config_set(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
     let (key, value) = keyvalue
         .split_once('=')
         .ok_or_else(|| SimpleError::new(format!("Invalid key=value format: '{}'", keyvalue)))?;
+    let _lock = lock_config()?;
     let mut map = load_raw_config()?;
     map.insert(
         key.to_string(),
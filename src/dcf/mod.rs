@@ -363,6 +363,10 @@ pub struct Package {
     // CRAN rebuilds them.
     pub built: Option<DCFBuilt>,
     pub license: Option<String>,
+    // for `rig pkg search`. PACKAGES files of CRAN-like repositories do not
+    // have these, ALLPACKAGES does.
+    pub title: Option<String>,
+    pub description: Option<String>,
     // used by the R-hub repos and pak
     pub platform: Option<String>,
     pub arch: Option<String>,
@@ -395,6 +399,8 @@ impl Package {
             path: None,
             built: None,
             license: None,
+            title: None,
+            description: None,
             platform: None,
             arch: None,
             graphics_api_version: None,
@@ -426,6 +432,8 @@ impl Package {
         let download_url = pkg.get("DownloadURL").map(|u| u.to_string());
         let built = pkg.get("Built").map(DCFBuilt::from_str).transpose()?;
         let license = pkg.get("License").map(|l| l.to_string());
+        let title = pkg.get("Title").map(|t| t.to_string());
+        let description = pkg.get("Description").map(|d| d.to_string());
         let platform = pkg.get("Platform").map(|p| p.to_string());
         let arch = pkg.get("Arch").map(|a| a.to_string());
         let graphics_api_version = pkg.get("GraphicsAPIVersion").map(|g| g.to_string());
@@ -443,6 +451,8 @@ impl Package {
             file,
             built,
             license,
+            title,
+            description,
             platform,
             arch,
             graphics_api_version,
@@ -0,0 +1,602 @@
+//! `rig doctor`: look for the usual ways an R setup breaks, and fix the ones
+//! that are safe to fix.
+//!
+//! Every check is a function that returns findings. A finding may carry a
+//! `Fix`, which is only applied with `--fix`. A fix never chooses for the
+//! user: it does not pick a new default R version, or a new set of
+//! repositories, those are suggestions only.
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use clap::ArgMatches;
+use log::{debug, info, warn};
+use tabular::*;
+
+use crate::escalate::escalate;
+use crate::library::{library_rprofile_markers, library_update_rprofile};
+use crate::output::OUTPUT;
+use crate::repos::{probe_default_repos, repos_profile_block, ReposBlock};
+use crate::utils::{get_mode, Mode};
+
+#[cfg(target_os = "macos")]
+use crate::macos::*;
+
+#[cfg(target_os = "windows")]
+use crate::windows::*;
+
+#[cfg(target_os = "linux")]
+use crate::linux::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Status::Ok => write!(f, "ok"),
+            Status::Warn => write!(f, "warn"),
+            Status::Fail => write!(f, "fail"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Fix {
+    RemoveLinks(Vec<PathBuf>),
+    MakeLinks,
+    #[cfg(target_os = "linux")]
+    UnpatchLdpaths(Vec<PathBuf>),
+    #[cfg(target_os = "linux")]
+    Fontconfig(String),
+    LibraryProfile(String),
+}
+
+/// The result of one check, and the shape of the `--json` output.
+#[derive(Debug, serde::Serialize)]
+struct Finding {
+    check: &'static str,
+    status: Status,
+    message: String,
+    suggestion: Option<String>,
+    fixable: bool,
+    #[serde(skip)]
+    fix: Option<Fix>,
+    /// Whether `--fix` fixed it, `null` if it did not try.
+    fixed: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fix_error: Option<String>,
+}
+
+impl Finding {
+    fn new(check: &'static str, status: Status, message: String) -> Finding {
+        Finding {
+            check,
+            status,
+            message,
+            suggestion: None,
+            fixable: false,
+            fix: None,
+            fixed: None,
+            fix_error: None,
+        }
+    }
+
+    fn ok(check: &'static str, message: impl Into<String>) -> Finding {
+        Finding::new(check, Status::Ok, message.into())
+    }
+
+    fn warn(check: &'static str, message: impl Into<String>) -> Finding {
+        Finding::new(check, Status::Warn, message.into())
+    }
+
+    fn fail(check: &'static str, message: impl Into<String>) -> Finding {
+        Finding::new(check, Status::Fail, message.into())
+    }
+
+    fn suggest(mut self, suggestion: impl Into<String>) -> Finding {
+        self.suggestion = Some(suggestion.into());
+        self
+    }
+
+    fn with_fix(mut self, fix: Fix) -> Finding {
+        self.fixable = true;
+        self.fix = Some(fix);
+        if self.suggestion.is_none() {
+            self.suggestion = Some("run `rig doctor --fix`".to_string());
+        }
+        self
+    }
+}
+
+pub fn sc_doctor(args: &ArgMatches, mainargs: &ArgMatches) -> Result<i32, Box<dyn Error>> {
+    let json = args.get_flag("json") || mainargs.get_flag("json");
+    let fix = args.get_flag("fix");
+
+    let mut findings = run_checks()?;
+
+    if fix && findings.iter().any(|f| f.fix.is_some()) {
+        // Every fix is to an R installation or to the quick links, which
+        // belong to root in admin mode.
+        if get_mode()? == Mode::Admin {
+            escalate("fixing the R setup")?;
+        }
+        for finding in findings.iter_mut() {
+            if let Some(what) = finding.fix.take() {
+                match apply_fix(&what) {
+                    Ok(()) => {
+                        info!("Fixed {}: {}", finding.check, finding.message);
+                        finding.fixed = Some(true);
+                    }
+                    Err(err) => {
+                        warn!("Failed to fix {}: {}", finding.check, err);
+                        finding.fixed = Some(false);
+                        finding.fix_error = Some(err.to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&findings)?);
+    } else {
+        print_findings(&findings);
+    }
+
+    let failed = findings
+        .iter()
+        .any(|f| f.status == Status::Fail && f.fixed != Some(true));
+    Ok(if failed { 1 } else { 0 })
+}
+
+fn run_checks() -> Result<Vec<Finding>, Box<dyn Error>> {
+    let installed = sc_get_list()?;
+    let mut findings = vec![check_default(&installed)?];
+
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
+    findings.extend(check_quick_links(&installed)?);
+
+    let mut rfindings: Vec<Finding> = vec![];
+    for rver in &installed {
+        #[cfg(target_os = "linux")]
+        {
+            rfindings.extend(check_ldpaths(rver)?);
+            rfindings.extend(check_fontconfig_setup(rver)?);
+        }
+        rfindings.push(check_library_profile(rver));
+        rfindings.push(check_repos_profile(rver));
+    }
+    // One row for a check that is fine for every R version, not one each.
+    for check in CHECKS_PER_VERSION {
+        let (mine, rest): (Vec<Finding>, Vec<Finding>) =
+            rfindings.into_iter().partition(|f| f.check == *check);
+        rfindings = rest;
+        if mine.is_empty() || mine.iter().all(|f| f.status == Status::Ok) {
+            if !installed.is_empty() {
+                findings.push(Finding::ok(check, "all R versions"));
+            }
+        } else {
+            findings.extend(mine.into_iter().filter(|f| f.status != Status::Ok));
+        }
+    }
+
+    if let Ok(Some(default)) = sc_get_default() {
+        findings.extend(check_repos(&default));
+    }
+    Ok(findings)
+}
+
+const CHECKS_PER_VERSION: &[&str] = &[
+    #[cfg(target_os = "linux")]
+    "ldpaths",
+    #[cfg(target_os = "linux")]
+    "fontconfig",
+    "library-profile",
+    "repos-profile",
+];
+
+// -- Default R version ------------------------------------------------------
+
+fn check_default(installed: &[String]) -> Result<Finding, Box<dyn Error>> {
+    let default = sc_get_default()?;
+    let dangling = if default.is_none() {
+        dangling_default()
+    } else {
+        None
+    };
+    Ok(default_finding(default, dangling, installed))
+}
+
+/// The version a `current` link points to, if it points to nothing.
+/// `sc_get_default()` cannot tell that apart from no default at all.
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+fn dangling_default() -> Option<String> {
+    let current = get_r_current().ok()?;
+    let target = std::fs::read_link(&current).ok()?;
+    if Path::new(&current).exists() {
+        return None;
+    }
+    target.file_name().map(|x| x.to_string_lossy().to_string())
+}
+
+#[cfg(target_os = "windows")]
+fn dangling_default() -> Option<String> {
+    None
+}
+
+fn default_finding(
+    default: Option<String>,
+    dangling: Option<String>,
+    installed: &[String],
+) -> Finding {
+    let suggestion = match installed.last() {
+        Some(v) => format!("run `rig default {}`", v),
+        None => "run `rig add release`".to_string(),
+    };
+    match (default, dangling) {
+        (Some(v), _) if installed.contains(&v) => Finding::ok("default", format!("R {}", v)),
+        (Some(v), _) | (None, Some(v)) => Finding::fail(
+            "default",
+            format!("the default R version, {}, is not installed", v),
+        )
+        .suggest(suggestion),
+        (None, None) if installed.is_empty() => {
+            Finding::warn("default", "no R versions are installed").suggest(suggestion)
+        }
+        (None, None) => Finding::warn("default", "no default R version").suggest(suggestion),
+    }
+}
+
+// -- Quick links ------------------------------------------------------------
+
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+fn check_quick_links(installed: &[String]) -> Result<Vec<Finding>, Box<dyn Error>> {
+    let binary_dir = crate::utils::get_binary_dir()?;
+    let dir = Path::new(&binary_dir);
+    let mut findings = vec![];
+
+    let dangling = dangling_links(dir);
+    if !dangling.is_empty() {
+        findings.push(
+            Finding::warn(
+                "quick-links",
+                format!("links to removed R versions: {}", path_list(&dangling)),
+            )
+            .with_fix(Fix::RemoveLinks(dangling)),
+        );
+    }
+    let missing = missing_links(dir, installed);
+    if !missing.is_empty() {
+        findings.push(
+            Finding::warn(
+                "quick-links",
+                format!("missing links: {}", path_list(&missing)),
+            )
+            .with_fix(Fix::MakeLinks),
+        );
+    }
+    if findings.is_empty() {
+        findings.push(Finding::ok("quick-links", binary_dir));
+    }
+    Ok(findings)
+}
+
+/// `R-*` symlinks in `dir` that point to something that does not exist.
+fn dangling_links(dir: &Path) -> Vec<PathBuf> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(x) => x,
+        Err(_) => return vec![],
+    };
+    let mut links: Vec<PathBuf> = entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            name.starts_with("R-")
+                && path
+                    .symlink_metadata()
+                    .map(|m| m.file_type().is_symlink())
+                    .unwrap_or(false)
+                && !path.exists()
+        })
+        .collect();
+    links.sort();
+    links
+}
+
+fn missing_links(dir: &Path, installed: &[String]) -> Vec<PathBuf> {
+    installed
+        .iter()
+        .map(|v| dir.join(format!("R-{}", v)))
+        .filter(|path| path.symlink_metadata().is_err())
+        .collect()
+}
+
+fn path_list(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|p| p.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// -- Linux: ldpaths and fontconfig --------------------------------------------
+
+#[cfg(target_os = "linux")]
+fn check_ldpaths(rver: &str) -> Result<Vec<Finding>, Box<dyn Error>> {
+    let stale = stale_ldpaths(rver)?;
+    if stale.is_empty() {
+        return Ok(vec![Finding::ok("ldpaths", format!("R {}", rver))]);
+    }
+    Ok(vec![Finding::warn(
+        "ldpaths",
+        format!(
+            "R {}: LD_LIBRARY_PATH setup left over from an earlier installation in {}",
+            rver,
+            path_list(&stale)
+        ),
+    )
+    .with_fix(Fix::UnpatchLdpaths(stale))])
+}
+
+#[cfg(target_os = "linux")]
+fn check_fontconfig_setup(rver: &str) -> Result<Vec<Finding>, Box<dyn Error>> {
+    Ok(vec![match check_fontconfig(rver)? {
+        None => Finding::ok("fontconfig", format!("R {}", rver)),
+        Some(problem) => Finding::fail(
+            "fontconfig",
+            format!("R {}: {}, plots may crash R", rver, problem),
+        )
+        .with_fix(Fix::Fontconfig(rver.to_string())),
+    }])
+}
+
+// -- Rprofile ---------------------------------------------------------------
+
+fn check_library_profile(rver: &str) -> Finding {
+    match library_rprofile_markers(rver) {
+        Ok(n) => library_profile_finding(rver, n),
+        Err(err) => Finding::fail(
+            "library-profile",
+            format!("R {}: cannot read the profile: {}", rver, err),
+        )
+        .suggest(format!("reinstall R {}", rver)),
+    }
+}
+
+fn library_profile_finding(rver: &str, markers: usize) -> Finding {
+    match markers {
+        2 => Finding::ok("library-profile", format!("R {}", rver)),
+        0 => Finding::warn(
+            "library-profile",
+            format!(
+                "R {} does not use the library set with `rig library default`",
+                rver
+            ),
+        )
+        .with_fix(Fix::LibraryProfile(rver.to_string())),
+        _ => Finding::fail(
+            "library-profile",
+            format!("R {}: the R_LIBS_USER setup in the profile is broken", rver),
+        )
+        .suggest(
+            "remove the lines between `## rig R_LIBS_USER start` and `## rig R_LIBS_USER end` \
+             from the profile, then run `rig doctor --fix`",
+        ),
+    }
+}
+
+fn check_repos_profile(rver: &str) -> Finding {
+    match repos_profile_block(rver) {
+        Ok(block) => repos_profile_finding(rver, block),
+        Err(err) => Finding::fail(
+            "repos-profile",
+            format!("R {}: cannot read the profile: {}", rver, err),
+        )
+        .suggest(format!("reinstall R {}", rver)),
+    }
+}
+
+// Not fixed automatically: setting up the repositories again would drop the
+// ones the user chose with `rig repos setup`.
+fn repos_profile_finding(rver: &str, block: ReposBlock) -> Finding {
+    let setup = format!("run `rig repos setup --r-version {}`", rver);
+    match block {
+        ReposBlock::Current => Finding::ok("repos-profile", format!("R {}", rver)),
+        ReposBlock::Old(_, _) => Finding::warn(
+            "repos-profile",
+            format!(
+                "R {}: the repositories were set up by an older version of rig",
+                rver
+            ),
+        )
+        .suggest(setup),
+        ReposBlock::Missing => Finding::warn(
+            "repos-profile",
+            format!("R {}: the repositories are not set up", rver),
+        )
+        .suggest(setup),
+        ReposBlock::Corrupt => Finding::fail(
+            "repos-profile",
+            format!("R {}: the repository setup in the profile is broken", rver),
+        )
+        .suggest(format!("reinstall R {}", rver)),
+    }
+}
+
+// -- Repositories -------------------------------------------------------------
+
+fn check_repos(rver: &str) -> Vec<Finding> {
+    let probes = match probe_default_repos(rver) {
+        Ok(x) => x,
+        Err(err) => {
+            return vec![Finding::warn(
+                "repos",
+                format!("cannot read the repositories of R {}: {}", rver, err),
+            )
+            .suggest(format!("run `rig repos setup --r-version {}`", rver))]
+        }
+    };
+    let mut findings: Vec<Finding> = vec![];
+    for (name, probe) in probes {
+        debug!("Probed {}: {:?} {:?}", probe.url, probe.status, probe.error);
+        let problem = match (probe.status, probe.error) {
+            (Some(200), _) => continue,
+            (Some(status), _) => format!("HTTP {}", status),
+            (None, Some(error)) => error,
+            (None, None) => "no response".to_string(),
+        };
+        findings.push(
+            Finding::warn(
+                "repos",
+                format!("{} is not reachable: {} ({})", name, problem, probe.url),
+            )
+            .suggest("check the network and proxy settings, see also `rig repos status`"),
+        );
+    }
+    if findings.is_empty() {
+        findings.push(Finding::ok(
+            "repos",
+            format!("the repositories of R {} are reachable", rver),
+        ));
+    }
+    findings
+}
+
+// -- Fixes ------------------------------------------------------------------
+
+fn apply_fix(fix: &Fix) -> Result<(), Box<dyn Error>> {
+    match fix {
+        Fix::RemoveLinks(paths) => {
+            for path in paths {
+                OUTPUT.status(&format!("Removing {}", path.display()));
+                info!("Removing {}", path.display());
+                std::fs::remove_file(path)?;
+            }
+        }
+        Fix::MakeLinks => sc_system_make_links()?,
+        #[cfg(target_os = "linux")]
+        Fix::UnpatchLdpaths(files) => {
+            for file in files {
+                OUTPUT.status(&format!(
+                    "Removing LD_LIBRARY_PATH setup from {}",
+                    file.display()
+                ));
+                unpatch_ldpaths(file)?;
+            }
+        }
+        #[cfg(target_os = "linux")]
+        Fix::Fontconfig(rver) => fix_fontconfig(rver)?,
+        Fix::LibraryProfile(rver) => library_update_rprofile(rver)?,
+    }
+    Ok(())
+}
+
+fn print_findings(findings: &[Finding]) {
+    let mut tab = Table::new("{:<}  {:<}  {:<}");
+    tab.add_row(row!("Check", "Status", "Details"));
+    for f in findings {
+        let status = match f.fixed {
+            Some(true) => "fixed".to_string(),
+            _ => f.status.to_string(),
+        };
+        tab.add_row(row!(f.check, status, &f.message));
+    }
+    println!("{}", tab);
+
+    let mut problems = 0;
+    for f in findings {
+        if f.status == Status::Ok || f.fixed == Some(true) {
+            continue;
+        }
+        problems += 1;
+        if let Some(err) = &f.fix_error {
+            OUTPUT.error(&format!("Failed to fix {}: {}", f.check, err));
+        } else if let Some(suggestion) = &f.suggestion {
+            OUTPUT.info(&format!("{}: {}", f.check, suggestion));
+        }
+    }
+    if problems == 0 {
+        OUTPUT.success("No problems found");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn versions(x: &[&str]) -> Vec<String> {
+        x.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn default_finding_tells_missing_from_removed() {
+        let installed = versions(&["4.4.3", "4.5.1"]);
+        let ok = default_finding(Some("4.5.1".to_string()), None, &installed);
+        assert_eq!(ok.status, Status::Ok);
+
+        let removed = default_finding(None, Some("4.3.0".to_string()), &installed);
+        assert_eq!(removed.status, Status::Fail);
+        assert!(removed.message.contains("4.3.0"));
+        assert_eq!(
+            removed.suggestion.as_deref(),
+            Some("run `rig default 4.5.1`")
+        );
+        assert!(!removed.fixable);
+
+        let none = default_finding(None, None, &installed);
+        assert_eq!(none.status, Status::Warn);
+
+        let empty = default_finding(None, None, &[]);
+        assert_eq!(empty.suggestion.as_deref(), Some("run `rig add release`"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dangling_and_missing_links_are_found() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("R-target");
+        std::fs::write(&target, "").unwrap();
+        std::os::unix::fs::symlink(&target, dir.path().join("R-4.5.1")).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("gone"), dir.path().join("R-4.3.0")).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("gone"), dir.path().join("other")).unwrap();
+
+        assert_eq!(dangling_links(dir.path()), vec![dir.path().join("R-4.3.0")]);
+        assert_eq!(
+            missing_links(dir.path(), &versions(&["4.5.1", "4.4.3"])),
+            vec![dir.path().join("R-4.4.3")]
+        );
+    }
+
+    #[test]
+    fn library_profile_markers_are_checked() {
+        assert_eq!(library_profile_finding("4.5.1", 2).status, Status::Ok);
+        let missing = library_profile_finding("4.5.1", 0);
+        assert_eq!(missing.status, Status::Warn);
+        assert_eq!(missing.fix, Some(Fix::LibraryProfile("4.5.1".to_string())));
+        let broken = library_profile_finding("4.5.1", 3);
+        assert_eq!(broken.status, Status::Fail);
+        assert!(broken.fix.is_none());
+    }
+
+    #[test]
+    fn old_repos_setup_is_not_fixed_automatically() {
+        let old = repos_profile_finding("4.5.1", ReposBlock::Old(3, 20));
+        assert_eq!(old.status, Status::Warn);
+        assert!(!old.fixable);
+        assert_eq!(
+            old.suggestion.as_deref(),
+            Some("run `rig repos setup --r-version 4.5.1`")
+        );
+        assert_eq!(
+            repos_profile_finding("4.5.1", ReposBlock::Current).status,
+            Status::Ok
+        );
+    }
+}
@@ -20,14 +20,16 @@ use log::*;
 use reqwest::StatusCode;
 use simple_error::bail;
 
+use crate::http;
+use crate::lock::FileLock;
 use crate::output::OUTPUT;
 #[cfg(target_os = "windows")]
 use crate::resolve::get_resolve;
 #[cfg(target_os = "windows")]
 use crate::rversion::Rversion;
-use crate::utils::write_atomically;
 #[cfg(any(target_os = "macos", target_os = "windows"))]
 use crate::utils::*;
+use crate::utils::{not_too_old, write_atomically};
 
 // ------------------------------------------------------------------------
 // synchronous API
@@ -59,26 +61,10 @@ pub fn download_r(args: &ArgMatches) -> Result<(Rversion, OsString), Box<dyn Err
     filename.push(version2.arch.unwrap_or("".to_string()));
     filename.push("-");
     filename.push(basename(&url).unwrap_or("foo"));
-    let filename_path = Path::new(&filename);
     let tmp_dir = crate::cache::ensure_download_dir()?;
     let target = tmp_dir.join(&filename);
-    if target.exists() && not_too_old(&target) {
-        OUTPUT.success(&format!(
-            "{} is cached at {}",
-            filename_path.display(),
-            target.display()
-        ));
-        info!(
-            "{} is cached at {}",
-            filename_path.display(),
-            target.display()
-        );
-    } else {
-        OUTPUT.status(&format!("Downloading {} -> {}", url, target.display()));
-        info!("Downloading {} -> {}", url, target.display());
-        let client = &reqwest::Client::new();
-        download_file(client, &url, target.as_os_str())?;
-    }
+    let sha256 = installer_sha256(&url, version.sha256.as_deref());
+    download_verified(&url, &target, sha256.as_deref())?;
 
     Ok((version, target.into_os_string()))
 }
@@ -97,13 +83,25 @@ pub fn download_file_sync(
     } else {
         OUTPUT.status(&format!("Downloading {} -> {}", url, target.display()));
         info!("Downloading {} -> {}", url, target.display());
-        let client = &reqwest::Client::new();
+        let client = &http::client()?;
         download_file(client, url, target.as_os_str())?;
     }
 
     Ok(target.into_os_string())
 }
 
+/// Download `url` to `opath`, through `<opath>.tmp`, which is renamed once it
+/// is complete.
+///
+/// A download that breaks off is resumed where it stopped, with a `Range`
+/// request, up to `http-retries` times. So is one that an earlier rig process
+/// left behind, if its partial file is less than a day old. The partial file
+/// `<opath>.tmp` has a `<opath>.tmp.etag` file next to it, with the `ETag`,
+/// or else the `Last-Modified` header of the response it came from, and the
+/// `Range` request sends it as `If-Range`, so the rest comes from the same
+/// version of the file: a server that has a new one sends all of it instead,
+/// and so does a server that ignores the `Range` header. A partial file
+/// without a validator is downloaded again from the start.
 #[tokio::main]
 pub async fn download_file(
     client: &reqwest::Client,
@@ -113,23 +111,7 @@ pub async fn download_file(
     let mut path = opath.to_os_string();
     path.push(".tmp");
     let path = Path::new(&path);
-    let resp = client.get(url).send().await;
-    let resp = match resp {
-        Ok(resp) => resp.error_for_status(),
-        Err(err) => {
-            OUTPUT.error(&format!("HTTP error at {}: {}", url, err));
-            error!("HTTP error at {}: {}", url, err);
-            bail!("HTTP error at {}: {}", url, err.to_string())
-        }
-    };
-    let resp = match resp {
-        Ok(resp) => resp,
-        Err(err) => {
-            OUTPUT.error(&format!("HTTP error at {}: {}", url, err));
-            error!("HTTP error at {}: {}", url, err);
-            bail!("HTTP error at {}: {}", url, err.to_string())
-        }
-    };
+    let validator_path = validator_path(path);
 
     // If dirname(path) is / then this is None
     let dir = Path::new(&path).parent();
@@ -141,7 +123,155 @@ pub async fn download_file(
             bail!("Cannot create directory {}: {}", dir, err.to_string())
         };
     };
-    let file = File::create(path);
+
+    // Two rig processes appending to the same partial file would corrupt it.
+    let _lock = FileLock::acquire_async(path).await?;
+    if path.exists() && !not_too_old(&path.to_path_buf()) {
+        debug!("Not resuming old partial download {}", path.display());
+        fs::remove_file(path)?;
+        let _ = fs::remove_file(&validator_path);
+    }
+
+    let _permit = http::host_permit(url).await;
+    let retries = http::settings()?.retries;
+    let mut attempt = 0;
+    while let DownloadPart::Interrupted(err) = download_part(client, url, path).await? {
+        if attempt >= retries {
+            OUTPUT.error(&format!("HTTP error at {}: {}", url, err));
+            error!("HTTP error at {}: {}", url, err);
+            bail!("HTTP error at {}: {}", url, err)
+        }
+        attempt += 1;
+        let delay = http::backoff(attempt, None);
+        let done = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+        OUTPUT.info(&format!(
+            "Download of {} interrupted after {} bytes, resuming",
+            url, done
+        ));
+        warn!(
+            "Download of {} interrupted after {} bytes: {}, resuming in {:.1} s ({}/{})",
+            url,
+            done,
+            err,
+            delay.as_secs_f64(),
+            attempt,
+            retries
+        );
+        tokio::time::sleep(delay).await;
+    }
+
+    if let Err(err) = std::fs::rename(Path::new(&path), Path::new(&opath)) {
+        OUTPUT.error(&format!("Failed to rename downloaded file: {}", err));
+        error!("Failed to rename downloaded file: {}", err);
+        bail!("Failed to rename downloaded file: {}", err.to_string())
+    };
+    let _ = fs::remove_file(&validator_path);
+
+    Ok(())
+}
+
+/// The file next to the partial download `path` that holds the validator of
+/// its response, see [`download_file`].
+fn validator_path(path: &Path) -> PathBuf {
+    let mut vpath = path.as_os_str().to_os_string();
+    vpath.push(".etag");
+    PathBuf::from(vpath)
+}
+
+/// How one request of [`download_file`] ended. A failure that another request
+/// cannot fix, e.g. a 404 or a full disk, is an error instead.
+enum DownloadPart {
+    Done,
+    Interrupted(String),
+}
+
+/// Download the rest of `url` into the partial file `path`: everything after
+/// the bytes that are already there, if they come from the same version of
+/// the file, see [`download_file`].
+async fn download_part(
+    client: &reqwest::Client,
+    url: &str,
+    path: &Path,
+) -> Result<DownloadPart, Box<dyn Error>> {
+    let validator_path = validator_path(path);
+    let validator = fs::read_to_string(&validator_path)
+        .ok()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty());
+    let mut offset = match &validator {
+        Some(_) => fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+        None => {
+            if path.exists() {
+                debug!(
+                    "No validator for partial download {}, downloading it again",
+                    path.display()
+                );
+            }
+            0
+        }
+    };
+    let mut resp = loop {
+        let mut req = client.get(url);
+        if offset > 0 {
+            req = req
+                .header("Range", format!("bytes={}-", offset))
+                .header("If-Range", validator.as_deref().unwrap_or_default());
+        }
+        let resp = match http::send(req).await {
+            Ok(resp) => resp,
+            Err(err) => {
+                OUTPUT.error(&format!("HTTP error at {}: {}", url, err));
+                error!("HTTP error at {}: {}", url, err);
+                bail!("HTTP error at {}: {}", url, err.to_string())
+            }
+        };
+        // 416, or a range we did not ask for: the partial file is not a
+        // prefix of what the server has now, start over.
+        let restart = resp.status() == StatusCode::RANGE_NOT_SATISFIABLE
+            || (resp.status() == StatusCode::PARTIAL_CONTENT && !http::resumes_at(&resp, offset));
+        if offset == 0 || !restart {
+            break resp;
+        }
+        debug!(
+            "Cannot resume {} at {} bytes ({}), downloading it again",
+            url,
+            offset,
+            resp.status()
+        );
+        fs::remove_file(path)?;
+        let _ = fs::remove_file(&validator_path);
+        offset = 0;
+    };
+    if let Err(err) = resp.error_for_status_ref() {
+        OUTPUT.error(&format!("HTTP error at {}: {}", url, err));
+        error!("HTTP error at {}: {}", url, err);
+        bail!("HTTP error at {}: {}", url, err.to_string())
+    }
+
+    let file = if resp.status() == StatusCode::PARTIAL_CONTENT {
+        info!("Resuming download of {} at {} bytes", url, offset);
+        fs::OpenOptions::new().append(true).open(path)
+    } else {
+        // A new partial file, from the version of the file this response
+        // has. Without a validator it cannot be resumed in another process.
+        let file = File::create(path);
+        let written = match http::validator(&resp) {
+            Some(validator) => fs::write(&validator_path, validator),
+            None => match fs::remove_file(&validator_path) {
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                x => x,
+            },
+        };
+        if let Err(err) = written {
+            warn!(
+                "Cannot write {}, {} cannot be resumed: {}",
+                validator_path.display(),
+                url,
+                err
+            );
+        }
+        file
+    };
     let mut file = match file {
         Ok(file) => file,
         Err(err) => {
@@ -154,18 +284,14 @@ pub async fn download_file(
             )
         }
     };
-    let mut stream = resp.bytes_stream();
 
-    while let Some(item) = stream.next().await {
-        let chunk = match item {
-            Ok(chunk) => chunk,
-            Err(err) => {
-                OUTPUT.error(&format!("HTTP error at {}: {}", url, err));
-                error!("HTTP error at {}: {}", url, err);
-                bail!("HTTP error at {}: {}", url, err.to_string())
-            }
+    loop {
+        let chunk = match http::next_chunk(&mut resp).await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => return Ok(DownloadPart::Done),
+            Err(err) => return Ok(DownloadPart::Interrupted(err.to_string())),
         };
-        if let Err(err) = file.write(&chunk) {
+        if let Err(err) = file.write_all(&chunk) {
             OUTPUT.error(&format!(
                 "Failed to write to file {}: {}",
                 path.display(),
@@ -179,18 +305,10 @@ pub async fn download_file(
             )
         };
     }
-
-    if let Err(err) = std::fs::rename(Path::new(&path), Path::new(&opath)) {
-        OUTPUT.error(&format!("Failed to rename downloaded file: {}", err));
-        error!("Failed to rename downloaded file: {}", err);
-        bail!("Failed to rename downloaded file: {}", err.to_string())
-    };
-
-    Ok(())
 }
 
 pub fn download_json_sync(urls: Vec<String>) -> Result<Vec<serde_json::Value>, Box<dyn Error>> {
-    let client = reqwest::Client::new();
+    let client = http::client()?;
     let client = &client;
     let resp = download_json_(client, urls)?;
     Ok(resp)
@@ -202,16 +320,17 @@ async fn download_if_newer(
     local_path: &PathBuf,
     etag: Option<&str>,
 ) -> Result<(bool, Option<String>), Box<dyn Error>> {
-    let mut req = client.get(url);
+    let _permit = http::host_permit(url).await;
+    let mut headers = vec![];
     if local_path.exists() {
         if let Some(etag_value) = etag {
-            req = req.header("If-None-Match", etag_value);
+            headers.push(("If-None-Match", etag_value));
         }
     }
     info!("Checking for updates for {}", local_path.display());
-    let resp = req.send().await?;
+    let resp = http::get(client, url, &headers).await?;
 
-    match resp.status() {
+    match resp.status {
         StatusCode::NOT_MODIFIED => {
             filetime::set_file_mtime(local_path, FileTime::now())?;
             Ok((false, None))
@@ -219,16 +338,8 @@ async fn download_if_newer(
 
         StatusCode::OK => {
             // 200 → new content
-            // Extract etag from response headers
-            let new_etag = resp
-                .headers()
-                .get("etag")
-                .and_then(|v| v.to_str().ok())
-                .map(|s| s.to_string());
-
-            let bytes = resp.bytes().await?;
-            write_atomically(local_path, &bytes)?;
-            Ok((true, new_etag))
+            write_atomically(local_path, &resp.bytes)?;
+            Ok((true, resp.etag))
         }
 
         status => {
@@ -247,7 +358,7 @@ pub async fn download_if_newer__(
 ) -> Result<(bool, Option<String>), Box<dyn Error>> {
     let client_ = match client {
         Some(c) => c,
-        None => &reqwest::Client::new(),
+        None => &http::client()?,
     };
     download_if_newer(client_, url, local_path, None).await
 }
@@ -344,7 +455,7 @@ pub fn download_first_available_(
 
     let client_ = match client {
         Some(c) => c,
-        None => &reqwest::Client::new(),
+        None => &http::client()?,
     };
 
     download_first_available__(client_, urls, local_path, etag)
@@ -383,7 +494,7 @@ pub fn fetch_optional_if_modified_(
 ) -> Result<ConditionalFetch, Box<dyn Error>> {
     let client_ = match client {
         Some(c) => c,
-        None => &reqwest::Client::new(),
+        None => &http::client()?,
     };
     fetch_optional_if_modified__(client_, url, etag)
 }
@@ -408,25 +519,21 @@ pub async fn fetch_optional_if_modified(
     url: &str,
     etag: Option<&str>,
 ) -> Result<ConditionalFetch, Box<dyn Error>> {
-    let mut req = client.get(url);
+    let _permit = http::host_permit(url).await;
+    let mut headers = vec![];
     if let Some(etag) = etag {
-        req = req.header("If-None-Match", etag);
+        headers.push(("If-None-Match", etag));
     }
     info!("Checking for updates for {}", url);
-    let resp = req.send().await?;
+    let resp = http::get(client, url, &headers).await?;
 
-    match resp.status() {
+    match resp.status {
         StatusCode::NOT_MODIFIED => Ok(ConditionalFetch::NotModified),
 
-        StatusCode::OK => {
-            let etag = resp
-                .headers()
-                .get("etag")
-                .and_then(|v| v.to_str().ok())
-                .map(|s| s.to_string());
-            let bytes = resp.bytes().await?.to_vec();
-            Ok(ConditionalFetch::Fetched { bytes, etag })
-        }
+        StatusCode::OK => Ok(ConditionalFetch::Fetched {
+            bytes: resp.bytes,
+            etag: resp.etag,
+        }),
 
         StatusCode::NOT_FOUND => {
             debug!("No such resource (404): {}", url);
@@ -440,6 +547,191 @@ pub async fn fetch_optional_if_modified(
     }
 }
 
+// ------------------------------------------------------------------------
+// verified downloads
+// ------------------------------------------------------------------------
+
+/// The sha256 of an R installer: the one the rversions API published with
+/// it, or else the one in a `<url>.sha256` file next to it, if there is one.
+pub fn installer_sha256(url: &str, published: Option<&str>) -> Option<String> {
+    if let Some(sha) = published.and_then(parse_sha256_file) {
+        return Some(sha);
+    }
+    let sha_url = format!("{}.sha256", url);
+    match fetch_optional_if_modified_(&sha_url, None, None) {
+        Ok(ConditionalFetch::Fetched { bytes, .. }) => {
+            let sha = parse_sha256_file(&String::from_utf8_lossy(&bytes));
+            if sha.is_none() {
+                warn!("Invalid checksum file at {}, ignoring it", sha_url);
+            }
+            sha
+        }
+        Ok(_) => None,
+        Err(err) => {
+            debug!("Cannot download checksum from {}: {}", sha_url, err);
+            None
+        }
+    }
+}
+
+/// The digest in a checksum file, either alone, or followed by the file
+/// name, as `sha256sum` writes it.
+fn parse_sha256_file(text: &str) -> Option<String> {
+    let sha = text.split_whitespace().next()?.to_lowercase();
+    if sha.len() == 64 && sha.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(sha)
+    } else {
+        None
+    }
+}
+
+pub fn check_sha256(path: &Path, expected: &str) -> Result<(), Box<dyn Error>> {
+    use sha2::{Digest, Sha256};
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    let got = hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+    if !got.eq_ignore_ascii_case(expected) {
+        bail!(
+            "Checksum mismatch for {}: expected {}, got {}",
+            path.display(),
+            expected,
+            got
+        );
+    }
+    debug!("Checksum of {} is {}, as expected", path.display(), got);
+    Ok(())
+}
+
+/// Download an R installer from `url` to `target`, and check it against
+/// `sha256`, before anything installs it.
+///
+/// A cached file that matches the checksum is used as it is, however old it
+/// is, and one that does not match is downloaded again. Without a checksum a
+/// cached file is used for a day, as before, with a warning, or not at all if
+/// the `require-checksum` config entry is set, see [`require_checksum`]. A
+/// download that does not match is deleted, so it cannot poison the cache.
+pub fn download_verified(
+    url: &str,
+    target: &Path,
+    sha256: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    if sha256.is_none() && require_checksum()? {
+        OUTPUT.error(&format!(
+            "No checksum is published for {}, and require-checksum is set",
+            url
+        ));
+        error!("No checksum for {}, and require-checksum is set", url);
+        bail!(
+            "No checksum is published for {}, refusing to use it because \
+             require-checksum is set",
+            url
+        );
+    }
+
+    let filename = target.file_name().unwrap_or_default().to_string_lossy();
+    if target.exists() {
+        match sha256 {
+            Some(sha) => match check_sha256(target, sha) {
+                Ok(()) => {
+                    OUTPUT.success(&format!(
+                        "{} is cached at {} (checksum verified)",
+                        filename,
+                        target.display()
+                    ));
+                    info!(
+                        "{} is cached at {}, checksum verified",
+                        filename,
+                        target.display()
+                    );
+                    return Ok(());
+                }
+                Err(err) => {
+                    OUTPUT.warn(&format!(
+                        "Cached {} is corrupt, downloading it again",
+                        filename
+                    ));
+                    warn!(
+                        "Cached {} is corrupt, downloading it again: {}",
+                        filename, err
+                    );
+                    fs::remove_file(target)?;
+                }
+            },
+            None if crate::utils::not_too_old(&target.to_path_buf()) => {
+                OUTPUT.success(&format!("{} is cached at {}", filename, target.display()));
+                info!("{} is cached at {}", filename, target.display());
+                return Ok(());
+            }
+            None => {}
+        }
+    }
+
+    OUTPUT.status(&format!("Downloading {} -> {}", url, target.display()));
+    info!("Downloading {} -> {}", url, target.display());
+    let client = &http::client()?;
+    download_file(client, url, target.as_os_str())?;
+
+    match sha256 {
+        Some(sha) => {
+            if let Err(err) = check_sha256(target, sha) {
+                let _ = fs::remove_file(target);
+                OUTPUT.error(&format!("Download of {} is corrupt: {}", url, err));
+                error!("Download of {} is corrupt: {}", url, err);
+                bail!("Download of {} is corrupt: {}", url, err);
+            }
+            OUTPUT.success(&format!("Verified checksum of {}", filename));
+            info!("Verified checksum of {}: {}", filename, sha);
+        }
+        None => {
+            OUTPUT.warn(&format!(
+                "No checksum is published for {}, rig cannot verify it",
+                url
+            ));
+            warn!("No checksum for {}, not verifying it", url);
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether a download without a published checksum is an error, instead of
+/// a warning: the `RIG_REQUIRE_CHECKSUM` environment variable, or else the
+/// `require-checksum` config entry, `true` or `false`. Defaults to `false`.
+pub fn require_checksum() -> Result<bool, Box<dyn Error>> {
+    if let Ok(val) = std::env::var("RIG_REQUIRE_CHECKSUM") {
+        match parse_bool(&val) {
+            Some(x) => Ok(x),
+            None => bail!(
+                "Invalid RIG_REQUIRE_CHECKSUM value: '{}', expected `true` or `false`",
+                val
+            ),
+        }
+    } else if let Some(val) = crate::config::get_global_config_value("require-checksum")? {
+        match parse_bool(&val) {
+            Some(x) => Ok(x),
+            None => bail!(
+                "Invalid 'require-checksum' in rig config: '{}', expected `true` or `false`",
+                val
+            ),
+        }
+    } else {
+        Ok(false)
+    }
+}
+
+fn parse_bool(x: &str) -> Option<bool> {
+    match x.trim() {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
 // ------------------------------------------------------------------------
 // probing URLs
 // ------------------------------------------------------------------------
@@ -546,12 +838,10 @@ fn probe_error_message(err: &reqwest::Error) -> String {
 /// Probe several URLs at once, returning the results in the order of the input.
 #[tokio::main]
 pub async fn probe_urls_(urls: &[String]) -> Vec<UrlProbe> {
-    // `reqwest::Client::new()` has no timeout of its own, and one shared client
-    // means one connection pool for the repositories that share a host.
-    let client = match reqwest::Client::builder().timeout(PROBE_TIMEOUT).build() {
-        Ok(c) => c,
-        Err(_) => reqwest::Client::new(),
-    };
+    // One shared client means one connection pool for the repositories that
+    // share a host. A probe is not retried, a slow or failing server is what
+    // it reports.
+    let client = http::client().unwrap_or_default();
     let client = &client;
     future::join_all(
         urls.iter()
@@ -574,31 +864,25 @@ async fn download_optional_if_newer(
     local_path: &PathBuf,
     etag: Option<&str>,
 ) -> Result<Option<(bool, Option<String>)>, Box<dyn Error>> {
-    let mut req = client.get(url);
+    let _permit = http::host_permit(url).await;
+    let mut headers = vec![];
     if local_path.exists() {
         if let Some(etag_value) = etag {
-            req = req.header("If-None-Match", etag_value);
+            headers.push(("If-None-Match", etag_value));
         }
     }
     info!("Checking for updates for {}", local_path.display());
-    let resp = req.send().await?;
+    let resp = http::get(client, url, &headers).await?;
 
-    match resp.status() {
+    match resp.status {
         StatusCode::NOT_MODIFIED => {
             filetime::set_file_mtime(local_path, FileTime::now())?;
             Ok(Some((false, None)))
         }
 
         StatusCode::OK => {
-            let new_etag = resp
-                .headers()
-                .get("etag")
-                .and_then(|v| v.to_str().ok())
-                .map(|s| s.to_string());
-
-            let bytes = resp.bytes().await?;
-            write_atomically(local_path, &bytes)?;
-            Ok(Some((true, new_etag)))
+            write_atomically(local_path, &resp.bytes)?;
+            Ok(Some((true, resp.etag)))
         }
 
         StatusCode::NOT_FOUND => {
@@ -658,7 +942,7 @@ pub fn download_optional_if_newer_(
 
     let client_ = match client {
         Some(c) => c,
-        None => &reqwest::Client::new(),
+        None => &http::client()?,
     };
 
     download_optional_if_newer__(client_, url, local_path, etag)
@@ -682,7 +966,7 @@ pub fn download_multiple_first_available_(
 
     let client_ = match client {
         Some(c) => c,
-        None => &reqwest::Client::new(),
+        None => &http::client().unwrap_or_default(),
     };
 
     download_multiple_first_available__(client_, downloads, update_older)
@@ -743,7 +1027,8 @@ pub fn download_multiple_first_available_with_progress<F>(
 
     let client_ = match client {
         Some(c) => c,
-        None => &reqwest::Client::new(),
+        // A bad HTTP setting fails each download, and the callback reports it.
+        None => &http::client().unwrap_or_default(),
     };
 
     download_multiple_with_progress_async(client_, downloads, update_older, progress_callback);
@@ -764,6 +1049,11 @@ async fn download_multiple_with_progress_async<F>(
         let client = client.clone();
         futures.push(async move {
             let result: Result<(bool, Option<String>), Box<dyn Error>> = async {
+                // Another rig process downloading the same file would write
+                // the same temporary file. Once it is done the file is here,
+                // and up to date.
+                let _lock = FileLock::acquire_async(&local_path).await?;
+
                 // Check if file is up to date before attempting download
                 if local_path.exists() {
                     let metadata = fs::metadata(&local_path)?;
@@ -821,13 +1111,12 @@ pub async fn download_json(
 ) -> Result<Vec<serde_json::Value>, Box<dyn Error>> {
     let vers: Vec<Result<serde_json::Value, Box<dyn Error>>> =
         future::join_all(urls.into_iter().map(|url| async move {
-            let json = client
-                .get(url)
-                .send()
-                .await?
-                .error_for_status()?
-                .json()
-                .await?;
+            let _permit = http::host_permit(&url).await;
+            let resp = http::get(client, &url, &[]).await?;
+            if resp.status != StatusCode::OK {
+                bail!("HTTP status {} from {}", resp.status, url);
+            }
+            let json = serde_json::from_slice(&resp.bytes)?;
             Ok(json)
         }))
         .await;
@@ -851,9 +1140,308 @@ pub async fn download_json(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use wiremock::matchers::{method, path};
+    use wiremock::matchers::{header, method, path};
     use wiremock::{Mock, MockServer, ResponseTemplate};
 
+    #[test]
+    fn check_sha256_accepts_the_right_digest() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("f");
+        std::fs::write(&file, b"hello").unwrap();
+        // sha256("hello")
+        let sha = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        assert!(check_sha256(&file, sha).is_ok());
+        assert!(check_sha256(&file, &"0".repeat(64)).is_err());
+    }
+
+    #[test]
+    fn require_checksum_is_true_or_false() {
+        assert_eq!(parse_bool("true"), Some(true));
+        assert_eq!(parse_bool(" false\n"), Some(false));
+        assert_eq!(parse_bool("yes"), None);
+    }
+
+    #[test]
+    fn a_checksum_file_has_the_digest_first() {
+        let sha = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        assert_eq!(parse_sha256_file(sha).as_deref(), Some(sha));
+        let line = format!("{}  R-4.4.1.tar.gz\n", sha.to_uppercase());
+        assert_eq!(parse_sha256_file(&line).as_deref(), Some(sha));
+        assert_eq!(parse_sha256_file("<html>Not Found</html>"), None);
+        assert_eq!(parse_sha256_file(""), None);
+    }
+
+    #[tokio::test]
+    async fn a_corrupt_cached_installer_is_downloaded_again() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/R-4.4.1.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("hello"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("R-4.4.1.tar.gz");
+        std::fs::write(&target, b"truncated").unwrap();
+        let url = format!("{}/R-4.4.1.tar.gz", mock_server.uri());
+        let sha = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+        let (url2, target2) = (url.clone(), target.clone());
+        let ok = tokio::task::spawn_blocking(move || {
+            download_verified(&url2, &target2, Some(sha)).is_ok()
+        })
+        .await
+        .unwrap();
+        assert!(ok);
+        assert_eq!(std::fs::read(&target).unwrap(), b"hello");
+
+        // Now the cached file is good, and is not downloaded again.
+        let ok = tokio::task::spawn_blocking(move || {
+            download_verified(&url, &target, Some(sha)).is_ok()
+        })
+        .await
+        .unwrap();
+        assert!(ok);
+    }
+
+    #[tokio::test]
+    async fn a_corrupt_download_is_not_kept() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/R-4.4.1.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("tampered"))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/R-4.4.1.tar.gz.sha256"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824  R-4.4.1.tar.gz\n",
+            ))
+            .mount(&mock_server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("R-4.4.1.tar.gz");
+        let url = format!("{}/R-4.4.1.tar.gz", mock_server.uri());
+
+        let target2 = target.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            let sha = installer_sha256(&url, None);
+            assert!(sha.is_some());
+            download_verified(&url, &target2, sha.as_deref()).is_ok()
+        })
+        .await
+        .unwrap();
+        assert!(!result);
+        assert!(!target.exists());
+    }
+
+    #[tokio::test]
+    async fn a_partial_download_is_resumed() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/rtools.exe"))
+            .and(header("Range", "bytes=6-"))
+            .and(header("If-Range", "\"v1\""))
+            .respond_with(
+                ResponseTemplate::new(206)
+                    .insert_header("Content-Range", "bytes 6-10/11")
+                    .set_body_string("world"),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("rtools.exe");
+        fs::write(dir.path().join("rtools.exe.tmp"), "hello ").unwrap();
+        fs::write(dir.path().join("rtools.exe.tmp.etag"), "\"v1\"").unwrap();
+        let url = format!("{}/rtools.exe", mock_server.uri());
+
+        let target2 = target.clone();
+        tokio::task::spawn_blocking(move || {
+            let client = reqwest::Client::new();
+            download_file(&client, &url, target2.as_os_str()).unwrap();
+        })
+        .await
+        .unwrap();
+        assert_eq!(fs::read_to_string(&target).unwrap(), "hello world");
+        assert!(!dir.path().join("rtools.exe.tmp").exists());
+        assert!(!dir.path().join("rtools.exe.tmp.etag").exists());
+    }
+
+    /// Another process left a partial file behind, without a validator, so
+    /// rig cannot tell whether the rest of the file on the server belongs to
+    /// it.
+    #[tokio::test]
+    async fn a_partial_download_without_a_validator_is_not_resumed() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/rtools.exe"))
+            .and(wiremock::matchers::header_exists("Range"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/rtools.exe"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("hello world"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("rtools.exe");
+        fs::write(dir.path().join("rtools.exe.tmp"), "HELLO ").unwrap();
+        let url = format!("{}/rtools.exe", mock_server.uri());
+
+        let target2 = target.clone();
+        tokio::task::spawn_blocking(move || {
+            let client = reqwest::Client::new();
+            download_file(&client, &url, target2.as_os_str()).unwrap();
+        })
+        .await
+        .unwrap();
+        assert_eq!(fs::read_to_string(&target).unwrap(), "hello world");
+    }
+
+    /// The partial file remembers the version of the file it came from, for
+    /// resuming it in another process.
+    #[tokio::test]
+    async fn a_new_partial_download_saves_its_validator() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/rtools.exe"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("ETag", "\"v2\"")
+                    .set_body_string("hello world"),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let tmp = dir.path().join("rtools.exe.tmp");
+        let url = format!("{}/rtools.exe", mock_server.uri());
+
+        let tmp2 = tmp.clone();
+        let done = tokio::task::spawn_blocking(move || {
+            let client = reqwest::Client::new();
+            tokio::runtime::Runtime::new()
+                .unwrap()
+                .block_on(download_part(&client, &url, &tmp2))
+                .unwrap()
+        })
+        .await
+        .unwrap();
+        assert!(matches!(done, DownloadPart::Done));
+        assert_eq!(fs::read_to_string(&tmp).unwrap(), "hello world");
+        assert_eq!(
+            fs::read_to_string(dir.path().join("rtools.exe.tmp.etag")).unwrap(),
+            "\"v2\""
+        );
+    }
+
+    #[tokio::test]
+    async fn a_partial_download_is_replaced_if_ranges_are_not_supported() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/rtools.exe"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("hello world"))
+            .mount(&mock_server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("rtools.exe");
+        fs::write(dir.path().join("rtools.exe.tmp"), "stale").unwrap();
+        let url = format!("{}/rtools.exe", mock_server.uri());
+
+        let target2 = target.clone();
+        tokio::task::spawn_blocking(move || {
+            let client = reqwest::Client::new();
+            download_file(&client, &url, target2.as_os_str()).unwrap();
+        })
+        .await
+        .unwrap();
+        assert_eq!(fs::read_to_string(&target).unwrap(), "hello world");
+    }
+
+    /// A server that sends the first half of `body` and hangs up, and then
+    /// answers the `Range` request for the rest. wiremock always sends whole
+    /// responses, so this one is by hand. Returns the URL, and the requests it
+    /// got.
+    async fn flaky_server(body: &'static [u8]) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/cli_3.6.3.tar.gz", listener.local_addr().unwrap());
+        let half = body.len() / 2;
+        let handle = tokio::spawn(async move {
+            let mut requests = vec![];
+            for part in 0..2 {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = vec![0u8; 4096];
+                let n = socket.read(&mut buf).await.unwrap();
+                requests.push(String::from_utf8_lossy(&buf[..n]).to_lowercase());
+                let resp = if part == 0 {
+                    let mut r = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nETag: \"v1\"\r\n\
+                         Connection: close\r\n\r\n",
+                        body.len()
+                    )
+                    .into_bytes();
+                    r.extend_from_slice(&body[..half]);
+                    r
+                } else {
+                    let mut r = format!(
+                        "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\n\
+                         Content-Range: bytes {}-{}/{}\r\nETag: \"v1\"\r\n\
+                         Connection: close\r\n\r\n",
+                        body.len() - half,
+                        half,
+                        body.len() - 1,
+                        body.len()
+                    )
+                    .into_bytes();
+                    r.extend_from_slice(&body[half..]);
+                    r
+                };
+                socket.write_all(&resp).await.unwrap();
+                socket.shutdown().await.unwrap();
+            }
+            requests
+        });
+        (url, handle)
+    }
+
+    #[tokio::test]
+    async fn a_package_download_that_breaks_off_is_resumed() {
+        let body: &'static [u8] = b"0123456789abcdefghij";
+        let (url, server) = flaky_server(body).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("cli_3.6.3.tar.gz");
+        let target2 = target.clone();
+        let ok = tokio::task::spawn_blocking(move || {
+            let mut ok = false;
+            download_multiple_first_available_with_progress(
+                vec![(vec![url], target2)],
+                None,
+                None,
+                |_, result| ok = result.is_ok(),
+            );
+            ok
+        })
+        .await
+        .unwrap();
+
+        assert!(ok);
+        assert_eq!(fs::read(&target).unwrap(), body);
+        let requests = server.await.unwrap();
+        assert!(requests[1].contains("range: bytes=10-"));
+        assert!(requests[1].contains("if-range: \"v1\""));
+    }
+
     #[test]
     fn test_download_multiple_first_available_no_downloads() {
         let downloads: Vec<(Vec<String>, PathBuf)> = vec![];
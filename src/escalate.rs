@@ -44,6 +44,7 @@ pub fn escalate(task: &str) -> Result<(), Box<dyn Error>> {
             "RIG_DOWNLOAD_DIR",
             "RIG_FONTS_SHA256",
             "RIG_FONTS_URL",
+            "RIG_LOCK_TIMEOUT",
             "RIG_MODE",
             "RIG_R_INSTALL_DIR",
             "RUST_BACKTRACE",
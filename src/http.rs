@@ -0,0 +1,459 @@
+//! The HTTP client rig downloads with, and the timeouts, retries and per-host
+//! limits that every download goes through.
+//!
+//! A `reqwest::Client` keeps its pooled connections on the tokio runtime that
+//! opened them, and rig starts a runtime for every download call
+//! (`#[tokio::main]`), so no client outlives a call: [`client`] builds one from
+//! the shared settings, and the call uses it for all of its requests. The
+//! per-host limits are process-wide, they do not belong to a runtime.
+//!
+//! `reqwest` 0.11 only has a timeout for the whole request, which would cut an
+//! R installer off on a slow network, so the read timeout is applied here:
+//! to the response headers in [`send`], and to every chunk of the body in
+//! [`next_chunk`], which [`get`] uses, too.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use log::{debug, warn};
+use rand::Rng;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use simple_error::bail;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+const DEFAULT_CONNECT_TIMEOUT: u64 = 30;
+const DEFAULT_READ_TIMEOUT: u64 = 60;
+const DEFAULT_RETRIES: u64 = 3;
+const DEFAULT_MAX_PER_HOST: u64 = 16;
+
+/// The delay before the first retry, it doubles for every further one.
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+
+/// No retry waits longer than this, not even if the server asks for it.
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub struct HttpSettings {
+    /// How long to wait for a TCP (and TLS) connection.
+    pub connect_timeout: Duration,
+    /// How long to wait for the response headers, or for the next chunk of
+    /// the body, before giving up on a request.
+    pub read_timeout: Duration,
+    /// How many times a failed request is tried again.
+    pub retries: u32,
+    /// How many requests may be in flight to the same host at once.
+    pub max_per_host: usize,
+}
+
+static SETTINGS: OnceLock<HttpSettings> = OnceLock::new();
+
+/// The HTTP settings, from the environment, or else from the rig config, or
+/// else the defaults. They are read once per process.
+pub fn settings() -> Result<&'static HttpSettings, Box<dyn Error>> {
+    if let Some(settings) = SETTINGS.get() {
+        return Ok(settings);
+    }
+    let settings = HttpSettings {
+        connect_timeout: Duration::from_secs(setting(
+            "RIG_HTTP_CONNECT_TIMEOUT",
+            "http-connect-timeout",
+            DEFAULT_CONNECT_TIMEOUT,
+        )?),
+        read_timeout: Duration::from_secs(setting(
+            "RIG_HTTP_READ_TIMEOUT",
+            "http-read-timeout",
+            DEFAULT_READ_TIMEOUT,
+        )?),
+        retries: setting("RIG_HTTP_RETRIES", "http-retries", DEFAULT_RETRIES)? as u32,
+        max_per_host: setting(
+            "RIG_HTTP_MAX_PER_HOST",
+            "http-max-per-host",
+            DEFAULT_MAX_PER_HOST,
+        )? as usize,
+    };
+    debug!("HTTP settings: {:?}", settings);
+    Ok(SETTINGS.get_or_init(|| settings))
+}
+
+fn setting(env: &str, key: &str, default: u64) -> Result<u64, Box<dyn Error>> {
+    if let Ok(val) = std::env::var(env) {
+        match parse_setting(key, &val) {
+            Some(x) => Ok(x),
+            None => bail!("Invalid {} value: '{}', {}", env, val, expected(key)),
+        }
+    } else if let Some(val) = crate::config::get_global_config_value(key)? {
+        match parse_setting(key, &val) {
+            Some(x) => Ok(x),
+            None => bail!(
+                "Invalid '{}' in rig config: '{}', {}",
+                key,
+                val,
+                expected(key)
+            ),
+        }
+    } else {
+        Ok(default)
+    }
+}
+
+fn parse_setting(key: &str, val: &str) -> Option<u64> {
+    let x = val.trim().parse::<u64>().ok()?;
+    match key {
+        "http-retries" if x <= 10 => Some(x),
+        "http-retries" => None,
+        _ if x == 0 => None,
+        _ => Some(x),
+    }
+}
+
+fn expected(key: &str) -> &'static str {
+    match key {
+        "http-retries" => "expected a number between 0 and 10",
+        "http-max-per-host" => "expected a positive number",
+        _ => "expected a positive number of seconds",
+    }
+}
+
+/// A client with rig's connect timeout. Build one per runtime, see the module
+/// docs, and use it for all requests of that runtime.
+pub fn client() -> Result<reqwest::Client, Box<dyn Error>> {
+    let settings = settings()?;
+    let client = reqwest::Client::builder()
+        .connect_timeout(settings.connect_timeout)
+        .build()?;
+    Ok(client)
+}
+
+// ------------------------------------------------------------------------
+// per-host limits
+// ------------------------------------------------------------------------
+
+static HOSTS: OnceLock<Mutex<HashMap<String, Arc<Semaphore>>>> = OnceLock::new();
+
+/// Wait for a free slot for a request to the host of `url`, and hold it until
+/// the permit is dropped. Hold it until the body is read, not only until the
+/// headers arrive, the body is most of the load on the server.
+///
+/// Take at most one permit at a time in a task: with `http-max-per-host` set to
+/// one, the second would wait for the first forever. A URL without a host has
+/// no limit.
+pub async fn host_permit(url: &str) -> Option<OwnedSemaphorePermit> {
+    let host = host_key(url)?;
+    let max_per_host = settings()
+        .map(|s| s.max_per_host)
+        .unwrap_or(DEFAULT_MAX_PER_HOST as usize);
+    let semaphore = {
+        let mut hosts = HOSTS
+            .get_or_init(|| Mutex::new(HashMap::new()))
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        hosts
+            .entry(host)
+            .or_insert_with(|| Arc::new(Semaphore::new(max_per_host)))
+            .clone()
+    };
+    // The semaphores are never closed, so this cannot fail.
+    semaphore.acquire_owned().await.ok()
+}
+
+fn host_key(url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(url).ok()?;
+    let host = url.host_str()?;
+    match url.port_or_known_default() {
+        Some(port) => Some(format!("{}:{}", host, port)),
+        None => Some(host.to_string()),
+    }
+}
+
+// ------------------------------------------------------------------------
+// retries and timeouts
+// ------------------------------------------------------------------------
+
+/// Send a request, and try again, with exponential backoff, if it fails in a
+/// way that might not happen again: a connection error, a timeout, or a 408,
+/// 429 or 5xx response. Any other response is returned as it is, the caller
+/// decides what a 404 means. So is a transient failure status after the last
+/// retry.
+///
+/// A request with a streaming body cannot be repeated, so it is sent once.
+pub async fn send(req: RequestBuilder) -> Result<Response, Box<dyn Error>> {
+    let settings = settings()?;
+    let (client, req) = req.build_split();
+    let req = req?;
+    let url = req.url().to_string();
+
+    let mut attempt: u32 = 0;
+    loop {
+        let this = match req.try_clone() {
+            Some(this) => this,
+            None => return Ok(client.execute(req).await?),
+        };
+        let result = tokio::time::timeout(settings.read_timeout, client.execute(this)).await;
+        let (reason, retry_after) = match result {
+            Ok(Ok(resp)) if !is_transient_status(resp.status()) => return Ok(resp),
+            Ok(Ok(resp)) if attempt >= settings.retries => return Ok(resp),
+            Ok(Ok(resp)) => (
+                format!("{} from {}", resp.status(), url),
+                retry_after(&resp),
+            ),
+            Ok(Err(err)) if !is_transient_error(&err) || attempt >= settings.retries => {
+                return Err(err.into());
+            }
+            Ok(Err(err)) => (err.to_string(), None),
+            Err(_) if attempt >= settings.retries => bail!(
+                "No response from {} in {} s",
+                url,
+                settings.read_timeout.as_secs()
+            ),
+            Err(_) => (format!("No response from {}", url), None),
+        };
+        attempt += 1;
+        let delay = backoff(attempt, retry_after);
+        warn!(
+            "{}, retrying in {:.1} s ({}/{})",
+            reason,
+            delay.as_secs_f64(),
+            attempt,
+            settings.retries
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+fn is_transient_status(status: StatusCode) -> bool {
+    status == StatusCode::REQUEST_TIMEOUT
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status == StatusCode::INTERNAL_SERVER_ERROR
+        || status == StatusCode::BAD_GATEWAY
+        || status == StatusCode::SERVICE_UNAVAILABLE
+        || status == StatusCode::GATEWAY_TIMEOUT
+}
+
+fn is_transient_error(err: &reqwest::Error) -> bool {
+    err.is_connect() || err.is_timeout() || err.is_request() || err.is_body()
+}
+
+/// The `Retry-After` of a 429 or 503, if it is a number of seconds. The HTTP
+/// date form is rare enough to fall back to the backoff for.
+fn retry_after(resp: &Response) -> Option<Duration> {
+    let val = resp.headers().get("retry-after")?.to_str().ok()?;
+    val.trim().parse::<u64>().ok().map(Duration::from_secs)
+}
+
+/// How long to wait before retry number `attempt`, counting from one: what the
+/// server asked for, or else `RETRY_BASE_DELAY`, doubled for each attempt,
+/// plus up to half of that again, so that the downloads that failed together
+/// do not all retry together.
+pub fn backoff(attempt: u32, retry_after: Option<Duration>) -> Duration {
+    if let Some(delay) = retry_after {
+        return delay.min(RETRY_MAX_DELAY);
+    }
+    let delay = RETRY_BASE_DELAY.saturating_mul(1 << attempt.clamp(1, 16).saturating_sub(1));
+    let jitter = rand::thread_rng().gen_range(0.0..0.5);
+    delay.mul_f64(1.0 + jitter).min(RETRY_MAX_DELAY)
+}
+
+/// The next chunk of a response body, or `None` at its end. Fails if the
+/// server sends nothing for longer than the read timeout.
+pub async fn next_chunk(resp: &mut Response) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    let timeout = settings()?.read_timeout;
+    match tokio::time::timeout(timeout, resp.chunk()).await {
+        Ok(chunk) => Ok(chunk?.map(|c| c.to_vec())),
+        Err(_) => bail!("No data from {} in {} s", resp.url(), timeout.as_secs()),
+    }
+}
+
+/// A response of [`get`], with its body.
+pub struct Body {
+    pub status: StatusCode,
+    pub etag: Option<String>,
+    /// The body of a 200 response, empty for any other status.
+    pub bytes: Vec<u8>,
+}
+
+/// `GET` `url` with the extra `headers`, see [`send`], and read the whole body
+/// of a 200 response.
+///
+/// A body that breaks off is resumed where it stopped, with a `Range` request,
+/// up to `http-retries` times. The `If-Range` header makes sure that the rest
+/// comes from the same version of the resource: a server that has a new one
+/// sends all of it instead. A resource without an `ETag` or a
+/// `Last-Modified` header is downloaded again from the start.
+pub async fn get(
+    client: &reqwest::Client,
+    url: &str,
+    headers: &[(&str, &str)],
+) -> Result<Body, Box<dyn Error>> {
+    let retries = settings()?.retries;
+    let mut attempt: u32 = 0;
+    let mut bytes: Vec<u8> = vec![];
+    let mut etag: Option<String> = None;
+    let mut validator: Option<String> = None;
+    loop {
+        let mut req = client.get(url);
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        let resuming = !bytes.is_empty() && validator.is_some();
+        if resuming {
+            req = req
+                .header("Range", format!("bytes={}-", bytes.len()))
+                .header("If-Range", validator.as_deref().unwrap_or_default());
+        }
+        let mut resp = send(req).await?;
+        let status = resp.status();
+        if resuming
+            && status == StatusCode::PARTIAL_CONTENT
+            && resumes_at(&resp, bytes.len() as u64)
+        {
+            debug!("Resuming {} at {} bytes", url, bytes.len());
+        } else if status == StatusCode::OK {
+            bytes.clear();
+            etag = header(&resp, "etag");
+            validator = self::validator(&resp);
+        } else if resuming {
+            // Cannot resume, e.g. 416, start over.
+            bytes.clear();
+            validator = None;
+            continue;
+        } else {
+            return Ok(Body {
+                status,
+                etag: header(&resp, "etag"),
+                bytes: vec![],
+            });
+        }
+
+        loop {
+            match next_chunk(&mut resp).await {
+                Ok(Some(chunk)) => bytes.extend_from_slice(&chunk),
+                Ok(None) => {
+                    return Ok(Body {
+                        status: StatusCode::OK,
+                        etag,
+                        bytes,
+                    })
+                }
+                Err(err) if attempt >= retries => return Err(err),
+                Err(err) => {
+                    attempt += 1;
+                    let delay = backoff(attempt, None);
+                    warn!(
+                        "Download of {} interrupted after {} bytes: {}, resuming in {:.1} s ({}/{})",
+                        url,
+                        bytes.len(),
+                        err,
+                        delay.as_secs_f64(),
+                        attempt,
+                        retries
+                    );
+                    tokio::time::sleep(delay).await;
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn header(resp: &Response, name: &str) -> Option<String> {
+    resp.headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.to_string())
+}
+
+/// The validator to resume a download of `resp` with, as `If-Range`: its
+/// `ETag`, or else its `Last-Modified` header.
+pub fn validator(resp: &Response) -> Option<String> {
+    header(resp, "etag").or_else(|| header(resp, "last-modified"))
+}
+
+/// Whether a 206 response starts at `offset`, e.g. `bytes 1000-9999/10000`.
+pub fn resumes_at(resp: &Response, offset: u64) -> bool {
+    resp.headers()
+        .get("content-range")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("bytes "))
+        .and_then(|v| v.split('-').next())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        == Some(offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn settings_are_validated() {
+        assert_eq!(parse_setting("http-read-timeout", " 120 "), Some(120));
+        assert_eq!(parse_setting("http-read-timeout", "0"), None);
+        assert_eq!(parse_setting("http-max-per-host", "-1"), None);
+        assert_eq!(parse_setting("http-retries", "0"), Some(0));
+        assert_eq!(parse_setting("http-retries", "11"), None);
+    }
+
+    #[test]
+    fn backoff_doubles_and_is_capped() {
+        for attempt in 1..4 {
+            let base = RETRY_BASE_DELAY * (1 << (attempt - 1));
+            let delay = backoff(attempt, None);
+            assert!(delay >= base && delay <= base.mul_f64(1.5));
+        }
+        assert_eq!(backoff(30, None), RETRY_MAX_DELAY);
+        assert_eq!(
+            backoff(1, Some(Duration::from_secs(2))),
+            Duration::from_secs(2)
+        );
+        assert_eq!(backoff(1, Some(Duration::from_secs(3600))), RETRY_MAX_DELAY);
+    }
+
+    #[test]
+    fn hosts_are_keyed_with_their_port() {
+        assert_eq!(
+            host_key("https://cloud.r-project.org/src/contrib/PACKAGES"),
+            Some("cloud.r-project.org:443".to_string())
+        );
+        assert_eq!(
+            host_key("http://127.0.0.1:8765/x"),
+            Some("127.0.0.1:8765".to_string())
+        );
+        assert_eq!(host_key("not a url"), None);
+    }
+
+    #[tokio::test]
+    async fn transient_failures_are_retried() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/flaky"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/flaky"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("ok"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/missing"))
+            .respond_with(ResponseTemplate::new(404))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = client().unwrap();
+        let url = format!("{}/flaky", server.uri());
+        let resp = get(&client, &url, &[]).await.unwrap();
+        assert_eq!(resp.status, StatusCode::OK);
+        assert_eq!(resp.bytes, b"ok");
+
+        // A 404 is an answer, not a failure, it is not asked again.
+        let url = format!("{}/missing", server.uri());
+        let resp = send(client.get(&url)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+}
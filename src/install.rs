@@ -1,10 +1,12 @@
 use std::cell::Cell;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::error::Error;
 use std::fs::OpenOptions;
+use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use futures::stream::{FuturesUnordered, StreamExt};
 use indicatif::{ProgressBar, ProgressStyle};
@@ -14,7 +16,9 @@ use tokio::fs::create_dir_all;
 use tokio::process::Command;
 use tokio::sync::Mutex;
 
+use crate::lock::FileLock;
 use crate::output::OUTPUT;
+use crate::progress::{millis, EventStream, ProgressEvent};
 
 /// The `DESCRIPTION` field recording which artifact an installed package came
 /// from: the sha256 of the upstream CRAN source tarball of its version.
@@ -39,6 +43,197 @@ pub struct PackageInfo {
     /// Value for the [`REMOTE_LINKINGTO_FIELD`] field, as
     /// `(package, version, sha256)`. Empty for a package without `LinkingTo:`.
     pub linkingto: Vec<(String, String, String)>,
+    /// Extra arguments for `R CMD INSTALL`, see [`InstallOptions`].
+    pub install_args: Vec<String>,
+    /// Extra environment variables for `R CMD INSTALL`, see [`InstallOptions`].
+    pub install_env: Vec<(String, String)>,
+}
+
+/// The prefix of the project manifest fields that hold the install options of
+/// one package: `Config/rig/install-args/<package>` and
+/// `Config/rig/install-env/<package>`.
+pub const INSTALL_OPTIONS_PREFIX: &str = "Config/rig/";
+
+/// Install options of one package, as written in the project manifest and in
+/// `pkg.lock`. Both are shell-like words, see [`split_args`]: `args` is a list
+/// of `R CMD INSTALL` arguments, e.g. `--no-test-load
+/// --configure-args="--with-x"`, and `env` a list of `NAME=value` assignments.
+/// They only apply to packages installed from source.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InstallOptions {
+    pub args: String,
+    pub env: String,
+}
+
+impl InstallOptions {
+    /// The options of every package a project manifest has options for, from
+    /// its `Config/rig/install-args/<package>` and
+    /// `Config/rig/install-env/<package>` fields. Errors on a malformed value,
+    /// so that a typo is reported when the project is solved, not when a
+    /// package fails to build.
+    pub fn from_fields<'a>(
+        fields: impl Iterator<Item = (&'a str, &'a str)>,
+    ) -> Result<BTreeMap<String, InstallOptions>, Box<dyn Error>> {
+        let mut out: BTreeMap<String, InstallOptions> = BTreeMap::new();
+        for (name, value) in fields {
+            let Some(rest) = name.strip_prefix(INSTALL_OPTIONS_PREFIX) else {
+                continue;
+            };
+            let value = value.trim().to_string();
+            if let Some(pkg) = rest.strip_prefix("install-args/") {
+                split_args(&value).map_err(|e| format!("Invalid {}: {}", name, e))?;
+                out.entry(pkg.to_string()).or_default().args = value;
+            } else if let Some(pkg) = rest.strip_prefix("install-env/") {
+                parse_env(&value).map_err(|e| format!("Invalid {}: {}", name, e))?;
+                out.entry(pkg.to_string()).or_default().env = value;
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Split `value` into words the way a POSIX shell would, without expanding
+/// anything: words are separated by whitespace, `'...'` quotes literally,
+/// `"..."` quotes with `\"` and `\\` escapes, and a backslash outside quotes
+/// escapes the next character. `--configure-args="--with-a --with-b"` is one
+/// word, `--configure-args=--with-a --with-b`.
+pub fn split_args(value: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if let Some(w) = word.take() {
+                    words.push(w);
+                }
+            }
+            '\'' => {
+                let w = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => w.push(c),
+                        None => bail!("Unterminated ' quote in `{}`", value),
+                    }
+                }
+            }
+            '"' => {
+                let w = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => w.push(c),
+                            Some(c) => {
+                                w.push('\\');
+                                w.push(c);
+                            }
+                            None => bail!("Unterminated \" quote in `{}`", value),
+                        },
+                        Some(c) => w.push(c),
+                        None => bail!("Unterminated \" quote in `{}`", value),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => bail!("Trailing backslash in `{}`", value),
+            },
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if let Some(w) = word {
+        words.push(w);
+    }
+    Ok(words)
+}
+
+/// Parse `NAME=value` assignments, quoted as for [`split_args`].
+pub fn parse_env(value: &str) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let mut out = vec![];
+    for word in split_args(value)? {
+        let Some((name, val)) = word.split_once('=') else {
+            bail!("`{}` is not a NAME=value assignment", word);
+        };
+        let valid = name
+            .chars()
+            .enumerate()
+            .all(|(i, c)| c == '_' || c.is_ascii_alphabetic() || (i > 0 && c.is_ascii_digit()));
+        if name.is_empty() || !valid {
+            bail!("`{}` is not a valid environment variable name", name);
+        }
+        out.push((name.to_string(), val.to_string()));
+    }
+    Ok(out)
+}
+
+/// `word`, quoted for the install log if a shell would need it.
+pub fn quote_word(word: &str) -> String {
+    let plain = !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_=./:,+@%".contains(c));
+    if plain {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+/// What happens to one package while it is installed, as the `print_fn` of
+/// [`install_package`] sees it.
+#[derive(Debug)]
+pub enum InstallEvent<'a> {
+    Started {
+        pkg: &'a PackageInfo,
+    },
+    /// `log` is the output of `R CMD INSTALL`, `None` if the package was
+    /// unpacked without it.
+    Finished {
+        pkg: &'a PackageInfo,
+        duration: Duration,
+        log: Option<&'a Path>,
+    },
+    Failed {
+        pkg: &'a PackageInfo,
+        error: &'a str,
+        log: Option<&'a Path>,
+    },
+}
+
+impl InstallEvent<'_> {
+    /// The line to show for the event, if any. Failures are always shown via
+    /// `OUTPUT`, so they have none.
+    pub fn message(&self) -> Option<String> {
+        match self {
+            InstallEvent::Finished { pkg, .. } => {
+                Some(format!("Installed {} {}", pkg.name, pkg.version))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn report<F>(print_fn: &Option<Arc<F>>, event: InstallEvent)
+where
+    F: Fn(&InstallEvent) + Send + Sync + 'static,
+{
+    match print_fn {
+        Some(print) => print(&event),
+        None => {
+            if let Some(msg) = event.message() {
+                OUTPUT.success(&msg);
+            }
+        }
+    }
+}
+
+/// Where `R CMD INSTALL` writes its output for a package.
+pub fn install_log_path(library_path: &Path, package: &str) -> PathBuf {
+    library_path
+        .join("_logs")
+        .join(format!("{}-install.log", package))
 }
 
 /// Install one R package into a library.
@@ -52,24 +247,33 @@ pub struct PackageInfo {
 /// * `pkg` - The package to install, and the provenance to record in it
 /// * `library_path` - Path to the R library directory where the package should be installed
 /// * `r_binary` - Path to the R binary to use for source installations
-/// * `print_fn` - Optional custom print function (e.g., for progress bars). If None, uses OUTPUT.
+/// * `jobs` - How many parallel `make` jobs a source installation may use
+/// * `print_fn` - Optional function that receives every [`InstallEvent`] (e.g.,
+///   for progress bars). If None, uses OUTPUT.
 pub async fn install_package<F>(
     pkg: &PackageInfo,
     library_path: &Path,
     r_binary: &str,
+    jobs: usize,
     print_fn: Option<Arc<F>>,
 ) -> Result<(), Box<dyn Error>>
 where
-    F: Fn(&str) + Send + Sync + 'static,
+    F: Fn(&InstallEvent) + Send + Sync + 'static,
 {
+    let started = Instant::now();
+    report(&print_fn, InstallEvent::Started { pkg });
+
     if pkg.binary {
         match install_binary_package(pkg, library_path) {
             Ok(()) => {
-                let msg = format!("Installed {} {}", pkg.name, pkg.version);
-                match print_fn {
-                    Some(ref print) => print(&msg),
-                    None => OUTPUT.success(&msg),
-                }
+                report(
+                    &print_fn,
+                    InstallEvent::Finished {
+                        pkg,
+                        duration: started.elapsed(),
+                        log: None,
+                    },
+                );
                 info!(
                     "Installed binary package {} {} into {}",
                     pkg.name,
@@ -89,7 +293,28 @@ where
         }
     }
 
-    r_cmd_install(pkg, library_path, r_binary, print_fn).await
+    let log_file_path = install_log_path(library_path, &pkg.name);
+    let result = r_cmd_install(pkg, library_path, r_binary, jobs, &log_file_path).await;
+    match &result {
+        Ok(()) => report(
+            &print_fn,
+            InstallEvent::Finished {
+                pkg,
+                duration: started.elapsed(),
+                log: Some(&log_file_path),
+            },
+        ),
+        Err(err) => report(
+            &print_fn,
+            InstallEvent::Failed {
+                pkg,
+                error: &err.to_string(),
+                // R may not have got as far as starting.
+                log: Some(log_file_path.as_path()).filter(|l| l.exists()),
+            },
+        ),
+    }
+    result
 }
 
 /// Unpack a built package into the library, without starting R.
@@ -297,15 +522,20 @@ fn drop_fields(text: &str, fields: &[&str]) -> String {
 
 /// Install a source package with `R CMD INSTALL`, and record the provenance in
 /// the result.
-async fn r_cmd_install<F>(
+///
+/// `jobs` goes into `MAKEFLAGS` as `-j<jobs>`. A `MAKEFLAGS` of the user is
+/// kept, but its job count is replaced by the budget's, see
+/// [`budget_makeflags`]. The package's
+/// [`InstallOptions`] are added to the command line and the environment, and
+/// the log starts with the full command and the variables rig set. The values
+/// of the package's variables may be secrets, so only their names are logged.
+async fn r_cmd_install(
     pkg: &PackageInfo,
     library_path: &Path,
     r_binary: &str,
-    print_fn: Option<Arc<F>>,
-) -> Result<(), Box<dyn Error>>
-where
-    F: Fn(&str) + Send + Sync + 'static,
-{
+    jobs: usize,
+    log_file_path: &Path,
+) -> Result<(), Box<dyn Error>> {
     let package_name: &str = &pkg.name;
     let package_path: &Path = &pkg.file_path;
     info!(
@@ -315,40 +545,64 @@ where
         library_path.display()
     );
 
-    let logs_dir = library_path.join("_logs");
-    create_dir_all(&logs_dir).await?;
+    if let Some(logs_dir) = log_file_path.parent() {
+        create_dir_all(logs_dir).await?;
+    }
 
-    let log_file_path = logs_dir.join(format!("{}-install.log", package_name));
-    let log_file = OpenOptions::new()
+    let mut log_file = OpenOptions::new()
         .create(true)
         .write(true)
         .truncate(true)
-        .open(&log_file_path)?;
+        .open(log_file_path)?;
+
+    let mut args: Vec<String> = vec![
+        "CMD".to_string(),
+        "INSTALL".to_string(),
+        "-l".to_string(),
+        library_path.to_string_lossy().to_string(),
+    ];
+    args.extend(pkg.install_args.iter().cloned());
+    args.push(package_path.to_string_lossy().to_string());
+
+    let user_makeflags = std::env::var("MAKEFLAGS").ok();
+    let makeflags = budget_makeflags(user_makeflags.as_deref(), jobs);
+    debug!("Building {} with MAKEFLAGS={}", package_name, makeflags);
+    let mut env: Vec<(String, String)> = vec![("MAKEFLAGS".to_string(), makeflags.clone())];
+    // The package's own settings come last, so they win, MAKEFLAGS included.
+    env.extend(pkg.install_env.iter().cloned());
+    let env_names: Vec<&str> = pkg.install_env.iter().map(|(n, _)| n.as_str()).collect();
+
+    // The log starts with what rig ran, so that it can be reproduced by hand.
+    let command_line: Vec<String> = std::iter::once(r_binary)
+        .chain(args.iter().map(|a| a.as_str()))
+        .map(quote_word)
+        .collect();
+    writeln!(log_file, "rig: {}", command_line.join(" "))?;
+    if !env_names.contains(&"MAKEFLAGS") {
+        writeln!(log_file, "rig: MAKEFLAGS={}", quote_word(&makeflags))?;
+    }
+    for name in &env_names {
+        writeln!(log_file, "rig: {} is set, see Config/rig/install-env", name)?;
+    }
+    if !pkg.install_args.is_empty() || !env_names.is_empty() {
+        info!(
+            "Installing {} with extra arguments {:?} and environment variables {:?}",
+            package_name, pkg.install_args, env_names
+        );
+    }
 
     let log_file_stderr = log_file.try_clone()?;
 
-    let status = Command::new(r_binary)
-        .arg("CMD")
-        .arg("INSTALL")
-        .arg("-l")
-        .arg(library_path)
-        .arg(package_path)
+    let mut cmd = Command::new(r_binary);
+    cmd.args(&args)
+        .envs(env)
         .stdout(Stdio::from(log_file))
-        .stderr(Stdio::from(log_file_stderr))
-        .status()
-        .await?;
+        .stderr(Stdio::from(log_file_stderr));
+    let status = cmd.status().await?;
 
     if status.success() {
         patch_description(&library_path.join(package_name), pkg)?;
 
-        // User output: Use custom print function if provided, otherwise use OUTPUT
-        let msg = format!("Installed {} {}", package_name, pkg.version);
-        if let Some(ref print) = print_fn {
-            print(&msg);
-        } else {
-            OUTPUT.success(&msg);
-        }
-
         info!(
             "Successfully installed package {} to {} (log: {})",
             package_name,
@@ -376,32 +630,206 @@ where
     }
 }
 
+/// The `MAKEFLAGS` of a build that got `jobs` jobs from the [`JobBudget`]:
+/// the user's `MAKEFLAGS`, if any, without its `-j`/`--jobs` flags, and
+/// `-j<jobs>`. Otherwise a `-j` in the environment would make every
+/// concurrent build run that many jobs, and oversubscribe the machine.
+fn budget_makeflags(user: Option<&str>, jobs: usize) -> String {
+    let mut flags: Vec<&str> = vec![];
+    let mut words = user.unwrap_or("").split_whitespace().peekable();
+    while let Some(word) = words.next() {
+        if word == "-j" || word == "--jobs" {
+            // The count may be the next word, or missing.
+            if words.peek().is_some_and(|w| w.parse::<usize>().is_ok()) {
+                words.next();
+            }
+            continue;
+        }
+        if word.starts_with("--jobs=")
+            || (word.starts_with("-j") && word[2..].parse::<usize>().is_ok())
+        {
+            continue;
+        }
+        flags.push(word);
+    }
+    let jobs = format!("-j{}", jobs);
+    flags.push(&jobs);
+    flags.join(" ")
+}
+
+/// How many CPUs the installation of a set of packages may keep busy.
+///
+/// Every `R CMD INSTALL` of a source package runs `make`, and `make` can run
+/// several compilers at once. Without coordination `max_concurrent` builds of
+/// `-j<ncpu>` each oversubscribe the machine, and `max_concurrent` serial builds
+/// leave it idle while one big package compiles. So the budget is shared: each
+/// source build reserves a number of jobs when it starts, gets them as
+/// `MAKEFLAGS=-j<n>`, and gives them back when it finishes. A build never
+/// starts without at least one free job. Binary packages are only unpacked,
+/// and do not count against the budget.
+///
+/// A build keeps its jobs until it finishes, so a build that happens to be
+/// ready alone must not take them all: the source builds that become ready
+/// right after it would be left with one job each. So no build gets more than
+/// its share of the total, split between as many builds as can run at once.
+#[derive(Debug)]
+pub struct JobBudget {
+    total: usize,
+    max_concurrent: usize,
+    in_use: usize,
+}
+
+impl JobBudget {
+    pub fn new(total: usize, max_concurrent: usize) -> JobBudget {
+        JobBudget {
+            total: total.max(1),
+            max_concurrent: max_concurrent.max(1),
+            in_use: 0,
+        }
+    }
+
+    fn free(&self) -> usize {
+        self.total.saturating_sub(self.in_use)
+    }
+
+    /// Reserve jobs for a build that is starting while `waiting` source builds
+    /// (this one included) are ready to start, and `left` source builds
+    /// (this one included) have not started yet. The free jobs are split
+    /// evenly between the waiting builds, but a build gets at most
+    /// `total / min(max_concurrent, left)` jobs. `None` if there are no free
+    /// jobs at all.
+    fn acquire(&mut self, waiting: usize, left: usize) -> Option<usize> {
+        let free = self.free();
+        if free == 0 {
+            return None;
+        }
+        let cap = self.total / left.min(self.max_concurrent).max(1);
+        let jobs = (free / waiting.max(1)).min(cap).max(1);
+        self.in_use += jobs;
+        Some(jobs)
+    }
+
+    fn release(&mut self, jobs: usize) {
+        self.in_use = self.in_use.saturating_sub(jobs);
+    }
+}
+
+/// The default job budget: the number of CPUs.
+pub fn default_jobs() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+/// Relative cost of installing a package, for [`critical_path_priorities`]. A
+/// source package is compiled, which usually takes far longer than unpacking a
+/// binary.
+fn install_cost(pkg: &PackageInfo) -> u64 {
+    if pkg.binary {
+        1
+    } else {
+        10
+    }
+}
+
+/// The priority of each package: its own install cost plus the most expensive
+/// chain of packages that (transitively) wait for it.
+///
+/// Of the packages that are ready to install, the one with the highest priority
+/// is on the critical path of the dependency graph, and starting it first
+/// shortens the whole install the most. A package nothing depends on has its
+/// own cost as its priority.
+fn critical_path_priorities(packages: &HashMap<String, PackageInfo>) -> HashMap<String, u64> {
+    let mut dependents: HashMap<&str, Vec<&str>> = HashMap::new();
+    for pkg in packages.values() {
+        for dep in &pkg.dependencies {
+            if packages.contains_key(dep) {
+                dependents
+                    .entry(dep.as_str())
+                    .or_default()
+                    .push(pkg.name.as_str());
+            }
+        }
+    }
+
+    fn visit<'a>(
+        name: &'a str,
+        packages: &'a HashMap<String, PackageInfo>,
+        dependents: &HashMap<&'a str, Vec<&'a str>>,
+        visiting: &mut HashSet<&'a str>,
+        out: &mut HashMap<String, u64>,
+    ) -> u64 {
+        if let Some(p) = out.get(name) {
+            return *p;
+        }
+        let own = install_cost(&packages[name]);
+        // A dependency cycle cannot be installed anyway, the installer reports
+        // it. Here it only must not recurse forever.
+        if !visiting.insert(name) {
+            return own;
+        }
+        let above = dependents
+            .get(name)
+            .map(|ds| {
+                ds.iter()
+                    .map(|d| visit(d, packages, dependents, visiting, out))
+                    .max()
+                    .unwrap_or(0)
+            })
+            .unwrap_or(0);
+        visiting.remove(name);
+        out.insert(name.to_string(), own + above);
+        own + above
+    }
+
+    let mut out = HashMap::new();
+    let mut visiting = HashSet::new();
+    for name in packages.keys() {
+        visit(name, packages, &dependents, &mut visiting, &mut out);
+    }
+    out
+}
+
+/// The order in which to consider ready packages: highest priority first, by
+/// name among equals, so that the same input always installs the same way.
+fn start_order(packages: &HashMap<String, PackageInfo>) -> Vec<String> {
+    let prio = critical_path_priorities(packages);
+    let mut names: Vec<String> = packages.keys().cloned().collect();
+    names.sort_by(|a, b| prio[b].cmp(&prio[a]).then_with(|| a.cmp(b)));
+    names
+}
+
 /// Install multiple packages respecting dependency order
 ///
 /// Packages are installed concurrently when possible, but dependencies
-/// are always installed before packages that depend on them.
+/// are always installed before packages that depend on them. Of the packages
+/// that could start, the ones on the critical path of the dependency graph
+/// start first, and source builds share the `jobs` budget, see [`JobBudget`].
 ///
 /// # Arguments
 /// * `packages` - List of packages with their file paths and dependencies
 /// * `library_path` - Path to the R library directory
 /// * `r_binary` - Path to the R binary to use for installation
 /// * `max_concurrent` - Maximum number of packages to install concurrently
-/// * `print_fn` - Optional print function for success messages (e.g., progress bar's println)
+/// * `jobs` - Total number of parallel `make` jobs the source builds may use
+/// * `print_fn` - Optional function receiving every [`InstallEvent`] (e.g., to print through a progress bar)
 /// * `progress_callback` - Optional callback called when each package completes installation
 ///
 /// # Returns
 /// * `Ok(())` if all installations succeeded
 /// * `Err` if any installation failed
+#[allow(clippy::too_many_arguments)]
 pub async fn install_package_tree_with_progress<P, F>(
     packages: Vec<PackageInfo>,
     library_path: &Path,
     r_binary: &str,
     max_concurrent: usize,
+    jobs: usize,
     print_fn: Option<Arc<P>>,
     mut progress_callback: Option<F>,
 ) -> Result<(), Box<dyn Error>>
 where
-    P: Fn(&str) + Send + Sync + 'static,
+    P: Fn(&InstallEvent) + Send + Sync + 'static,
     F: FnMut(&str, bool),
 {
     let package_count = packages.len();
@@ -409,8 +837,8 @@ where
     OUTPUT.status(&format!("Installing {} packages.", package_count));
 
     info!(
-        "Installing {} packages in dependency order with max_concurrent={}",
-        package_count, max_concurrent
+        "Installing {} packages in dependency order with max_concurrent={}, jobs={}",
+        package_count, max_concurrent, jobs
     );
 
     let package_map: Arc<HashMap<String, PackageInfo>> = Arc::new(
@@ -419,10 +847,12 @@ where
             .map(|pkg| (pkg.name.clone(), pkg))
             .collect(),
     );
+    let order: Arc<Vec<String>> = Arc::new(start_order(&package_map));
 
     let installed = Arc::new(Mutex::new(HashSet::new()));
     let failed: Arc<Mutex<HashSet<String>>> = Arc::new(Mutex::new(HashSet::new()));
     let installing = Arc::new(Mutex::new(HashSet::new()));
+    let budget = Arc::new(Mutex::new(JobBudget::new(jobs, max_concurrent)));
 
     let mut running_tasks = FuturesUnordered::new();
 
@@ -432,16 +862,18 @@ where
     #[allow(clippy::too_many_arguments)]
     async fn try_start_packages<P>(
         package_map: Arc<HashMap<String, PackageInfo>>,
+        order: Arc<Vec<String>>,
         installed: Arc<Mutex<HashSet<String>>>,
         failed: Arc<Mutex<HashSet<String>>>,
         installing: Arc<Mutex<HashSet<String>>>,
+        budget: Arc<Mutex<JobBudget>>,
         library_path: PathBuf,
         r_binary: String,
         max_to_start: usize,
         print_fn: Option<Arc<P>>,
     ) -> Vec<tokio::task::JoinHandle<Result<String, String>>>
     where
-        P: Fn(&str) + Send + Sync + 'static,
+        P: Fn(&InstallEvent) + Send + Sync + 'static,
     {
         let installed_set = installed.lock().await.clone();
         let failed_set = failed.lock().await.clone();
@@ -450,7 +882,26 @@ where
         let mut new_tasks = Vec::new();
         let mut started = 0;
 
-        for (name, pkg) in package_map.iter() {
+        // The source builds that have not started yet, and the ones of them
+        // that could start now, for splitting the free jobs between them.
+        let left: Vec<&PackageInfo> = order
+            .iter()
+            .map(|name| &package_map[name])
+            .filter(|pkg| {
+                !pkg.binary
+                    && !installed_set.contains(&pkg.name)
+                    && !failed_set.contains(&pkg.name)
+                    && !installing_set.contains(&pkg.name)
+            })
+            .collect();
+        let mut left_sources = left.len();
+        let mut waiting_sources = left
+            .iter()
+            .filter(|pkg| pkg.dependencies.iter().all(|d| installed_set.contains(d)))
+            .count();
+
+        for name in order.iter() {
+            let pkg = &package_map[name];
             if started >= max_to_start {
                 break;
             }
@@ -476,6 +927,21 @@ where
                 // TODO: can this happen? We quit on the first failure, no?
                 error!("Skipping package {} because a dependency failed", name);
             } else if all_deps_installed {
+                // A binary is unpacked, it needs no jobs. A source build waits
+                // for a free one, but a binary behind it in the order can
+                // still start.
+                let jobs = if pkg.binary {
+                    0
+                } else {
+                    let jobs = budget.lock().await.acquire(waiting_sources, left_sources);
+                    waiting_sources = waiting_sources.saturating_sub(1);
+                    left_sources = left_sources.saturating_sub(1);
+                    match jobs {
+                        Some(jobs) => jobs,
+                        None => continue,
+                    }
+                };
+
                 installing_set.insert(name.clone());
                 started += 1;
                 let name_clone = name.clone();
@@ -485,6 +951,7 @@ where
                 let installed_clone = Arc::clone(&installed);
                 let failed_clone = Arc::clone(&failed);
                 let installing_clone = Arc::clone(&installing);
+                let budget_clone = Arc::clone(&budget);
                 let print_fn_clone = print_fn.clone();
 
                 let task = tokio::spawn(async move {
@@ -492,6 +959,7 @@ where
                         &pkg_clone,
                         &library_path_clone,
                         &r_binary_clone,
+                        jobs.max(1),
                         print_fn_clone,
                     )
                     .await
@@ -500,6 +968,7 @@ where
                         Err(e) => Err(e.to_string()),
                     };
 
+                    budget_clone.lock().await.release(jobs);
                     installing_clone.lock().await.remove(&name_clone);
 
                     match result {
@@ -527,9 +996,11 @@ where
 
     let initial_tasks = try_start_packages(
         Arc::clone(&package_map),
+        Arc::clone(&order),
         Arc::clone(&installed),
         Arc::clone(&failed),
         Arc::clone(&installing),
+        Arc::clone(&budget),
         library_path.clone(),
         r_binary.clone(),
         max_concurrent,
@@ -561,9 +1032,11 @@ where
         if can_start > 0 {
             let new_tasks = try_start_packages(
                 Arc::clone(&package_map),
+                Arc::clone(&order),
                 Arc::clone(&installed),
                 Arc::clone(&failed),
                 Arc::clone(&installing),
+                Arc::clone(&budget),
                 library_path.clone(),
                 r_binary.clone(),
                 can_start,
@@ -630,16 +1103,28 @@ where
 ///
 /// The synchronous entry point both `rig pkg install` and `rig proj deploy` use:
 /// it owns the tokio runtime and the progress bar, so that the callers only have
-/// to decide *what* to install.
+/// to decide *what* to install. With an `events` stream there is no progress
+/// bar, and every [`InstallEvent`] goes to the stream instead.
 pub fn install_packages(
     packages: Vec<PackageInfo>,
     library_path: &Path,
     r_binary: &str,
     max_concurrent: usize,
+    jobs: usize,
+    events: Option<Arc<EventStream>>,
 ) -> Result<usize, Box<dyn Error>> {
     let total = packages.len();
 
-    let install_pb = ProgressBar::new(total as u64);
+    // One rig process installs into a library at a time. R's own `00LOCK`
+    // only covers one package, and two installs would still replace each
+    // other's dependencies.
+    let _lock = FileLock::acquire(library_path)?;
+
+    let install_pb = if events.is_some() {
+        ProgressBar::hidden()
+    } else {
+        ProgressBar::new(total as u64)
+    };
     install_pb.set_style(
         ProgressStyle::default_bar()
             .template("{msg} [{bar:40.green/blue}] {pos}/{len} packages")
@@ -653,8 +1138,13 @@ pub fn install_packages(
     // Per-package messages have to go through the bar, or they overwrite it.
     let print_fn = Arc::new({
         let pb = install_pb.clone();
-        move |msg: &str| {
-            pb.println(msg);
+        move |event: &InstallEvent| match &events {
+            Some(events) => events.emit(&progress_event(event)),
+            None => {
+                if let Some(msg) = event.message() {
+                    pb.println(msg);
+                }
+            }
         }
     });
 
@@ -664,6 +1154,7 @@ pub fn install_packages(
         library_path,
         r_binary,
         max_concurrent,
+        jobs,
         Some(print_fn),
         Some(|_pkg_name: &str, success: bool| {
             if success {
@@ -679,6 +1170,28 @@ pub fn install_packages(
     Ok(installed_count.get())
 }
 
+fn progress_event<'a>(event: &'a InstallEvent) -> ProgressEvent<'a> {
+    match event {
+        InstallEvent::Started { pkg } => ProgressEvent::InstallStarted {
+            package: &pkg.name,
+            version: &pkg.version,
+            binary: pkg.binary,
+        },
+        InstallEvent::Finished { pkg, duration, log } => ProgressEvent::InstallFinished {
+            package: &pkg.name,
+            version: &pkg.version,
+            binary: pkg.binary,
+            duration_ms: millis(*duration),
+            log: *log,
+        },
+        InstallEvent::Failed { pkg, error, log } => ProgressEvent::Error {
+            package: Some(&pkg.name),
+            message: error,
+            log: *log,
+        },
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -702,6 +1215,8 @@ mod tests {
                 .iter()
                 .map(|(p, v, s)| (p.to_string(), v.to_string(), s.to_string()))
                 .collect(),
+            install_args: vec![],
+            install_env: vec![],
         }
     }
 
@@ -757,6 +1272,65 @@ mod tests {
         assert!(desc.contains("RemoteHash: abc"));
     }
 
+    type Seen = Arc<std::sync::Mutex<Vec<String>>>;
+
+    /// The events `install_package` reports, as `kind package log`.
+    fn recorder() -> (Seen, Arc<impl Fn(&InstallEvent) + Send + Sync + 'static>) {
+        let seen = Arc::new(std::sync::Mutex::new(vec![]));
+        let seen2 = Arc::clone(&seen);
+        let print_fn = Arc::new(move |event: &InstallEvent| {
+            let line = match event {
+                InstallEvent::Started { pkg } => format!("started {}", pkg.name),
+                InstallEvent::Finished { pkg, log, .. } => {
+                    format!("finished {} {}", pkg.name, log.is_some())
+                }
+                InstallEvent::Failed { pkg, log, .. } => {
+                    format!("failed {} {}", pkg.name, log.is_some())
+                }
+            };
+            seen2.lock().unwrap().push(line);
+        });
+        (seen, print_fn)
+    }
+
+    #[tokio::test]
+    async fn installing_a_binary_reports_its_progress() {
+        let tmp = tempfile::tempdir().unwrap();
+        let lib = tmp.path().join("lib");
+        std::fs::create_dir_all(&lib).unwrap();
+        let archive = tmp.path().join("foo_1.0.0.tgz");
+        tarball(&archive, "foo", DESC, &[]);
+
+        let (seen, print_fn) = recorder();
+        let pkg = info("foo", &archive, None, &[]);
+        install_package(&pkg, &lib, "R", 1, Some(print_fn))
+            .await
+            .unwrap();
+
+        assert_eq!(*seen.lock().unwrap(), ["started foo", "finished foo false"]);
+    }
+
+    /// The archive is not a built package, so `R CMD INSTALL` gets it, and
+    /// there is no such R.
+    #[tokio::test]
+    async fn a_failed_install_reports_its_log() {
+        let tmp = tempfile::tempdir().unwrap();
+        let lib = tmp.path().join("lib");
+        std::fs::create_dir_all(&lib).unwrap();
+        let archive = tmp.path().join("foo_1.0.0.tgz");
+        tarball(&archive, "bar", DESC, &[]);
+
+        let (seen, print_fn) = recorder();
+        let pkg = info("foo", &archive, None, &[]);
+        let r_binary = tmp.path().join("no-such-R");
+        let result =
+            install_package(&pkg, &lib, &r_binary.to_string_lossy(), 1, Some(print_fn)).await;
+
+        assert!(result.is_err());
+        assert_eq!(*seen.lock().unwrap(), ["started foo", "failed foo true"]);
+        assert!(install_log_path(&lib, "foo").exists());
+    }
+
     #[test]
     fn a_binary_zip_is_unpacked_into_the_library() {
         let tmp = tempfile::tempdir().unwrap();
@@ -920,4 +1494,204 @@ mod tests {
         assert_eq!(parse_linkingto("cpp11, tzdb@0.4.0=bb").len(), 1);
         assert!(parse_linkingto("").is_empty());
     }
+
+    // ----------------------------------------------------------------
+    // Scheduling
+
+    fn node(name: &str, binary: bool, deps: &[&str]) -> PackageInfo {
+        let mut pkg = info(name, Path::new("unused"), None, &[]);
+        pkg.binary = binary;
+        pkg.dependencies = deps.iter().map(|d| d.to_string()).collect();
+        pkg
+    }
+
+    fn graph(nodes: Vec<PackageInfo>) -> HashMap<String, PackageInfo> {
+        nodes.into_iter().map(|p| (p.name.clone(), p)).collect()
+    }
+
+    #[test]
+    fn the_job_budget_is_split_between_waiting_builds() {
+        let mut budget = JobBudget::new(8, 4);
+        assert_eq!(budget.acquire(2, 2), Some(4));
+        assert_eq!(budget.acquire(1, 1), Some(4));
+        assert_eq!(budget.acquire(1, 1), None);
+        budget.release(4);
+        // The last build gets everything that is free.
+        assert_eq!(budget.acquire(1, 1), Some(4));
+        budget.release(4);
+        budget.release(4);
+        // More builds than jobs: one job each, until there are none.
+        for _ in 0..8 {
+            assert_eq!(budget.acquire(20, 20), Some(1));
+        }
+        assert_eq!(budget.acquire(12, 12), None);
+    }
+
+    #[test]
+    fn a_lone_ready_build_does_not_take_every_job() {
+        // One build is ready, but five more are still to come, and four
+        // may run at once: it gets a quarter of the jobs.
+        let mut budget = JobBudget::new(8, 4);
+        assert_eq!(budget.acquire(1, 6), Some(2));
+        // Two builds left, the cap is half of the jobs.
+        assert_eq!(budget.acquire(1, 2), Some(4));
+        assert_eq!(budget.acquire(1, 1), Some(2));
+        assert_eq!(budget.acquire(1, 1), None);
+        // With a single concurrent build, it may use every job.
+        let mut budget = JobBudget::new(8, 1);
+        assert_eq!(budget.acquire(1, 6), Some(8));
+    }
+
+    #[test]
+    fn the_budget_replaces_the_jobs_of_the_user_makeflags() {
+        assert_eq!(budget_makeflags(None, 4), "-j4");
+        assert_eq!(budget_makeflags(Some(""), 2), "-j2");
+        assert_eq!(budget_makeflags(Some("-j16"), 2), "-j2");
+        assert_eq!(budget_makeflags(Some("-k -j 16 -s"), 3), "-k -s -j3");
+        assert_eq!(budget_makeflags(Some("--jobs=8 -O"), 1), "-O -j1");
+        assert_eq!(budget_makeflags(Some("--jobs -k"), 2), "-k -j2");
+        assert_eq!(budget_makeflags(Some("CC=clang"), 2), "CC=clang -j2");
+    }
+
+    #[test]
+    fn a_zero_budget_still_builds() {
+        let mut budget = JobBudget::new(0, 4);
+        assert_eq!(budget.acquire(3, 3), Some(1));
+    }
+
+    /// `base` is at the bottom of the long chain of source packages, so it
+    /// starts first, even though `aaa` sorts before it and is ready as well.
+    #[test]
+    fn the_critical_path_starts_first() {
+        let pkgs = graph(vec![
+            node("aaa", false, &[]),
+            node("base", false, &[]),
+            node("mid", false, &["base"]),
+            node("top", false, &["mid"]),
+            node("bin", true, &[]),
+        ]);
+        let prio = critical_path_priorities(&pkgs);
+        assert_eq!(prio["top"], 10);
+        assert_eq!(prio["mid"], 20);
+        assert_eq!(prio["base"], 30);
+        assert_eq!(prio["aaa"], 10);
+        assert_eq!(prio["bin"], 1);
+        assert_eq!(start_order(&pkgs), ["base", "mid", "aaa", "top", "bin"]);
+    }
+
+    /// A binary that a source package waits for is on its critical path too.
+    #[test]
+    fn a_binary_under_a_source_build_goes_early() {
+        let pkgs = graph(vec![
+            node("src", false, &[]),
+            node("rcpp", true, &[]),
+            node("uses", false, &["rcpp"]),
+        ]);
+        assert_eq!(start_order(&pkgs), ["rcpp", "src", "uses"]);
+    }
+
+    #[test]
+    fn a_dependency_cycle_does_not_hang_the_priorities() {
+        let pkgs = graph(vec![node("a", false, &["b"]), node("b", false, &["a"])]);
+        let prio = critical_path_priorities(&pkgs);
+        assert_eq!(prio.len(), 2);
+    }
+
+    // ----------------------------------------------------------------
+    // Install options
+
+    #[test]
+    fn install_args_are_split_like_a_shell_would() {
+        assert_eq!(
+            split_args(r#"--no-test-load --configure-args="--with-a --with-b""#).unwrap(),
+            ["--no-test-load", "--configure-args=--with-a --with-b"]
+        );
+        assert_eq!(
+            split_args(r#"'it''s' a\ b "q\"q" "c:\d""#).unwrap(),
+            ["its", "a b", "q\"q", "c:\\d"]
+        );
+        assert_eq!(split_args("  \n ").unwrap(), Vec::<String>::new());
+        assert_eq!(split_args("''").unwrap(), [""]);
+        assert!(split_args("--configure-args='--with-a").is_err());
+        assert!(split_args("a\\").is_err());
+    }
+
+    #[test]
+    fn install_env_is_a_list_of_assignments() {
+        assert_eq!(
+            parse_env("ARROW_R_DEV=true LIBARROW_MINIMAL='a b' EMPTY=").unwrap(),
+            [
+                ("ARROW_R_DEV".to_string(), "true".to_string()),
+                ("LIBARROW_MINIMAL".to_string(), "a b".to_string()),
+                ("EMPTY".to_string(), "".to_string()),
+            ]
+        );
+        assert!(parse_env("ARROW_R_DEV").is_err());
+        assert!(parse_env("1X=1").is_err());
+        assert!(parse_env("A-B=1").is_err());
+    }
+
+    #[test]
+    fn install_options_come_from_config_fields() {
+        let fields = [
+            ("Package", "myproj"),
+            ("Config/rig/install-args/arrow", " --no-test-load "),
+            ("Config/rig/install-env/arrow", "ARROW_R_DEV=true"),
+            ("Config/rig/install-env/sf", "PROJ_LIB=/opt/proj"),
+            ("Config/Needs/website", "pkgdown"),
+        ];
+        let opts = InstallOptions::from_fields(fields.into_iter()).unwrap();
+        assert_eq!(opts.len(), 2);
+        assert_eq!(
+            opts["arrow"],
+            InstallOptions {
+                args: "--no-test-load".to_string(),
+                env: "ARROW_R_DEV=true".to_string(),
+            }
+        );
+        assert_eq!(opts["sf"].args, "");
+
+        let bad = [("Config/rig/install-env/sf", "PROJ_LIB")];
+        let err = InstallOptions::from_fields(bad.into_iter()).unwrap_err();
+        assert!(err.to_string().contains("Config/rig/install-env/sf"));
+    }
+
+    /// A fake `R` that records its arguments and one environment variable, so
+    /// the test can see what `R CMD INSTALL` would have got.
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn install_options_reach_r_cmd_install_and_the_log() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let lib = tmp.path().join("lib");
+        std::fs::create_dir_all(lib.join("foo")).unwrap();
+        std::fs::write(lib.join("foo/DESCRIPTION"), DESC).unwrap();
+        let r = tmp.path().join("R");
+        std::fs::write(
+            &r,
+            "#!/bin/sh\nfor a in \"$@\"; do echo \"arg: $a\"; done\necho \"env: $ARROW_R_DEV\"\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&r, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut pkg = info("foo", &tmp.path().join("foo_1.0.0.tar.gz"), None, &[]);
+        pkg.binary = false;
+        pkg.install_args = vec!["--configure-args=--with-a --with-b".to_string()];
+        pkg.install_env = vec![("ARROW_R_DEV".to_string(), "s3cr3t".to_string())];
+        let log = install_log_path(&lib, "foo");
+        r_cmd_install(&pkg, &lib, &r.to_string_lossy(), 2, &log)
+            .await
+            .unwrap();
+
+        let log = std::fs::read_to_string(&log).unwrap();
+        assert!(log.contains("rig: ") && log.contains(" CMD INSTALL -l "));
+        assert!(log.contains(" '--configure-args=--with-a --with-b' "));
+        assert!(log.contains("rig: MAKEFLAGS=-j2\n"));
+        assert!(log.contains("rig: ARROW_R_DEV is set, see Config/rig/install-env\n"));
+        assert!(log.contains("arg: --configure-args=--with-a --with-b\n"));
+        // The package gets the value, the rig lines of the log do not.
+        assert!(log.contains("env: s3cr3t\n"));
+        assert_eq!(log.matches("s3cr3t").count(), 1);
+    }
 }
@@ -22,14 +22,17 @@ mod dcf;
 mod download;
 mod escalate;
 mod hardcoded;
+mod http;
 mod install;
 mod library;
+mod lock;
 mod macos;
 mod output;
 mod pager;
 mod pak;
 mod pkg;
 mod platform;
+mod progress;
 mod proj;
 mod rds;
 mod renv;
@@ -38,6 +41,7 @@ mod repositories;
 mod resolve;
 mod run;
 mod rversion;
+mod shim;
 mod solver;
 mod textfmt;
 mod utils;
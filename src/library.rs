@@ -138,9 +138,14 @@ pub fn sc_library_get_list(
 pub fn sc_library_add(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
     let new: String = args.get_one::<String>("lib-name").unwrap().to_string();
     let rver = library_rver(args)?;
+    library_add(&new, &rver)
+}
+
+/// Create the library `new` for the installed R version `rver`.
+pub fn library_add(new: &str, rver: &str) -> Result<(), Box<dyn Error>> {
     let libs = sc_library_get_list(Some(rver.to_string()), true)?;
     let names: Vec<String> = libs.iter().map(|x| x.name.to_owned()).collect();
-    if names.contains(&new) {
+    if names.iter().any(|n| n == new) {
         OUTPUT.error(&format!("Library '{}' already exists for R {}.", new, rver));
         error!("Library '{}' already exists for R {}.", new, rver);
         bail!("Library '{}' already exists for R {}", new, rver);
@@ -159,7 +164,7 @@ pub fn sc_library_add(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
             bail!("Internal error, no main library for R {}", rver)
         }
         Some(main) => {
-            let dir = main.as_path().join("__".to_string() + &new);
+            let dir = main.as_path().join("__".to_string() + new);
             std::fs::create_dir_all(&dir)?;
         }
     };
@@ -338,6 +343,20 @@ pub fn sc_library_set_default(name: &str, rver: Option<&str>) -> Result<(), Box<
     Ok(())
 }
 
+/// The number of `R_LIBS_USER` block markers in the system `Rprofile` of an R
+/// version: 2 if the library setup is there, 0 if it is not, anything else if
+/// the file is broken.
+pub fn library_rprofile_markers(rver: &str) -> Result<usize, Box<dyn Error>> {
+    let rprofile = get_system_profile(rver)?;
+    count_rprofile_markers(&read_lines(&rprofile)?)
+}
+
+fn count_rprofile_markers(lines: &[String]) -> Result<usize, Box<dyn Error>> {
+    let re_start = Regex::new("^## rig R_LIBS_USER start")?;
+    let re_end = Regex::new("^## rig R_LIBS_USER end")?;
+    Ok(grep_lines(&re_start, lines).len() + grep_lines(&re_end, lines).len())
+}
+
 pub fn library_update_rprofile(rver: &str) -> Result<(), Box<dyn Error>> {
     let rprofile = get_system_profile(rver)?;
     let lines = match read_lines(&rprofile) {
@@ -358,11 +377,7 @@ pub fn library_update_rprofile(rver: &str) -> Result<(), Box<dyn Error>> {
             )
         }
     };
-    let re_start = Regex::new("^## rig R_LIBS_USER start")?;
-    let re_end = Regex::new("^## rig R_LIBS_USER end")?;
-    let idx_start = grep_lines(&re_start, &lines);
-    let idx_end = grep_lines(&re_end, &lines);
-    let nmarkers = idx_start.len() + idx_end.len();
+    let nmarkers = count_rprofile_markers(&lines)?;
     if nmarkers != 0 && nmarkers != 2 {
         OUTPUT.error(&format!(
             "Invalid system Rprofile file at {}. Must include a \
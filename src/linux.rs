@@ -265,71 +265,17 @@ pub fn sc_add(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
         println!("{}", uid);
     }
 
-    // Decide which build to install.
-    let platform = get_platform(args)?;
-    let mut install_platform = platform.clone();
-    let version = match get_resolve_for(args, &platform) {
-        Ok(v) => v,
-        Err(e) => {
-            if mode == Mode::Admin
-                && !platform_explicitly_set(args)
-                && !is_portable_platform(&platform)
-            {
-                let portable = user_mode_platform()?;
-                OUTPUT.warn(&format!(
-                    "No distro-specific R build for platform `{}`, \
-                     falling back to the portable build `{}`.",
-                    platform, portable
-                ));
-                warn!(
-                    "No build for platform {}, falling back to portable build {}",
-                    platform, portable
-                );
-                install_platform = portable.clone();
-                get_resolve_for(args, &portable)?
-            } else {
-                return Err(e);
-            }
-        }
-    };
-    let alias = get_alias(args);
-    let ver = version.version.to_owned();
-    let verstr = match ver {
-        Some(ref x) => x,
-        None => "???",
-    };
-
-    let url: String = match &version.url {
-        Some(s) => s.to_string(),
-        None => {
-            OUTPUT.error(&format!(
-                "Cannot find a download url for R version {}",
-                verstr
-            ));
-            error!("Cannot find a download url for R version {}", verstr);
-            bail!("Cannot find a download url for R version {}", verstr)
-        }
-    };
-
-    let filename = basename(&url).unwrap_or("foo");
-    let tmp_dir = ensure_download_dir()?;
-    let target = tmp_dir.join(filename);
-    if target.exists() && not_too_old(&target) {
-        OUTPUT.success(&format!("{} is cached at {}", filename, target.display()));
-        info!("{} is cached at {}", filename, target.display());
-    } else {
-        OUTPUT.status(&format!("Downloading {} -> {}", url, target.display()));
-        info!("Downloading {} -> {}", url, target.display());
-        let client = &reqwest::Client::new();
-        download_file(client, &url, target.as_os_str())?;
-    }
-
-    let portable = is_portable_archive(&target);
-    let dirname = if portable {
-        safe_user_install(&target, &version, &install_platform)?
+    let (dirname, portable, alias) = if args.get_flag("from-source") {
+        let build = crate::rbuild::build_r_from_source(args)?;
+        // A debug or sanitizer build must not take over `R-release`.
+        let alias = match build.profile.suffix() {
+            None => get_alias(args),
+            Some(_) => None,
+        };
+        (build.dirname, false, alias)
     } else {
-        let platform = detect_platform()?;
-        add_package(target.as_os_str(), &platform)?
+        let (dirname, portable) = add_binary_build(args, mode)?;
+        (dirname, portable, get_alias(args))
     };
 
     set_default_if_none(dirname.to_string())?;
@@ -388,6 +334,71 @@ pub fn sc_add(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+// Download and install a prebuilt R: a distro package, or a portable build.
+// Returns the name of the installation and whether it is portable.
+fn add_binary_build(args: &ArgMatches, mode: Mode) -> Result<(String, bool), Box<dyn Error>> {
+    // Decide which build to install.
+    let platform = get_platform(args)?;
+    let mut install_platform = platform.clone();
+    let version = match get_resolve_for(args, &platform) {
+        Ok(v) => v,
+        Err(e) => {
+            if mode == Mode::Admin
+                && !platform_explicitly_set(args)
+                && !is_portable_platform(&platform)
+            {
+                let portable = user_mode_platform()?;
+                OUTPUT.warn(&format!(
+                    "No distro-specific R build for platform `{}`, \
+                     falling back to the portable build `{}`.",
+                    platform, portable
+                ));
+                warn!(
+                    "No build for platform {}, falling back to portable build {}",
+                    platform, portable
+                );
+                install_platform = portable.clone();
+                get_resolve_for(args, &portable)?
+            } else {
+                return Err(e);
+            }
+        }
+    };
+    let ver = version.version.to_owned();
+    let verstr = match ver {
+        Some(ref x) => x,
+        None => "???",
+    };
+
+    let url: String = match &version.url {
+        Some(s) => s.to_string(),
+        None => {
+            OUTPUT.error(&format!(
+                "Cannot find a download url for R version {}",
+                verstr
+            ));
+            error!("Cannot find a download url for R version {}", verstr);
+            bail!("Cannot find a download url for R version {}", verstr)
+        }
+    };
+
+    let filename = basename(&url).unwrap_or("foo");
+    let tmp_dir = ensure_download_dir()?;
+    let target = tmp_dir.join(filename);
+    let sha256 = installer_sha256(&url, version.sha256.as_deref());
+    download_verified(&url, &target, sha256.as_deref())?;
+
+    let portable = is_portable_archive(&target);
+    let dirname = if portable {
+        safe_user_install(&target, &version, &install_platform)?
+    } else {
+        let platform = detect_platform()?;
+        add_package(target.as_os_str(), &platform)?
+    };
+
+    Ok((dirname, portable))
+}
+
 fn select_linux_tools(platform: &OsVersion) -> Result<LinuxTools, Box<dyn Error>> {
     if platform.distro.as_deref() == Some("debian") || platform.distro.as_deref() == Some("ubuntu")
     {
@@ -936,7 +947,7 @@ fn shell_escape_dquoted(path: &str) -> String {
     out
 }
 
-fn patch_ldpaths(dest: &Path, libdir: &Path) -> Result<(), Box<dyn Error>> {
+fn ldpaths_files(dest: &Path) -> Vec<PathBuf> {
     let etc = dest.join("lib").join("R").join("etc");
     let mut files = vec![etc.join("ldpaths")];
     // `R_ARCH` is empty on the builds rig installs, but a sub-architecture
@@ -949,9 +960,12 @@ fn patch_ldpaths(dest: &Path, libdir: &Path) -> Result<(), Box<dyn Error>> {
             }
         }
     }
+    files
+}
 
+fn patch_ldpaths(dest: &Path, libdir: &Path) -> Result<(), Box<dyn Error>> {
     let dir = libdir.to_string_lossy().to_string();
-    for file in files {
+    for file in ldpaths_files(dest) {
         if !file.exists() {
             debug!("No {}, not patching LD_LIBRARY_PATH", file.display());
             continue;
@@ -969,6 +983,38 @@ fn patch_ldpaths(dest: &Path, libdir: &Path) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+/// The `ldpaths` files of an R installation that have an `LD_LIBRARY_PATH`
+/// block from rig that does nothing: the installation is not a portable one,
+/// or the library directory the block adds is gone. Such a block is left over
+/// from an earlier installation into the same directory.
+pub fn stale_ldpaths(rver: &str) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let dest = Path::new(&get_r_root()?).join(rver);
+    let portable = read_install_platform(&dest).is_some();
+    let libdir_ok = get_user_lib_dir()?.is_dir();
+    if portable && libdir_ok {
+        return Ok(vec![]);
+    }
+    let mut stale = vec![];
+    for file in ldpaths_files(&dest) {
+        let existing = match std::fs::read_to_string(&file) {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+        if existing.lines().any(|l| l.trim() == LDPATHS_BLOCK_START) {
+            stale.push(file);
+        }
+    }
+    Ok(stale)
+}
+
+pub fn unpatch_ldpaths(file: &Path) -> Result<(), Box<dyn Error>> {
+    let existing = std::fs::read_to_string(file)?;
+    let out = remove_fenced_block(&existing, LDPATHS_BLOCK_START, LDPATHS_BLOCK_END);
+    debug!("Removing the LD_LIBRARY_PATH block from {}", file.display());
+    std::fs::write(file, out)?;
+    Ok(())
+}
+
 fn setup_user_libs(dest: &Path) -> Result<(), Box<dyn Error>> {
     let libdir = get_user_lib_dir()?;
     let libs = dest.join("lib").join("R").join("lib").join(".libs");
@@ -1033,7 +1079,7 @@ pub fn read_install_platform(dir: &Path) -> Option<String> {
 }
 
 // Extract a gzip-compressed tarball into `dest`, in-process (no external `tar`).
-fn unpack_tar_gz(archive: &Path, dest: &Path) -> Result<(), Box<dyn Error>> {
+pub fn unpack_tar_gz(archive: &Path, dest: &Path) -> Result<(), Box<dyn Error>> {
     let file = std::fs::File::open(archive)?;
     let decoder = flate2::read::GzDecoder::new(file);
     let mut ar = tar::Archive::new(decoder);
@@ -1168,6 +1214,12 @@ pub fn sc_system_make_links() -> Result<(), Box<dyn Error>> {
     let base = Path::new(&rroot);
     let binpath = get_r_binpath()?;
 
+    // Turn older `R` and `Rscript` links into shims, once there is an R for
+    // them to run.
+    if mode == Mode::User && sc_get_default()?.is_some() {
+        crate::shim::make_shims(Path::new(&binary_dir))?;
+    }
+
     // Create new links
     for ver in vers {
         let linkfile = Path::new(&binary_dir).join("R-".to_string() + &ver);
@@ -1332,15 +1384,20 @@ pub fn sc_get_list() -> Result<Vec<String>, Box<dyn Error>> {
 }
 
 // Create (or refresh) the `R` and `Rscript` symlinks in the binary directory.
-// They point at the current default R through the `current` symlink (e.g.
-// `~/.local/share/rig/r/current/bin/R` in user mode), so they keep working as
-// the default version changes. In user mode the binary directory is
-// `~/.local/bin`, which is how R ends up on the user's PATH.
+// In user mode the binary directory is `~/.local/bin`, which is how R ends up
+// on the user's PATH, and the links are shims that point at rig itself, which
+// then selects an R version for the working directory, see `crate::shim`.
+// Otherwise they point at the current default R through the `current` symlink
+// (e.g. `/opt/R/current/bin/R`), so they keep working as the default version
+// changes.
 pub fn make_current_r_links() -> Result<(), Box<dyn Error>> {
     let binary_dir = get_binary_dir()?;
     if get_mode()? == Mode::User {
         std::fs::create_dir_all(&binary_dir)?;
         check_local_bin_path()?;
+        if crate::shim::make_shims(Path::new(&binary_dir))? {
+            return Ok(());
+        }
     }
 
     let cur = get_r_current()?;
@@ -2021,7 +2078,7 @@ fn download_cacert(force: bool) -> Result<PathBuf, Box<dyn Error>> {
         cert.display()
     ));
     info!("Downloading CA bundle {} -> {}", CACERT_URL, cert.display());
-    let client = &reqwest::Client::new();
+    let client = &crate::http::client()?;
     download_file(client, CACERT_URL, cert.as_os_str())?;
     Ok(cert)
 }
@@ -2060,6 +2117,14 @@ fn render_renviron_cert(existing: &str, cert: &str) -> String {
 // CA-bundle block and the base `Rprofile` fontconfig block, so re-running with
 // a new path updates the block in place instead of duplicating it.
 fn render_fenced_block(existing: &str, start: &str, end: &str, body: &str) -> String {
+    let mut out = remove_fenced_block(existing, start, end);
+    out.push_str(&format!("{}\n{}\n{}\n", start, body, end));
+    out
+}
+
+// `existing` without the block between `start` and `end`, and without trailing
+// blank lines, so they do not accumulate across re-runs.
+fn remove_fenced_block(existing: &str, start: &str, end: &str) -> String {
     let mut kept: Vec<String> = Vec::new();
     let mut in_block = false;
     for line in existing.lines() {
@@ -2070,7 +2135,6 @@ fn render_fenced_block(existing: &str, start: &str, end: &str, body: &str) -> St
             _ => {}
         }
     }
-    // Trim trailing blank lines so they do not accumulate across re-runs.
     while matches!(kept.last(), Some(l) if l.trim().is_empty()) {
         kept.pop();
     }
@@ -2079,7 +2143,6 @@ fn render_fenced_block(existing: &str, start: &str, end: &str, body: &str) -> St
     if !out.is_empty() {
         out.push('\n');
     }
-    out.push_str(&format!("{}\n{}\n{}\n", start, body, end));
     out
 }
 
@@ -2202,7 +2265,7 @@ fn download_fonts(force: bool) -> Result<(), Box<dyn Error>> {
         fonts.display()
     ));
     info!("Downloading fallback fonts {} -> {}", url, target.display());
-    let client = &reqwest::Client::new();
+    let client = &crate::http::client()?;
     if let Err(e) = download_file(client, &url, target.as_os_str()) {
         bail!(
             "Cannot download the fallback fonts from {}: {}.\n        \
@@ -2257,28 +2320,6 @@ fn download_fonts(force: bool) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-fn check_sha256(path: &Path, expected: &str) -> Result<(), Box<dyn Error>> {
-    use sha2::{Digest, Sha256};
-    let mut file = std::fs::File::open(path)?;
-    let mut hasher = Sha256::new();
-    std::io::copy(&mut file, &mut hasher)?;
-    let got = hasher
-        .finalize()
-        .iter()
-        .map(|b| format!("{:02x}", b))
-        .collect::<String>();
-    if got != expected {
-        bail!(
-            "Checksum mismatch for {}: expected {}, got {}",
-            path.display(),
-            expected,
-            got
-        );
-    }
-    debug!("Checksum of {} is {}, as expected", path.display(), got);
-    Ok(())
-}
-
 // The `fonts.conf` rig installs. It is self-contained on purpose: it does not
 // include the host's fontconfig configuration, whose syntax may be newer than
 // the libfontconfig bundled in the R build understands. It does list the
@@ -2395,6 +2436,33 @@ fn setup_user_fonts(rver: &str, with_fonts: bool, force: bool) -> Result<(), Box
     Ok(())
 }
 
+/// What is wrong with the fontconfig setup of a portable R installation, if
+/// anything: the `fonts.conf` file is missing, or the R version does not use
+/// it. `None` for other installations, they use the system's fontconfig.
+pub fn check_fontconfig(rver: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let dest = Path::new(&get_r_root()?).join(rver);
+    if read_install_platform(&dest).is_none() {
+        return Ok(None);
+    }
+    let fonts_conf = get_fonts_conf_path()?;
+    if !fonts_conf.exists() {
+        return Ok(Some(format!("{} is missing", fonts_conf.display())));
+    }
+    let rprofile = get_system_profile(rver)?;
+    let existing = std::fs::read_to_string(&rprofile).unwrap_or_default();
+    if !existing.lines().any(|l| l.trim() == FONTS_BLOCK_START) {
+        return Ok(Some(format!(
+            "{} does not set FONTCONFIG_FILE",
+            rprofile.display()
+        )));
+    }
+    Ok(None)
+}
+
+pub fn fix_fontconfig(rver: &str) -> Result<(), Box<dyn Error>> {
+    setup_user_fonts(rver, true, false)
+}
+
 pub fn set_cert_envvar() {
     match std::env::var("SSL_CERT_FILE") {
         Ok(_) => {
@@ -2616,17 +2684,6 @@ Usage: /lib/ld-musl-x86_64.so.1 [options] [--] pathname\n";
         assert_eq!(third, second);
     }
 
-    #[test]
-    fn check_sha256_accepts_the_right_digest() {
-        let dir = tempfile::tempdir().unwrap();
-        let file = dir.path().join("f");
-        std::fs::write(&file, b"hello").unwrap();
-        // sha256("hello")
-        let sha = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
-        assert!(check_sha256(&file, sha).is_ok());
-        assert!(check_sha256(&file, &"0".repeat(64)).is_err());
-    }
-
     // Create the bundled library directory of a portable R installation, with
     // the given library file names in it.
     fn write_bundled_libs(root: &Path, names: &[&str]) -> PathBuf {
@@ -2884,6 +2941,18 @@ Usage: /lib/ld-musl-x86_64.so.1 [options] [--] pathname\n";
         assert!(patch_ldpaths(dir.path(), libdir).is_ok());
     }
 
+    #[test]
+    fn unpatch_ldpaths_restores_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("ldpaths");
+        std::fs::write(&file, render_ldpaths_lib_dir(LDPATHS, "/old/lib")).unwrap();
+        unpatch_ldpaths(&file).unwrap();
+        let out = std::fs::read_to_string(&file).unwrap();
+        assert!(!out.contains(LDPATHS_BLOCK_START));
+        assert!(!out.contains("/old/lib"));
+        assert_eq!(out.trim_end(), LDPATHS.trim_end());
+    }
+
     #[test]
     fn user_mode_install_spec_uses_alias_when_available() {
         let aliases = vec![
@@ -0,0 +1,291 @@
+//! Advisory file locks, so that rig processes that run at the same time, e.g.
+//! several `rig pkg install` jobs on a CI machine, do not write the same file
+//! at once.
+//!
+//! A lock is held on a lock file in `<cache>/locks`, not on the file it
+//! protects: the protected files are replaced by renaming, or are SQLite
+//! databases, and `rig cache clean` must not delete a lock that is held. The
+//! lock file holds the process id of its holder, for the message of the
+//! waiting processes. The lock is released when the `FileLock` is dropped,
+//! or when the process exits, however it exits.
+//!
+//! The locks are advisory: they only keep rig processes apart.
+
+use std::error::Error;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+use simple_error::bail;
+
+use crate::cache::get_cache_dir;
+use crate::output::OUTPUT;
+use crate::utils::calculate_hash;
+
+/// How long to wait for a lock, unless configured otherwise.
+const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// How often a waiting process tries again.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+static LOCK_TIMEOUT: OnceLock<Duration> = OnceLock::new();
+
+/// Set the lock timeout from `--lock-timeout`, it overrides the environment
+/// variable and the config.
+pub fn set_lock_timeout(timeout: Duration) {
+    let _ = LOCK_TIMEOUT.set(timeout);
+}
+
+pub fn get_lock_timeout() -> Result<Duration, Box<dyn Error>> {
+    if let Some(timeout) = LOCK_TIMEOUT.get() {
+        return Ok(*timeout);
+    }
+    let timeout = if let Ok(val) = std::env::var("RIG_LOCK_TIMEOUT") {
+        match parse_lock_timeout(&val) {
+            Some(t) => t,
+            None => bail!(
+                "Invalid RIG_LOCK_TIMEOUT value: '{}', expected a number of seconds",
+                val
+            ),
+        }
+    } else if let Some(val) = crate::config::get_global_config_value("lock-timeout")? {
+        match parse_lock_timeout(&val) {
+            Some(t) => t,
+            None => bail!(
+                "Invalid 'lock-timeout' in rig config: '{}', expected a number of seconds",
+                val
+            ),
+        }
+    } else {
+        DEFAULT_LOCK_TIMEOUT
+    };
+    let _ = LOCK_TIMEOUT.set(timeout);
+    Ok(timeout)
+}
+
+pub fn parse_lock_timeout(x: &str) -> Option<Duration> {
+    x.trim().parse::<u64>().ok().map(Duration::from_secs)
+}
+
+/// The lock file of `path`. The name keeps the file name of `path`, to make
+/// the locks directory readable, and a hash of the whole path, to make it
+/// unique.
+pub fn lock_file_for(path: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let path = std::path::absolute(path)?;
+    let name = path
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let hash = calculate_hash(&path.to_string_lossy());
+    Ok(get_cache_dir()?
+        .join("locks")
+        .join(format!("{}-{}.lock", name, &hash[..16])))
+}
+
+/// A held lock, released on drop.
+#[derive(Debug)]
+pub struct FileLock {
+    // Closing the file releases the lock.
+    _file: Option<File>,
+}
+
+impl FileLock {
+    /// Lock `path` (see `lock_file_for`), waiting for other processes to
+    /// release it, up to the lock timeout.
+    pub fn acquire(path: &Path) -> Result<FileLock, Box<dyn Error>> {
+        let mut waiter = Waiter::new(path)?;
+        loop {
+            match waiter.try_acquire()? {
+                Some(lock) => return Ok(lock),
+                None => std::thread::sleep(POLL_INTERVAL),
+            }
+        }
+    }
+
+    /// Like `acquire`, but does not block the async runtime while waiting.
+    pub async fn acquire_async(path: &Path) -> Result<FileLock, Box<dyn Error>> {
+        let mut waiter = Waiter::new(path)?;
+        loop {
+            match waiter.try_acquire()? {
+                Some(lock) => return Ok(lock),
+                None => tokio::time::sleep(POLL_INTERVAL).await,
+            }
+        }
+    }
+}
+
+struct Waiter {
+    path: PathBuf,
+    lock_file: PathBuf,
+    // Moved into the `FileLock` once locked.
+    file: Option<File>,
+    start: Instant,
+    timeout: Duration,
+    announced: bool,
+}
+
+impl Waiter {
+    fn new(path: &Path) -> Result<Waiter, Box<dyn Error>> {
+        let lock_file = lock_file_for(path)?;
+        Waiter::with_lock_file(path, lock_file, get_lock_timeout()?)
+    }
+
+    fn with_lock_file(
+        path: &Path,
+        lock_file: PathBuf,
+        timeout: Duration,
+    ) -> Result<Waiter, Box<dyn Error>> {
+        if let Some(parent) = lock_file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_file)?;
+        Ok(Waiter {
+            path: path.to_path_buf(),
+            lock_file,
+            file: Some(file),
+            start: Instant::now(),
+            timeout,
+            announced: false,
+        })
+    }
+
+    /// `Some` with the lock, or `None` if another process holds it and there
+    /// is still time to wait.
+    fn try_acquire(&mut self) -> Result<Option<FileLock>, Box<dyn Error>> {
+        let attempt = match &self.file {
+            Some(file) => file.try_lock(),
+            None => bail!("Lock on {} is already taken", self.path.display()),
+        };
+        match attempt {
+            Ok(()) => {
+                debug!("Locked {}", self.path.display());
+                let file = self.file.take();
+                if let Some(file) = &file {
+                    // Not fatal, the pid is only for the message of others.
+                    let _ = file.set_len(0);
+                    let _ = write!(&*file, "{}", std::process::id());
+                }
+                Ok(Some(FileLock { _file: file }))
+            }
+            Err(TryLockError::WouldBlock) => {
+                self.wait()?;
+                Ok(None)
+            }
+            Err(TryLockError::Error(err)) if err.kind() == std::io::ErrorKind::Unsupported => {
+                // E.g. some network file systems. Going on without the lock is
+                // what rig did before it had locks.
+                warn!(
+                    "Cannot lock {}, file locking is not supported: {}",
+                    self.lock_file.display(),
+                    err
+                );
+                Ok(Some(FileLock { _file: None }))
+            }
+            Err(TryLockError::Error(err)) => {
+                bail!("Cannot lock {}: {}", self.lock_file.display(), err)
+            }
+        }
+    }
+
+    fn wait(&mut self) -> Result<(), Box<dyn Error>> {
+        let holder = match self.holder() {
+            Some(pid) => format!("held by pid {}", pid),
+            None => "held by another process".to_string(),
+        };
+        if self.start.elapsed() >= self.timeout {
+            bail!(
+                "Timed out after {}s waiting for lock on {}, {}",
+                self.timeout.as_secs(),
+                self.path.display(),
+                holder
+            );
+        }
+        if !self.announced {
+            self.announced = true;
+            OUTPUT.status(&format!(
+                "Waiting for lock {} ({})",
+                holder,
+                self.path.display()
+            ));
+            info!("Waiting for lock {} ({})", holder, self.path.display());
+        }
+        Ok(())
+    }
+
+    // On Windows the holder's lock keeps others from reading the file, so the
+    // pid is unknown there.
+    fn holder(&self) -> Option<u32> {
+        std::fs::read_to_string(&self.lock_file)
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn waiter(dir: &Path, timeout: u64) -> Waiter {
+        Waiter::with_lock_file(
+            &dir.join("packages.db"),
+            dir.join("locks").join("packages.db.lock"),
+            Duration::from_secs(timeout),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn lock_is_exclusive_until_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock = waiter(dir.path(), 10).try_acquire().unwrap();
+        assert!(lock.is_some());
+
+        let mut other = waiter(dir.path(), 10);
+        assert!(other.try_acquire().unwrap().is_none());
+        assert!(other.announced);
+        assert_eq!(other.holder(), Some(std::process::id()));
+
+        drop(lock);
+        assert!(other.try_acquire().unwrap().is_some());
+    }
+
+    #[test]
+    fn waiting_times_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let _lock = waiter(dir.path(), 10).try_acquire().unwrap();
+        let err = waiter(dir.path(), 0).try_acquire().unwrap_err();
+        let msg = err.to_string();
+        assert!(msg.contains("Timed out"));
+        assert!(msg.contains(&format!("held by pid {}", std::process::id())));
+    }
+
+    #[test]
+    fn lock_files_are_per_path() {
+        let a = lock_file_for(Path::new("/cache/packages.db")).unwrap();
+        let b = lock_file_for(Path::new("/other/packages.db")).unwrap();
+        assert_ne!(a, b);
+        assert!(a
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("packages.db-"));
+        assert_eq!(a.parent().unwrap().file_name().unwrap(), "locks");
+    }
+
+    #[test]
+    fn lock_timeout_is_seconds() {
+        assert_eq!(parse_lock_timeout("30"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_lock_timeout(" 0 "), Some(Duration::ZERO));
+        assert_eq!(parse_lock_timeout("1m"), None);
+    }
+}
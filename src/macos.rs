@@ -114,6 +114,11 @@ pub fn get_r_current() -> Result<String, Box<dyn Error>> {
 }
 
 pub fn sc_add(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    if args.get_flag("from-source") {
+        OUTPUT.error("`--from-source` is only supported on Linux.");
+        error!("`--from-source` is only supported on Linux");
+        bail!("`--from-source` is only supported on Linux");
+    }
     if get_mode()? == crate::utils::Mode::Admin {
         escalate("adding new R versions")?;
     }
@@ -151,18 +156,9 @@ pub fn sc_add(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
     let filename = prefix + "-" + basename(&url).unwrap_or("foo");
     let tmp_dir = ensure_download_dir()?;
     let target = tmp_dir.join(&filename);
-    let cache = target.exists() && not_too_old(&target);
     let target_str = target.to_owned().into_os_string();
-    let target_dsp = target.display();
-    if cache {
-        OUTPUT.success(&format!("{} is cached at {}", filename, target_dsp));
-        info!("{} is cached at {}", filename, target_dsp);
-    } else {
-        OUTPUT.status(&format!("Downloading {} -> {}", url, target_dsp));
-        info!("Downloading {} -> {}", url, target_dsp);
-        let client = &reqwest::Client::new();
-        download_file(client, &url, &target_str)?;
-    }
+    let sha256 = installer_sha256(&url, version.sha256.as_deref());
+    download_verified(&url, &target, sha256.as_deref())?;
 
     sc_system_forget()?;
 
@@ -756,6 +752,12 @@ pub fn sc_system_make_links() -> Result<(), Box<dyn Error>> {
     OUTPUT.status("Updating R-* quick links (as needed)");
     info!("Updating R-* quick links (as needed)");
 
+    // Turn older `R` and `Rscript` links into shims, once there is an R for
+    // them to run.
+    if mode == crate::utils::Mode::User && sc_get_default()?.is_some() {
+        crate::shim::make_shims(Path::new(&binary_dir))?;
+    }
+
     // https://github.com/r-lib/rig/issues/197
     let old_umask = umask(Mode::from_bits(0o022).unwrap());
 
@@ -1861,8 +1863,21 @@ pub fn sc_set_default(ver: &str) -> Result<(), Box<dyn Error>> {
     }
     check_local_bin_path()?;
 
+    // In user mode `R` and `Rscript` are shims that select an R version for
+    // the working directory, see `crate::shim`. Otherwise, and if they cannot
+    // be, they point at the default R.
+    let shims = get_mode()? == crate::utils::Mode::User
+        && match crate::shim::make_shims(Path::new(&binary_dir)) {
+            Ok(made) => made,
+            Err(e) => {
+                OUTPUT.warn(&format!("Cannot create R shims in {}: {}", binary_dir, e));
+                warn!("Cannot create R shims in {}: {}", binary_dir, e);
+                false
+            }
+        };
+
     let r = Path::new(&binary_dir).join("R");
-    if !r.exists() {
+    if !shims && !r.exists() {
         debug!("Creating {}", r.display());
         let tgt = Path::new(&get_r_default_bindir()?).join("R");
         if let Err(e) = std::os::unix::fs::symlink(&tgt, &r) {
@@ -1872,7 +1887,7 @@ pub fn sc_set_default(ver: &str) -> Result<(), Box<dyn Error>> {
     }
 
     let rscript = Path::new(&binary_dir).join("Rscript");
-    if !rscript.exists() {
+    if !shims && !rscript.exists() {
         debug!("Creating {}", rscript.display());
         let tgt = Path::new(&get_r_default_bindir()?).join("Rscript");
         if let Err(e) = std::os::unix::fs::symlink(&tgt, &rscript) {
@@ -4,7 +4,7 @@ use std::io::{IsTerminal, Write};
 use std::sync::{Arc, Mutex};
 
 use clap::ArgMatches;
-use log::{error, info, Level, LevelFilter};
+use log::{debug, error, info, warn, Level, LevelFilter};
 use owo_colors::OwoColorize;
 use simple_error::*;
 use tabular::*;
@@ -31,25 +31,33 @@ use windows::*;
 mod linux;
 #[cfg(target_os = "linux")]
 use linux::*;
+#[cfg(target_os = "linux")]
+mod rbuild;
 
 use resolve::*;
 
 mod alias;
+mod apply;
+mod audit;
 mod cache;
 mod common;
 mod config;
 mod dcf;
 mod dirs;
+mod doctor;
 mod download;
 mod hardcoded;
+mod http;
 mod install;
 mod library;
+mod lock;
 mod output;
 mod pager;
 mod pak;
 mod pkg;
 mod platform;
 mod ppm;
+mod progress;
 mod proj;
 mod rds;
 mod renv;
@@ -58,10 +66,13 @@ mod repositories;
 mod resolve;
 mod run;
 mod rversion;
+mod sccache;
+mod shim;
 mod solver;
 mod sysreqs;
 mod test;
 mod textfmt;
+mod upgrade;
 mod utils;
 
 use cache::get_logs_dir;
@@ -88,6 +99,11 @@ fn main() {
 }
 
 fn main_() -> i32 {
+    // Started through an `R` or `Rscript` link: run R, not rig.
+    if let Some(prog) = shim::shim_program() {
+        return shim::run_shim(prog);
+    }
+
     let args = parse_args();
 
     // -- set up logger output --------------------------------------------
@@ -224,6 +240,12 @@ fn main_() -> i32 {
         }
     }
 
+    if let Some(timeout) = args.get_one::<u64>("lock-timeout") {
+        lock::set_lock_timeout(std::time::Duration::from_secs(*timeout));
+        // For the rig processes this one starts, e.g. in `rig upgrade`.
+        std::env::set_var("RIG_LOCK_TIMEOUT", timeout.to_string());
+    }
+
     #[cfg(target_os = "linux")]
     set_cert_envvar();
 
@@ -251,11 +273,17 @@ fn main_() -> i32 {
 fn main__(args: &ArgMatches) -> Result<i32, Box<dyn Error>> {
     let mut retval: i32 = 0;
     match args.subcommand() {
-        Some(("add", sub)) => sc_add(sub)?,
+        Some(("add", sub)) => sc_add_pinned(sub)?,
         Some(("default", sub)) => sc_default(sub, args)?,
+        Some(("pin", sub)) => sc_pin(sub, args)?,
         Some(("list", sub)) => sc_list(sub, args)?,
         Some(("proj", sub)) => sc_proj(sub, args)?,
         Some(("rm", sub)) => sc_rm(sub)?,
+        Some(("upgrade", sub)) => upgrade::sc_upgrade(sub, args)?,
+        Some(("apply", sub)) => apply::sc_apply(sub, args)?,
+        Some(("export", sub)) => apply::sc_export(sub, args)?,
+        Some(("doctor", sub)) => retval = doctor::sc_doctor(sub, args)?,
+        Some(("audit", sub)) => retval = audit::sc_audit(sub, args)?,
         Some(("system", sub)) => sc_system(sub, args)?,
         Some(("rtools", sub)) => sc_system_rtools(sub, args)?,
         Some(("pkg", sub)) => sc_pkg(sub, args)?,
@@ -267,6 +295,7 @@ fn main__(args: &ArgMatches) -> Result<i32, Box<dyn Error>> {
         Some(("config", sub)) => crate::config::sc_config(sub, args)?,
         Some(("sysreqs", sub)) => sc_sysreqs(sub, args)?,
         Some(("available", sub)) => sc_available(sub, args)?,
+        Some(("cache", sub)) => sccache::sc_cache(sub, args)?,
         Some(("run", sub)) => retval = sc_run(sub, args)?,
         Some(("test", sub)) => sc_test(sub, args)?,
         _ => (), // unreachable
@@ -344,6 +373,7 @@ fn sc_list(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn Error
     struct InstalledVersionWithDefault<'a> {
         name: &'a str,
         default: bool,
+        selected: bool,
         version: &'a Option<String>,
         aliases: &'a Vec<String>,
         path: &'a Option<String>,
@@ -356,6 +386,45 @@ fn sc_list(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn Error
         Some(v) => v,
     };
 
+    // The version the working directory selects, if it selects one. Without
+    // a request it is the default, which is marked already. A version file
+    // that cannot be read only loses the mark, it does not fail the listing.
+    let request = std::env::current_dir()
+        .map_err(|e| e.into())
+        .and_then(|dir| shim::find_version_request(&dir));
+    let request = match request {
+        Ok(request) => request,
+        Err(err) => {
+            OUTPUT.warn(&format!(
+                "Cannot find the R version of the working directory: {}",
+                err
+            ));
+            warn!(
+                "Cannot find the R version of the working directory: {}",
+                err
+            );
+            None
+        }
+    };
+    let selected = match request {
+        None => None,
+        Some((request, source)) => match shim::pick_version(&request, &vers, Some(&def)) {
+            Some(v) => Some((v.name.clone(), source)),
+            None => {
+                OUTPUT.warn(&format!(
+                    "R {} is not installed (asked for by {})",
+                    request, source
+                ));
+                warn!("R {} is not installed (asked for by {})", request, source);
+                None
+            }
+        },
+    };
+    let is_selected = |name: &str| match &selected {
+        Some((sel, _)) => sel == name,
+        None => def == name,
+    };
+
     if args.get_flag("plain") {
         if args.get_flag("json") || mainargs.get_flag("json") {
             OUTPUT.error("Error: --plain cannot be used with --json");
@@ -371,6 +440,7 @@ fn sc_list(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn Error
             .map(|ver| InstalledVersionWithDefault {
                 name: &ver.name,
                 default: def == ver.name,
+                selected: is_selected(&ver.name),
                 version: &ver.version,
                 aliases: &ver.aliases,
                 path: &ver.path,
@@ -379,11 +449,22 @@ fn sc_list(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn Error
             .collect();
         println!("{}", serde_json::to_string_pretty(&json_vers)?);
     } else {
-        let mut tab = Table::new("{:<} {:<}  {:<}  {:<}");
-        tab.add_row(row!["*", "name", "version", "aliases"]);
+        let mut tab = Table::new("{:<} {:<}  {:<}  {:<}  {:<}");
+        tab.add_row(row!["*", "name", "version", "aliases", ""]);
         tab.add_heading("------------------------------------------");
         for ver in vers {
             let dflt = if def == ver.name { "*" } else { " " };
+            // Name the file only, the full path is in `rig pin`.
+            let sel = match &selected {
+                Some((name, source)) if name == &ver.name => match source {
+                    shim::Source::File(path) => format!(
+                        "(selected by {})",
+                        path.file_name().unwrap_or_default().to_string_lossy()
+                    ),
+                    source => format!("(selected by {})", source),
+                },
+                _ => "".to_string(),
+            };
             let note = match ver.version {
                 None => "(broken?)".to_string(),
                 Some(v) => {
@@ -395,7 +476,7 @@ fn sc_list(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn Error
                 }
             };
             let als = ver.aliases.join(", ");
-            tab.add_row(row!(dflt, ver.name, note, als));
+            tab.add_row(row!(dflt, ver.name, note, als, sel));
         }
 
         print!("{}", tab);
@@ -433,6 +514,122 @@ fn sc_default(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn Er
 
 // ------------------------------------------------------------------------
 
+fn sc_pin(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    #[derive(serde::Serialize)]
+    struct PinnedVersion {
+        version: String,
+        source: String,
+    }
+
+    let dir = std::env::current_dir()?;
+    let Some(ver) = args.get_one::<String>("version") else {
+        let (request, source) = match shim::find_version_request(&dir)? {
+            Some(x) => x,
+            None => bail!(
+                "No R version is pinned in {} or above it, pin one with `rig pin <version>`",
+                dir.display()
+            ),
+        };
+        if args.get_flag("json") || mainargs.get_flag("json") {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&PinnedVersion {
+                    version: request.to_string(),
+                    source: source.to_string(),
+                })?
+            );
+        } else {
+            println!("{} ({})", request, source);
+        }
+        return Ok(());
+    };
+
+    let installed = sc_get_list_details()?;
+    let version = pin_version(ver, args, &installed)?;
+    let path = shim::write_version_file(&dir, &version)?;
+    OUTPUT.success(&format!("Pinned R {} in {}", version, path.display()));
+    info!("Pinned R {} in {}", version, path.display());
+
+    let request = shim::Request::Version(version.clone());
+    if shim::pick_version(&request, &installed, None).is_none() {
+        OUTPUT.info(&format!(
+            "R {} is not installed, install it with `rig add`",
+            version
+        ));
+    }
+
+    Ok(())
+}
+
+/// What `rig pin` writes for `ver`. The moving targets of the rversions API
+/// are resolved to a version number, everything else must be a version
+/// number, or the name or alias of an installed version.
+fn pin_version(
+    ver: &str,
+    args: &ArgMatches,
+    installed: &[rversion::InstalledVersion],
+) -> Result<String, Box<dyn Error>> {
+    if ver == "release" || ver == "oldrel" || ver.starts_with("oldrel/") {
+        let platform = get_platform(args)?;
+        let arch = get_arch(&platform, args);
+        let resolved = resolve_versions(vec![ver.to_string()], &platform, &arch)?;
+        return match &resolved[0].version {
+            Some(v) => {
+                debug!("Resolved {} to R {}", ver, v);
+                Ok(v.to_string())
+            }
+            None => bail!("Cannot resolve R version {}", ver),
+        };
+    }
+
+    let known = ver == "devel"
+        || ver == "next"
+        || shim::parse_partial(ver).is_some()
+        || installed
+            .iter()
+            .any(|v| v.name == ver || v.aliases.iter().any(|a| a == ver));
+    if !known {
+        bail!(
+            "Unknown R version: {}, use a version number, an installed version, \
+             or one of release, oldrel, oldrel/n, devel and next",
+            ver
+        );
+    }
+    Ok(ver.to_string())
+}
+
+/// `rig add`, which, without a version, installs the version the working
+/// directory asks for, unless it is installed already.
+fn sc_add_pinned(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    if !args.contains_id("str") {
+        let dir = std::env::current_dir()?;
+        if let Some((request, source)) = shim::find_version_request(&dir)? {
+            let installed = sc_get_list_details()?;
+            let default = sc_get_default()?;
+            if let Some(v) = shim::pick_version(&request, &installed, default.as_deref()) {
+                OUTPUT.success(&format!(
+                    "R {} is already installed (asked for by {})",
+                    v.name, source
+                ));
+                info!(
+                    "R {} is already installed (asked for by {})",
+                    v.name, source
+                );
+                return Ok(());
+            }
+            OUTPUT.status(&format!(
+                "Installing R {} (asked for by {})",
+                shim::add_version(args),
+                source
+            ));
+            info!("Installing R {} (asked for by {})", request, source);
+        }
+    }
+    sc_add(args)
+}
+
+// ------------------------------------------------------------------------
+
 pub fn sc_system_setup_user_lib(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
     let vers = args.get_many::<String>("version");
     let vers: Vec<String> = match vers {
@@ -1,8 +1,8 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use serde::{Deserialize, Serialize};
 
-use crate::install::{format_linkingto, REMOTE_HASH_FIELD, REMOTE_LINKINGTO_FIELD};
+use crate::install::{format_linkingto, InstallOptions, REMOTE_HASH_FIELD, REMOTE_LINKINGTO_FIELD};
 use crate::proj::BASE_PKGS;
 use crate::solver::*;
 
@@ -27,6 +27,11 @@ pub struct PakLockfilePackage {
     pub dep_types: Vec<String>,
     pub params: Vec<String>,
     pub install_args: String,
+    /// Environment variables for `R CMD INSTALL`, see [`InstallOptions`].
+    /// Not a pak field, so it is left out when empty, and pak lockfiles do not
+    /// need it.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub install_env: String,
     pub sysreqs: String,
 }
 
@@ -43,7 +48,7 @@ pub struct PakLockfile {
 /// Archive suffixes a repository serves packages as. Matched whole, because a
 /// package version contains dots (`pak_0.9.5.tgz`), so neither the first nor
 /// the last `.` of a file name marks where its extension starts.
-const ARCHIVE_SUFFIXES: [&str; 4] = [".tar.gz", ".tgz", ".zip", ".tar.bz2"];
+pub(crate) const ARCHIVE_SUFFIXES: [&str; 4] = [".tar.gz", ".tgz", ".zip", ".tar.bz2"];
 
 /// Short identity of one artifact, for the cache file name.
 ///
@@ -59,7 +64,7 @@ const ARCHIVE_SUFFIXES: [&str; 4] = [".tar.gz", ".tgz", ".zip", ".tar.bz2"];
 /// `LinkingTo` provenance, but it describes what the tarball will be compiled
 /// against later, not what the file is, and keying on it would cache a
 /// separate copy of one tarball per solve.
-fn artifact_cache_key(sha256: Option<&str>, linkingto: Option<&str>) -> Option<String> {
+pub(crate) fn artifact_cache_key(sha256: Option<&str>, linkingto: Option<&str>) -> Option<String> {
     let sha256 = sha256?;
     let hash = crate::utils::calculate_hash(&format!("{}\n{}", sha256, linkingto.unwrap_or("")));
     Some(hash[..8].to_string())
@@ -216,6 +221,7 @@ impl PakLockfile {
                 dep_types: vec![],
                 params: vec![],
                 install_args: "".to_string(),
+                install_env: "".to_string(),
                 sysreqs: "".to_string(),
             });
         }
@@ -228,6 +234,26 @@ impl PakLockfile {
             packages: pkgs,
         }
     }
+
+    /// Record the project's install options in the packages they are for.
+    /// Returns the names of the packages that have options but are not in the
+    /// lockfile.
+    pub fn set_install_options(
+        &mut self,
+        options: &BTreeMap<String, InstallOptions>,
+    ) -> Vec<String> {
+        let mut unused = vec![];
+        for (name, opts) in options {
+            match self.packages.iter_mut().find(|p| &p.package == name) {
+                Some(pkg) => {
+                    pkg.install_args = opts.args.clone();
+                    pkg.install_env = opts.env.clone();
+                }
+                None => unused.push(name.clone()),
+            }
+        }
+        unused
+    }
 }
 
 #[cfg(test)]
@@ -375,4 +401,40 @@ mod tests {
         // Nothing recognisable to cut before: the key is appended.
         assert_eq!(keyed_file_name("pak", "3f9a1c2e"), "pak-3f9a1c2e");
     }
+
+    #[test]
+    fn install_options_go_into_the_lockfile() {
+        let json = r#"{
+          "lockfile_version": 1, "os": "linux", "r_version": "4.5.1", "platform": "x86_64",
+          "packages": [{
+            "ref": "arrow", "package": "arrow", "version": "21.0.0", "type": "standard",
+            "direct": false, "binary": false, "dependencies": [], "vignettes": false,
+            "metadata": {}, "sources": [], "target": "src/contrib/arrow_21.0.0.tar.gz",
+            "platform": "source", "rversion": "4.5.1", "directpkg": false,
+            "license": "Apache", "dep_types": [], "params": [], "install_args": "",
+            "sysreqs": ""
+          }]
+        }"#;
+        // A pak lockfile has no `install_env`.
+        let mut lockfile: PakLockfile = serde_json::from_str(json).unwrap();
+        assert_eq!(lockfile.packages[0].install_env, "");
+        let out = serde_json::to_string(&lockfile).unwrap();
+        assert!(!out.contains("install_env"));
+
+        let mut options = BTreeMap::new();
+        options.insert(
+            "arrow".to_string(),
+            InstallOptions {
+                args: "--no-test-load".to_string(),
+                env: "ARROW_R_DEV=true".to_string(),
+            },
+        );
+        options.insert("sf".to_string(), InstallOptions::default());
+        assert_eq!(lockfile.set_install_options(&options), ["sf"]);
+
+        let out = serde_json::to_string(&lockfile).unwrap();
+        let back: PakLockfile = serde_json::from_str(&out).unwrap();
+        assert_eq!(back.packages[0].install_args, "--no-test-load");
+        assert_eq!(back.packages[0].install_env, "ARROW_R_DEV=true");
+    }
 }
@@ -0,0 +1,524 @@
+//! `rig pkg check`: whether the packages of a library work together.
+//!
+//! R checks the dependencies of a package when it installs it, but nothing
+//! keeps them in check afterwards: removing or updating a package can leave its
+//! reverse dependencies without a dependency, or with one that is too old, and
+//! a library carried over from another R version holds compiled code built for
+//! that one. R only notices when the package is loaded, if at all. This command
+//! looks at the `DESCRIPTION` files of the library, and reports what would
+//! break, see [`check_library`].
+
+use std::collections::{BTreeSet, HashMap};
+use std::env;
+use std::error::Error;
+use std::io::IsTerminal;
+
+use clap::ArgMatches;
+use log::{debug, error, info, warn};
+use simple_error::*;
+use tabular::*;
+
+use crate::common::{get_r_system_library, get_r_version_data_version};
+use crate::dcf::{DepVersionSpec, RDepType, VersionConstraintType};
+use crate::install::default_jobs;
+use crate::library::library_rver;
+use crate::output::OUTPUT;
+use crate::solver::is_base_package;
+
+use super::install::{install_into, requested_deps, InstallOptions};
+use super::list::{
+    print_table, read_installed, resolve_library, InstalledPackage, ResolvedLibrary,
+};
+
+pub fn sc_pkg_check(
+    args: &ArgMatches,
+    pkgargs: &ArgMatches,
+    mainargs: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    let json = args.get_flag("json") || pkgargs.get_flag("json") || mainargs.get_flag("json");
+    let lib = resolve_library(args)?;
+
+    // The R version is needed for the base packages and the `Built` check, but
+    // a library given as a path can be checked without one, and without any R
+    // installed, only less thoroughly. Fixing it needs one, to install with.
+    let rname = match &lib.rversion {
+        Some(rver) => Some(rver.clone()),
+        None if args.get_flag("fix") => Some(library_rver(args)?),
+        None => library_rver(args).ok(),
+    };
+    let r = rname.as_ref().and_then(|name| RInstall::new(name));
+    if r.is_none() {
+        OUTPUT.warn("No R version to check against, skipping the R version checks");
+        warn!("Checking {} without an R version", lib.path.display());
+    }
+
+    let mut problems = check(&lib, r.as_ref())?;
+    if !args.get_flag("fix") || problems.is_empty() {
+        return report(&lib, &problems, json);
+    }
+
+    if !json {
+        print_problems(&lib, &problems);
+        println!();
+    }
+    // `rname` is set, `--fix` required it above.
+    fix(&lib, rname.as_ref().unwrap(), &problems, json)?;
+    problems = check(&lib, r.as_ref())?;
+    if problems.is_empty() {
+        if json {
+            print_problems_json(&problems)?;
+        } else {
+            OUTPUT.success(&format!("Fixed all problems {}", lib.tag()));
+        }
+        info!("Fixed all problems in {}", lib.path.display());
+        return Ok(());
+    }
+    if !json {
+        OUTPUT.println("Problems left after fixing:");
+    }
+    report(&lib, &problems, json)
+}
+
+/// The R version a library belongs to: its version number, and the packages
+/// of its system library, which are installed for every library of it.
+struct RInstall {
+    version: String,
+    system: Vec<InstalledPackage>,
+}
+
+impl RInstall {
+    /// `None` if the R version is not installed, or rig cannot tell its version
+    /// number.
+    fn new(name: &str) -> Option<RInstall> {
+        let version = match get_r_version_data_version(name) {
+            Ok(version) => version,
+            Err(err) => {
+                debug!("Cannot find the version of R {}: {}", name, err);
+                return None;
+            }
+        };
+        let system = match get_r_system_library(name).map(|path| read_installed(&path)) {
+            Ok(Ok(system)) => system,
+            Ok(Err(err)) | Err(err) => {
+                warn!("Cannot read the system library of R {}: {}", name, err);
+                vec![]
+            }
+        };
+        Some(RInstall { version, system })
+    }
+}
+
+fn check(lib: &ResolvedLibrary, r: Option<&RInstall>) -> Result<Vec<Problem>, Box<dyn Error>> {
+    let installed = read_installed(&lib.path)?;
+    let system = r.map(|r| r.system.as_slice()).unwrap_or_default();
+    Ok(check_library(
+        &installed,
+        system,
+        r.map(|r| r.version.as_str()),
+    ))
+}
+
+/// Print the problems, and fail if there are any.
+fn report(lib: &ResolvedLibrary, problems: &[Problem], json: bool) -> Result<(), Box<dyn Error>> {
+    if json {
+        print_problems_json(problems)?;
+    } else if problems.is_empty() {
+        OUTPUT.success(&format!("No problems found {}", lib.tag()));
+    } else {
+        print_problems(lib, problems);
+    }
+
+    if !problems.is_empty() {
+        let msg = format!(
+            "Found {} {} {}",
+            problems.len(),
+            if problems.len() == 1 {
+                "problem"
+            } else {
+                "problems"
+            },
+            lib.tag()
+        );
+        OUTPUT.error(&msg);
+        error!("{}", msg);
+        bail!("{}", msg);
+    }
+
+    Ok(())
+}
+
+// ------------------------------------------------------------------------
+// The checks
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum ProblemKind {
+    /// A hard dependency is not installed.
+    Missing,
+    /// A hard dependency is installed, in the wrong version.
+    Version,
+    /// The package was built for another minor version of R.
+    RVersion,
+    /// The package was compiled against another `LinkingTo` dependency than
+    /// the installed one.
+    LinkingTo,
+}
+
+impl ProblemKind {
+    fn label(&self) -> &'static str {
+        match self {
+            ProblemKind::Missing => "missing dependency",
+            ProblemKind::Version => "version conflict",
+            ProblemKind::RVersion => "built for another R",
+            ProblemKind::LinkingTo => "LinkingTo mismatch",
+        }
+    }
+}
+
+/// Something wrong with one installed package.
+#[derive(Debug, serde::Serialize)]
+struct Problem {
+    package: String,
+    version: String,
+    kind: ProblemKind,
+    details: String,
+    /// The package to install to fix the problem, if installing one can: the
+    /// dependency for a dependency problem, the package itself otherwise.
+    fix: Option<String>,
+}
+
+/// Check each package of `installed` against the library it is in.
+///
+/// A dependency can be in the library or in R's `system` library, the library
+/// wins if it is in both, as it comes first in `.libPaths()`. `rversion` is the
+/// version of R the library belongs to, which is also the version of the base
+/// packages. Without one, neither the `R` dependencies, nor the `Built` fields
+/// are checked.
+///
+/// The hard dependencies are `Depends` and `Imports`. `LinkingTo` is not
+/// needed after a package is compiled, so it only matters what the package was
+/// compiled against: if that is not what is installed now, the package's
+/// compiled code can disagree with the dependency's about their shared data
+/// structures.
+fn check_library(
+    installed: &[InstalledPackage],
+    system: &[InstalledPackage],
+    rversion: Option<&str>,
+) -> Vec<Problem> {
+    let mut by_name: HashMap<&str, &InstalledPackage> = HashMap::new();
+    for pkg in system.iter().chain(installed.iter()) {
+        by_name.insert(pkg.package.as_str(), pkg);
+    }
+
+    let mut problems: Vec<Problem> = vec![];
+    for pkg in installed.iter().filter(|p| !is_base_package(&p.package)) {
+        let mut problem = |kind: ProblemKind, details: String, fix: Option<&str>| {
+            problems.push(Problem {
+                package: pkg.package.clone(),
+                version: pkg.version.clone(),
+                kind,
+                details,
+                fix: fix.map(|x| x.to_string()),
+            })
+        };
+
+        for dep in pkg.dependencies.dependencies.iter() {
+            let hard = dep
+                .types
+                .iter()
+                .any(|t| matches!(t, RDepType::Depends | RDepType::Imports));
+            if !hard {
+                continue;
+            }
+            if dep.name == "R" {
+                if let Some(rversion) = rversion {
+                    if !dep.satisfies(rversion).unwrap_or(true) {
+                        problem(
+                            ProblemKind::Version,
+                            format!("needs {}, this is R {}", requirement(dep), rversion),
+                            None,
+                        );
+                    }
+                }
+                continue;
+            }
+
+            let base = is_base_package(&dep.name);
+            let have = match by_name.get(dep.name.as_str()) {
+                Some(inst) => Some(inst.version.as_str()),
+                // The base packages are always there, and have R's version.
+                None if base => rversion,
+                None => {
+                    problem(
+                        ProblemKind::Missing,
+                        format!("needs {}, which is not installed", requirement(dep)),
+                        Some(&dep.name),
+                    );
+                    continue;
+                }
+            };
+            if let Some(have) = have {
+                // An unparseable version is not this command's business.
+                if !dep.satisfies(have).unwrap_or(true) {
+                    problem(
+                        ProblemKind::Version,
+                        format!("needs {}, {} is installed", requirement(dep), have),
+                        if base { None } else { Some(&dep.name) },
+                    );
+                }
+            }
+        }
+
+        if let (Some(built), Some(rversion)) = (&pkg.built_r, rversion) {
+            if minor_version(built) != minor_version(rversion) {
+                problem(
+                    ProblemKind::RVersion,
+                    format!("built for R {}, this is R {}", built, rversion),
+                    Some(&pkg.package),
+                );
+            }
+        }
+
+        for (dep, version, hash) in pkg.linkingto.iter() {
+            let inst = match by_name.get(dep.as_str()) {
+                Some(inst) => inst,
+                None => continue,
+            };
+            // Without a recorded hash the version is all there is to compare.
+            let same = match &inst.hash {
+                Some(have) => have == hash,
+                None => &inst.version == version,
+            };
+            if !same {
+                let details = if &inst.version == version {
+                    format!("compiled against another build of {} {}", dep, version)
+                } else {
+                    format!(
+                        "compiled against {} {}, {} is installed",
+                        dep, version, inst.version
+                    )
+                };
+                problem(ProblemKind::LinkingTo, details, Some(&pkg.package));
+            }
+        }
+    }
+
+    problems.sort_by(|a, b| {
+        a.package
+            .to_lowercase()
+            .cmp(&b.package.to_lowercase())
+            .then_with(|| a.kind.cmp(&b.kind))
+    });
+    problems
+}
+
+/// A dependency as its `DESCRIPTION` writes it, e.g. `cli (>= 3.6.0)`.
+fn requirement(dep: &DepVersionSpec) -> String {
+    let constraints: Vec<String> = dep
+        .constraints
+        .iter()
+        .map(|c| {
+            let op = match c.constraint_type {
+                VersionConstraintType::Less => "<",
+                VersionConstraintType::LessOrEqual => "<=",
+                VersionConstraintType::Equal => "==",
+                VersionConstraintType::Greater => ">",
+                VersionConstraintType::GreaterOrEqual => ">=",
+            };
+            format!("({} {})", op, c.version)
+        })
+        .collect();
+    if constraints.is_empty() {
+        dep.name.clone()
+    } else {
+        format!("{} {}", dep.name, constraints.join(" "))
+    }
+}
+
+/// `4.5` for `4.5.1`: the part of an R version that compiled code depends on.
+fn minor_version(version: &str) -> String {
+    version.split('.').take(2).collect::<Vec<_>>().join(".")
+}
+
+// ------------------------------------------------------------------------
+// Fixing
+
+/// Install what fixes the problems, except packages that did not come from a
+/// repository, those the repository version would replace.
+fn fix(
+    lib: &ResolvedLibrary,
+    rver: &str,
+    problems: &[Problem],
+    json: bool,
+) -> Result<(), Box<dyn Error>> {
+    let installed: HashMap<String, InstalledPackage> = read_installed(&lib.path)?
+        .into_iter()
+        .map(|p| (p.package.clone(), p))
+        .collect();
+
+    let mut names: BTreeSet<String> = BTreeSet::new();
+    let mut rebuild: Vec<String> = vec![];
+    for problem in problems {
+        let name = match &problem.fix {
+            Some(name) => name,
+            None => continue,
+        };
+        if let Some(remote) = installed.get(name).and_then(|p| p.remote.as_ref()) {
+            OUTPUT.warn(&format!(
+                "Not reinstalling {}, it was installed from {}",
+                name, remote
+            ));
+            warn!("Not reinstalling {} from {}", name, remote);
+            continue;
+        }
+        names.insert(name.clone());
+        if matches!(problem.kind, ProblemKind::RVersion | ProblemKind::LinkingTo) {
+            rebuild.push(name.clone());
+        }
+    }
+    if names.is_empty() {
+        info!("Nothing --fix can install");
+        return Ok(());
+    }
+
+    let names: Vec<String> = names.into_iter().collect();
+    let deps = requested_deps(&names)?;
+    let options = InstallOptions {
+        platform: None,
+        prefer_binary: None,
+        reinstall: false,
+        rebuild: &rebuild,
+        dry_run: false,
+        jobs: default_jobs(),
+        json,
+        print_plan: !json,
+    };
+    install_into(lib, rver, &deps, &options, None)?;
+    Ok(())
+}
+
+// ------------------------------------------------------------------------
+// Reporting
+
+fn print_problems(lib: &ResolvedLibrary, problems: &[Problem]) {
+    use owo_colors::OwoColorize;
+
+    let color = std::io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+    let count = problems.len();
+    let head = format!(
+        "{} {}",
+        count,
+        if count == 1 { "problem" } else { "problems" }
+    );
+    let tag = lib.tag();
+    if color {
+        println!("{} {}", head.red().bold(), tag.dimmed());
+    } else {
+        println!("{} {}", head, tag);
+    }
+    println!();
+
+    let mut tab: Table = Table::new("{:<}   {:<}   {:<}   {:<}");
+    tab.add_row(row!("Package", "Version", "Problem", "Details"));
+    for problem in problems {
+        tab.add_row(row!(
+            &problem.package,
+            &problem.version,
+            problem.kind.label(),
+            &problem.details
+        ));
+    }
+    print_table(&tab);
+}
+
+fn print_problems_json(problems: &[Problem]) -> Result<(), Box<dyn Error>> {
+    println!("{}", serde_json::to_string_pretty(problems)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dcf::PackageDependencies;
+
+    fn pkg(name: &str, version: &str, imports: &str) -> InstalledPackage {
+        let mut pkg = InstalledPackage::for_test(name, version, None, vec![]);
+        pkg.dependencies = PackageDependencies::from_str(imports, "Imports").unwrap();
+        pkg
+    }
+
+    fn kinds(problems: &[Problem]) -> Vec<(&str, ProblemKind)> {
+        problems
+            .iter()
+            .map(|p| (p.package.as_str(), p.kind))
+            .collect()
+    }
+
+    #[test]
+    fn a_consistent_library_has_no_problems() {
+        let mut usethis = pkg("usethis", "3.0.0", "cli (>= 3.0.0), glue, MASS");
+        usethis.built_r = Some("4.5.0".to_string());
+        let lib = [
+            pkg("glue", "1.8.0", "utils"),
+            pkg("cli", "3.6.3", ""),
+            usethis,
+        ];
+        // A recommended package, installed with R.
+        let system = [pkg("MASS", "7.3-65", "")];
+        assert!(check_library(&lib, &system, Some("4.5.1")).is_empty());
+    }
+
+    #[test]
+    fn missing_and_conflicting_dependencies() {
+        let lib = [
+            pkg("usethis", "3.0.0", "cli (>= 3.6.0), glue, utils (>= 5.0.0)"),
+            pkg("cli", "3.5.0", ""),
+        ];
+        let problems = check_library(&lib, &[], Some("4.5.1"));
+        assert_eq!(
+            kinds(&problems),
+            [
+                ("usethis", ProblemKind::Missing),
+                ("usethis", ProblemKind::Version),
+                ("usethis", ProblemKind::Version),
+            ]
+        );
+        assert_eq!(
+            problems[0].details,
+            "needs glue, which is not installed".to_string()
+        );
+        assert_eq!(problems[0].fix.as_deref(), Some("glue"));
+        assert_eq!(
+            problems[1].details,
+            "needs cli (>= 3.6.0), 3.5.0 is installed".to_string()
+        );
+        // A base package cannot be installed, only R updated.
+        assert_eq!(problems[2].fix, None);
+    }
+
+    #[test]
+    fn builds_for_another_r_and_stale_linkingto() {
+        let mut cpp11 = pkg("cpp11", "0.5.2", "");
+        cpp11.hash = Some("new".to_string());
+        let mut tzdb = pkg("tzdb", "0.4.0", "");
+        tzdb.built_r = Some("4.4.2".to_string());
+        tzdb.linkingto = vec![("cpp11".to_string(), "0.5.0".to_string(), "old".to_string())];
+        let problems = check_library(&[cpp11, tzdb], &[], Some("4.5.1"));
+        assert_eq!(
+            kinds(&problems),
+            [
+                ("tzdb", ProblemKind::RVersion),
+                ("tzdb", ProblemKind::LinkingTo),
+            ]
+        );
+        assert_eq!(
+            problems[1].details,
+            "compiled against cpp11 0.5.0, 0.5.2 is installed"
+        );
+
+        // Without an R version, only the provenance is checked.
+        let mut tzdb = pkg("tzdb", "0.4.0", "R (>= 9.0)");
+        tzdb.built_r = Some("4.4.2".to_string());
+        assert!(check_library(&[tzdb], &[], None).is_empty());
+    }
+}
@@ -33,6 +33,10 @@ pub fn sc_pkg_deps(
     let dev = args.get_flag("dev");
     let json = args.get_flag("json") || pkgargs.get_flag("json") || mainargs.get_flag("json");
 
+    if args.get_flag("reverse") {
+        return super::revdeps::sc_pkg_deps_reverse(args, &package, json);
+    }
+
     let loader = DbSourcePackageLoader::new()?;
 
     if args.get_flag("recursive") {
@@ -502,7 +506,7 @@ pub(crate) fn type_list(types: &[RDepType]) -> String {
 
 /// The `Needed by` cell of a row. Popular packages are needed by most of the
 /// closure, so we only name the first few.
-fn needed_by_cell(needed_by: &[String]) -> String {
+pub(super) fn needed_by_cell(needed_by: &[String]) -> String {
     const MAX: usize = 3;
     if needed_by.len() <= MAX {
         needed_by.join(", ")
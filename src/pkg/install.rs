@@ -22,6 +22,7 @@ use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fs;
 use std::io::ErrorKind;
+use std::sync::Arc;
 
 use clap::ArgMatches;
 use log::{debug, info};
@@ -39,10 +40,11 @@ use crate::linux::get_r_binary;
 
 use crate::cache::get_cache_dir;
 use crate::dcf::{DepVersionSpec, PackageDependencies, RDepType};
-use crate::install::{install_packages, PackageInfo, REMOTE_HASH_FIELD};
+use crate::install::{default_jobs, install_packages, PackageInfo, REMOTE_HASH_FIELD};
 use crate::library::library_rver;
 use crate::output::OUTPUT;
 use crate::pak::{PakLockfile, PakLockfilePackage};
+use crate::progress::{EventStream, ProgressEvent};
 use crate::proj::{
     download_lockfile_packages, lockfile_package_info, proj_binary_target, sc_proj_solve_deps,
     BASE_PKGS,
@@ -60,9 +62,19 @@ pub fn sc_pkg_install(
     mainargs: &ArgMatches,
 ) -> Result<(), Box<dyn Error>> {
     let json = args.get_flag("json") || pkgargs.get_flag("json") || mainargs.get_flag("json");
-    let reinstall = args.get_flag("reinstall");
-    let dry_run = args.get_flag("dry-run");
+    let events = EventStream::from_args(args)?;
+    let result = pkg_install(args, json, events.clone());
+    if let (Err(err), Some(events)) = (&result, &events) {
+        events.fail(&err.to_string());
+    }
+    result
+}
 
+fn pkg_install(
+    args: &ArgMatches,
+    json: bool,
+    events: Option<Arc<EventStream>>,
+) -> Result<(), Box<dyn Error>> {
     let names: Vec<String> = args
         .get_many::<String>("package")
         .unwrap()
@@ -79,14 +91,64 @@ pub fn sc_pkg_install(
         None => library_rver(args)?,
     };
 
-    let target = proj_binary_target(args.get_one::<String>("platform"), &rver)?;
-    let prefer_binary = args.get_one::<usize>("prefer-binary").copied();
+    let options = InstallOptions {
+        platform: args.get_one::<String>("platform"),
+        prefer_binary: args.get_one::<usize>("prefer-binary").copied(),
+        reinstall: args.get_flag("reinstall"),
+        rebuild: &[],
+        dry_run: args.get_flag("dry-run"),
+        jobs: args
+            .get_one::<usize>("jobs")
+            .copied()
+            .unwrap_or_else(default_jobs),
+        json,
+        print_plan: true,
+    };
+    install_into(&lib, &rver, &deps, &options, events)?;
+
+    Ok(())
+}
+
+/// How [`install_into`] goes about installing, the settings `rig pkg install`
+/// and `rig upgrade` share.
+pub(crate) struct InstallOptions<'a> {
+    pub(crate) platform: Option<&'a String>,
+    pub(crate) prefer_binary: Option<usize>,
+    pub(crate) reinstall: bool,
+    /// Packages to install even if the library already has the same artifact,
+    /// e.g. because `rig pkg check` found them built for another R.
+    pub(crate) rebuild: &'a [String],
+    pub(crate) dry_run: bool,
+    pub(crate) jobs: usize,
+    /// JSON plan, and no success messages.
+    pub(crate) json: bool,
+    pub(crate) print_plan: bool,
+}
+
+/// Solve `deps` for R `rver`, and install what the library does not already
+/// have. Returns how many packages were installed, which is zero for a dry
+/// run.
+pub(crate) fn install_into(
+    lib: &ResolvedLibrary,
+    rver: &str,
+    deps: &PackageDependencies,
+    options: &InstallOptions,
+    events: Option<Arc<EventStream>>,
+) -> Result<usize, Box<dyn Error>> {
+    let json = options.json;
+    let names: Vec<String> = deps.dependencies.iter().map(|d| d.name.clone()).collect();
+
+    let target = proj_binary_target(options.platform, rver)?;
+    let prefer_binary = options.prefer_binary;
     if prefer_binary.is_some() && target.is_none() {
         OUTPUT.warn("There are no binary packages to prefer, ignoring --prefer-binary");
         info!("Ignoring --prefer-binary: solving for source packages only");
     }
 
-    let (registry, solution) = sc_proj_solve_deps(&rver, &deps, target, prefer_binary)?;
+    if let Some(events) = &events {
+        events.emit(&ProgressEvent::SolveStarted { packages: &names });
+    }
+    let (registry, solution) = sc_proj_solve_deps(rver, deps, target, prefer_binary)?;
     OUTPUT.success("Solved dependencies");
     info!("Solved dependencies");
 
@@ -105,17 +167,32 @@ pub fn sc_pkg_install(
         debug!("Library {} does not exist yet", lib.path.display());
         vec![]
     };
-    let plan = plan_installs(&lockfile.packages, &installed, reinstall);
+    let plan = plan_installs(
+        &lockfile.packages,
+        &installed,
+        options.reinstall,
+        options.rebuild,
+    );
+    if let Some(events) = &events {
+        events.emit(&ProgressEvent::SolveFinished {
+            packages: plan.len(),
+        });
+    }
 
-    if json {
+    // Standard output belongs to the event stream, if that is where it goes.
+    if !options.print_plan {
+        debug!("Not printing the plan");
+    } else if events.as_ref().is_some_and(|e| e.is_stdout()) {
+        debug!("Not printing the plan, standard output has the progress events");
+    } else if json {
         print_plan_json(&plan)?;
     } else {
-        print_plan(&lib, &plan);
+        print_plan(lib, &plan);
     }
 
-    if dry_run {
+    if options.dry_run {
         info!("--dry-run, not installing anything");
-        return Ok(());
+        return Ok(0);
     }
 
     let todo: Vec<&PakLockfilePackage> = plan
@@ -129,12 +206,12 @@ pub fn sc_pkg_install(
             OUTPUT.success(&format!("Everything is up to date {}", lib.tag()));
         }
         info!("Nothing to install");
-        return Ok(());
+        return Ok(0);
     }
 
     // The library may not exist yet, and `R CMD INSTALL` will not create it.
     if let Err(err) = fs::create_dir_all(&lib.path) {
-        bail!("{}", library_error(&lib, err));
+        bail!("{}", library_error(lib, err));
     }
 
     let to_download = PakLockfile {
@@ -144,30 +221,32 @@ pub fn sc_pkg_install(
         platform: lockfile.platform.clone(),
         packages: todo.iter().map(|p| (*p).clone()).collect(),
     };
-    download_lockfile_packages(&to_download)?;
+    download_lockfile_packages(&to_download, events.as_deref())?;
 
     let cache_dir = get_cache_dir()?;
     let installing: HashSet<&str> = todo.iter().map(|p| p.package.as_str()).collect();
     let packages: Vec<PackageInfo> = todo
         .iter()
         .map(|p| {
-            let mut info = lockfile_package_info(p, &cache_dir);
+            let mut info = lockfile_package_info(p, &cache_dir)?;
             // A package whose dependency is already installed must not wait for
             // it: the installer only starts a package once every name in its
             // `dependencies` has been installed *by this run*, and a name that
             // is not being installed at all would stall the whole batch.
             info.dependencies
                 .retain(|d| installing.contains(d.as_str()));
-            info
+            Ok(info)
         })
-        .collect();
+        .collect::<Result<_, Box<dyn Error>>>()?;
 
-    let r_binary = get_r_binary(&rver)?;
+    let r_binary = get_r_binary(rver)?;
     let n = install_packages(
         packages,
         &lib.path,
         &r_binary.to_string_lossy(),
         MAX_CONCURRENT,
+        options.jobs,
+        events,
     )?;
 
     if !json {
@@ -176,7 +255,7 @@ pub fn sc_pkg_install(
     }
     info!("Installed {} packages into {}", n, lib.path.display());
 
-    Ok(())
+    Ok(n)
 }
 
 // ------------------------------------------------------------------------
@@ -186,7 +265,7 @@ pub fn sc_pkg_install(
 ///
 /// They are `Depends` with no version constraint: the command asks for the
 /// packages, and leaves it to the solve to say which versions that means.
-fn requested_deps(names: &[String]) -> Result<PackageDependencies, Box<dyn Error>> {
+pub(crate) fn requested_deps(names: &[String]) -> Result<PackageDependencies, Box<dyn Error>> {
     let mut deps = PackageDependencies::new();
     let mut base: Vec<&str> = vec![];
 
@@ -245,7 +324,8 @@ struct Planned<'a> {
 /// artifact*: the same version, installed from the same upstream tarball, and
 /// compiled against the same `LinkingTo` dependency versions the solution has.
 /// Anything else is installed, including a package whose provenance was never
-/// recorded, since there is no way to tell whether that one matches.
+/// recorded, since there is no way to tell whether that one matches. So is every
+/// package in `rebuild`, whatever is installed.
 ///
 /// The `LinkingTo` check is iterated to a fixpoint, because replacing a package
 /// invalidates every compiled package built against it, which in turn
@@ -256,6 +336,7 @@ fn plan_installs<'a>(
     solved: &'a [PakLockfilePackage],
     installed: &[InstalledPackage],
     reinstall: bool,
+    rebuild: &[String],
 ) -> Vec<Planned<'a>> {
     let by_name: HashMap<&str, &InstalledPackage> =
         installed.iter().map(|p| (p.package.as_str(), p)).collect();
@@ -276,6 +357,8 @@ fn plan_installs<'a>(
         .map(|package| {
             let (install, reason) = if reinstall {
                 (true, "--reinstall".to_string())
+            } else if rebuild.contains(&package.package) {
+                (true, "needs a rebuild".to_string())
             } else {
                 match needs_install(
                     package,
@@ -480,6 +563,7 @@ mod tests {
             dep_types: vec![],
             params: vec![],
             install_args: String::new(),
+            install_env: String::new(),
             sysreqs: String::new(),
         }
     }
@@ -509,7 +593,7 @@ mod tests {
         installed: &[InstalledPackage],
         reinstall: bool,
     ) -> HashMap<String, (bool, String)> {
-        plan_installs(solved, installed, reinstall)
+        plan_installs(solved, installed, reinstall, &[])
             .into_iter()
             .map(|p| (p.package.package.clone(), (p.install, p.reason)))
             .collect()
@@ -679,6 +763,32 @@ mod tests {
         assert_eq!(out["cli"].1, "--reinstall");
     }
 
+    /// `rig pkg check --fix` rebuilds packages that are otherwise up to date,
+    /// and what was compiled against them follows.
+    #[test]
+    fn rebuild_installs_the_named_packages_and_their_linkingto_dependents() {
+        let out: HashMap<String, (bool, String)> = plan_installs(
+            &[
+                solved("cli", "3.6.3", Some("aa")),
+                solved("cpp11", "0.5.0", Some("cp")),
+                solved("tzdb", "0.4.0", Some("tz")),
+            ],
+            &[
+                inst("cli", "3.6.3", Some("aa"), &[]),
+                inst("cpp11", "0.5.0", Some("cp"), &[]),
+                inst("tzdb", "0.4.0", Some("tz"), &[("cpp11", "0.5.0", "cp")]),
+            ],
+            false,
+            &["cpp11".to_string()],
+        )
+        .into_iter()
+        .map(|p| (p.package.package.clone(), (p.install, p.reason)))
+        .collect();
+        assert!(!out["cli"].0);
+        assert_eq!(out["cpp11"].1, "needs a rebuild");
+        assert!(out["tzdb"].0);
+    }
+
     #[test]
     fn base_packages_cannot_be_installed() {
         let err = requested_deps(&["stats".to_string()]).unwrap_err();
@@ -0,0 +1,569 @@
+//! `rig pkg licenses` and `rig proj licenses`: the licenses of the packages of
+//! a library or a lockfile, grouped by license, with an optional policy of
+//! licenses that must not appear.
+//!
+//! R packages write their `License` field in many equivalent ways, e.g.
+//! `GPL (== 2)` and `GPL-2`, so the licenses are normalized before grouping,
+//! see [`normalize_license`].
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::IsTerminal;
+
+use clap::ArgMatches;
+use lazy_static::lazy_static;
+use log::{error, info};
+use regex::Regex;
+use simple_error::*;
+
+use crate::output::OUTPUT;
+use crate::pak::PakLockfile;
+use crate::repos::cranlike_metadata::allpackages_licenses;
+use crate::solver::is_base_package;
+use crate::textfmt::{reflow, wrap};
+
+use super::list::{read_installed, resolve_library};
+
+pub fn sc_pkg_licenses(
+    args: &ArgMatches,
+    pkgargs: &ArgMatches,
+    mainargs: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    let json = args.get_flag("json") || pkgargs.get_flag("json") || mainargs.get_flag("json");
+    let lib = resolve_library(args)?;
+
+    // The base packages are part of R, and are licensed with it.
+    let packages: Vec<LicensedPackage> = read_installed(&lib.path)?
+        .into_iter()
+        .filter(|pkg| !is_base_package(&pkg.package))
+        .map(|pkg| LicensedPackage {
+            package: pkg.package,
+            version: pkg.version,
+            license: pkg.license,
+        })
+        .collect();
+
+    report_licenses(packages, &deny_list(args), json, &lib.tag())
+}
+
+/// `rig proj licenses`: the licenses of the packages of a lockfile.
+pub(crate) fn proj_licenses(args: &ArgMatches, json: bool) -> Result<(), Box<dyn Error>> {
+    let path = args.get_one::<String>("lockfile").unwrap();
+    let contents = match fs::read_to_string(path) {
+        Ok(x) => x,
+        Err(err) => bail!(
+            "Cannot read lockfile {}: {}. Create one with `rig proj solve`.",
+            path,
+            err
+        ),
+    };
+    let lockfile: PakLockfile = serde_json::from_str(&contents)?;
+
+    let licenses = lockfile_licenses(&lockfile)?;
+    let packages: Vec<LicensedPackage> = lockfile
+        .packages
+        .iter()
+        .map(|pkg| LicensedPackage {
+            package: pkg.package.clone(),
+            version: pkg.version.clone(),
+            license: licenses.get(&pkg.package).cloned(),
+        })
+        .collect();
+
+    report_licenses(packages, &deny_list(args), json, &format!("({})", path))
+}
+
+/// The licenses of the packages of a lockfile, keyed by package name.
+/// Lockfiles from `rig proj solve` do not record the licenses, pak's do, so
+/// the missing ones are looked up in the CRAN metadata.
+pub(super) fn lockfile_licenses(
+    lockfile: &PakLockfile,
+) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let mut licenses: HashMap<String, String> = HashMap::new();
+    let mut missing: Vec<(String, String)> = vec![];
+    for pkg in lockfile.packages.iter() {
+        if pkg.license.is_empty() || pkg.license == "UNKNOWN" {
+            missing.push((pkg.package.clone(), pkg.version.clone()));
+        } else {
+            licenses.insert(pkg.package.clone(), pkg.license.clone());
+        }
+    }
+    if !missing.is_empty() {
+        licenses.extend(allpackages_licenses(&missing)?);
+    }
+    Ok(licenses)
+}
+
+/// The licenses of `--deny`, normalized.
+fn deny_list(args: &ArgMatches) -> Vec<String> {
+    args.get_many::<String>("deny")
+        .map(|v| v.map(|l| normalize_alternative(l)).collect())
+        .unwrap_or_default()
+}
+
+/// Print the licenses of `packages`, grouped by license, and fail if any of
+/// them has a license of `deny`.
+fn report_licenses(
+    packages: Vec<LicensedPackage>,
+    deny: &[String],
+    json: bool,
+    tag: &str,
+) -> Result<(), Box<dyn Error>> {
+    let groups = group_licenses(packages, deny);
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&groups)?);
+    } else {
+        print_licenses(&groups, tag);
+    }
+
+    let denied: Vec<String> = groups
+        .iter()
+        .filter(|g| g.denied)
+        .flat_map(|g| g.packages.iter().map(|p| p.package.clone()))
+        .collect();
+    if !denied.is_empty() {
+        let msg = format!(
+            "{} {} a denied license: {}",
+            denied.len(),
+            if denied.len() == 1 {
+                "package has"
+            } else {
+                "packages have"
+            },
+            denied.join(", ")
+        );
+        OUTPUT.error(&msg);
+        error!("{}", msg);
+        bail!("{}", msg);
+    }
+
+    Ok(())
+}
+
+// ------------------------------------------------------------------------
+// Grouping and the policy
+
+/// A package, with the `License` field of its `DESCRIPTION`, if it has one.
+#[derive(Debug, Clone, serde::Serialize)]
+struct LicensedPackage {
+    package: String,
+    version: String,
+    license: Option<String>,
+}
+
+/// The packages that have the same license, after normalization.
+#[derive(Debug, serde::Serialize)]
+struct LicenseGroup {
+    /// The normalized license, `UNKNOWN` for the packages without one.
+    license: String,
+    /// Whether `--deny` forbids it.
+    denied: bool,
+    packages: Vec<LicensedPackage>,
+}
+
+/// Group the packages by their normalized license, the largest group first.
+fn group_licenses(packages: Vec<LicensedPackage>, deny: &[String]) -> Vec<LicenseGroup> {
+    let mut by_license: BTreeMap<String, Vec<LicensedPackage>> = BTreeMap::new();
+    for pkg in packages {
+        let license = match &pkg.license {
+            Some(license) => normalize_license(license),
+            None => "UNKNOWN".to_string(),
+        };
+        by_license.entry(license).or_default().push(pkg);
+    }
+
+    let mut groups: Vec<LicenseGroup> = by_license
+        .into_iter()
+        .map(|(license, mut packages)| {
+            packages.sort_by_key(|p| p.package.to_lowercase());
+            LicenseGroup {
+                denied: license != "UNKNOWN" && is_denied(&license, deny),
+                license,
+                packages,
+            }
+        })
+        .collect();
+    groups.sort_by(|a, b| {
+        b.packages
+            .len()
+            .cmp(&a.packages.len())
+            .then_with(|| a.license.to_lowercase().cmp(&b.license.to_lowercase()))
+    });
+    groups
+}
+
+lazy_static! {
+    static ref FILE_LICENSE: Regex =
+        Regex::new(r"(?i)^(?:(.*?)\s*\+\s*)?file\s+LICEN[CS]E$").unwrap();
+    static ref GPL_EXACT: Regex =
+        Regex::new(r"(?i)^(A|L)?GPL\s*(?:-\s*|\(\s*==\s*)([0-9.]+)\s*\)?$").unwrap();
+    static ref GPL_LATER: Regex =
+        Regex::new(r"(?i)^(A|L)?GPL\s*\(\s*>=?\s*([0-9.]+)\s*\)$").unwrap();
+    static ref GPL_ANY: Regex = Regex::new(r"(?i)^(A|L)?GPL$").unwrap();
+    static ref APACHE: Regex =
+        Regex::new(r"(?i)^Apache(?:\s+License|-)\s*(?:\(\s*==\s*)?([0-9.]+)\s*\)?$").unwrap();
+    static ref BSD: Regex = Regex::new(r"(?i)^BSD[ _-]?([23])[ _-]?clause$").unwrap();
+}
+
+/// The licenses of the GPL family, and the versions of them R knows, see
+/// `share/licenses/license.db` in R.
+const GPL_VERSIONS: &[(&str, &[&str])] = &[
+    ("GPL", &["2", "3"]),
+    ("LGPL", &["2", "2.1", "3"]),
+    ("AGPL", &["3"]),
+];
+
+/// Normalize a `License` field, so that the equivalent ways of writing a
+/// license compare equal: the alternatives of `A | B` are normalized one by
+/// one, see [`normalize_alternative`], and sorted.
+fn normalize_license(license: &str) -> String {
+    let mut alternatives: Vec<String> = reflow(license)
+        .split('|')
+        .map(normalize_alternative)
+        .filter(|a| !a.is_empty())
+        .collect();
+    alternatives.sort_by_key(|a| a.to_lowercase());
+    alternatives.dedup();
+    alternatives.join(" | ")
+}
+
+/// Normalize one alternative of a `License` field, e.g. `GPL (== 2)` and
+/// `GPL-2.0` are both `GPL-2`, `GPL (>= 2.0)` is `GPL (>= 2)` and
+/// `Apache License (== 2.0)` is `Apache License 2.0`. `+ file LICENSE`, which
+/// adds restrictions or a copyright holder to the license, is kept.
+fn normalize_alternative(alternative: &str) -> String {
+    let alternative = reflow(alternative);
+    let (name, file) = match FILE_LICENSE.captures(&alternative) {
+        Some(caps) => match caps.get(1) {
+            Some(name) => (name.as_str().to_string(), " + file LICENSE"),
+            None => return "file LICENSE".to_string(),
+        },
+        None => (alternative.clone(), ""),
+    };
+
+    let name = if let Some(caps) = GPL_EXACT.captures(&name) {
+        format!("{}-{}", gpl_family(&caps), gpl_version(&caps[2]))
+    } else if let Some(caps) = GPL_LATER.captures(&name) {
+        format!("{} (>= {})", gpl_family(&caps), gpl_version(&caps[2]))
+    } else if let Some(caps) = GPL_ANY.captures(&name) {
+        gpl_family(&caps)
+    } else if let Some(caps) = APACHE.captures(&name) {
+        let version = &caps[1];
+        let version = if version.contains('.') {
+            version.to_string()
+        } else {
+            format!("{}.0", version)
+        };
+        format!("Apache License {}", version)
+    } else if let Some(caps) = BSD.captures(&name) {
+        format!("BSD_{}_clause", &caps[1])
+    } else {
+        name
+    };
+
+    format!("{}{}", name, file)
+}
+
+fn gpl_family(caps: &regex::Captures) -> String {
+    format!(
+        "{}GPL",
+        caps.get(1)
+            .map(|p| p.as_str().to_uppercase())
+            .unwrap_or_default()
+    )
+}
+
+/// `2.0` is `2`, but `2.1` stays.
+fn gpl_version(version: &str) -> String {
+    version.strip_suffix(".0").unwrap_or(version).to_string()
+}
+
+/// The concrete licenses a normalized alternative allows a choice from, e.g.
+/// `GPL (>= 2)` allows `GPL-2` and `GPL-3`, and a plain `GPL` any version.
+/// `+ file LICENSE` does not change the license itself, so it is dropped.
+fn concrete_licenses(alternative: &str) -> Vec<String> {
+    let name = alternative
+        .strip_suffix(" + file LICENSE")
+        .unwrap_or(alternative);
+    let family_versions = |family: &str| {
+        GPL_VERSIONS
+            .iter()
+            .find(|(f, _)| *f == family)
+            .map(|(_, versions)| *versions)
+    };
+
+    if let Some(caps) = GPL_LATER.captures(name) {
+        let family = gpl_family(&caps);
+        let min = caps[2].parse::<f64>().unwrap_or(0.0);
+        if let Some(versions) = family_versions(&family) {
+            let later: Vec<String> = versions
+                .iter()
+                .filter(|v| v.parse::<f64>().unwrap_or(0.0) >= min)
+                .map(|v| format!("{}-{}", family, v))
+                .collect();
+            if !later.is_empty() {
+                return later;
+            }
+        }
+    } else if let Some(caps) = GPL_ANY.captures(name) {
+        let family = gpl_family(&caps);
+        if let Some(versions) = family_versions(&family) {
+            return versions
+                .iter()
+                .map(|v| format!("{}-{}", family, v))
+                .collect();
+        }
+    }
+    vec![name.to_string()]
+}
+
+/// Whether a normalized license is forbidden by the normalized licenses of
+/// `deny`. A package can be used under any of the alternatives of its license,
+/// and under any version of a `GPL (>= 2)` style one, so it is only denied if
+/// all of those are.
+fn is_denied(license: &str, deny: &[String]) -> bool {
+    if deny.is_empty() {
+        return false;
+    }
+    let denied: HashSet<String> = deny
+        .iter()
+        .flat_map(|d| concrete_licenses(d))
+        .map(|d| d.to_lowercase())
+        .collect();
+    license
+        .split(" | ")
+        .flat_map(concrete_licenses)
+        .all(|l| denied.contains(&l.to_lowercase()))
+}
+
+/// The SPDX license expression of a `License` field, e.g. `GPL-2.0-or-later`
+/// for `GPL (>= 2)`, and `GPL-2.0-only OR GPL-3.0-only` for `GPL-2 | GPL-3`.
+/// `None` if an alternative has no SPDX identifier, e.g. `file LICENSE`.
+pub(super) fn spdx_license(license: &str) -> Option<String> {
+    let alternatives: Vec<String> = normalize_license(license)
+        .split(" | ")
+        .map(spdx_alternative)
+        .collect::<Option<Vec<String>>>()?;
+    Some(alternatives.join(" OR "))
+}
+
+fn spdx_alternative(alternative: &str) -> Option<String> {
+    let name = alternative
+        .strip_suffix(" + file LICENSE")
+        .unwrap_or(alternative);
+    let spdx_version = |v: &str| {
+        if v.contains('.') {
+            v.to_string()
+        } else {
+            format!("{}.0", v)
+        }
+    };
+
+    if let Some(caps) = GPL_EXACT.captures(name) {
+        return Some(format!(
+            "{}-{}-only",
+            gpl_family(&caps),
+            spdx_version(&caps[2])
+        ));
+    }
+    if let Some(caps) = GPL_LATER.captures(name) {
+        return Some(format!(
+            "{}-{}-or-later",
+            gpl_family(&caps),
+            spdx_version(&caps[2])
+        ));
+    }
+    if let Some(caps) = GPL_ANY.captures(name) {
+        let family = gpl_family(&caps);
+        let (_, versions) = GPL_VERSIONS.iter().find(|(f, _)| *f == family)?;
+        return Some(format!("{}-{}-or-later", family, spdx_version(versions[0])));
+    }
+    let spdx = match name {
+        "MIT" => "MIT",
+        "Apache License 2.0" => "Apache-2.0",
+        "BSD_2_clause" => "BSD-2-Clause",
+        "BSD_3_clause" => "BSD-3-Clause",
+        "CC0" => "CC0-1.0",
+        "CC BY 4.0" => "CC-BY-4.0",
+        "CC BY-SA 4.0" => "CC-BY-SA-4.0",
+        "Artistic-2.0" => "Artistic-2.0",
+        "MPL-2.0" => "MPL-2.0",
+        "EUPL" | "EUPL (>= 1.2)" => "EUPL-1.2",
+        "BSL-1.0" => "BSL-1.0",
+        _ => return None,
+    };
+    Some(spdx.to_string())
+}
+
+// ------------------------------------------------------------------------
+// Output
+
+/// Pretty-print the license groups: a header line with the number of
+/// packages and licenses, then each license, with its packages below it.
+fn print_licenses(groups: &[LicenseGroup], tag: &str) {
+    use owo_colors::OwoColorize;
+
+    let color = std::io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+
+    let num_pkgs: usize = groups.iter().map(|g| g.packages.len()).sum();
+    let head = format!(
+        "{} {}, {} {}",
+        num_pkgs,
+        if num_pkgs == 1 { "package" } else { "packages" },
+        groups.len(),
+        if groups.len() == 1 {
+            "license"
+        } else {
+            "licenses"
+        }
+    );
+    if color {
+        println!("{} {}", head.bold(), tag.dimmed());
+    } else {
+        println!("{} {}", head, tag);
+    }
+
+    for group in groups {
+        println!();
+        let title = format!("{} ({})", group.license, group.packages.len());
+        match (color, group.denied) {
+            (true, true) => println!("{} {}", title.cyan().bold(), "denied".red().bold()),
+            (true, false) => println!("{}", title.cyan().bold()),
+            (false, true) => println!("{} denied", title),
+            (false, false) => println!("{}", title),
+        }
+        let list = group
+            .packages
+            .iter()
+            .map(|p| format!("{} {}", p.package, p.version))
+            .collect::<Vec<String>>()
+            .join(", ");
+        for line in wrap(&list, 76) {
+            println!("  {}", line);
+        }
+    }
+    info!("Listed the licenses of {} packages", num_pkgs);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pkg(name: &str, license: Option<&str>) -> LicensedPackage {
+        LicensedPackage {
+            package: name.to_string(),
+            version: "1.0.0".to_string(),
+            license: license.map(|l| l.to_string()),
+        }
+    }
+
+    fn deny(licenses: &[&str]) -> Vec<String> {
+        licenses.iter().map(|l| normalize_alternative(l)).collect()
+    }
+
+    #[test]
+    fn licenses_are_normalized() {
+        assert_eq!(normalize_license("GPL (== 2)"), "GPL-2");
+        assert_eq!(normalize_license("GPL-2.0"), "GPL-2");
+        assert_eq!(normalize_license("GPL-3 | GPL-2"), "GPL-2 | GPL-3");
+        assert_eq!(normalize_license("GPL (>= 2.0)"), "GPL (>= 2)");
+        assert_eq!(normalize_license("LGPL (>= 2.1)"), "LGPL (>= 2.1)");
+        assert_eq!(
+            normalize_license("MIT +   file\n    LICENCE"),
+            "MIT + file LICENSE"
+        );
+        assert_eq!(normalize_license("file LICENSE"), "file LICENSE");
+        assert_eq!(
+            normalize_license("Apache License (== 2.0)"),
+            "Apache License 2.0"
+        );
+        assert_eq!(normalize_license("Apache License 2"), "Apache License 2.0");
+        assert_eq!(
+            normalize_license("BSD 3-clause + file LICENSE"),
+            "BSD_3_clause + file LICENSE"
+        );
+        assert_eq!(normalize_license("CC0"), "CC0");
+    }
+
+    #[test]
+    fn packages_are_grouped_by_normalized_license() {
+        let groups = group_licenses(
+            vec![
+                pkg("cli", Some("MIT + file LICENSE")),
+                pkg("rlang", Some("MIT + file LICENCE")),
+                pkg("Rcpp", Some("GPL (>= 2)")),
+                pkg("internal", None),
+            ],
+            &[],
+        );
+        let summary: Vec<(&str, usize)> = groups
+            .iter()
+            .map(|g| (g.license.as_str(), g.packages.len()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![("MIT + file LICENSE", 2), ("GPL (>= 2)", 1), ("UNKNOWN", 1)]
+        );
+        assert_eq!(groups[0].packages[0].package, "cli");
+    }
+
+    #[test]
+    fn a_license_is_denied_only_without_an_allowed_choice() {
+        let gpl3 = deny(&["GPL-3"]);
+        assert!(is_denied("GPL-3", &gpl3));
+        assert!(is_denied("GPL-3 + file LICENSE", &gpl3));
+        assert!(is_denied("GPL (>= 3)", &gpl3));
+        assert!(!is_denied("GPL-2 | GPL-3", &gpl3));
+        assert!(!is_denied("GPL (>= 2)", &gpl3));
+        assert!(!is_denied("LGPL-3", &gpl3));
+        assert!(!is_denied("MIT + file LICENSE", &gpl3));
+
+        // A family without a version denies all of its versions.
+        let gpl = deny(&["GPL"]);
+        assert!(is_denied("GPL-2 | GPL-3", &gpl));
+        assert!(is_denied("GPL (>= 2)", &gpl));
+        assert!(!is_denied("AGPL-3", &gpl));
+
+        let both = deny(&["GPL-2", "GPL (== 3)"]);
+        assert!(is_denied("GPL-2 | GPL-3", &both));
+        assert!(!is_denied("GPL-2 | MIT + file LICENSE", &both));
+    }
+
+    #[test]
+    fn licenses_map_to_spdx_expressions() {
+        assert_eq!(spdx_license("GPL (>= 2)").unwrap(), "GPL-2.0-or-later");
+        assert_eq!(
+            spdx_license("GPL-3 | GPL-2").unwrap(),
+            "GPL-2.0-only OR GPL-3.0-only"
+        );
+        assert_eq!(spdx_license("LGPL (>= 2.1)").unwrap(), "LGPL-2.1-or-later");
+        assert_eq!(spdx_license("MIT + file LICENSE").unwrap(), "MIT");
+        assert_eq!(
+            spdx_license("Apache License (== 2.0)").unwrap(),
+            "Apache-2.0"
+        );
+        assert_eq!(spdx_license("GPL").unwrap(), "GPL-2.0-or-later");
+        assert_eq!(spdx_license("file LICENSE"), None);
+        assert_eq!(spdx_license("MIT | file LICENSE"), None);
+    }
+
+    #[test]
+    fn denied_groups_are_marked() {
+        let groups = group_licenses(
+            vec![
+                pkg("a", Some("GPL-3")),
+                pkg("b", Some("MIT + file LICENSE")),
+            ],
+            &deny(&["gpl-3"]),
+        );
+        let denied: Vec<&str> = groups
+            .iter()
+            .filter(|g| g.denied)
+            .map(|g| g.license.as_str())
+            .collect();
+        assert_eq!(denied, vec!["GPL-3"]);
+    }
+}
@@ -22,7 +22,7 @@ use log::debug;
 use simple_error::*;
 use tabular::*;
 
-use crate::dcf::DCFBuilt;
+use crate::dcf::{DCFBuilt, PackageDependencies, RDepType};
 use crate::install::{parse_linkingto, REMOTE_HASH_FIELD, REMOTE_LINKINGTO_FIELD};
 use crate::library::{library_rver, sc_library_get_default, sc_library_get_list};
 use crate::textfmt::reflow;
@@ -60,16 +60,16 @@ pub fn sc_pkg_list(
 /// `name` and `rversion` are only known for a library of an R installation, and
 /// are unset when `--library` named a directory directly: rig does not need to
 /// know which R version, if any, that directory belongs to, and never asks.
-pub(super) struct ResolvedLibrary {
-    pub(super) name: Option<String>,
-    pub(super) path: PathBuf,
-    pub(super) rversion: Option<String>,
+pub(crate) struct ResolvedLibrary {
+    pub(crate) name: Option<String>,
+    pub(crate) path: PathBuf,
+    pub(crate) rversion: Option<String>,
 }
 
 impl ResolvedLibrary {
     /// How to name this library in the header line of a listing: the R version
     /// and library name it belongs to, when it has them, and always its path.
-    pub(super) fn tag(&self) -> String {
+    pub(crate) fn tag(&self) -> String {
         match (&self.rversion, &self.name) {
             (Some(rver), Some(name)) => format!("(R {}, {}: {})", rver, name, self.path.display()),
             _ => format!("({})", self.path.display()),
@@ -85,7 +85,7 @@ impl ResolvedLibrary {
 ///   `rig library list` prints them;
 /// * without `--library` it is the default library of the R version, i.e. the
 ///   path `rig library default --json` reports.
-pub(super) fn resolve_library(args: &ArgMatches) -> Result<ResolvedLibrary, Box<dyn Error>> {
+pub(crate) fn resolve_library(args: &ArgMatches) -> Result<ResolvedLibrary, Box<dyn Error>> {
     let lib = args.get_one::<String>("library");
 
     if let Some(lib) = lib {
@@ -149,35 +149,40 @@ fn looks_like_path(lib: &str) -> bool {
 /// this is a report on what is installed, so a version rig cannot parse is
 /// still shown as it is.
 #[derive(Debug)]
-pub(super) struct InstalledPackage {
-    pub(super) package: String,
-    pub(super) version: String,
+pub(crate) struct InstalledPackage {
+    pub(crate) package: String,
+    pub(crate) version: String,
     /// The directory the package is installed in, i.e. the one holding its
     /// `DESCRIPTION`. Usually named after the package, but the `Package` field
     /// of the `DESCRIPTION` is what `package` reports, so the two can differ.
-    pub(super) path: PathBuf,
-    built_r: Option<String>,
-    platform: Option<String>,
+    pub(crate) path: PathBuf,
+    pub(crate) built_r: Option<String>,
+    pub(crate) platform: Option<String>,
     /// Where the package came from: the repository name (`CRAN`) for a
     /// repository install, otherwise the remote type (`github`, `git`, …).
-    source: Option<String>,
+    pub(crate) source: Option<String>,
     /// Which remote, for a package that was not installed from a repository:
     /// the `user/repo` of a GitHub install, the URL of a git one, etc.
-    remote: Option<String>,
+    pub(crate) remote: Option<String>,
     /// The `RemoteHash` field, i.e. which upstream CRAN artifact this package
     /// was installed from. Only `rig pkg install` writes it, so it is unset for
     /// anything installed by R, pak or renv.
-    pub(super) hash: Option<String>,
+    pub(crate) hash: Option<String>,
     /// The `RemoteLinkingToHashes` field: what the package was compiled against,
     /// as `(package, version, sha256)`.
-    pub(super) linkingto: Vec<(String, String, String)>,
+    pub(crate) linkingto: Vec<(String, String, String)>,
+    /// The `License` field.
+    pub(crate) license: Option<String>,
+    /// What the package depends on, from all five dependency fields. A field
+    /// that cannot be parsed is left out.
+    pub(crate) dependencies: PackageDependencies,
 }
 
 #[cfg(test)]
 impl InstalledPackage {
     /// An installed package with only the fields the install planner looks at,
     /// so that its tests do not need a library on disk.
-    pub(super) fn for_test(
+    pub(crate) fn for_test(
         package: &str,
         version: &str,
         hash: Option<&str>,
@@ -193,6 +198,8 @@ impl InstalledPackage {
             remote: None,
             hash: hash.map(|x| x.to_string()),
             linkingto,
+            license: None,
+            dependencies: PackageDependencies::new(),
         }
     }
 }
@@ -204,7 +211,7 @@ impl InstalledPackage {
 /// (see [`crate::library::sc_library_get_list`]) live in the main library
 /// directory, and a package whose installation was interrupted has no
 /// `DESCRIPTION` yet.
-pub(super) fn read_installed(path: &Path) -> Result<Vec<InstalledPackage>, Box<dyn Error>> {
+pub(crate) fn read_installed(path: &Path) -> Result<Vec<InstalledPackage>, Box<dyn Error>> {
     debug!("Listing packages in {}", path.display());
 
     let entries = match std::fs::read_dir(path) {
@@ -291,6 +298,21 @@ fn read_package(dir: &Path, dir_name: &str) -> Result<Option<InstalledPackage>,
         .map(|x| parse_linkingto(&reflow(x)))
         .unwrap_or_default();
 
+    let mut dependencies = PackageDependencies::new();
+    for dep_type in RDepType::all() {
+        let field = dep_type.to_string();
+        if let Some(list) = para.get(&field) {
+            match PackageDependencies::from_str(&reflow(list), &field) {
+                Ok(mut deps) => dependencies.append(&mut deps),
+                Err(err) => debug!(
+                    "Ignoring unparseable {} field of {}: {}",
+                    field, package, err
+                ),
+            }
+        }
+    }
+    dependencies.simplify();
+
     Ok(Some(InstalledPackage {
         package,
         version,
@@ -301,6 +323,8 @@ fn read_package(dir: &Path, dir_name: &str) -> Result<Option<InstalledPackage>,
         remote,
         hash,
         linkingto,
+        license: para.get("License").map(reflow),
+        dependencies,
     }))
 }
 
@@ -451,7 +475,7 @@ fn print_installed(lib: &ResolvedLibrary, pkgs: &[InstalledPackage]) {
 /// The five columns here are wide and their widths only known once `tabular`
 /// has laid them out, so the rule is measured from the rendered table rather
 /// than being added as a fixed-width heading row.
-fn print_table(tab: &Table) {
+pub(super) fn print_table(tab: &Table) {
     let rendered = tab.to_string();
     let width = rendered
         .lines()
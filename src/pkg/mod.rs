@@ -4,26 +4,46 @@
 //! (`crate::repos::cranlike_metadata`) and, for full DESCRIPTION files of
 //! arbitrary versions, from P3M's sync manifests ([`manifest`]).
 
+use std::collections::HashSet;
 use std::env;
 use std::error::Error;
 use std::io::IsTerminal;
 
 use clap::ArgMatches;
 use lazy_static::lazy_static;
+use log::{debug, warn};
 use simple_error::*;
 use tabular::*;
 
-use crate::common::get_default_r_version;
+use crate::common::{get_default_r_version, sc_get_list_details};
 use crate::dcf::{Package, RDepType, RPackageVersion};
+use crate::output::OUTPUT;
 use crate::proj::BASE_PKGS;
 use crate::repos::cranlike_metadata::{self, repos_get_packages, ArchivedPackage};
+use crate::repos::{package_repos, serves_linux_binaries, PackageRepo};
+use crate::rversion::InstalledVersion;
 use crate::textfmt::{reflow, wrap, write_field};
 
+#[cfg(target_os = "macos")]
+use crate::macos::sc_get_default;
+
+#[cfg(target_os = "windows")]
+use crate::windows::sc_get_default;
+
+#[cfg(target_os = "linux")]
+use crate::linux::sc_get_default;
+
+mod check;
 pub(crate) mod deps;
-mod install;
-mod list;
+pub(crate) mod install;
+pub(crate) mod licenses;
+pub(crate) mod list;
 mod manifest;
 mod remove;
+mod revdeps;
+pub(crate) mod sbom;
+mod search;
+mod size;
 #[cfg(test)]
 mod stub;
 pub(crate) mod tree;
@@ -31,11 +51,16 @@ pub(crate) mod tree;
 pub fn sc_pkg(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn Error>> {
     match args.subcommand() {
         Some(("available", s)) => sc_pkg_available(s, args, mainargs),
+        Some(("check", s)) => check::sc_pkg_check(s, args, mainargs),
         Some(("deps", s)) => deps::sc_pkg_deps(s, args, mainargs),
         Some(("info", s)) => sc_pkg_info(s, args, mainargs),
         Some(("install", s)) => install::sc_pkg_install(s, args, mainargs),
+        Some(("licenses", s)) => licenses::sc_pkg_licenses(s, args, mainargs),
         Some(("list", s)) => list::sc_pkg_list(s, args, mainargs),
         Some(("remove", s)) => remove::sc_pkg_remove(s, args, mainargs),
+        Some(("revdeps", s)) => revdeps::sc_pkg_revdeps(s, args, mainargs),
+        Some(("search", s)) => search::sc_pkg_search(s, args, mainargs),
+        Some(("size", s)) => size::sc_pkg_size(s, args, mainargs),
         Some(("tree", s)) => tree::sc_pkg_tree(s, args, mainargs),
         _ => Ok(()), // unreachable
     }
@@ -53,10 +78,16 @@ fn sc_pkg_available(
     } else {
         crate::platform::detect_platform()?
     };
-    let r_version = if args.contains_id("r-version") {
-        args.get_one::<String>("r-version").unwrap().to_string()
-    } else {
-        get_default_r_version()?.ok_or("Cannot determine default R version")?
+    // The installation whose repositories are listed, if `--r-version` names
+    // one, or else the default one. `--r-version` can also be a version that
+    // is not installed.
+    let installed = available_installation(args.get_one::<String>("r-version"))?;
+    let r_version = match args.get_one::<String>("r-version") {
+        Some(v) => installed
+            .as_ref()
+            .and_then(|i| i.version.clone())
+            .unwrap_or_else(|| v.to_string()),
+        None => get_default_r_version()?.ok_or("Cannot determine default R version")?,
     };
     let pkg_type = if args.contains_id("pkg-type") {
         match crate::platform::resolve_package_type_synonyms(
@@ -70,15 +101,50 @@ fn sc_pkg_available(
     } else {
         "source".to_string()
     };
-    let mut packages = repos_get_packages("https://cloud.r-project.org", &pkg_type, &r_version)?;
+
+    let specs: Vec<String> = args
+        .get_many::<String>("repo")
+        .map(|v| v.cloned().collect())
+        .unwrap_or_default();
+    let repos = package_repos(
+        &specs,
+        installed.as_ref().map(|i| i.name.as_str()),
+        &platform,
+        &r_version,
+    )?;
+    let binary_type = if pkg_type == "source" {
+        crate::platform::platform_to_pkg_type(&platform, &r_version)
+    } else {
+        None
+    };
+
+    let mut packages: Vec<AvailablePackage> = vec![];
+    let mut failed = 0;
+    for repo in repos.iter() {
+        match repo_available_packages(repo, &pkg_type, binary_type.as_deref(), &r_version) {
+            Ok(mut pkgs) => packages.append(&mut pkgs),
+            Err(e) => {
+                // One unreachable repository should not hide the others.
+                failed += 1;
+                if failed == repos.len() {
+                    return Err(e);
+                }
+                OUTPUT.warn(&format!("Cannot list packages of {}: {}", repo.name, e));
+                warn!("Cannot list packages of {}: {}", repo.name, e);
+            }
+        }
+    }
+
     // Order the listing case-insensitively by package name, breaking ties by
     // version, so the output is stable regardless of how the metadata was
-    // stored or downloaded.
+    // stored or downloaded. The same version from several repositories stays
+    // in the order of the repositories.
     packages.sort_by(|a, b| {
-        a.name
+        a.package
+            .name
             .to_lowercase()
-            .cmp(&b.name.to_lowercase())
-            .then_with(|| a.version.cmp(&b.version))
+            .cmp(&b.package.name.to_lowercase())
+            .then_with(|| a.package.version.cmp(&b.package.version))
     });
 
     // Echo the platform in the header only when the user asked for a specific
@@ -103,6 +169,135 @@ fn sc_pkg_available(
     Ok(())
 }
 
+/// A package of `rig pkg available`, in one repository.
+struct AvailablePackage {
+    package: Package,
+    repo: String,
+    /// Whether the repository has a binary of this version for the platform.
+    binary: bool,
+}
+
+/// The installation `r_version` names, by name or alias, or the default
+/// installation if `r_version` is `None`.
+fn available_installation(
+    r_version: Option<&String>,
+) -> Result<Option<InstalledVersion>, Box<dyn Error>> {
+    let name = match r_version {
+        Some(v) => v.to_string(),
+        None => match sc_get_default()? {
+            Some(d) => d,
+            None => return Ok(None),
+        },
+    };
+    Ok(sc_get_list_details()?
+        .into_iter()
+        .find(|i| i.name == name || i.aliases.contains(&name)))
+}
+
+/// The packages of one repository. With a `binary_type` the binary index of
+/// the repository is looked up as well, to tell which packages have a binary.
+fn repo_available_packages(
+    repo: &PackageRepo,
+    pkg_type: &str,
+    binary_type: Option<&str>,
+    r_version: &str,
+) -> Result<Vec<AvailablePackage>, Box<dyn Error>> {
+    let packages = repos_get_packages(&repo.url, pkg_type, r_version)?;
+
+    // P3M serves Linux binaries as source packages, and a binary index lists
+    // binaries only.
+    let all_binary = pkg_type != "source" || serves_linux_binaries(&repo.url);
+    let binaries: HashSet<(String, String)> = match binary_type {
+        Some(bt) if !all_binary => match repos_get_packages(&repo.url, bt, r_version) {
+            Ok(bins) => bins
+                .into_iter()
+                .map(|p| (p.name, p.version.to_string()))
+                .collect(),
+            Err(e) => {
+                // E.g. a repository with source packages only.
+                debug!("No {} packages in {}: {}", bt, repo.name, e);
+                HashSet::new()
+            }
+        },
+        _ => HashSet::new(),
+    };
+
+    Ok(packages
+        .into_iter()
+        .map(|package| {
+            let binary = all_binary
+                || binaries.contains(&(package.name.clone(), package.version.to_string()));
+            AvailablePackage {
+                package,
+                repo: repo.name.clone(),
+                binary,
+            }
+        })
+        .collect())
+}
+
+/// The packages, as `(name, version)` pairs, that the default repositories of
+/// the default R version have a binary of, for this platform.
+fn binary_packages() -> Result<HashSet<(String, String)>, Box<dyn Error>> {
+    Ok(default_repo_packages(None)?
+        .into_iter()
+        .filter(|p| p.binary)
+        .map(|p| (p.package.name, p.package.version.to_string()))
+        .collect())
+}
+
+/// The names of the packages the default repositories of the installed R
+/// version `r_version` have, CRAN, Bioconductor, r-universe or whatever it is
+/// set up with.
+pub(crate) fn repo_package_names(r_version: &str) -> Result<HashSet<String>, Box<dyn Error>> {
+    Ok(default_repo_packages(Some(&r_version.to_string()))?
+        .into_iter()
+        .map(|p| p.package.name)
+        .collect())
+}
+
+/// The packages of the default repositories of an R version, `r_version` or
+/// the default one, for this platform, in the order of the repositories.
+/// Fails if none of the repositories can be listed.
+fn default_repo_packages(
+    r_version: Option<&String>,
+) -> Result<Vec<AvailablePackage>, Box<dyn Error>> {
+    let platform = crate::platform::detect_platform()?;
+    let installed = available_installation(r_version)?;
+    let r_version = match r_version {
+        Some(v) => installed
+            .as_ref()
+            .and_then(|i| i.version.clone())
+            .unwrap_or_else(|| v.to_string()),
+        None => get_default_r_version()?.ok_or("Cannot determine default R version")?,
+    };
+    let repos = package_repos(
+        &[],
+        installed.as_ref().map(|i| i.name.as_str()),
+        &platform,
+        &r_version,
+    )?;
+    let binary_type = crate::platform::platform_to_pkg_type(&platform, &r_version);
+
+    let mut packages: Vec<AvailablePackage> = vec![];
+    let mut failed = 0;
+    for repo in repos.iter() {
+        match repo_available_packages(repo, "source", binary_type.as_deref(), &r_version) {
+            Ok(pkgs) => packages.extend(pkgs),
+            Err(e) => {
+                // Without any repository every package would look unknown.
+                failed += 1;
+                if failed == repos.len() {
+                    return Err(e);
+                }
+                debug!("Cannot list packages of {}: {}", repo.name, e);
+            }
+        }
+    }
+
+    Ok(packages)
+}
+
 /// Count the hard dependencies of a package: `Depends`, `Imports` and
 /// `LinkingTo`, excluding R itself and the base packages. This matches the
 /// `Deps` column of `rig pkg info --versions`.
@@ -127,10 +322,12 @@ fn num_hard_deps(pkg: &Package) -> usize {
 ///
 /// A colored header line names the number of packages and the context they
 /// were resolved for (R version, package type, platform); the table then lists
-/// each package with its version and hard-dependency count. The full
-/// dependency lists are available via `--json`.
+/// each package with its version, repository, whether the repository has a
+/// binary of it, and its hard-dependency count. A package in several
+/// repositories has a row for each. The full dependency lists are available
+/// via `--json`.
 fn print_package_list(
-    packages: &[Package],
+    packages: &[AvailablePackage],
     r_version: &str,
     pkg_type: &str,
     platform: Option<&str>,
@@ -140,7 +337,11 @@ fn print_package_list(
     let color = std::io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
 
     // -- Header ------------------------------------------------------------
-    let count = packages.len();
+    let count = packages
+        .iter()
+        .map(|p| p.package.name.as_str())
+        .collect::<HashSet<_>>()
+        .len();
     let pkg_word = if count == 1 { "package" } else { "packages" };
     let head = if color {
         format!("{} {}", count.cyan().bold(), pkg_word)
@@ -162,32 +363,43 @@ fn print_package_list(
     println!();
 
     // -- Table -------------------------------------------------------------
-    let mut tab: Table = Table::new("{:<}   {:<}   {:>}");
-    tab.add_row(row!("Package", "Version", "Deps"));
+    let mut tab: Table = Table::new("{:<}   {:<}   {:<}   {:<}   {:>}");
+    tab.add_row(row!("Package", "Version", "Repository", "Binary", "Deps"));
     tab.add_heading("------------------------------------------------------------");
     for pkg in packages {
-        tab.add_row(row!(&pkg.name, &pkg.version, num_hard_deps(pkg)));
+        tab.add_row(row!(
+            &pkg.package.name,
+            &pkg.package.version,
+            &pkg.repo,
+            if pkg.binary { "X" } else { "" },
+            num_hard_deps(&pkg.package)
+        ));
     }
 
     print!("{}", tab);
 }
 
-/// Print the package listing as a JSON array, one object per package, with the
-/// full dependency information (name, types and version constraints).
-fn print_package_list_json(packages: &[Package]) -> Result<(), Box<dyn Error>> {
+/// Print the package listing as a JSON array, one object per package and
+/// repository, with the full dependency information (name, types and version
+/// constraints).
+fn print_package_list_json(packages: &[AvailablePackage]) -> Result<(), Box<dyn Error>> {
     #[derive(serde::Serialize)]
     struct PackageListEntry<'a> {
         package: &'a str,
         version: String,
+        repository: &'a str,
+        binary: bool,
         dependencies: &'a [crate::dcf::DepVersionSpec],
     }
 
     let entries: Vec<PackageListEntry> = packages
         .iter()
         .map(|pkg| PackageListEntry {
-            package: &pkg.name,
-            version: pkg.version.to_string(),
-            dependencies: &pkg.dependencies.dependencies,
+            package: &pkg.package.name,
+            version: pkg.package.version.to_string(),
+            repository: &pkg.repo,
+            binary: pkg.binary,
+            dependencies: &pkg.package.dependencies.dependencies,
         })
         .collect();
 
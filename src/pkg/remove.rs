@@ -18,6 +18,7 @@ use clap::ArgMatches;
 use log::{debug, info};
 use simple_error::*;
 
+use crate::lock::FileLock;
 use crate::output::OUTPUT;
 use crate::proj::BASE_PKGS;
 
@@ -38,6 +39,7 @@ pub fn sc_pkg_remove(
         .collect();
 
     let lib = resolve_library(args)?;
+    let _lock = FileLock::acquire(&lib.path)?;
     let installed = read_installed(&lib.path)?;
     let targets = select_packages(&names, &installed, &lib, force)?;
 
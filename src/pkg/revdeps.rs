@@ -0,0 +1,474 @@
+//! `rig pkg revdeps` and `rig pkg deps --reverse`: the packages that depend on
+//! a package.
+//!
+//! The packages on CRAN come from the reverse dependency index of the metadata
+//! database ([`DbReverseDepLoader`]), the packages of a library from their
+//! installed `DESCRIPTION` files. Either way the walk is the same: breadth
+//! first, upwards from the queried package, as deep as asked.
+
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::error::Error;
+use std::io::IsTerminal;
+
+use clap::ArgMatches;
+use simple_error::*;
+use tabular::*;
+
+use crate::dcf::{RDepType, DEP_TYPES_SOFT};
+use crate::output::OUTPUT;
+use crate::repos::cranlike_metadata::{DbReverseDepLoader, ReverseDep};
+
+use super::deps::{needed_by_cell, type_list};
+use super::list::{read_installed, resolve_library, InstalledPackage};
+
+pub fn sc_pkg_revdeps(
+    args: &ArgMatches,
+    pkgargs: &ArgMatches,
+    mainargs: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    let package = args.get_one::<String>("package").unwrap();
+    let json = args.get_flag("json") || pkgargs.get_flag("json") || mainargs.get_flag("json");
+    let depth = if args.get_flag("recursive") {
+        None
+    } else {
+        Some(*args.get_one::<u64>("depth").unwrap() as usize)
+    };
+    let types = wanted_types(
+        args.get_many::<String>("dependencies")
+            .map(|v| v.map(|s| s.as_str()).collect()),
+        args.get_flag("dev"),
+    )?;
+
+    // `--r-version` alone means the default library of that R version.
+    if args.contains_id("library") || args.contains_id("r-version") {
+        let lib = resolve_library(args)?;
+        let source = LibraryReverseDeps::new(&read_installed(&lib.path)?);
+        let tag = lib.tag();
+        reverse_deps(&source, package, &types, depth, json, Some(&tag))
+    } else {
+        let source = DbReverseDepLoader::new()?;
+        reverse_deps(&source, package, &types, depth, json, None)
+    }
+}
+
+/// `rig pkg deps --reverse`: the packages on CRAN that depend on `package`,
+/// directly, or with `--recursive`, transitively. `--dev` adds the packages
+/// that suggest or enhance it.
+pub(super) fn sc_pkg_deps_reverse(
+    args: &ArgMatches,
+    package: &str,
+    json: bool,
+) -> Result<(), Box<dyn Error>> {
+    let depth = if args.get_flag("recursive") {
+        None
+    } else {
+        Some(1)
+    };
+    let types = wanted_types(None, args.get_flag("dev"))?;
+    let source = DbReverseDepLoader::new()?;
+    reverse_deps(&source, package, &types, depth, json, None)
+}
+
+fn reverse_deps(
+    source: &dyn ReverseDepSource,
+    package: &str,
+    types: &[RDepType],
+    depth: Option<usize>,
+    json: bool,
+    tag: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let version = source.version(package)?;
+    let rows = walk_revdeps(source, package, types, depth)?;
+
+    // A typo would otherwise look like a package nothing depends on.
+    if version.is_none() && rows.is_empty() && tag.is_none() {
+        OUTPUT.warn(&format!("Package '{}' is not on CRAN", package));
+        log::warn!("Package '{}' is not on CRAN", package);
+    }
+
+    if json {
+        print_revdeps_json(&rows)?;
+    } else {
+        print_revdeps(package, version.as_deref(), tag, depth != Some(1), &rows);
+    }
+    Ok(())
+}
+
+/// The dependency types to follow: the ones `--dependencies` names, or else
+/// the hard ones, plus the soft ones with `--dev`.
+fn wanted_types(names: Option<Vec<&str>>, dev: bool) -> Result<Vec<RDepType>, Box<dyn Error>> {
+    match names {
+        Some(names) => {
+            let mut types: Vec<RDepType> = vec![];
+            for name in names {
+                let t = match RDepType::all()
+                    .iter()
+                    .find(|t| t.to_string().eq_ignore_ascii_case(name))
+                {
+                    Some(t) => t.clone(),
+                    None => bail!(
+                        "Invalid dependency type: '{}', must be one of Depends, Imports, \
+                         LinkingTo, Suggests or Enhances.",
+                        name
+                    ),
+                };
+                if !types.contains(&t) {
+                    types.push(t);
+                }
+            }
+            Ok(types)
+        }
+        None => Ok(RDepType::all()
+            .iter()
+            .filter(|t| dev || !DEP_TYPES_SOFT.contains(t))
+            .cloned()
+            .collect()),
+    }
+}
+
+// ------------------------------------------------------------------------
+// Where the reverse dependencies come from
+
+/// Answers "which packages depend on this one?", for [`walk_revdeps`].
+trait ReverseDepSource {
+    /// The packages that depend on `package` directly, in any way.
+    fn reverse_deps(&self, package: &str) -> Result<Vec<ReverseDep>, Box<dyn Error>>;
+    /// The version of `package`, if the source has it.
+    fn version(&self, package: &str) -> Result<Option<String>, Box<dyn Error>>;
+}
+
+impl ReverseDepSource for DbReverseDepLoader {
+    fn reverse_deps(&self, package: &str) -> Result<Vec<ReverseDep>, Box<dyn Error>> {
+        DbReverseDepLoader::reverse_deps(self, package)
+    }
+
+    fn version(&self, package: &str) -> Result<Option<String>, Box<dyn Error>> {
+        self.latest_version(package)
+    }
+}
+
+/// The reverse dependencies among the packages installed in a library.
+struct LibraryReverseDeps {
+    revdeps: HashMap<String, Vec<ReverseDep>>,
+    versions: HashMap<String, String>,
+}
+
+impl LibraryReverseDeps {
+    fn new(installed: &[InstalledPackage]) -> Self {
+        let mut revdeps: HashMap<String, Vec<ReverseDep>> = HashMap::new();
+        let mut versions: HashMap<String, String> = HashMap::new();
+        for pkg in installed {
+            versions.insert(pkg.package.clone(), pkg.version.clone());
+            for dep in pkg.dependencies.dependencies.iter() {
+                if dep.name == "R" {
+                    continue;
+                }
+                revdeps
+                    .entry(dep.name.clone())
+                    .or_default()
+                    .push(ReverseDep {
+                        package: pkg.package.clone(),
+                        version: pkg.version.clone(),
+                        types: dep.types.clone(),
+                    });
+            }
+        }
+        LibraryReverseDeps { revdeps, versions }
+    }
+}
+
+impl ReverseDepSource for LibraryReverseDeps {
+    fn reverse_deps(&self, package: &str) -> Result<Vec<ReverseDep>, Box<dyn Error>> {
+        Ok(self.revdeps.get(package).cloned().unwrap_or_default())
+    }
+
+    fn version(&self, package: &str) -> Result<Option<String>, Box<dyn Error>> {
+        Ok(self.versions.get(package).cloned())
+    }
+}
+
+// ------------------------------------------------------------------------
+// The walk
+
+/// One row of the reverse dependency table: a package that depends on the
+/// queried package, directly or through the packages in `depends_on`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RevDepRow {
+    name: String,
+    version: String,
+    /// How it depends on the packages in `depends_on`, e.g. `Imports`.
+    types: Vec<RDepType>,
+    /// The shortest distance from the queried package, 1 for a package that
+    /// depends on it directly.
+    depth: usize,
+    /// The packages of the previous level it depends on.
+    depends_on: Vec<String>,
+}
+
+/// The packages that depend on `package`, up to `max_depth` levels up, or all
+/// of them if `None`.
+///
+/// `types` selects the dependencies of the first level. Above that only the
+/// hard ones among them are followed, the same as `rig pkg deps --recursive`
+/// does downwards: a package that suggests a reverse dependency does not need
+/// it to be installed, so it does not break with it.
+fn walk_revdeps(
+    source: &dyn ReverseDepSource,
+    package: &str,
+    types: &[RDepType],
+    max_depth: Option<usize>,
+) -> Result<Vec<RevDepRow>, Box<dyn Error>> {
+    let hard_types: Vec<RDepType> = types
+        .iter()
+        .filter(|t| !DEP_TYPES_SOFT.contains(t))
+        .cloned()
+        .collect();
+
+    let mut rows: Vec<RevDepRow> = vec![];
+    // Row index of each package we have seen, which doubles as the "visited"
+    // set of the walk.
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    let mut queue: VecDeque<(String, usize)> = VecDeque::new();
+    queue.push_back((package.to_string(), 0));
+
+    while let Some((name, depth)) = queue.pop_front() {
+        if max_depth.is_some_and(|max| depth >= max) {
+            continue;
+        }
+        let wanted = if depth == 0 { types } else { &hard_types };
+        for revdep in source.reverse_deps(&name)? {
+            let rtypes: Vec<RDepType> = revdep
+                .types
+                .iter()
+                .filter(|t| wanted.contains(t))
+                .cloned()
+                .collect();
+            // The queried package itself can only be part of a cycle.
+            if rtypes.is_empty() || revdep.package == package {
+                continue;
+            }
+            if let Some(&idx) = seen.get(&revdep.package) {
+                let row = &mut rows[idx];
+                if !row.depends_on.contains(&name) {
+                    row.depends_on.push(name.clone());
+                }
+                for t in rtypes {
+                    if !row.types.contains(&t) {
+                        row.types.push(t);
+                    }
+                }
+                continue;
+            }
+            seen.insert(revdep.package.clone(), rows.len());
+            queue.push_back((revdep.package.clone(), depth + 1));
+            rows.push(RevDepRow {
+                name: revdep.package,
+                version: revdep.version,
+                types: rtypes,
+                depth: depth + 1,
+                depends_on: vec![name.clone()],
+            });
+        }
+    }
+
+    for row in rows.iter_mut() {
+        row.types
+            .sort_by_key(|t| RDepType::all().iter().position(|a| a == t));
+        row.depends_on.sort_by_key(|p| p.to_lowercase());
+    }
+    rows.sort_by_key(|r| (r.depth, r.name.to_lowercase()));
+
+    Ok(rows)
+}
+
+// ------------------------------------------------------------------------
+// Output
+
+/// Pretty-print the reverse dependencies of a package. The header names the
+/// package, and the library for a library, and how many packages depend on
+/// it. The table of a deeper walk has the depth and the packages each one
+/// depends on in place of the dependency types.
+fn print_revdeps(
+    name: &str,
+    version: Option<&str>,
+    tag: Option<&str>,
+    recursive: bool,
+    rows: &[RevDepRow],
+) {
+    use owo_colors::OwoColorize;
+
+    let color = std::io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+
+    // -- Header ------------------------------------------------------------
+    let count = if recursive {
+        let num_direct = rows.iter().filter(|r| r.depth == 1).count();
+        format!(
+            "{} direct, {} total reverse dependencies",
+            num_direct,
+            rows.len()
+        )
+    } else if rows.len() == 1 {
+        "1 reverse dependency".to_string()
+    } else {
+        format!("{} reverse dependencies", rows.len())
+    };
+    let head = match version {
+        Some(version) if color => format!("{} {}", name.cyan().bold(), version.bold()),
+        Some(version) => format!("{} {}", name, version),
+        None if color => name.cyan().bold().to_string(),
+        None => name.to_string(),
+    };
+    match tag {
+        Some(tag) if color => println!("{} — {} {}", head, count, tag.dimmed()),
+        Some(tag) => println!("{} — {} {}", head, count, tag),
+        None => println!("{} — {}", head, count),
+    }
+    if rows.is_empty() {
+        return;
+    }
+    println!();
+
+    // -- Table -------------------------------------------------------------
+    if recursive {
+        let mut tab: Table = Table::new("{:<}   {:<}   {:>}   {:<}");
+        tab.add_row(row!("Package", "Version", "Depth", "Depends on"));
+        tab.add_heading("-------------------------------------------------------");
+        for row in rows {
+            tab.add_row(row!(
+                &row.name,
+                &row.version,
+                &row.depth,
+                needed_by_cell(&row.depends_on)
+            ));
+        }
+        print!("{}", tab);
+    } else {
+        let mut tab: Table = Table::new("{:<}   {:<}   {:<}");
+        tab.add_row(row!("Package", "Version", "Type"));
+        tab.add_heading("-------------------------------------------------------");
+        for row in rows {
+            tab.add_row(row!(&row.name, &row.version, type_list(&row.types)));
+        }
+        print!("{}", tab);
+    }
+}
+
+/// Print the reverse dependencies as a JSON array, one object per package.
+fn print_revdeps_json(rows: &[RevDepRow]) -> Result<(), Box<dyn Error>> {
+    #[derive(serde::Serialize)]
+    struct RevDepEntry<'a> {
+        package: &'a str,
+        version: &'a str,
+        types: Vec<String>,
+        depth: usize,
+        depends_on: &'a [String],
+    }
+
+    let entries: Vec<RevDepEntry> = rows
+        .iter()
+        .map(|row| RevDepEntry {
+            package: &row.name,
+            version: &row.version,
+            types: row.types.iter().map(|t| t.to_string()).collect(),
+            depth: row.depth,
+            depends_on: &row.depends_on,
+        })
+        .collect();
+
+    println!("{}", serde_json::to_string_pretty(&entries)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pkg::stub::stub_deps;
+
+    /// A library of `(name, version, deps)` packages, `deps` as in
+    /// [`crate::pkg::stub::Stub`].
+    fn library(packages: &[(&str, &str, &str)]) -> LibraryReverseDeps {
+        let installed: Vec<InstalledPackage> = packages
+            .iter()
+            .map(|(name, version, deps)| {
+                let mut pkg = InstalledPackage::for_test(name, version, None, vec![]);
+                pkg.dependencies = stub_deps(deps);
+                pkg
+            })
+            .collect();
+        LibraryReverseDeps::new(&installed)
+    }
+
+    fn names(rows: &[RevDepRow]) -> Vec<&str> {
+        rows.iter().map(|r| r.name.as_str()).collect()
+    }
+
+    fn hard() -> Vec<RDepType> {
+        wanted_types(None, false).unwrap()
+    }
+
+    #[test]
+    fn direct_reverse_deps_are_the_hard_ones() {
+        let lib = library(&[
+            ("cli", "3.6.3", "Depends: R (>= 3.4)"),
+            ("glue", "1.8.0", "Imports: cli"),
+            ("pillar", "1.9.0", "Imports: cli, glue; LinkingTo: cli"),
+            ("testthat", "3.2.3", "Suggests: cli"),
+        ]);
+
+        let rows = walk_revdeps(&lib, "cli", &hard(), Some(1)).unwrap();
+        assert_eq!(names(&rows), vec!["glue", "pillar"]);
+        assert_eq!(rows[1].types, vec![RDepType::Imports, RDepType::LinkingTo]);
+
+        let dev = wanted_types(None, true).unwrap();
+        let rows = walk_revdeps(&lib, "cli", &dev, Some(1)).unwrap();
+        assert_eq!(names(&rows), vec!["glue", "pillar", "testthat"]);
+    }
+
+    #[test]
+    fn deeper_levels_follow_hard_deps_only() {
+        let lib = library(&[
+            ("a", "1.0.0", ""),
+            ("b", "1.0.0", "Imports: a"),
+            ("c", "1.0.0", "Suggests: a"),
+            ("d", "1.0.0", "Imports: b, c"),
+            ("e", "1.0.0", "Suggests: b"),
+            ("f", "1.0.0", "Depends: d"),
+        ]);
+
+        let dev = wanted_types(None, true).unwrap();
+        let rows = walk_revdeps(&lib, "a", &dev, None).unwrap();
+        assert_eq!(names(&rows), vec!["b", "c", "d", "f"]);
+        let d = rows.iter().find(|r| r.name == "d").unwrap();
+        assert_eq!(d.depth, 2);
+        assert_eq!(d.depends_on, vec!["b", "c"]);
+
+        let rows = walk_revdeps(&lib, "a", &dev, Some(2)).unwrap();
+        assert_eq!(names(&rows), vec!["b", "c", "d"]);
+    }
+
+    #[test]
+    fn dependency_types_filter_the_first_level() {
+        let lib = library(&[
+            ("cpp11", "0.5.2", ""),
+            ("a", "1.0.0", "LinkingTo: cpp11"),
+            ("b", "1.0.0", "Imports: cpp11"),
+        ]);
+
+        let types = wanted_types(Some(vec!["linkingto"]), false).unwrap();
+        let rows = walk_revdeps(&lib, "cpp11", &types, Some(1)).unwrap();
+        assert_eq!(names(&rows), vec!["a"]);
+
+        assert!(wanted_types(Some(vec!["Requires"]), false).is_err());
+    }
+
+    #[test]
+    fn a_cycle_terminates() {
+        let lib = library(&[("a", "1.0.0", "Imports: b"), ("b", "1.0.0", "Imports: a")]);
+
+        let rows = walk_revdeps(&lib, "a", &hard(), None).unwrap();
+        assert_eq!(names(&rows), vec!["b"]);
+        assert_eq!(lib.version("a").unwrap().as_deref(), Some("1.0.0"));
+        assert_eq!(lib.version("z").unwrap(), None);
+    }
+}
@@ -0,0 +1,766 @@
+//! `rig proj sbom`: a software bill of materials of the packages of a lockfile
+//! or a library, in the CycloneDX or the SPDX JSON format.
+//!
+//! Both formats get the same facts: every package with its package URL
+//! (`pkg:cran/cli@3.6.3`, or `pkg:github/r-lib/cli@3.6.3.9000` for one from
+//! GitHub), the sha256 of its CRAN tarball when rig knows it,
+//! its license, and what it depends on; and R itself, with its version and
+//! platform.
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::ArgMatches;
+use log::info;
+use serde_json::{json, Value};
+use simple_error::*;
+
+use crate::output::OUTPUT;
+use crate::pak::{PakLockfile, PakLockfilePackage};
+use crate::solver::is_base_package;
+
+use super::licenses::{lockfile_licenses, spdx_license};
+use super::list::{read_installed, resolve_library, InstalledPackage};
+
+/// `rig proj sbom`: from `--library`, or else from the lockfile.
+pub(crate) fn proj_sbom(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let sbom = if args.contains_id("library") {
+        let lib = resolve_library(args)?;
+        let installed = read_installed(&lib.path)?;
+        from_library(
+            &lib.path.display().to_string(),
+            lib.rversion.as_deref(),
+            &installed,
+        )
+    } else {
+        let path = args.get_one::<String>("lockfile").unwrap();
+        let contents = match fs::read_to_string(path) {
+            Ok(x) => x,
+            Err(err) => bail!(
+                "Cannot read lockfile {}: {}. Create one with `rig proj solve`.",
+                path,
+                err
+            ),
+        };
+        let lockfile: PakLockfile = serde_json::from_str(&contents)?;
+        let licenses = lockfile_licenses(&lockfile)?;
+        from_lockfile(&project_name(), &lockfile, &licenses)
+    };
+
+    let doc = DocInfo::now();
+    let out = match args.get_one::<String>("format").unwrap().as_str() {
+        "spdx" => spdx(&sbom, &doc),
+        _ => cyclonedx(&sbom, &doc),
+    };
+    let out = serde_json::to_string_pretty(&out)?;
+
+    match args.get_one::<String>("output") {
+        Some(path) => {
+            fs::write(path, out + "\n")?;
+            OUTPUT.success(&format!(
+                "Written SBOM of {} packages to {}",
+                sbom.packages.len(),
+                path
+            ));
+            info!(
+                "Written SBOM of {} packages to {}",
+                sbom.packages.len(),
+                path
+            );
+        }
+        None => println!("{}", out),
+    }
+
+    Ok(())
+}
+
+/// The project is the current directory, name it after that.
+fn project_name() -> String {
+    std::env::current_dir()
+        .ok()
+        .and_then(|d| d.file_name().map(|n| n.to_string_lossy().to_string()))
+        .unwrap_or_else(|| "project".to_string())
+}
+
+// ------------------------------------------------------------------------
+// What goes into the SBOM
+
+/// The facts of an SBOM, whichever format it is written in.
+#[derive(Debug)]
+struct Sbom {
+    /// The project, or the library.
+    name: String,
+    r_version: Option<String>,
+    platform: Option<String>,
+    packages: Vec<SbomPackage>,
+}
+
+#[derive(Debug)]
+struct SbomPackage {
+    name: String,
+    version: String,
+    purl: String,
+    /// sha256 of the CRAN source tarball the package was built from.
+    sha256: Option<String>,
+    license: Option<String>,
+    download_url: Option<String>,
+    /// The packages of the SBOM this one depends on, by name, `R` for R
+    /// itself and the base packages.
+    dependencies: Vec<String>,
+}
+
+fn from_lockfile(name: &str, lockfile: &PakLockfile, licenses: &HashMap<String, String>) -> Sbom {
+    let packages = lockfile
+        .packages
+        .iter()
+        .map(|pkg| SbomPackage {
+            name: pkg.package.clone(),
+            version: pkg.version.clone(),
+            purl: lockfile_purl(pkg),
+            sha256: pkg.metadata.get(crate::install::REMOTE_HASH_FIELD).cloned(),
+            license: licenses.get(&pkg.package).cloned(),
+            download_url: pkg.sources.first().cloned(),
+            dependencies: pkg.dependencies.clone(),
+        })
+        .collect();
+
+    Sbom {
+        name: name.to_string(),
+        r_version: Some(lockfile.r_version.clone()).filter(|v| !v.is_empty()),
+        platform: Some(lockfile.platform.clone()).filter(|p| !p.is_empty()),
+        packages,
+    }
+}
+
+/// The SBOM of a library. The R version is the one the library belongs to,
+/// or else the one most of its packages were built with, and the platform is
+/// the one of the packages with compiled code.
+fn from_library(name: &str, rversion: Option<&str>, installed: &[InstalledPackage]) -> Sbom {
+    let names: HashSet<&str> = installed.iter().map(|p| p.package.as_str()).collect();
+
+    let mut packages: Vec<SbomPackage> = vec![];
+    for pkg in installed.iter() {
+        if is_base_package(&pkg.package) {
+            continue;
+        }
+        let mut dependencies: Vec<String> = vec![];
+        for dep in pkg.dependencies.dependencies.iter() {
+            let dep_name = if dep.name == "R" || is_base_package(&dep.name) {
+                "R"
+            } else if names.contains(dep.name.as_str()) {
+                dep.name.as_str()
+            } else {
+                continue; // not installed, so not part of the SBOM
+            };
+            if !dependencies.iter().any(|d| d == dep_name) {
+                dependencies.push(dep_name.to_string());
+            }
+        }
+        packages.push(SbomPackage {
+            name: pkg.package.clone(),
+            version: pkg.version.clone(),
+            purl: library_purl(pkg),
+            sha256: pkg.hash.clone(),
+            license: pkg.license.clone(),
+            download_url: None,
+            dependencies,
+        });
+    }
+    packages.sort_by_key(|p| p.name.to_lowercase());
+
+    let r_version = rversion
+        .map(|v| v.to_string())
+        .or_else(|| most_common(installed.iter().filter_map(|p| p.built_r.as_deref())));
+    let platform = most_common(installed.iter().filter_map(|p| p.platform.as_deref()));
+
+    Sbom {
+        name: name.to_string(),
+        r_version,
+        platform,
+        packages,
+    }
+}
+
+/// The package URL of an installed package: a package from a repository is a
+/// CRAN (or Bioconductor) package, one from a code host is that repository.
+/// pak's `standard` remote type is a package from a repository, too.
+fn library_purl(pkg: &InstalledPackage) -> String {
+    let source = pkg.source.as_deref().unwrap_or("CRAN");
+    match (source, &pkg.remote) {
+        ("github" | "gitlab" | "bitbucket", Some(remote)) if remote.matches('/').count() == 1 => {
+            format!("pkg:{}/{}@{}", source, remote, pkg.version)
+        }
+        ("standard", _) => format!("pkg:cran/{}@{}", pkg.package, pkg.version),
+        (_, Some(_)) => format!("pkg:generic/{}@{}", pkg.package, pkg.version),
+        (repo, None) if repo.to_lowercase().starts_with("bioc") => {
+            format!("pkg:bioconductor/{}@{}", pkg.package, pkg.version)
+        }
+        _ => format!("pkg:cran/{}@{}", pkg.package, pkg.version),
+    }
+}
+
+/// The package URL of a lockfile entry, from its pak package type and
+/// reference, the same way [`library_purl`] reads an installed package: a
+/// GitHub (GitLab, Bitbucket) package is that repository, a package from a
+/// repository is a CRAN, or Bioconductor, package, anything else is generic.
+fn lockfile_purl(pkg: &PakLockfilePackage) -> String {
+    let rtype = pkg.r#type.as_str();
+    match rtype {
+        "github" | "gitlab" | "bitbucket" => match hosted_repo(&pkg.r#ref) {
+            Some(repo) => format!("pkg:{}/{}@{}", rtype, repo, pkg.version),
+            None => format!("pkg:generic/{}@{}", pkg.package, pkg.version),
+        },
+        "bioc" => format!("pkg:bioconductor/{}@{}", pkg.package, pkg.version),
+        "standard" | "cran"
+            if pkg
+                .sources
+                .iter()
+                .any(|url| url.contains("bioconductor.org")) =>
+        {
+            format!("pkg:bioconductor/{}@{}", pkg.package, pkg.version)
+        }
+        "standard" | "cran" => format!("pkg:cran/{}@{}", pkg.package, pkg.version),
+        _ => format!("pkg:generic/{}@{}", pkg.package, pkg.version),
+    }
+}
+
+/// The `owner/repo` of a pak code host reference, e.g. `r-lib/cli`,
+/// `github::r-lib/cli@v3.6.3` or `cli=r-lib/cli#123`. `None` for one that
+/// points into a subdirectory, or at a self-hosted instance.
+fn hosted_repo(pkg_ref: &str) -> Option<&str> {
+    let repo = match pkg_ref.split_once("::") {
+        Some((_, rest)) => rest,
+        None => pkg_ref,
+    };
+    let repo = match repo.split_once('=') {
+        Some((_, rest)) => rest,
+        None => repo,
+    };
+    let repo = repo.split(['@', '#']).next().unwrap_or(repo);
+    if repo.matches('/').count() == 1 && !repo.starts_with('/') && !repo.ends_with('/') {
+        Some(repo)
+    } else {
+        None
+    }
+}
+
+fn most_common<'a>(values: impl Iterator<Item = &'a str>) -> Option<String> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for value in values {
+        *counts.entry(value).or_default() += 1;
+    }
+    counts
+        .into_iter()
+        .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(a.0)))
+        .map(|(value, _)| value.to_string())
+}
+
+/// The packages of the SBOM that nothing else in it depends on, i.e. the ones
+/// the project itself needs.
+fn roots(sbom: &Sbom) -> Vec<&SbomPackage> {
+    let needed: HashSet<&str> = sbom
+        .packages
+        .iter()
+        .flat_map(|p| p.dependencies.iter().map(|d| d.as_str()))
+        .collect();
+    sbom.packages
+        .iter()
+        .filter(|p| !needed.contains(p.name.as_str()))
+        .collect()
+}
+
+// ------------------------------------------------------------------------
+// The formats
+
+/// When and as what the SBOM document is created.
+struct DocInfo {
+    /// RFC 3339, UTC.
+    timestamp: String,
+    uuid: String,
+}
+
+impl DocInfo {
+    /// `SOURCE_DATE_EPOCH` is the time for a reproducible build, if set.
+    fn now() -> DocInfo {
+        let secs = std::env::var("SOURCE_DATE_EPOCH")
+            .ok()
+            .and_then(|s| s.parse::<u64>().ok())
+            .unwrap_or_else(|| {
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0)
+            });
+        DocInfo {
+            timestamp: rfc3339(secs),
+            uuid: uuid_v4(rand::random()),
+        }
+    }
+}
+
+fn tool_version() -> String {
+    env!("CARGO_PKG_VERSION").to_string()
+}
+
+/// A CycloneDX 1.5 JSON document.
+fn cyclonedx(sbom: &Sbom, doc: &DocInfo) -> Value {
+    let bom_ref: HashMap<&str, &str> = sbom
+        .packages
+        .iter()
+        .map(|p| (p.name.as_str(), p.purl.as_str()))
+        .collect();
+    let dep_ref = |name: &str| bom_ref.get(name).copied().unwrap_or("R").to_string();
+
+    let mut components: Vec<Value> = vec![];
+    if let Some(r_version) = &sbom.r_version {
+        let mut r = json!({
+            "type": "platform",
+            "bom-ref": "R",
+            "name": "R",
+            "version": r_version,
+        });
+        if let Some(platform) = &sbom.platform {
+            r["properties"] = json!([{ "name": "platform", "value": platform }]);
+        }
+        components.push(r);
+    }
+    for pkg in sbom.packages.iter() {
+        let mut c = json!({
+            "type": "library",
+            "bom-ref": pkg.purl,
+            "name": pkg.name,
+            "version": pkg.version,
+            "purl": pkg.purl,
+        });
+        if let Some(sha256) = &pkg.sha256 {
+            c["hashes"] = json!([{ "alg": "SHA-256", "content": sha256 }]);
+        }
+        if let Some(license) = &pkg.license {
+            c["licenses"] = match spdx_license(license) {
+                Some(expr) => json!([{ "expression": expr }]),
+                None => json!([{ "license": { "name": license } }]),
+            };
+        }
+        if let Some(url) = &pkg.download_url {
+            c["externalReferences"] = json!([{ "type": "distribution", "url": url }]);
+        }
+        components.push(c);
+    }
+
+    let mut project_deps: Vec<String> = roots(sbom).iter().map(|p| p.purl.clone()).collect();
+    if sbom.r_version.is_some() {
+        project_deps.insert(0, "R".to_string());
+    }
+    let mut dependencies = vec![json!({ "ref": "project", "dependsOn": project_deps })];
+    for pkg in sbom.packages.iter() {
+        let deps: Vec<String> = pkg
+            .dependencies
+            .iter()
+            .filter(|d| *d != "R" || sbom.r_version.is_some())
+            .map(|d| dep_ref(d))
+            .collect();
+        dependencies.push(json!({ "ref": pkg.purl, "dependsOn": deps }));
+    }
+
+    json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.5",
+        "serialNumber": format!("urn:uuid:{}", doc.uuid),
+        "version": 1,
+        "metadata": {
+            "timestamp": doc.timestamp,
+            "tools": {
+                "components": [{
+                    "type": "application",
+                    "name": "rig",
+                    "version": tool_version(),
+                }],
+            },
+            "component": {
+                "type": "application",
+                "bom-ref": "project",
+                "name": sbom.name,
+            },
+        },
+        "components": components,
+        "dependencies": dependencies,
+    })
+}
+
+/// An SPDX 2.3 JSON document.
+fn spdx(sbom: &Sbom, doc: &DocInfo) -> Value {
+    let mut packages: Vec<Value> = vec![json!({
+        "name": sbom.name,
+        "SPDXID": "SPDXRef-Project",
+        "downloadLocation": "NOASSERTION",
+        "filesAnalyzed": false,
+    })];
+    let mut relationships: Vec<Value> = vec![json!({
+        "spdxElementId": "SPDXRef-DOCUMENT",
+        "relationshipType": "DESCRIBES",
+        "relatedSpdxElement": "SPDXRef-Project",
+    })];
+    let depends_on = |from: &str, to: &str| {
+        json!({
+            "spdxElementId": from,
+            "relationshipType": "DEPENDS_ON",
+            "relatedSpdxElement": to,
+        })
+    };
+
+    if let Some(r_version) = &sbom.r_version {
+        let mut r = json!({
+            "name": "R",
+            "SPDXID": "SPDXRef-R",
+            "versionInfo": r_version,
+            "downloadLocation": "NOASSERTION",
+            "filesAnalyzed": false,
+            "primaryPackagePurpose": "FRAMEWORK",
+        });
+        if let Some(platform) = &sbom.platform {
+            r["comment"] = json!(format!("Platform: {}", platform));
+        }
+        packages.push(r);
+        relationships.push(depends_on("SPDXRef-Project", "SPDXRef-R"));
+    }
+
+    for pkg in sbom.packages.iter() {
+        let license = pkg
+            .license
+            .as_deref()
+            .and_then(spdx_license)
+            .unwrap_or_else(|| "NOASSERTION".to_string());
+        let mut p = json!({
+            "name": pkg.name,
+            "SPDXID": spdx_id(&pkg.name),
+            "versionInfo": pkg.version,
+            "downloadLocation": pkg.download_url.as_deref().unwrap_or("NOASSERTION"),
+            "filesAnalyzed": false,
+            "licenseConcluded": "NOASSERTION",
+            "licenseDeclared": license,
+            "copyrightText": "NOASSERTION",
+            "primaryPackagePurpose": "LIBRARY",
+            "externalRefs": [{
+                "referenceCategory": "PACKAGE-MANAGER",
+                "referenceType": "purl",
+                "referenceLocator": pkg.purl,
+            }],
+        });
+        if let Some(sha256) = &pkg.sha256 {
+            p["checksums"] = json!([{ "algorithm": "SHA256", "checksumValue": sha256 }]);
+        }
+        if let Some(license) = &pkg.license {
+            p["licenseComments"] = json!(format!("License: {}", license));
+        }
+        packages.push(p);
+
+        for dep in pkg.dependencies.iter() {
+            if dep == "R" {
+                if sbom.r_version.is_some() {
+                    relationships.push(depends_on(&spdx_id(&pkg.name), "SPDXRef-R"));
+                }
+            } else {
+                relationships.push(depends_on(&spdx_id(&pkg.name), &spdx_id(dep)));
+            }
+        }
+    }
+    for root in roots(sbom) {
+        relationships.push(depends_on("SPDXRef-Project", &spdx_id(&root.name)));
+    }
+
+    let namespace_name: String = sbom
+        .name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    json!({
+        "spdxVersion": "SPDX-2.3",
+        "dataLicense": "CC0-1.0",
+        "SPDXID": "SPDXRef-DOCUMENT",
+        "name": sbom.name,
+        "documentNamespace": format!(
+            "https://spdx.org/spdxdocs/{}-{}",
+            namespace_name.trim_matches('-'),
+            doc.uuid
+        ),
+        "creationInfo": {
+            "created": doc.timestamp,
+            "creators": [format!("Tool: rig-{}", tool_version())],
+        },
+        "packages": packages,
+        "relationships": relationships,
+    })
+}
+
+/// An SPDX identifier may only have letters, digits, `.` and `-`; R package
+/// names only have letters, digits and `.`.
+fn spdx_id(package: &str) -> String {
+    let id: String = package
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!("SPDXRef-Package-{}", id)
+}
+
+/// Seconds since the epoch as an RFC 3339 UTC timestamp, e.g.
+/// `2026-10-18T09:30:00Z`.
+fn rfc3339(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+    // Howard Hinnant's `civil_from_days`.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// A random (version 4) UUID from 16 random bytes.
+fn uuid_v4(mut bytes: [u8; 16]) -> String {
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pak::PakLockfilePackage;
+    use crate::pkg::stub::stub_deps;
+
+    fn doc() -> DocInfo {
+        DocInfo {
+            timestamp: rfc3339(0),
+            uuid: uuid_v4([0; 16]),
+        }
+    }
+
+    fn locked(name: &str, version: &str, deps: &[&str]) -> PakLockfilePackage {
+        let mut metadata = HashMap::new();
+        metadata.insert(
+            crate::install::REMOTE_HASH_FIELD.to_string(),
+            format!("{}-sha", name),
+        );
+        PakLockfilePackage {
+            r#ref: name.to_string(),
+            package: name.to_string(),
+            version: version.to_string(),
+            r#type: "standard".to_string(),
+            direct: false,
+            binary: false,
+            dependencies: deps.iter().map(|d| d.to_string()).collect(),
+            vignettes: false,
+            metadata,
+            sources: vec![format!("https://cran.r-project.org/{}.tar.gz", name)],
+            target: "x".to_string(),
+            platform: "source".to_string(),
+            rversion: "4.5.1".to_string(),
+            directpkg: false,
+            license: "UNKNOWN".to_string(),
+            dep_types: vec![],
+            params: vec![],
+            install_args: "".to_string(),
+            install_env: "".to_string(),
+            sysreqs: "".to_string(),
+        }
+    }
+
+    fn lockfile_sbom() -> Sbom {
+        let lockfile = PakLockfile {
+            lockfile_version: 1,
+            os: "linux".to_string(),
+            r_version: "4.5.1".to_string(),
+            platform: "x86_64-pc-linux-gnu".to_string(),
+            packages: vec![
+                locked("cli", "3.6.3", &[]),
+                locked("glue", "1.8.0", &["cli"]),
+            ],
+        };
+        let mut licenses = HashMap::new();
+        licenses.insert("cli".to_string(), "MIT + file LICENSE".to_string());
+        licenses.insert("glue".to_string(), "file LICENSE".to_string());
+        from_lockfile("myproj", &lockfile, &licenses)
+    }
+
+    #[test]
+    fn timestamps_and_uuids() {
+        assert_eq!(rfc3339(0), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc3339(951782400), "2000-02-29T00:00:00Z");
+        assert_eq!(rfc3339(1792315845), "2026-10-18T09:30:45Z");
+        assert_eq!(uuid_v4([0; 16]), "00000000-0000-4000-8000-000000000000");
+    }
+
+    #[test]
+    fn cyclonedx_has_purls_hashes_licenses_and_dependencies() {
+        let bom = cyclonedx(&lockfile_sbom(), &doc());
+
+        assert_eq!(bom["bomFormat"], "CycloneDX");
+        let components = bom["components"].as_array().unwrap();
+        assert_eq!(components[0]["name"], "R");
+        assert_eq!(components[0]["version"], "4.5.1");
+        assert_eq!(
+            components[0]["properties"][0]["value"],
+            "x86_64-pc-linux-gnu"
+        );
+        let cli = &components[1];
+        assert_eq!(cli["purl"], "pkg:cran/cli@3.6.3");
+        assert_eq!(cli["hashes"][0]["content"], "cli-sha");
+        assert_eq!(cli["licenses"][0]["expression"], "MIT");
+        assert_eq!(
+            components[2]["licenses"][0]["license"]["name"],
+            "file LICENSE"
+        );
+
+        let deps = bom["dependencies"].as_array().unwrap();
+        assert_eq!(deps[0]["ref"], "project");
+        assert_eq!(deps[0]["dependsOn"], json!(["R", "pkg:cran/glue@1.8.0"]));
+        assert_eq!(deps[2]["ref"], "pkg:cran/glue@1.8.0");
+        assert_eq!(deps[2]["dependsOn"], json!(["pkg:cran/cli@3.6.3"]));
+    }
+
+    #[test]
+    fn spdx_has_packages_and_relationships() {
+        let bom = spdx(&lockfile_sbom(), &doc());
+
+        assert_eq!(bom["spdxVersion"], "SPDX-2.3");
+        assert_eq!(
+            bom["documentNamespace"],
+            "https://spdx.org/spdxdocs/myproj-00000000-0000-4000-8000-000000000000"
+        );
+        let packages = bom["packages"].as_array().unwrap();
+        let cli = packages.iter().find(|p| p["name"] == "cli").unwrap();
+        assert_eq!(cli["SPDXID"], "SPDXRef-Package-cli");
+        assert_eq!(cli["licenseDeclared"], "MIT");
+        assert_eq!(cli["checksums"][0]["checksumValue"], "cli-sha");
+        assert_eq!(
+            cli["externalRefs"][0]["referenceLocator"],
+            "pkg:cran/cli@3.6.3"
+        );
+        let glue = packages.iter().find(|p| p["name"] == "glue").unwrap();
+        assert_eq!(glue["licenseDeclared"], "NOASSERTION");
+
+        let rels: Vec<(String, String, String)> = bom["relationships"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| {
+                (
+                    r["spdxElementId"].as_str().unwrap().to_string(),
+                    r["relationshipType"].as_str().unwrap().to_string(),
+                    r["relatedSpdxElement"].as_str().unwrap().to_string(),
+                )
+            })
+            .collect();
+        let rel = |a: &str, t: &str, b: &str| (a.to_string(), t.to_string(), b.to_string());
+        assert!(rels.contains(&rel("SPDXRef-DOCUMENT", "DESCRIBES", "SPDXRef-Project")));
+        assert!(rels.contains(&rel("SPDXRef-Project", "DEPENDS_ON", "SPDXRef-R")));
+        assert!(rels.contains(&rel(
+            "SPDXRef-Package-glue",
+            "DEPENDS_ON",
+            "SPDXRef-Package-cli"
+        )));
+        assert!(rels.contains(&rel(
+            "SPDXRef-Project",
+            "DEPENDS_ON",
+            "SPDXRef-Package-glue"
+        )));
+        assert!(!rels.contains(&rel("SPDXRef-Project", "DEPENDS_ON", "SPDXRef-Package-cli")));
+    }
+
+    #[test]
+    fn a_lockfile_sbom_uses_the_package_type_and_ref() {
+        let mut glue = locked("glue", "1.8.0.9000", &["cli"]);
+        glue.r#type = "github".to_string();
+        glue.r#ref = "tidyverse/glue@main".to_string();
+        glue.sources = vec!["https://api.github.com/repos/tidyverse/glue/tarball/abc".to_string()];
+        let mut limma = locked("limma", "3.62.1", &[]);
+        limma.sources = vec![
+            "https://bioconductor.org/packages/3.20/bioc/src/contrib/limma_3.62.1.tar.gz"
+                .to_string(),
+        ];
+        let mut local = locked("mypkg", "0.1.0", &[]);
+        local.r#type = "local".to_string();
+        local.r#ref = "local::./mypkg".to_string();
+        let lockfile = PakLockfile {
+            lockfile_version: 1,
+            os: "linux".to_string(),
+            r_version: "4.5.1".to_string(),
+            platform: "x86_64-pc-linux-gnu".to_string(),
+            packages: vec![locked("cli", "3.6.3", &[]), glue, limma, local],
+        };
+
+        let sbom = from_lockfile("myproj", &lockfile, &HashMap::new());
+
+        let purls: Vec<&str> = sbom.packages.iter().map(|p| p.purl.as_str()).collect();
+        assert_eq!(
+            purls,
+            vec![
+                "pkg:cran/cli@3.6.3",
+                "pkg:github/tidyverse/glue@1.8.0.9000",
+                "pkg:bioconductor/limma@3.62.1",
+                "pkg:generic/mypkg@0.1.0",
+            ]
+        );
+        assert_eq!(hosted_repo("github::r-lib/cli"), Some("r-lib/cli"));
+        assert_eq!(hosted_repo("cli=r-lib/cli#123"), Some("r-lib/cli"));
+        assert_eq!(hosted_repo("r-lib/actions/setup-r"), None);
+    }
+
+    #[test]
+    fn a_library_sbom_uses_the_remote_provenance() {
+        let mut cli = InstalledPackage::for_test("cli", "3.6.3", Some("abc"), vec![]);
+        cli.source = Some("CRAN".to_string());
+        cli.built_r = Some("4.5.1".to_string());
+        cli.dependencies = stub_deps("Depends: R (>= 3.4); Imports: utils");
+        let mut glue = InstalledPackage::for_test("glue", "1.8.0-9000", None, vec![]);
+        glue.source = Some("github".to_string());
+        glue.remote = Some("tidyverse/glue".to_string());
+        glue.platform = Some("aarch64-apple-darwin20".to_string());
+        glue.dependencies = stub_deps("Imports: cli, missing");
+        let base = InstalledPackage::for_test("utils", "4.5.1", None, vec![]);
+
+        let sbom = from_library("/lib", None, &[glue, cli, base]);
+
+        assert_eq!(sbom.r_version.as_deref(), Some("4.5.1"));
+        assert_eq!(sbom.platform.as_deref(), Some("aarch64-apple-darwin20"));
+        let names: Vec<&str> = sbom.packages.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["cli", "glue"]);
+        assert_eq!(sbom.packages[0].purl, "pkg:cran/cli@3.6.3");
+        assert_eq!(sbom.packages[0].sha256.as_deref(), Some("abc"));
+        assert_eq!(sbom.packages[0].dependencies, vec!["R"]);
+        assert_eq!(
+            sbom.packages[1].purl,
+            "pkg:github/tidyverse/glue@1.8.0-9000"
+        );
+        assert_eq!(sbom.packages[1].dependencies, vec!["cli"]);
+    }
+}
@@ -0,0 +1,95 @@
+//! `rig pkg search`: find CRAN packages by the words of their title and
+//! description.
+//!
+//! The search runs on the full text index of the metadata database
+//! (`crate::repos::cranlike_metadata`), so it works offline once the
+//! metadata is cached.
+
+use std::env;
+use std::error::Error;
+use std::io::IsTerminal;
+
+use clap::ArgMatches;
+use tabular::*;
+
+use crate::repos::cranlike_metadata::{search_packages, SearchResult};
+use crate::textfmt::reflow;
+
+pub fn sc_pkg_search(
+    args: &ArgMatches,
+    pkgargs: &ArgMatches,
+    mainargs: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    let json = args.get_flag("json") || pkgargs.get_flag("json") || mainargs.get_flag("json");
+    let query = args
+        .get_many::<String>("query")
+        .unwrap()
+        .map(|s| s.as_str())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let limit = *args.get_one::<usize>("limit").unwrap();
+
+    let mut results = search_packages(&query, limit)?;
+    for result in results.iter_mut() {
+        result.title = result.title.as_deref().map(reflow);
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+    } else {
+        let color = std::io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+        print_search_results(&query, &results, color);
+    }
+
+    Ok(())
+}
+
+/// Pretty-print the results of `rig pkg search`, best match first. Packages
+/// CRAN has archived are marked with the date they were archived.
+fn print_search_results(query: &str, results: &[SearchResult], color: bool) {
+    use owo_colors::OwoColorize;
+
+    // -- Header ------------------------------------------------------------
+    let count = results.len();
+    let pkg_word = if count == 1 { "package" } else { "packages" };
+    let head = if color {
+        format!("{} {}", count.cyan().bold(), pkg_word)
+    } else {
+        format!("{} {}", count, pkg_word)
+    };
+    let tag = format!("(matching '{}')", query);
+    println!(
+        "{} {}",
+        head,
+        if color { tag.dimmed().to_string() } else { tag }
+    );
+    if count == 0 {
+        return;
+    }
+    println!();
+
+    // -- Table -------------------------------------------------------------
+    let mut tab: Table = Table::new("{:<}   {:<}   {:<}");
+    tab.add_row(row!("Package", "Version", "Title"));
+    tab.add_heading("------------------------------------------------------------");
+    for result in results {
+        let mut title = result.title.clone().unwrap_or_default();
+        if let Some(archived) = &result.archived {
+            let note = format!("(archived {})", archived);
+            title = format!(
+                "{} {}",
+                title,
+                if color {
+                    note.yellow().to_string()
+                } else {
+                    note
+                }
+            )
+            .trim_start()
+            .to_string();
+        }
+        tab.add_row(row!(&result.package, &result.version, title));
+    }
+
+    print!("{}", tab);
+}
@@ -0,0 +1,523 @@
+//! `rig pkg size`: how much disk space the packages of a library take, each on
+//! its own and together with everything it depends on.
+//!
+//! The second number is the one that matters when deciding on a new
+//! dependency: a small package can pull in a lot. It is the size of the
+//! package's closure over its hard dependencies, i.e. everything that has to be
+//! installed for it. The base packages and the packages of R's system library
+//! are installed with R, so they do not count. A package that is not installed
+//! is weighed by the `filesize` of its repository entry, the size of the
+//! package file, which is an estimate: installed packages are bigger.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::IsTerminal;
+use std::path::Path;
+
+use clap::ArgMatches;
+use log::{debug, warn};
+use simple_error::*;
+use tabular::*;
+
+use crate::common::get_r_system_library;
+use crate::dcf::{PackageDependencies, RDepType};
+use crate::library::library_rver;
+use crate::output::OUTPUT;
+use crate::solver::is_base_package;
+use crate::textfmt::format_bytes;
+
+use super::default_repo_packages;
+use super::list::{print_table, read_installed, resolve_library, ResolvedLibrary};
+
+pub fn sc_pkg_size(
+    args: &ArgMatches,
+    pkgargs: &ArgMatches,
+    mainargs: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    let json = args.get_flag("json") || pkgargs.get_flag("json") || mainargs.get_flag("json");
+    let lib = resolve_library(args)?;
+    let rver = match &lib.rversion {
+        Some(rver) => Some(rver.clone()),
+        None => library_rver(args).ok(),
+    };
+
+    let mut nodes: HashMap<String, Node> = HashMap::new();
+    for pkg in read_installed(&lib.path)? {
+        if is_base_package(&pkg.package) {
+            continue;
+        }
+        let size = dir_size(&pkg.path);
+        nodes.insert(
+            pkg.package.clone(),
+            Node {
+                package: pkg.package,
+                version: pkg.version,
+                size: Some(size),
+                installed: true,
+                deps: hard_deps(&pkg.dependencies),
+            },
+        );
+    }
+    let free = match &rver {
+        Some(rver) => system_packages(rver),
+        None => HashSet::new(),
+    };
+
+    let mut weights = Weights {
+        nodes,
+        free,
+        rver,
+        loaded: false,
+    };
+    match args.get_one::<String>("package") {
+        Some(package) => {
+            let closure = weights.closure(package)?;
+            if json {
+                print_closure_json(&closure)?;
+            } else {
+                print_closure(&lib, &closure);
+            }
+        }
+        None => {
+            let mut packages: Vec<String> = weights
+                .nodes
+                .values()
+                .filter(|n| n.installed)
+                .map(|n| n.package.clone())
+                .collect();
+            packages.sort();
+            let mut closures: Vec<Closure> = vec![];
+            for package in packages {
+                closures.push(weights.closure(&package)?);
+            }
+            closures.sort_by(|a, b| {
+                b.total()
+                    .cmp(&a.total())
+                    .then_with(|| a.root().package.cmp(&b.root().package))
+            });
+            if json {
+                print_library_json(&closures)?;
+            } else {
+                print_library(&lib, &closures);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The names of the packages in the system library of R `rver`.
+fn system_packages(rver: &str) -> HashSet<String> {
+    match get_r_system_library(rver).map(|path| read_installed(&path)) {
+        Ok(Ok(pkgs)) => pkgs.into_iter().map(|p| p.package).collect(),
+        Ok(Err(err)) | Err(err) => {
+            warn!("Cannot read the system library of R {}: {}", rver, err);
+            HashSet::new()
+        }
+    }
+}
+
+/// The total size of the files in a directory, recursively, without following
+/// symbolic links. What cannot be read counts as empty.
+fn dir_size(path: &Path) -> u64 {
+    let meta = match fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(err) => {
+            debug!("Cannot stat {}: {}", path.display(), err);
+            return 0;
+        }
+    };
+    if !meta.is_dir() {
+        return meta.len();
+    }
+    match fs::read_dir(path) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| dir_size(&e.path()))
+            .sum(),
+        Err(err) => {
+            debug!("Cannot list {}: {}", path.display(), err);
+            0
+        }
+    }
+}
+
+/// The hard dependencies of a package, without R and the base packages.
+fn hard_deps(deps: &PackageDependencies) -> Vec<String> {
+    deps.dependencies
+        .iter()
+        .filter(|d| {
+            d.types.iter().any(|t| {
+                matches!(
+                    t,
+                    RDepType::Depends | RDepType::Imports | RDepType::LinkingTo
+                )
+            })
+        })
+        .filter(|d| !is_base_package(&d.name))
+        .map(|d| d.name.clone())
+        .collect()
+}
+
+// ------------------------------------------------------------------------
+// Weighing
+
+/// A package, as far as its weight goes.
+#[derive(Debug, Clone)]
+struct Node {
+    package: String,
+    version: String,
+    /// The installed size, or the size of the package file for a package that
+    /// is not installed. `None` if the repository does not know it.
+    size: Option<u64>,
+    installed: bool,
+    deps: Vec<String>,
+}
+
+/// A package, and everything that has to be installed for it.
+#[derive(Debug)]
+struct Closure {
+    /// The package itself comes first.
+    nodes: Vec<Node>,
+    /// Dependencies neither the library nor the repositories have.
+    unknown: Vec<String>,
+}
+
+impl Closure {
+    fn root(&self) -> &Node {
+        &self.nodes[0]
+    }
+
+    fn total(&self) -> u64 {
+        self.nodes.iter().filter_map(|n| n.size).sum()
+    }
+
+    /// Whether the total is not simply the sum of installed sizes.
+    fn estimated(&self) -> bool {
+        !self.unknown.is_empty() || self.nodes.iter().any(|n| !n.installed)
+    }
+}
+
+/// The packages to weigh: the installed ones, and, once a closure needs one
+/// that is not installed, those of the repositories.
+struct Weights {
+    nodes: HashMap<String, Node>,
+    /// Packages that are installed with R.
+    free: HashSet<String>,
+    /// The R version whose repositories to look packages up in.
+    rver: Option<String>,
+    loaded: bool,
+}
+
+impl Weights {
+    fn closure(&mut self, package: &str) -> Result<Closure, Box<dyn Error>> {
+        if let Some(closure) = closure(package, &self.nodes, &self.free) {
+            if closure.unknown.is_empty() || self.loaded {
+                return Ok(closure);
+            }
+        }
+        if !self.loaded {
+            self.load_repositories();
+        }
+        match closure(package, &self.nodes, &self.free) {
+            Some(closure) => Ok(closure),
+            None => bail!(
+                "{} is neither installed, nor available from the repositories",
+                package
+            ),
+        }
+    }
+
+    /// Add the packages of the repositories that are not installed. Only once,
+    /// and only warn if it fails: the installed packages can still be weighed.
+    fn load_repositories(&mut self) {
+        self.loaded = true;
+        let available = match default_repo_packages(self.rver.as_ref()) {
+            Ok(x) => x,
+            Err(err) => {
+                OUTPUT.warn(&format!(
+                    "Cannot list the packages of the repositories, sizes are incomplete: {}",
+                    err
+                ));
+                warn!("Cannot list the packages of the repositories: {}", err);
+                return;
+            }
+        };
+        // The first repository that has a package wins, as in R.
+        for pkg in available {
+            let name = pkg.package.name.clone();
+            self.nodes.entry(name).or_insert_with(|| Node {
+                package: pkg.package.name,
+                version: pkg.package.version.to_string(),
+                size: pkg.package.filesize,
+                installed: false,
+                deps: hard_deps(&pkg.package.dependencies),
+            });
+        }
+    }
+}
+
+/// The closure of `package` over `nodes`, breadth first, so that direct
+/// dependencies come before indirect ones. `None` if `nodes` does not have the
+/// package itself.
+fn closure(
+    package: &str,
+    nodes: &HashMap<String, Node>,
+    free: &HashSet<String>,
+) -> Option<Closure> {
+    let root = nodes.get(package)?;
+    let mut seen: HashSet<&str> = HashSet::from([package]);
+    let mut queue: VecDeque<&Node> = VecDeque::from([root]);
+    let mut out = Closure {
+        nodes: vec![],
+        unknown: vec![],
+    };
+    while let Some(node) = queue.pop_front() {
+        out.nodes.push(node.clone());
+        for dep in node.deps.iter() {
+            if free.contains(dep) || !seen.insert(dep.as_str()) {
+                continue;
+            }
+            match nodes.get(dep) {
+                Some(dep) => queue.push_back(dep),
+                None => out.unknown.push(dep.clone()),
+            }
+        }
+    }
+    Some(out)
+}
+
+// ------------------------------------------------------------------------
+// Reporting
+
+/// A size, `~` marking an estimate and `?` an unknown one.
+fn size_cell(size: Option<u64>, estimated: bool) -> String {
+    match size {
+        None => "?".to_string(),
+        Some(size) if estimated => format!("~{}", format_bytes(size)),
+        Some(size) => format_bytes(size),
+    }
+}
+
+fn print_library(lib: &ResolvedLibrary, closures: &[Closure]) {
+    use owo_colors::OwoColorize;
+
+    let color = std::io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+    let count = closures.len();
+    let total: u64 = closures.iter().filter_map(|c| c.root().size).sum();
+    let head = format!(
+        "{} {}, {}",
+        count,
+        if count == 1 { "package" } else { "packages" },
+        format_bytes(total)
+    );
+    let tag = lib.tag();
+    if color {
+        println!("{} {}", head.cyan().bold(), tag.dimmed());
+    } else {
+        println!("{} {}", head, tag);
+    }
+    if count == 0 {
+        return;
+    }
+    println!();
+
+    let mut tab: Table = Table::new("{:<}   {:<}   {:>}   {:>}   {:>}");
+    tab.add_row(row!("Package", "Version", "Size", "Deps", "With deps"));
+    for closure in closures {
+        let root = closure.root();
+        tab.add_row(row!(
+            &root.package,
+            &root.version,
+            size_cell(root.size, false),
+            closure.nodes.len() - 1 + closure.unknown.len(),
+            size_cell(Some(closure.total()), closure.estimated())
+        ));
+    }
+    print_table(&tab);
+}
+
+fn print_closure(lib: &ResolvedLibrary, closure: &Closure) {
+    use owo_colors::OwoColorize;
+
+    let color = std::io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+    let root = closure.root();
+    let deps = closure.nodes.len() - 1 + closure.unknown.len();
+    let head = format!(
+        "{} {}: {} {}, {} in total",
+        root.package,
+        root.version,
+        deps,
+        if deps == 1 {
+            "dependency"
+        } else {
+            "dependencies"
+        },
+        size_cell(Some(closure.total()), closure.estimated())
+    );
+    let tag = lib.tag();
+    if color {
+        println!("{} {}", head.cyan().bold(), tag.dimmed());
+    } else {
+        println!("{} {}", head, tag);
+    }
+    println!();
+
+    // The package itself, then the heaviest dependencies first.
+    let mut deps: Vec<&Node> = closure.nodes.iter().skip(1).collect();
+    deps.sort_by(|a, b| {
+        b.size
+            .cmp(&a.size)
+            .then_with(|| a.package.to_lowercase().cmp(&b.package.to_lowercase()))
+    });
+    let mut tab: Table = Table::new("{:<}   {:<}   {:>}   {:<}");
+    tab.add_row(row!("Package", "Version", "Size", "Installed"));
+    for node in std::iter::once(root).chain(deps) {
+        tab.add_row(row!(
+            &node.package,
+            &node.version,
+            size_cell(node.size, !node.installed),
+            if node.installed { "yes" } else { "no" }
+        ));
+    }
+    for name in closure.unknown.iter() {
+        tab.add_row(row!(name, "?", "?", "no"));
+    }
+    print_table(&tab);
+
+    if !closure.unknown.is_empty() {
+        println!();
+        OUTPUT.warn(&format!(
+            "Unknown dependencies, not counted: {}",
+            closure.unknown.join(", ")
+        ));
+    }
+}
+
+#[derive(serde::Serialize)]
+struct NodeEntry<'a> {
+    package: &'a str,
+    version: &'a str,
+    size: Option<u64>,
+    installed: bool,
+}
+
+#[derive(serde::Serialize)]
+struct ClosureEntry<'a> {
+    package: &'a str,
+    version: &'a str,
+    size: Option<u64>,
+    dependencies: usize,
+    total_size: u64,
+    estimated: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    closure: Option<Vec<NodeEntry<'a>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unknown: Option<&'a [String]>,
+}
+
+impl<'a> ClosureEntry<'a> {
+    fn new(closure: &'a Closure, full: bool) -> ClosureEntry<'a> {
+        let root = closure.root();
+        ClosureEntry {
+            package: &root.package,
+            version: &root.version,
+            size: root.size,
+            dependencies: closure.nodes.len() - 1 + closure.unknown.len(),
+            total_size: closure.total(),
+            estimated: closure.estimated(),
+            closure: full.then(|| {
+                closure
+                    .nodes
+                    .iter()
+                    .skip(1)
+                    .map(|n| NodeEntry {
+                        package: &n.package,
+                        version: &n.version,
+                        size: n.size,
+                        installed: n.installed,
+                    })
+                    .collect()
+            }),
+            unknown: full.then_some(closure.unknown.as_slice()),
+        }
+    }
+}
+
+fn print_library_json(closures: &[Closure]) -> Result<(), Box<dyn Error>> {
+    let entries: Vec<ClosureEntry> = closures
+        .iter()
+        .map(|c| ClosureEntry::new(c, false))
+        .collect();
+    println!("{}", serde_json::to_string_pretty(&entries)?);
+    Ok(())
+}
+
+fn print_closure_json(closure: &Closure) -> Result<(), Box<dyn Error>> {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&ClosureEntry::new(closure, true))?
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(name: &str, size: Option<u64>, installed: bool, deps: &[&str]) -> (String, Node) {
+        (
+            name.to_string(),
+            Node {
+                package: name.to_string(),
+                version: "1.0.0".to_string(),
+                size,
+                installed,
+                deps: deps.iter().map(|d| d.to_string()).collect(),
+            },
+        )
+    }
+
+    #[test]
+    fn the_closure_counts_every_package_once() {
+        let nodes: HashMap<String, Node> = HashMap::from([
+            node("usethis", Some(100), true, &["cli", "glue", "MASS"]),
+            node("cli", Some(50), true, &["glue"]),
+            node("glue", Some(10), true, &[]),
+            node("MASS", Some(1000), true, &[]),
+        ]);
+        let free = HashSet::from(["MASS".to_string()]);
+        let closure = closure("usethis", &nodes, &free).unwrap();
+        let names: Vec<&str> = closure.nodes.iter().map(|n| n.package.as_str()).collect();
+        assert_eq!(names, ["usethis", "cli", "glue"]);
+        assert_eq!(closure.total(), 160);
+        assert!(!closure.estimated());
+    }
+
+    #[test]
+    fn uninstalled_and_unknown_packages_make_an_estimate() {
+        let nodes: HashMap<String, Node> = HashMap::from([
+            node("usethis", Some(100), true, &["cli", "gone"]),
+            node("cli", Some(20), false, &[]),
+        ]);
+        let closure = closure("usethis", &nodes, &HashSet::new()).unwrap();
+        assert_eq!(closure.total(), 120);
+        assert_eq!(closure.unknown, ["gone"]);
+        assert!(closure.estimated());
+        assert_eq!(size_cell(Some(120), true), "~120 B");
+        assert_eq!(size_cell(None, true), "?");
+
+        assert!(super::closure("nope", &nodes, &HashSet::new()).is_none());
+    }
+
+    #[test]
+    fn dir_size_adds_up_the_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        fs::create_dir_all(tmp.path().join("pkg/R")).unwrap();
+        fs::write(tmp.path().join("pkg/DESCRIPTION"), "12345").unwrap();
+        fs::write(tmp.path().join("pkg/R/pkg"), "123").unwrap();
+        assert_eq!(dir_size(&tmp.path().join("pkg")), 8);
+    }
+}
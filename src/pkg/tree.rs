@@ -10,6 +10,9 @@
 //! `--why` prints the same closure the other way around: the named package is
 //! the root and the tree grows towards the packages that need it, down to the
 //! queried package, which becomes a leaf. See [`invert_tree`].
+//!
+//! `--format dot|mermaid|graphml` prints the closure as a graph instead, every
+//! package once and every dependency an edge, for drawing. See [`tree_graph`].
 
 use std::collections::{HashMap, HashSet};
 use std::env;
@@ -18,12 +21,14 @@ use std::fmt::Write;
 use std::io::IsTerminal;
 
 use clap::ArgMatches;
+use log::warn;
 use simple_error::*;
 
 use super::deps::{
     newest_version, requirements, root_package, type_rank, version_cell_for, wanted_dep, Newest,
 };
 use crate::dcf::{DepVersionSpec, RDepType, RPackageVersion, DEP_TYPES_SOFT};
+use crate::output::OUTPUT;
 use crate::repos::DbSourcePackageLoader;
 use crate::solver::{is_base_package, PackageVersionLoader};
 
@@ -42,6 +47,7 @@ pub fn sc_pkg_tree(
     let no_base = args.get_flag("no-base");
     let why = args.get_one::<String>("why").map(|s| s.as_str());
     let json = args.get_flag("json") || pkgargs.get_flag("json") || mainargs.get_flag("json");
+    let format = TreeFormat::from_args(args, json);
 
     let loader = DbSourcePackageLoader::new()?;
     let tree = dep_tree(&loader, &package, &ver, dev, no_base)?;
@@ -50,7 +56,7 @@ pub fn sc_pkg_tree(
         None => tree,
     };
 
-    print_tree(&tree, json)
+    print_tree(&tree, format)
 }
 
 /// `rig proj tree`: the dependency tree of a project's manifest.
@@ -70,7 +76,7 @@ pub(crate) fn proj_tree(
     dev: bool,
     no_base: bool,
     why: Option<&str>,
-    json: bool,
+    format: TreeFormat,
 ) -> Result<(), Box<dyn Error>> {
     let loader = DbSourcePackageLoader::new()?;
     let tree = tree_from_deps(
@@ -86,17 +92,64 @@ pub(crate) fn proj_tree(
         None => tree,
     };
 
-    print_tree(&tree, json)
+    print_tree(&tree, format)
 }
 
-/// Print a tree, as one nested JSON object with `--json`, otherwise as the
-/// colored tree, with the color left out when stdout is not a terminal.
-fn print_tree(tree: &DepTree, json: bool) -> Result<(), Box<dyn Error>> {
-    if json {
-        print_tree_json(tree)?;
-    } else {
-        let color = std::io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
-        print!("{}", render_tree(tree, color));
+/// How `rig pkg tree` and `rig proj tree` print the tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TreeFormat {
+    Text,
+    Json,
+    Dot,
+    Mermaid,
+    GraphMl,
+}
+
+impl TreeFormat {
+    /// `--format`, if given, otherwise `--json` or the text tree.
+    pub(crate) fn from_args(args: &ArgMatches, json: bool) -> TreeFormat {
+        match args.get_one::<String>("format").map(|f| f.as_str()) {
+            Some("dot") => TreeFormat::Dot,
+            Some("mermaid") => TreeFormat::Mermaid,
+            Some("graphml") => TreeFormat::GraphMl,
+            _ if json => TreeFormat::Json,
+            _ => TreeFormat::Text,
+        }
+    }
+}
+
+/// Print a tree, as one nested JSON object with `--json`, as a graph with
+/// `--format`, otherwise as the colored tree, with the color left out when
+/// stdout is not a terminal.
+fn print_tree(tree: &DepTree, format: TreeFormat) -> Result<(), Box<dyn Error>> {
+    match format {
+        TreeFormat::Text => {
+            let color = std::io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+            print!("{}", render_tree(tree, color));
+        }
+        TreeFormat::Json => print_tree_json(tree)?,
+        TreeFormat::Dot | TreeFormat::Mermaid | TreeFormat::GraphMl => {
+            // Only the colors need the binaries, so the graph is still worth
+            // drawing without them.
+            let binaries = match super::binary_packages() {
+                Ok(binaries) => Some(binaries),
+                Err(err) => {
+                    OUTPUT.warn(&format!(
+                        "Cannot tell which packages have binaries: {}",
+                        err
+                    ));
+                    warn!("Cannot tell which packages have binaries: {}", err);
+                    None
+                }
+            };
+            let graph = tree_graph(tree, binaries.as_ref());
+            let out = match format {
+                TreeFormat::Dot => render_dot(&graph),
+                TreeFormat::Mermaid => render_mermaid(&graph),
+                _ => render_graphml(&graph),
+            };
+            print!("{}", out);
+        }
     }
 
     Ok(())
@@ -856,6 +909,343 @@ fn tree_json(tree: &DepTree) -> Result<String, Box<dyn Error>> {
     Ok(out)
 }
 
+// ------------------------------------------------------------------------
+// Graph output
+
+/// The dependency graph of a tree, for `--format dot|mermaid|graphml`: every
+/// package of the tree once, the root first, and every edge once.
+#[derive(Debug)]
+struct DepGraph {
+    name: String,
+    nodes: Vec<GraphNode>,
+    edges: Vec<GraphEdge>,
+    /// Whether the graph is of an inverted, `--why` tree. It is drawn bottom
+    /// up then, so that its root is at the top, like in the text tree.
+    inverted: bool,
+}
+
+#[derive(Debug)]
+struct GraphNode {
+    name: String,
+    version: Option<String>,
+    kind: NodeKind,
+}
+
+/// What a node is colored by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NodeKind {
+    /// The queried package or project, or the `--why` package.
+    Root,
+    /// R or a base package, which ship with R.
+    Base,
+    /// The repositories have a binary build of it for this platform.
+    Binary,
+    /// The repositories only have the source package.
+    Source,
+    /// rig could not look up the binaries.
+    Unknown,
+}
+
+impl NodeKind {
+    fn name(&self) -> &'static str {
+        match self {
+            NodeKind::Root => "root",
+            NodeKind::Base => "base",
+            NodeKind::Binary => "binary",
+            NodeKind::Source => "source",
+            NodeKind::Unknown => "unknown",
+        }
+    }
+
+    fn color(&self) -> &'static str {
+        match self {
+            NodeKind::Root => "#9ecae1",
+            NodeKind::Base => "#d9d9d9",
+            NodeKind::Binary => "#c7e9c0",
+            NodeKind::Source => "#fdd0a2",
+            NodeKind::Unknown => "#ffffff",
+        }
+    }
+}
+
+/// An edge from a package to one it needs, by index into the nodes.
+#[derive(Debug)]
+struct GraphEdge {
+    from: usize,
+    to: usize,
+    types: Vec<RDepType>,
+}
+
+impl GraphEdge {
+    /// e.g. `Imports, LinkingTo`.
+    fn label(&self) -> String {
+        self.types
+            .iter()
+            .map(|t| t.to_string())
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+
+    /// A soft edge is drawn dashed.
+    fn soft(&self) -> bool {
+        self.types.iter().all(|t| DEP_TYPES_SOFT.contains(t))
+    }
+}
+
+/// The graph of a tree, from the edges [`collect_edges`] finds in it. The
+/// edges always point from a package to the one it needs, in an inverted tree,
+/// too: there a child needs its parent.
+///
+/// `binaries` are the `(name, version)` pairs that have a binary build, or
+/// `None` if they are not known.
+fn tree_graph(tree: &DepTree, binaries: Option<&HashSet<(String, String)>>) -> DepGraph {
+    let mut parents: HashMap<String, Vec<Dependent>> = HashMap::new();
+    let mut versions: HashMap<String, Option<RPackageVersion>> = HashMap::new();
+    collect_edges(&tree.root, &mut parents, &mut versions);
+    versions.insert(tree.root.name.clone(), tree.root.version.clone());
+
+    let mut names: Vec<&String> = versions
+        .keys()
+        .filter(|name| **name != tree.root.name)
+        .collect();
+    names.sort_by_key(|name| (r_first(name), name.to_lowercase()));
+    names.insert(0, &tree.root.name);
+
+    let nodes: Vec<GraphNode> = names
+        .iter()
+        .map(|name| {
+            let version = versions[*name].as_ref().map(|v| v.to_string());
+            let kind = if **name == tree.root.name {
+                NodeKind::Root
+            } else if is_base_package(name) {
+                NodeKind::Base
+            } else {
+                match (binaries, &version) {
+                    (None, _) => NodeKind::Unknown,
+                    (Some(bin), Some(v)) if bin.contains(&(name.to_string(), v.clone())) => {
+                        NodeKind::Binary
+                    }
+                    _ => NodeKind::Source,
+                }
+            };
+            GraphNode {
+                name: name.to_string(),
+                version,
+                kind,
+            }
+        })
+        .collect();
+    let index: HashMap<&str, usize> = nodes
+        .iter()
+        .enumerate()
+        .map(|(i, n)| (n.name.as_str(), i))
+        .collect();
+
+    let mut edges: Vec<GraphEdge> = vec![];
+    for (package, dependents) in parents.iter() {
+        for dependent in dependents {
+            let (from, to) = if tree.why.is_some() {
+                (package.as_str(), dependent.name.as_str())
+            } else {
+                (dependent.name.as_str(), package.as_str())
+            };
+            let types: Vec<RDepType> = RDepType::all()
+                .iter()
+                .filter(|t| dependent.types.contains(t))
+                .cloned()
+                .collect();
+            edges.push(GraphEdge {
+                from: index[from],
+                to: index[to],
+                types,
+            });
+        }
+    }
+    edges.sort_by_key(|e| (e.from, e.to));
+
+    DepGraph {
+        name: tree.root.name.clone(),
+        nodes,
+        edges,
+        inverted: tree.why.is_some(),
+    }
+}
+
+/// A node's label: the package and its version, if it has one.
+fn graph_label(node: &GraphNode) -> String {
+    match &node.version {
+        Some(version) => format!("{} {}", node.name, version),
+        None => node.name.clone(),
+    }
+}
+
+/// The graph in Graphviz's DOT language, e.g. for `dot -Tsvg`.
+fn render_dot(graph: &DepGraph) -> String {
+    fn escape(s: &str) -> String {
+        s.replace('\\', "\\\\").replace('"', "\\\"")
+    }
+    fn quote(s: &str) -> String {
+        format!("\"{}\"", escape(s))
+    }
+
+    let mut out = String::new();
+    let _ = writeln!(out, "digraph {} {{", quote(&graph.name));
+    let _ = writeln!(
+        out,
+        "  rankdir={};",
+        if graph.inverted { "BT" } else { "TB" }
+    );
+    let _ = writeln!(
+        out,
+        "  node [shape=box, style=\"rounded,filled\", fontname=\"Helvetica\"];"
+    );
+    let _ = writeln!(out, "  edge [fontname=\"Helvetica\", fontsize=10];");
+    for node in graph.nodes.iter() {
+        let _ = writeln!(
+            out,
+            "  {} [label={}, fillcolor=\"{}\"{}];",
+            quote(&node.name),
+            match &node.version {
+                Some(version) => format!("\"{}\\n{}\"", escape(&node.name), escape(version)),
+                None => quote(&node.name),
+            },
+            node.kind.color(),
+            if node.kind == NodeKind::Root {
+                ", penwidth=2"
+            } else {
+                ""
+            }
+        );
+    }
+    for edge in graph.edges.iter() {
+        let _ = writeln!(
+            out,
+            "  {} -> {} [label={}{}];",
+            quote(&graph.nodes[edge.from].name),
+            quote(&graph.nodes[edge.to].name),
+            quote(&edge.label()),
+            if edge.soft() { ", style=dashed" } else { "" }
+        );
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// The graph as a Mermaid flowchart, e.g. for a Markdown document.
+fn render_mermaid(graph: &DepGraph) -> String {
+    fn quote(s: &str) -> String {
+        format!("\"{}\"", s.replace('"', "#quot;"))
+    }
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "flowchart {}",
+        if graph.inverted { "BT" } else { "TB" }
+    );
+    for (i, node) in graph.nodes.iter().enumerate() {
+        let _ = writeln!(out, "  n{}[{}]", i, quote(&graph_label(node)));
+    }
+    for edge in graph.edges.iter() {
+        let arrow = if edge.soft() { "-.->" } else { "-->" };
+        let _ = writeln!(
+            out,
+            "  n{} {}|{}| n{}",
+            edge.from,
+            arrow,
+            quote(&edge.label()),
+            edge.to
+        );
+    }
+    for kind in [
+        NodeKind::Root,
+        NodeKind::Base,
+        NodeKind::Binary,
+        NodeKind::Source,
+        NodeKind::Unknown,
+    ] {
+        let members: Vec<String> = graph
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| n.kind == kind)
+            .map(|(i, _)| format!("n{}", i))
+            .collect();
+        if members.is_empty() {
+            continue;
+        }
+        let _ = writeln!(out, "  classDef {} fill:{}", kind.name(), kind.color());
+        let _ = writeln!(out, "  class {} {}", members.join(","), kind.name());
+    }
+    out
+}
+
+/// The graph in GraphML, e.g. for Gephi or yEd. The kind and color of a node
+/// and the dependency types of an edge are data attributes.
+fn render_graphml(graph: &DepGraph) -> String {
+    fn escape(s: &str) -> String {
+        s.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    }
+
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str("<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
+    for (id, target) in [
+        ("name", "node"),
+        ("version", "node"),
+        ("kind", "node"),
+        ("color", "node"),
+        ("types", "edge"),
+    ] {
+        let _ = writeln!(
+            out,
+            "  <key id=\"{}\" for=\"{}\" attr.name=\"{}\" attr.type=\"string\"/>",
+            id, target, id
+        );
+    }
+    let _ = writeln!(
+        out,
+        "  <graph id=\"{}\" edgedefault=\"directed\">",
+        escape(&graph.name)
+    );
+    for (i, node) in graph.nodes.iter().enumerate() {
+        let _ = writeln!(out, "    <node id=\"n{}\">", i);
+        let _ = writeln!(
+            out,
+            "      <data key=\"name\">{}</data>",
+            escape(&node.name)
+        );
+        if let Some(version) = &node.version {
+            let _ = writeln!(
+                out,
+                "      <data key=\"version\">{}</data>",
+                escape(version)
+            );
+        }
+        let _ = writeln!(out, "      <data key=\"kind\">{}</data>", node.kind.name());
+        let _ = writeln!(
+            out,
+            "      <data key=\"color\">{}</data>",
+            node.kind.color()
+        );
+        out.push_str("    </node>\n");
+    }
+    for (i, edge) in graph.edges.iter().enumerate() {
+        let _ = writeln!(
+            out,
+            "    <edge id=\"e{}\" source=\"n{}\" target=\"n{}\">",
+            i, edge.from, edge.to
+        );
+        let _ = writeln!(out, "      <data key=\"types\">{}</data>", edge.label());
+        out.push_str("    </edge>\n");
+    }
+    out.push_str("  </graph>\n</graphml>\n");
+    out
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1958,4 +2348,134 @@ mod tests {
             Some("[DS]".to_string())
         );
     }
+
+    // ---------------------------------------------------------------------
+    // Graph output
+
+    fn graph_stub() -> Stub {
+        Stub {
+            packages: vec![
+                (
+                    "a",
+                    "1.0.0",
+                    "Depends: R (>= 4.1); Imports: b, c; Suggests: d",
+                ),
+                ("b", "1.1.0", "Imports: c, utils; LinkingTo: c"),
+                ("c", "1.2.0", ""),
+                ("d", "1.3.0", "Imports: c"),
+            ],
+        }
+    }
+
+    fn binaries(x: &[(&str, &str)]) -> HashSet<(String, String)> {
+        x.iter()
+            .map(|(n, v)| (n.to_string(), v.to_string()))
+            .collect()
+    }
+
+    /// The edges as `from -> to (types)`.
+    fn edges(graph: &DepGraph) -> Vec<String> {
+        graph
+            .edges
+            .iter()
+            .map(|e| {
+                format!(
+                    "{} -> {} ({})",
+                    graph.nodes[e.from].name,
+                    graph.nodes[e.to].name,
+                    e.label()
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn the_graph_has_every_package_and_edge_once() {
+        let tree = dep_tree(&graph_stub(), "a", "latest", true, false).unwrap();
+        let graph = tree_graph(&tree, Some(&binaries(&[("b", "1.1.0")])));
+
+        let nodes: Vec<(&str, NodeKind)> = graph
+            .nodes
+            .iter()
+            .map(|n| (n.name.as_str(), n.kind))
+            .collect();
+        assert_eq!(
+            nodes,
+            vec![
+                ("a", NodeKind::Root),
+                ("R", NodeKind::Base),
+                ("b", NodeKind::Binary),
+                ("c", NodeKind::Source),
+                ("d", NodeKind::Source),
+                ("utils", NodeKind::Base),
+            ]
+        );
+        assert_eq!(
+            edges(&graph),
+            vec![
+                "a -> R (Depends)",
+                "a -> b (Imports)",
+                "a -> c (Imports)",
+                "a -> d (Suggests)",
+                "b -> c (Imports, LinkingTo)",
+                "b -> utils (Imports)",
+                "d -> c (Imports)",
+            ]
+        );
+        assert!(!graph.inverted);
+
+        let unknown = tree_graph(&tree, None);
+        assert_eq!(unknown.nodes[2].kind, NodeKind::Unknown);
+    }
+
+    #[test]
+    fn an_inverted_graph_still_points_at_what_is_needed() {
+        let tree = dep_tree(&graph_stub(), "a", "latest", true, false).unwrap();
+        let tree = invert_tree(&tree, "c", true, false).unwrap();
+        let graph = tree_graph(&tree, None);
+
+        assert!(graph.inverted);
+        assert_eq!(graph.nodes[0].name, "c");
+        assert_eq!(graph.nodes[0].kind, NodeKind::Root);
+        assert_eq!(
+            edges(&graph),
+            vec![
+                "a -> c (Imports)",
+                "a -> b (Imports)",
+                "a -> d (Suggests)",
+                "b -> c (Imports, LinkingTo)",
+                "d -> c (Imports)",
+            ]
+        );
+    }
+
+    #[test]
+    fn the_graph_formats() {
+        let tree = dep_tree(&graph_stub(), "a", "latest", true, true).unwrap();
+        let graph = tree_graph(&tree, Some(&binaries(&[("b", "1.1.0")])));
+
+        let dot = render_dot(&graph);
+        assert!(dot.starts_with("digraph \"a\" {\n  rankdir=TB;\n"));
+        assert!(dot.contains("  \"a\" [label=\"a\\n1.0.0\", fillcolor=\"#9ecae1\", penwidth=2];\n"));
+        assert!(dot.contains("  \"b\" [label=\"b\\n1.1.0\", fillcolor=\"#c7e9c0\"];\n"));
+        assert!(dot.contains("  \"b\" -> \"c\" [label=\"Imports, LinkingTo\"];\n"));
+        assert!(dot.contains("  \"a\" -> \"d\" [label=\"Suggests\", style=dashed];\n"));
+        assert!(dot.ends_with("}\n"));
+
+        let mermaid = render_mermaid(&graph);
+        assert!(mermaid.starts_with("flowchart TB\n  n0[\"a 1.0.0\"]\n"));
+        assert!(mermaid.contains("  n1 -->|\"Imports, LinkingTo\"| n2\n"));
+        assert!(mermaid.contains("  n0 -.->|\"Suggests\"| n3\n"));
+        assert!(mermaid.contains("  class n2,n3 source\n"));
+        assert!(!mermaid.contains("classDef base"));
+
+        let graphml = render_graphml(&graph);
+        assert!(graphml.contains("  <graph id=\"a\" edgedefault=\"directed\">\n"));
+        assert!(graphml.contains("      <data key=\"kind\">binary</data>\n"));
+        assert!(graphml.contains(
+            "    <edge id=\"e2\" source=\"n0\" target=\"n3\">\n      \
+             <data key=\"types\">Suggests</data>\n"
+        ));
+        assert!(graphml.ends_with("</graph>\n</graphml>\n"));
+    }
 }
@@ -10,8 +10,8 @@
 //! * [`ppm_platforms`], [`ppm_status`] and `r-versions` read P3M's status
 //!   document at `<ppm>/__api__/status`, where `<ppm>` honors
 //!   `PACKAGEMANAGER_ADDRESS`.
-//! * [`ppm_builds`] reads the per-package binary index, which is rig's own
-//!   derived data on its own host and does *not* follow
+//! * [`ppm_builds`] and [`ppm_coverage`] read the per-package binary index,
+//!   which is rig's own derived data on its own host and does *not* follow
 //!   `PACKAGEMANAGER_ADDRESS`. No P3M instance serves those files.
 
 use std::env;
@@ -25,16 +25,19 @@ use tabular::{Row, Table};
 use crate::repos::binaries::{ppm_url, PpmDistro, PpmStatus};
 
 mod ppm_builds;
+mod ppm_coverage;
 mod ppm_platforms;
 mod ppm_status;
 
 use ppm_builds::sc_ppm_builds;
+use ppm_coverage::sc_ppm_coverage;
 use ppm_platforms::sc_ppm_platforms;
 use ppm_status::sc_ppm_status;
 
 pub fn sc_ppm(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn Error>> {
     match args.subcommand() {
         Some(("builds", s)) => sc_ppm_builds(s, args, mainargs),
+        Some(("coverage", s)) => sc_ppm_coverage(s, args, mainargs),
         Some(("platforms", s)) => sc_ppm_platforms(s, args, mainargs),
         Some(("r-versions", s)) => sc_ppm_r_versions(s, args, mainargs),
         Some(("status", s)) => sc_ppm_status(s, args, mainargs),
@@ -0,0 +1,337 @@
+//! `rig ppm coverage`: which packages of a lockfile have no P3M binary for a
+//! build target, and so would be compiled from source there.
+//!
+//! Like `rig ppm builds`, this reads the per-package binary index from rig's
+//! own host, one index per package, and narrows it to each target with
+//! [`artifacts_for_target`], the same way a solve does.
+
+use std::error::Error;
+use std::fs;
+
+use clap::ArgMatches;
+use log::{info, warn};
+use owo_colors::OwoColorize;
+use simple_error::bail;
+use tabular::{row, Table};
+
+use crate::dcf::RPackageVersion;
+use crate::output::OUTPUT;
+use crate::pak::{PakLockfile, PakLockfilePackage};
+use crate::platform::detect_platform;
+use crate::ppm::{print_table, use_color, want_json};
+use crate::repos::binaries::loader::{artifacts_for_target, BinaryTarget};
+use crate::repos::binaries::{
+    load_binary_index, prefetch_binary_indices, validate_package_name, BinaryIndex, PpmStatus,
+};
+use crate::repos::cranlike_metadata::minor_r_version;
+
+/// The coverage of one build target, and the shape of the `--json` output.
+#[derive(Debug, serde::Serialize)]
+struct TargetCoverage {
+    /// `jammy-x86_64`, as a lockfile spells the target.
+    target: String,
+    r_version: String,
+    /// The repository packages of the lockfile, the ones that can have a
+    /// binary.
+    packages: usize,
+    /// The packages without a binary of their locked version.
+    missing: Vec<MissingBinary>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct MissingBinary {
+    package: String,
+    version: String,
+    /// The newest version that does have a binary for the target, if any.
+    newest_binary: Option<String>,
+}
+
+pub fn sc_ppm_coverage(
+    args: &ArgMatches,
+    ppmargs: &ArgMatches,
+    mainargs: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    let path = args.get_one::<String>("lockfile").unwrap();
+    let contents = match fs::read_to_string(path) {
+        Ok(x) => x,
+        Err(err) => bail!(
+            "Cannot read lockfile {}: {}. Create one with `rig proj solve`.",
+            path,
+            err
+        ),
+    };
+    let lockfile: PakLockfile = serde_json::from_str(&contents)?;
+    let targets = coverage_targets(args, &lockfile)?;
+
+    // Only repository packages are on P3M. Anything else, e.g. a GitHub
+    // package, is always built from source.
+    let (packages, others): (Vec<&PakLockfilePackage>, Vec<&PakLockfilePackage>) = lockfile
+        .packages
+        .iter()
+        .partition(|p| p.r#type == "standard");
+    if !others.is_empty() {
+        let names: Vec<&str> = others.iter().map(|p| p.package.as_str()).collect();
+        OUTPUT.info(&format!(
+            "Skipping packages that are not from a repository: {}",
+            names.join(", ")
+        ));
+        info!("Skipping non-repository packages: {}", names.join(", "));
+    }
+
+    let names: Vec<String> = packages.iter().map(|p| p.package.clone()).collect();
+    prefetch_binary_indices(&names, None);
+
+    let mut reports: Vec<TargetCoverage> = targets
+        .iter()
+        .map(|t| TargetCoverage {
+            target: t.name(),
+            r_version: t.r_version.clone(),
+            packages: packages.len(),
+            missing: vec![],
+        })
+        .collect();
+    for pkg in packages.iter() {
+        // Before the name is echoed into a URL or a cache path.
+        validate_package_name(&pkg.package)?;
+        let cached = load_binary_index(&pkg.package, None)?;
+        let index = cached.as_ref().map(|c| &c.index);
+        for (target, report) in targets.iter().zip(reports.iter_mut()) {
+            if let Some(missing) = missing_binary(index, pkg, target) {
+                report.missing.push(missing);
+            }
+        }
+    }
+
+    // No binary of any version of any package is more likely a misspelled
+    // target than a real one without binaries.
+    for report in reports.iter() {
+        let none = report.missing.iter().all(|m| m.newest_binary.is_none());
+        if report.packages > 0 && report.missing.len() == report.packages && none {
+            OUTPUT.warn(&format!(
+                "No binaries at all for {}, R {}. See `rig ppm platforms` for the \
+                 targets P3M builds for.",
+                report.target, report.r_version
+            ));
+            warn!("No binaries for {} R {}", report.target, report.r_version);
+        }
+    }
+
+    if want_json(args, ppmargs, mainargs) {
+        println!("{}", serde_json::to_string_pretty(&reports)?);
+    } else {
+        print_coverage(&reports);
+    }
+
+    Ok(())
+}
+
+/// The targets to check: every `--platform` with every `--r-version`.
+///
+/// A platform is a P3M platform name, with an optional arch, the way a lockfile
+/// names the target: `jammy`, `noble-arm64`, `macos-arm64`. The arch defaults
+/// to `x86_64`. Without `--platform` the target is this machine's, and without
+/// `--r-version` the R version is the lockfile's.
+fn coverage_targets(
+    args: &ArgMatches,
+    lockfile: &PakLockfile,
+) -> Result<Vec<BinaryTarget>, Box<dyn Error>> {
+    let platforms: Vec<(String, String)> = match args.get_many::<String>("platform") {
+        Some(platforms) => platforms.map(|p| parse_target(p)).collect(),
+        None => {
+            let platform = detect_platform()?;
+            match PpmStatus::load(None)?.ppm_platform(&platform) {
+                Some(target) => vec![target],
+                None => bail!("P3M has no binaries for this platform, use --platform"),
+            }
+        }
+    };
+    let r_versions: Vec<String> = match args.get_many::<String>("r-version") {
+        Some(versions) => versions.cloned().collect(),
+        None if lockfile.r_version.is_empty() || lockfile.r_version == "*" => {
+            bail!("The lockfile does not name an R version, use --r-version")
+        }
+        None => vec![lockfile.r_version.clone()],
+    };
+
+    let mut targets = vec![];
+    for (platform, arch) in platforms.iter() {
+        for r_version in r_versions.iter() {
+            targets.push(BinaryTarget {
+                platform: platform.clone(),
+                arch: arch.clone(),
+                r_version: minor_r_version(r_version)?,
+            });
+        }
+    }
+    Ok(targets)
+}
+
+/// `noble-arm64` is `(noble, arm64)`, `jammy` is `(jammy, x86_64)`.
+fn parse_target(target: &str) -> (String, String) {
+    for arch in ["x86_64", "arm64"] {
+        if let Some(platform) = target.strip_suffix(&format!("-{}", arch)) {
+            return (platform.to_string(), arch.to_string());
+        }
+    }
+    (target.to_string(), "x86_64".to_string())
+}
+
+/// `None` if the index has a binary of the locked version for the target,
+/// otherwise what is missing, with the newest version that has one.
+fn missing_binary(
+    index: Option<&BinaryIndex>,
+    pkg: &PakLockfilePackage,
+    target: &BinaryTarget,
+) -> Option<MissingBinary> {
+    let binaries = index
+        .map(|index| artifacts_for_target(index, target).binaries)
+        .unwrap_or_default();
+    let locked = RPackageVersion::from_str(&pkg.version).ok();
+    let has_locked = binaries
+        .iter()
+        .any(|b| Some(&b.version) == locked.as_ref() || b.version.original == pkg.version);
+    if has_locked {
+        return None;
+    }
+    Some(MissingBinary {
+        package: pkg.package.clone(),
+        version: pkg.version.clone(),
+        newest_binary: binaries
+            .iter()
+            .map(|b| &b.version)
+            .max()
+            .map(|v| v.to_string()),
+    })
+}
+
+fn print_coverage(reports: &[TargetCoverage]) {
+    let color = use_color();
+
+    // -- Summary, one line per target ---------------------------------------
+    for report in reports {
+        let target = format!("{}, R {}:", report.target, report.r_version);
+        let summary = if report.missing.is_empty() {
+            format!("all {} packages have a binary", report.packages)
+        } else {
+            format!(
+                "{} of {} packages have no binary",
+                report.missing.len(),
+                report.packages
+            )
+        };
+        if color && report.missing.is_empty() {
+            println!("{} {}", target.bold(), summary.green());
+        } else if color {
+            println!("{} {}", target.bold(), summary.yellow());
+        } else {
+            println!("{} {}", target, summary);
+        }
+    }
+    if reports.iter().all(|r| r.missing.is_empty()) {
+        return;
+    }
+    println!();
+
+    // -- Table -------------------------------------------------------------
+    let mut tab: Table = Table::new("{:<}   {:<}   {:<}   {:<}   {:<}");
+    tab.add_row(row!(
+        "package",
+        "version",
+        "target",
+        "r_version",
+        "newest_binary"
+    ));
+    for report in reports {
+        for missing in report.missing.iter() {
+            tab.add_row(row!(
+                &missing.package,
+                &missing.version,
+                &report.target,
+                &report.r_version,
+                missing.newest_binary.as_deref().unwrap_or("-")
+            ));
+        }
+    }
+    print_table(&tab);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repos::binaries::{blob, parse_binaries_tsv};
+    use std::path::PathBuf;
+
+    fn index(name: &str, package: &str) -> BinaryIndex {
+        let bytes = fs::read(PathBuf::from("tests/fixtures/binaries").join(name)).unwrap();
+        let rows = parse_binaries_tsv(&bytes).unwrap();
+        let blob = blob::build(package, &rows).unwrap();
+        BinaryIndex::open_blob(&blob).unwrap()
+    }
+
+    fn locked(package: &str, version: &str) -> PakLockfilePackage {
+        let json = format!(
+            r#"{{
+              "ref": "{0}", "package": "{0}", "version": "{1}", "type": "standard",
+              "direct": true, "binary": false, "dependencies": [], "vignettes": false,
+              "metadata": {{}}, "sources": [], "target": "", "platform": "source",
+              "rversion": "4.5", "directpkg": true, "license": "", "dep_types": [],
+              "params": [], "install_args": "", "sysreqs": ""
+            }}"#,
+            package, version
+        );
+        serde_json::from_str(&json).unwrap()
+    }
+
+    fn target(platform: &str, arch: &str, r_version: &str) -> BinaryTarget {
+        BinaryTarget {
+            platform: platform.to_string(),
+            arch: arch.to_string(),
+            r_version: r_version.to_string(),
+        }
+    }
+
+    #[test]
+    fn targets_are_spelled_like_lockfiles() {
+        assert_eq!(
+            parse_target("noble-arm64"),
+            ("noble".to_string(), "arm64".to_string())
+        );
+        assert_eq!(
+            parse_target("jammy"),
+            ("jammy".to_string(), "x86_64".to_string())
+        );
+        assert_eq!(
+            parse_target("manylinux_2_28-x86_64"),
+            ("manylinux_2_28".to_string(), "x86_64".to_string())
+        );
+    }
+
+    #[test]
+    fn a_locked_version_with_a_binary_is_covered() {
+        let index = index("pak.tsv.zst", "pak");
+        let version = index.latest_version().unwrap().original.to_string();
+        let pkg = locked("pak", &version);
+        assert!(missing_binary(Some(&index), &pkg, &target("jammy", "x86_64", "4.5")).is_none());
+    }
+
+    #[test]
+    fn a_missing_binary_names_the_newest_one() {
+        let index = index("pak.tsv.zst", "pak");
+        let newest = index.latest_version().unwrap().original.to_string();
+
+        // A version P3M never built.
+        let pkg = locked("pak", "0.0.1");
+        let missing =
+            missing_binary(Some(&index), &pkg, &target("jammy", "x86_64", "4.5")).unwrap();
+        assert_eq!(missing.newest_binary, Some(newest));
+
+        // A target P3M does not build for.
+        let pkg = locked("pak", "0.9.5");
+        let missing =
+            missing_binary(Some(&index), &pkg, &target("nosuchdistro", "x86_64", "4.5")).unwrap();
+        assert_eq!(missing.newest_binary, None);
+
+        // A package without an index at all.
+        assert!(missing_binary(None, &pkg, &target("jammy", "x86_64", "4.5")).is_some());
+    }
+}
@@ -0,0 +1,270 @@
+//! Machine-readable progress for `rig pkg install` and `rig proj deploy`.
+//!
+//! With `--progress=jsonl` the progress bars are replaced by a stream of JSON
+//! objects, one per line, for IDEs and CI dashboards to follow. The stream goes
+//! to standard output, or to the file descriptor given in `--progress-fd`, and
+//! every line is flushed as soon as it is written. Human readable messages
+//! still go to standard error, as always.
+//!
+//! The events are emitted from the same places the progress bars are updated
+//! from: the callbacks of the concurrent downloader and the installer's
+//! `print_fn`, see [`crate::install::InstallEvent`].
+
+use std::error::Error;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use clap::ArgMatches;
+use log::debug;
+use serde::Serialize;
+use simple_error::*;
+
+/// One line of the event stream. The `event` field names the kind of the
+/// event, and every event also has a `time_ms` field, the time it happened, in
+/// milliseconds since the Unix epoch.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum ProgressEvent<'a> {
+    /// Resolving the dependencies of the `packages` asked for.
+    SolveStarted {
+        packages: &'a [String],
+    },
+    /// The resolution picked `packages` packages, installed or not.
+    SolveFinished {
+        packages: usize,
+    },
+    DownloadStarted {
+        package: &'a str,
+        version: &'a str,
+    },
+    /// `bytes` is the size of the file in the cache, which is also there when
+    /// it was `cached` from an earlier download.
+    DownloadFinished {
+        package: &'a str,
+        version: &'a str,
+        bytes: u64,
+        cached: bool,
+    },
+    InstallStarted {
+        package: &'a str,
+        version: &'a str,
+        binary: bool,
+    },
+    /// `log` is the `R CMD INSTALL` output, binary packages have none.
+    InstallFinished {
+        package: &'a str,
+        version: &'a str,
+        binary: bool,
+        duration_ms: u64,
+        log: Option<&'a Path>,
+    },
+    /// A package failed to download or install, or, without `package`, the
+    /// command failed for another reason.
+    Error {
+        package: Option<&'a str>,
+        message: &'a str,
+        log: Option<&'a Path>,
+    },
+}
+
+/// Where the events go. Cheap to share between the install tasks.
+pub struct EventStream {
+    out: Mutex<Box<dyn Write + Send>>,
+    stdout: bool,
+    errored: AtomicBool,
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    #[serde(flatten)]
+    event: &'a ProgressEvent<'a>,
+    time_ms: u64,
+}
+
+impl EventStream {
+    pub fn new(out: Box<dyn Write + Send>, stdout: bool) -> EventStream {
+        EventStream {
+            out: Mutex::new(out),
+            stdout,
+            errored: AtomicBool::new(false),
+        }
+    }
+
+    /// The stream `--progress` and `--progress-fd` select, `None` for the
+    /// progress bars.
+    pub fn from_args(args: &ArgMatches) -> Result<Option<Arc<EventStream>>, Box<dyn Error>> {
+        match args.get_one::<String>("progress").map(|s| s.as_str()) {
+            Some("jsonl") => {}
+            _ => return Ok(None),
+        };
+
+        let stream = match args.get_one::<i32>("progress-fd") {
+            Some(fd) => EventStream::new(open_fd(*fd)?, false),
+            None => EventStream::new(Box::new(std::io::stdout()), true),
+        };
+
+        Ok(Some(Arc::new(stream)))
+    }
+
+    /// Whether the events go to standard output, which then must not carry
+    /// anything else.
+    pub fn is_stdout(&self) -> bool {
+        self.stdout
+    }
+
+    pub fn emit(&self, event: &ProgressEvent) {
+        if matches!(event, ProgressEvent::Error { .. }) {
+            self.errored.store(true, Ordering::SeqCst);
+        }
+
+        let time_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let line = match serde_json::to_string(&Line { event, time_ms }) {
+            Ok(line) => line,
+            Err(err) => {
+                debug!("Cannot serialize progress event {:?}: {}", event, err);
+                return;
+            }
+        };
+
+        // A reader that went away must not fail the installation.
+        let mut out = self.out.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(err) = writeln!(out, "{}", line).and_then(|_| out.flush()) {
+            debug!("Cannot write progress event: {}", err);
+        }
+    }
+
+    /// Report that the command failed, unless the failure of a package already
+    /// said so.
+    pub fn fail(&self, message: &str) {
+        if !self.errored.load(Ordering::SeqCst) {
+            self.emit(&ProgressEvent::Error {
+                package: None,
+                message,
+                log: None,
+            });
+        }
+    }
+}
+
+/// Milliseconds, for the `duration_ms` fields.
+pub fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
+
+#[cfg(unix)]
+fn open_fd(fd: i32) -> Result<Box<dyn Write + Send>, Box<dyn Error>> {
+    use nix::fcntl::{fcntl, FcntlArg};
+    use std::os::fd::FromRawFd;
+
+    if fd <= 2 {
+        bail!(
+            "--progress-fd must be a file descriptor other than standard input, \
+            output and error, use --progress=jsonl alone for standard output"
+        );
+    }
+    if fcntl(fd, FcntlArg::F_GETFD).is_err() {
+        bail!("File descriptor {} given in --progress-fd is not open", fd);
+    }
+    // Safety: the descriptor is open, and rig does not use it for anything else.
+    let file = unsafe { std::fs::File::from_raw_fd(fd) };
+    Ok(Box::new(file))
+}
+
+#[cfg(not(unix))]
+fn open_fd(_fd: i32) -> Result<Box<dyn Write + Send>, Box<dyn Error>> {
+    bail!(
+        "--progress-fd is not supported on this platform, use --progress=jsonl for standard output"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A writer the test can read back.
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn lines(buf: &Buffer) -> Vec<serde_json::Value> {
+        String::from_utf8(buf.0.lock().unwrap().clone())
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn events_are_one_json_object_per_line() {
+        let buf = Buffer::default();
+        let stream = EventStream::new(Box::new(buf.clone()), false);
+        stream.emit(&ProgressEvent::DownloadFinished {
+            package: "cli",
+            version: "3.6.3",
+            bytes: 1234,
+            cached: false,
+        });
+        stream.emit(&ProgressEvent::InstallFinished {
+            package: "cli",
+            version: "3.6.3",
+            binary: false,
+            duration_ms: 1500,
+            log: Some(Path::new("/lib/_logs/cli-install.log")),
+        });
+
+        let events = lines(&buf);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["event"], "download-finished");
+        assert_eq!(events[0]["package"], "cli");
+        assert_eq!(events[0]["bytes"], 1234);
+        assert_eq!(events[0]["cached"], false);
+        assert!(events[0]["time_ms"].as_u64().unwrap() > 0);
+        assert_eq!(events[1]["event"], "install-finished");
+        assert_eq!(events[1]["duration_ms"], 1500);
+        assert_eq!(events[1]["log"], "/lib/_logs/cli-install.log");
+    }
+
+    #[test]
+    fn a_failure_is_reported_once() {
+        let buf = Buffer::default();
+        let stream = EventStream::new(Box::new(buf.clone()), false);
+        stream.emit(&ProgressEvent::Error {
+            package: Some("cli"),
+            message: "Installation failed for cli",
+            log: None,
+        });
+        stream.fail("Installation completed with 1 failures");
+
+        let events = lines(&buf);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["event"], "error");
+        assert_eq!(events[0]["package"], "cli");
+    }
+
+    #[test]
+    fn a_failure_without_a_package_is_reported() {
+        let buf = Buffer::default();
+        let stream = EventStream::new(Box::new(buf.clone()), true);
+        stream.fail("Cannot solve dependencies");
+
+        let events = lines(&buf);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["event"], "error");
+        assert!(events[0]["package"].is_null());
+        assert_eq!(events[0]["message"], "Cannot solve dependencies");
+    }
+}
@@ -1,14 +1,15 @@
 use std::cell::Cell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
 use std::fs::{self, File};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 
 use clap::ArgMatches;
 use deb822_fast::Deb822;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use log::{error, info};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use log::{error, info, warn};
 use pubgrub::{resolve, SelectedDependencies};
 use simple_error::*;
 use tabular::*;
@@ -18,15 +19,19 @@ use crate::common::get_default_r_version;
 use crate::dcf::*;
 use crate::download::download_multiple_first_available_with_progress;
 use crate::install::{
-    install_packages, parse_linkingto, PackageInfo, REMOTE_HASH_FIELD, REMOTE_LINKINGTO_FIELD,
+    default_jobs, install_packages, parse_env, parse_linkingto, split_args, InstallOptions,
+    PackageInfo, REMOTE_HASH_FIELD, REMOTE_LINKINGTO_FIELD,
 };
 use crate::output::OUTPUT;
 use crate::pak::{PakLockfile, PakLockfilePackage};
 use crate::pkg::deps::{
     dep_count, print_deps_json, print_deps_recursive, print_header, type_list, walk_deps,
 };
-use crate::pkg::tree::proj_tree;
+use crate::pkg::licenses::proj_licenses;
+use crate::pkg::sbom::proj_sbom;
+use crate::pkg::tree::{proj_tree, TreeFormat};
 use crate::platform::{detect_platform, parse_platform_string};
+use crate::progress::{EventStream, ProgressEvent};
 use crate::renv::*;
 use crate::repos::binaries::loader::{BinaryTarget, P3mBinaryLoader};
 use crate::repos::*;
@@ -56,6 +61,8 @@ pub fn sc_proj(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn E
         Some(("tree", s)) => sc_proj_tree(s, args, mainargs),
         Some(("solve", s)) => sc_proj_solve(s, args, mainargs),
         Some(("deploy", s)) => sc_proj_deploy(s, args, mainargs),
+        Some(("licenses", s)) => sc_proj_licenses(s, args, mainargs),
+        Some(("sbom", s)) => proj_sbom(s),
         _ => Ok(()), // unreachable
     }
 }
@@ -97,6 +104,26 @@ fn proj_read_deps(input: &str, dev: bool) -> Result<Package, Box<dyn Error>> {
     Ok(package)
 }
 
+/// Read the per-package install options of the project's manifest, see
+/// [`InstallOptions`].
+fn proj_read_install_options(
+    input: &str,
+) -> Result<BTreeMap<String, InstallOptions>, Box<dyn Error>> {
+    let desc = Deb822::from_reader(File::open(input)?)?;
+    let Some(paragraph) = desc.iter().next() else {
+        return Ok(BTreeMap::new());
+    };
+    let options = InstallOptions::from_fields(paragraph.iter())?;
+    if !options.is_empty() {
+        info!(
+            "Install options from {} for {}",
+            input,
+            options.keys().cloned().collect::<Vec<_>>().join(", ")
+        );
+    }
+    Ok(options)
+}
+
 /// Parse dependencies from DESCRIPTION file and print them out
 fn sc_proj_deps(
     args: &ArgMatches,
@@ -243,7 +270,7 @@ fn sc_proj_tree(
         dev,
         no_base,
         why,
-        json,
+        TreeFormat::from_args(args, json),
     )
 }
 
@@ -405,6 +432,7 @@ fn sc_proj_solve(
     let default_input = "DESCRIPTION".to_string();
     let input: &String = args.get_one::<String>("input").unwrap_or(&default_input);
     let mut pkg_deps = proj_read_deps(input, dev)?.dependencies;
+    let install_options = proj_read_install_options(input)?;
 
     if args.get_flag("renv") {
         pkg_deps.dependencies.push(DepVersionSpec {
@@ -438,7 +466,24 @@ fn sc_proj_solve(
         info!("Written renv lockfile to renv.lock");
     }
 
-    let lockfile = PakLockfile::from_solution(&registry, &solution);
+    let mut lockfile = PakLockfile::from_solution(&registry, &solution);
+    for name in lockfile.set_install_options(&install_options) {
+        OUTPUT.warn(&format!(
+            "{} has install options in {}, but it is not a dependency",
+            name, input
+        ));
+        warn!("Ignoring install options of {}, not a dependency", name);
+    }
+    for pkg in lockfile.packages.iter() {
+        if pkg.binary && install_options.contains_key(&pkg.package) {
+            OUTPUT.warn(&format!(
+                "{} is a binary package, its install options only apply to a \
+                source install (--platform source)",
+                pkg.package
+            ));
+            info!("{} has install options, but it is a binary", pkg.package);
+        }
+    }
     fs::write("pkg.lock", serde_json::to_string_pretty(&lockfile)?)?;
     OUTPUT.success("Written package lockfile to pkg.lock");
     info!("Written package lockfile to pkg.lock");
@@ -475,15 +520,33 @@ fn sc_proj_solve(
     Ok(())
 }
 
+fn sc_proj_licenses(
+    args: &ArgMatches,
+    projargs: &ArgMatches,
+    mainargs: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    let json = args.get_flag("json") || projargs.get_flag("json") || mainargs.get_flag("json");
+    proj_licenses(args, json)
+}
+
 fn sc_proj_deploy(
     args: &ArgMatches,
     _libargs: &ArgMatches,
     _mainargs: &ArgMatches,
 ) -> Result<(), Box<dyn Error>> {
+    let events = EventStream::from_args(args)?;
+    let result = proj_deploy(args, events.clone());
+    if let (Err(err), Some(events)) = (&result, &events) {
+        events.fail(&err.to_string());
+    }
+    result
+}
+
+fn proj_deploy(args: &ArgMatches, events: Option<Arc<EventStream>>) -> Result<(), Box<dyn Error>> {
     // First, download all packages
     OUTPUT.status("Downloading packages");
     info!("Downloading packages");
-    proj_download()?;
+    proj_download(events.as_deref())?;
 
     // Read the lockfile to get package information
     let lockfile_content = fs::read_to_string("pkg.lock")?;
@@ -495,7 +558,7 @@ fn sc_proj_deploy(
     // Build Vec<PackageInfo> from lockfile
     let mut packages: Vec<PackageInfo> = Vec::new();
     for pkg in &lockfile.packages {
-        packages.push(lockfile_package_info(pkg, &cache_dir));
+        packages.push(lockfile_package_info(pkg, &cache_dir)?);
     }
 
     // Get library path - required argument
@@ -531,7 +594,19 @@ fn sc_proj_deploy(
         library_path.display()
     );
 
-    let installed = install_packages(packages, &library_path, r_binary, max_concurrent)?;
+    let jobs = args
+        .get_one::<usize>("jobs")
+        .copied()
+        .unwrap_or_else(default_jobs);
+
+    let installed = install_packages(
+        packages,
+        &library_path,
+        r_binary,
+        max_concurrent,
+        jobs,
+        events,
+    )?;
 
     OUTPUT.success(&format!(
         "Deployment complete, installed {} packages",
@@ -543,8 +618,15 @@ fn sc_proj_deploy(
 
 /// What to install for one lockfile entry, including the provenance
 /// `PakLockfile::from_solution` recorded in its `metadata`.
-pub(crate) fn lockfile_package_info(pkg: &PakLockfilePackage, cache_dir: &Path) -> PackageInfo {
-    PackageInfo {
+pub(crate) fn lockfile_package_info(
+    pkg: &PakLockfilePackage,
+    cache_dir: &Path,
+) -> Result<PackageInfo, Box<dyn Error>> {
+    let install_args = split_args(&pkg.install_args)
+        .map_err(|e| format!("Invalid install_args for {}: {}", pkg.package, e))?;
+    let install_env = parse_env(&pkg.install_env)
+        .map_err(|e| format!("Invalid install_env for {}: {}", pkg.package, e))?;
+    Ok(PackageInfo {
         name: pkg.package.clone(),
         version: pkg.version.clone(),
         binary: pkg.binary,
@@ -556,24 +638,30 @@ pub(crate) fn lockfile_package_info(pkg: &PakLockfilePackage, cache_dir: &Path)
             .get(REMOTE_LINKINGTO_FIELD)
             .map(|s| parse_linkingto(s))
             .unwrap_or_default(),
-    }
+        install_args,
+        install_env,
+    })
 }
 
 /// Cache package files forever. They are immutable on PPM.
 /// This will be different for CRAN and CRAN-like repositories.
 pub(crate) const PACKAGE_FILE_TTL: Duration = Duration::MAX;
 
-pub fn proj_download() -> Result<(), Box<dyn Error>> {
+pub fn proj_download(events: Option<&EventStream>) -> Result<(), Box<dyn Error>> {
     let lockfile_content = fs::read_to_string("pkg.lock")?;
     let lockfile: PakLockfile = serde_json::from_str(&lockfile_content)?;
-    download_lockfile_packages(&lockfile)
+    download_lockfile_packages(&lockfile, events)
 }
 
 /// Download every package a lockfile names into the package cache.
 ///
 /// Split out from [`proj_download`] so that `rig pkg install`, which solves in
-/// memory and never writes a lockfile, can use it too.
-pub(crate) fn download_lockfile_packages(lockfile: &PakLockfile) -> Result<(), Box<dyn Error>> {
+/// memory and never writes a lockfile, can use it too. With an `events` stream
+/// there are no progress bars, and every download is reported to the stream.
+pub(crate) fn download_lockfile_packages(
+    lockfile: &PakLockfile,
+    events: Option<&EventStream>,
+) -> Result<(), Box<dyn Error>> {
     // Get cache directory
     let cache_dir = get_cache_dir()?;
 
@@ -588,7 +676,11 @@ pub(crate) fn download_lockfile_packages(lockfile: &PakLockfile) -> Result<(), B
     let total = downloads.len();
 
     // Create progress bars
-    let multi_progress = MultiProgress::new();
+    let multi_progress = if events.is_some() {
+        MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+    } else {
+        MultiProgress::new()
+    };
     let overall_pb = multi_progress.add(ProgressBar::new(total as u64));
     overall_pb.set_style(
         ProgressStyle::default_bar()
@@ -606,12 +698,31 @@ pub(crate) fn download_lockfile_packages(lockfile: &PakLockfile) -> Result<(), B
     // Download all packages concurrently with progress updates
     OUTPUT.status(&format!("Downloading {} packages", total));
     info!("Downloading {} packages", total);
+    // All downloads start at once, the callback only sees them finish.
+    if let Some(events) = events {
+        for pkg in &lockfile.packages {
+            events.emit(&ProgressEvent::DownloadStarted {
+                package: &pkg.package,
+                version: &pkg.version,
+            });
+        }
+    }
     download_multiple_first_available_with_progress(
         downloads,
         Some(PACKAGE_FILE_TTL),
         None,
         |idx, result| match result {
             Ok((downloaded, _etag)) => {
+                if let Some(events) = events {
+                    let pkg = &lockfile.packages[idx];
+                    let path = cache_dir.join("packages").join(&pkg.target);
+                    events.emit(&ProgressEvent::DownloadFinished {
+                        package: &pkg.package,
+                        version: &pkg.version,
+                        bytes: fs::metadata(&path).map(|m| m.len()).unwrap_or(0),
+                        cached: !*downloaded,
+                    });
+                }
                 if *downloaded {
                     success_count.set(success_count.get() + 1);
                     overall_pb.println(format!("✓ Downloaded: {}", lockfile.packages[idx].package));
@@ -622,6 +733,13 @@ pub(crate) fn download_lockfile_packages(lockfile: &PakLockfile) -> Result<(), B
                 overall_pb.inc(1);
             }
             Err(e) => {
+                if let Some(events) = events {
+                    events.emit(&ProgressEvent::Error {
+                        package: Some(&lockfile.packages[idx].package),
+                        message: &e.to_string(),
+                        log: None,
+                    });
+                }
                 error.set(Some((idx, e.to_string())));
                 overall_pb.finish_and_clear();
             }
@@ -0,0 +1,444 @@
+#![cfg(target_os = "linux")]
+
+//! Building R from source, for `rig add --from-source`.
+//!
+//! rig downloads the source tarball of the resolved version from CRAN (or the
+//! daily snapshot for `devel` and `next`), runs `configure` with the options
+//! of a build profile, `make` and `make install`, into the same directory an
+//! installed build would go into. The output of the build goes into a log
+//! file in the build directory, which is kept if the build fails.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use clap::ArgMatches;
+use log::{debug, info, warn};
+use simple_error::*;
+
+use crate::cache::ensure_download_dir;
+use crate::common::{get_arch, user_mode_dev_dirname};
+use crate::download::{download_verified, installer_sha256, require_checksum};
+use crate::linux::{get_r_root, read_install_platform, unpack_tar_gz, user_mode_platform};
+use crate::output::OUTPUT;
+use crate::resolve::resolve_versions;
+use crate::utils::basename;
+
+const CRAN_SOURCE_URL: &str = "https://cran.r-project.org/src/base";
+const DAILY_SOURCE_URL: &str = "https://stat.ethz.ch/R/daily";
+
+/// The platform name recorded in `metadata.json` for builds from source.
+pub const SOURCE_PLATFORM: &str = "source";
+
+/// How to configure R.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Profile {
+    Default,
+    /// `--enable-R-shlib`, for RStudio, Positron and embedding R.
+    Shlib,
+    /// No optimization, with debug symbols, for a debugger.
+    Debug,
+    /// AddressSanitizer and UndefinedBehaviorSanitizer.
+    Sanitizers,
+}
+
+impl Profile {
+    pub fn from_name(name: &str) -> Result<Profile, Box<dyn Error>> {
+        match name {
+            "default" => Ok(Profile::Default),
+            "shlib" => Ok(Profile::Shlib),
+            "debug" => Ok(Profile::Debug),
+            "sanitizers" => Ok(Profile::Sanitizers),
+            _ => bail!("Unknown build profile: {}", name),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Profile::Default => "default",
+            Profile::Shlib => "shlib",
+            Profile::Debug => "debug",
+            Profile::Sanitizers => "sanitizers",
+        }
+    }
+
+    /// Builds that are not fit for everyday use are installed under a name
+    /// with this suffix, next to a regular build of the same version.
+    pub fn suffix(&self) -> Option<&'static str> {
+        match self {
+            Profile::Default | Profile::Shlib => None,
+            Profile::Debug => Some("debug"),
+            Profile::Sanitizers => Some("san"),
+        }
+    }
+
+    fn configure_args(&self) -> Vec<String> {
+        let args: &[&str] = match self {
+            Profile::Default => &[],
+            Profile::Shlib => &["--enable-R-shlib"],
+            Profile::Debug => &[
+                "--enable-R-shlib",
+                "CFLAGS=-g -O0",
+                "CXXFLAGS=-g -O0",
+                "FFLAGS=-g -O0",
+                "FCFLAGS=-g -O0",
+            ],
+            Profile::Sanitizers => &[
+                "CC=gcc -fsanitize=address,undefined -fno-omit-frame-pointer",
+                "CXX=g++ -fsanitize=address,undefined -fno-omit-frame-pointer",
+                "CFLAGS=-g -O1",
+                "CXXFLAGS=-g -O1",
+                "MAIN_LDFLAGS=-fsanitize=address,undefined",
+            ],
+        };
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    /// The environment of the build. R runs during the build, and the leak
+    /// checker would fail it at the first R process that exits.
+    fn build_env(&self) -> Vec<(&'static str, &'static str)> {
+        match self {
+            Profile::Sanitizers => vec![("ASAN_OPTIONS", "detect_leaks=0")],
+            _ => vec![],
+        }
+    }
+}
+
+/// An R version built from source.
+pub struct SourceBuild {
+    /// The name of the installation directory, within the R root.
+    pub dirname: String,
+    pub profile: Profile,
+}
+
+/// Build the R version of `rig add --from-source` and install it.
+pub fn build_r_from_source(args: &ArgMatches) -> Result<SourceBuild, Box<dyn Error>> {
+    let profile = match args.get_one::<String>("profile") {
+        Some(p) => Profile::from_name(p)?,
+        None => Profile::Default,
+    };
+    let mut configure_args = profile.configure_args();
+    if let Some(extra) = args.get_one::<String>("configure-args") {
+        configure_args.extend(crate::install::split_args(extra)?);
+    }
+
+    let url = source_url(args)?;
+    let filename = basename(&url).unwrap_or("R.tar.gz");
+    let target = ensure_download_dir()?.join(filename);
+    // CRAN publishes no checksums for the R sources, neither for the release
+    // tarballs nor for the daily snapshots. So the tarball is only verified
+    // if there is a `.sha256` file next to it, which only a tarball from a
+    // custom URL might have.
+    let sha256 = installer_sha256(&url, None);
+    if sha256.is_none() && !require_checksum()? {
+        OUTPUT.warn(&format!(
+            "Building R from an unverified source tarball, there is no checksum for {}",
+            url
+        ));
+        warn!(
+            "No checksum for R source tarball {}, building it unverified",
+            url
+        );
+    }
+    download_verified(&url, &target, sha256.as_deref())?;
+
+    let root = PathBuf::from(get_r_root()?);
+    std::fs::create_dir_all(&root)?;
+    let build_dir = ensure_download_dir()?.join(format!("rig-build-{}", std::process::id()));
+    if build_dir.exists() {
+        std::fs::remove_dir_all(&build_dir)?;
+    }
+    std::fs::create_dir_all(&build_dir)?;
+
+    OUTPUT.status(&format!("Unpacking {}", target.display()));
+    info!(
+        "Unpacking {} into {}",
+        target.display(),
+        build_dir.display()
+    );
+    unpack_tar_gz(&target, &build_dir)?;
+    let src = find_source_dir(&build_dir)?;
+
+    let version_file = std::fs::read_to_string(src.join("VERSION"))?;
+    let dirname = match source_dirname(&version_file, profile) {
+        Some(d) => d,
+        None => bail!(
+            "Cannot read the R version from {}",
+            src.join("VERSION").display()
+        ),
+    };
+    let dest = root.join(&dirname);
+    // Only replace what rig installed from a tarball or from source. An admin
+    // mode installation from a distro package belongs to the package manager.
+    if dest.exists() && read_install_platform(&dest).is_none() {
+        bail!(
+            "R {} is already installed at {}, remove it first with `rig rm {}`",
+            dirname,
+            dest.display(),
+            dirname
+        );
+    }
+
+    let log_path = build_dir.join("rig-build.log");
+    let mut log = File::create(&log_path)?;
+    let env = profile.build_env();
+
+    OUTPUT.status(&format!(
+        "Configuring R {} ({} profile), log: {}",
+        dirname,
+        profile.name(),
+        log_path.display()
+    ));
+    let mut configure = Command::new(src.join("configure"));
+    configure
+        .arg(format!("--prefix={}", dest.display()))
+        .args(&configure_args);
+    run_logged(&mut configure, &src, &env, &mut log, &log_path, "configure")?;
+
+    let mut make = Command::new("make");
+    if std::env::var_os("MAKEFLAGS").is_none() {
+        make.arg(format!("-j{}", crate::install::default_jobs()));
+    }
+    OUTPUT.status(&format!("Building R {}", dirname));
+    run_logged(&mut make, &src, &env, &mut log, &log_path, "make")?;
+
+    // Install into a staging directory next to the destination, and only
+    // replace an existing installation once `make install` has succeeded.
+    // `DESTDIR` keeps the configured prefix, so the paths built into R are
+    // the final ones, and the staging directory is on the same filesystem,
+    // so that the swap is a rename.
+    let staging = root.join(format!(".rig-extract-{}", std::process::id()));
+    if staging.exists() {
+        std::fs::remove_dir_all(&staging)?;
+    }
+    OUTPUT.status(&format!("Installing R {} to {}", dirname, dest.display()));
+    let mut install = Command::new("make");
+    install
+        .arg("install")
+        .arg(format!("DESTDIR={}", staging.display()));
+    let installed = run_logged(
+        &mut install,
+        &src,
+        &env,
+        &mut log,
+        &log_path,
+        "make install",
+    );
+    let staged = staging.join(dest.strip_prefix("/").unwrap_or(&dest));
+    if let Err(e) = installed.and_then(|_| write_source_metadata(&staged, profile, &configure_args))
+    {
+        let _ = std::fs::remove_dir_all(&staging);
+        return Err(e);
+    }
+
+    // The old installation goes into the staging directory, so it is removed
+    // with it, and put back if the new one cannot be moved in.
+    let old = staging.join("old");
+    if dest.exists() {
+        OUTPUT.status(&format!("Replacing existing {}", dest.display()));
+        info!("Replacing existing {}", dest.display());
+        std::fs::rename(&dest, &old)?;
+    }
+    debug!("Moving {} to {}", staged.display(), dest.display());
+    if let Err(e) = std::fs::rename(&staged, &dest) {
+        if old.exists() {
+            let _ = std::fs::rename(&old, &dest);
+        }
+        let _ = std::fs::remove_dir_all(&staging);
+        return Err(e.into());
+    }
+    let _ = std::fs::remove_dir_all(&staging);
+    let _ = std::fs::remove_dir_all(&build_dir);
+
+    OUTPUT.success(&format!("Built and installed R to {}", dest.display()));
+    info!("Built and installed R to {}", dest.display());
+
+    Ok(SourceBuild { dirname, profile })
+}
+
+/// The source tarball of the version to add. `devel` and `next` are the daily
+/// snapshots, other versions are resolved to a version number, with the
+/// portable builds, which exist for every Linux distribution.
+fn source_url(args: &ArgMatches) -> Result<String, Box<dyn Error>> {
+    let ver = crate::shim::add_version(args);
+    match ver.as_str() {
+        _ if ver.starts_with("http://") || ver.starts_with("https://") => Ok(ver),
+        "devel" => Ok(format!("{}/R-devel.tar.gz", DAILY_SOURCE_URL)),
+        "next" => Ok(format!("{}/R-patched.tar.gz", DAILY_SOURCE_URL)),
+        _ => {
+            let platform = user_mode_platform()?;
+            let arch = get_arch(&platform, args);
+            let resolved = resolve_versions(vec![ver.to_string()], &platform, &arch)?;
+            match &resolved[0].version {
+                Some(v) => source_tarball_url(v),
+                None => bail!("Cannot resolve R version {}", ver),
+            }
+        }
+    }
+}
+
+/// The CRAN source tarball of an R version.
+fn source_tarball_url(version: &str) -> Result<String, Box<dyn Error>> {
+    let major = match version.split('.').next() {
+        Some(m) if !m.is_empty() && m.chars().all(|c| c.is_ascii_digit()) => m,
+        _ => bail!("Invalid R version: {}", version),
+    };
+    Ok(format!(
+        "{}/R-{}/R-{}.tar.gz",
+        CRAN_SOURCE_URL, major, version
+    ))
+}
+
+/// The unpacked sources, the directory with `configure` in it.
+fn find_source_dir(build_dir: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    if build_dir.join("configure").exists() {
+        return Ok(build_dir.to_path_buf());
+    }
+    for entry in std::fs::read_dir(build_dir)? {
+        let path = entry?.path();
+        if path.join("configure").exists() {
+            return Ok(path);
+        }
+    }
+    bail!(
+        "No R sources in {}, no `configure` script",
+        build_dir.display()
+    )
+}
+
+/// The installation directory name for the `VERSION` file of the sources,
+/// e.g. `4.4.1`, or `devel` for `4.6.0 Under development (unstable)`, with
+/// the suffix of the profile, if it has one.
+fn source_dirname(version_file: &str, profile: Profile) -> Option<String> {
+    let line = version_file.lines().next()?.trim();
+    let (version, status) = match line.split_once(' ') {
+        Some((v, s)) => (v, s.trim()),
+        None => (line, ""),
+    };
+    if version.is_empty() {
+        return None;
+    }
+    let base = user_mode_dev_dirname(Some(status)).unwrap_or_else(|| version.to_string());
+    Some(match profile.suffix() {
+        Some(suffix) => format!("{}-{}", base, suffix),
+        None => base,
+    })
+}
+
+fn run_logged(
+    cmd: &mut Command,
+    dir: &Path,
+    env: &[(&str, &str)],
+    log: &mut File,
+    log_path: &Path,
+    what: &str,
+) -> Result<(), Box<dyn Error>> {
+    let cmdline = std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|a| crate::install::quote_word(&a.to_string_lossy()))
+        .collect::<Vec<_>>()
+        .join(" ");
+    writeln!(log, "rig: {}", cmdline)?;
+    for (name, value) in env {
+        writeln!(log, "rig: {}={}", name, value)?;
+    }
+    log.flush()?;
+    debug!("Running {} in {}", cmdline, dir.display());
+
+    let status = cmd
+        .current_dir(dir)
+        .envs(env.iter().copied())
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log.try_clone()?)
+        .status()
+        .map_err(|e| format!("Cannot run {}: {}", what, e))?;
+    if !status.success() {
+        let msg = format!(
+            "Building R failed at `{}` ({}), the build log is at {}",
+            what,
+            status,
+            log_path.display()
+        );
+        OUTPUT.error(&msg);
+        bail!(msg);
+    }
+    Ok(())
+}
+
+fn write_source_metadata(
+    dest: &Path,
+    profile: Profile,
+    configure_args: &[String],
+) -> Result<(), Box<dyn Error>> {
+    let metadata = serde_json::json!({
+        "platform": SOURCE_PLATFORM,
+        "profile": profile.name(),
+        "configure-args": configure_args,
+    });
+    let path = dest.join("metadata.json");
+    debug!("Writing installation metadata to {}", path.display());
+    std::fs::write(&path, serde_json::to_string_pretty(&metadata)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_tarballs_are_in_the_major_version_directory() {
+        assert_eq!(
+            source_tarball_url("4.4.1").unwrap(),
+            "https://cran.r-project.org/src/base/R-4/R-4.4.1.tar.gz"
+        );
+        assert_eq!(
+            source_tarball_url("3.6.3").unwrap(),
+            "https://cran.r-project.org/src/base/R-3/R-3.6.3.tar.gz"
+        );
+        assert!(source_tarball_url("devel").is_err());
+    }
+
+    #[test]
+    fn the_directory_name_comes_from_the_version_file() {
+        let dirname = |v, p| source_dirname(v, p);
+        assert_eq!(
+            dirname("4.4.1\n", Profile::Default).as_deref(),
+            Some("4.4.1")
+        );
+        assert_eq!(dirname("4.4.1\n", Profile::Shlib).as_deref(), Some("4.4.1"));
+        assert_eq!(
+            dirname("4.4.1\n", Profile::Debug).as_deref(),
+            Some("4.4.1-debug")
+        );
+        assert_eq!(
+            dirname("4.6.0 Under development (unstable)\n", Profile::Default).as_deref(),
+            Some("devel")
+        );
+        assert_eq!(
+            dirname("4.5.2 Patched\n", Profile::Sanitizers).as_deref(),
+            Some("next-san")
+        );
+        assert_eq!(dirname("", Profile::Default), None);
+    }
+
+    #[test]
+    fn profiles_configure_r() {
+        assert!(Profile::Default.configure_args().is_empty());
+        assert_eq!(Profile::Shlib.configure_args(), ["--enable-R-shlib"]);
+        assert!(Profile::Sanitizers
+            .configure_args()
+            .iter()
+            .any(|a| a.starts_with("CC=") && a.contains("-fsanitize=address")));
+        assert_eq!(
+            Profile::Sanitizers.build_env(),
+            [("ASAN_OPTIONS", "detect_leaks=0")]
+        );
+        for name in ["default", "shlib", "debug", "sanitizers"] {
+            assert_eq!(Profile::from_name(name).unwrap().name(), name);
+        }
+        assert!(Profile::from_name("fast").is_err());
+    }
+}
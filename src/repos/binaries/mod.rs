@@ -71,6 +71,7 @@ use crate::download::{
     download_optional_if_newer_, fetch_optional_if_modified, fetch_optional_if_modified_,
     ConditionalFetch,
 };
+use crate::lock::FileLock;
 
 /// How long a cached index or status document is used without asking the
 /// server, matching the default in `crate::download`.
@@ -371,6 +372,15 @@ fn store_index(package: &str, tsv: &[u8], etag: Option<String>) -> Result<Vec<u8
     );
     let blob_path = binary_index_blob_file(package)?;
     let etag_path = binary_index_etag_file(package)?;
+    // Each write is atomic, but the pair is not: another rig process must not
+    // put its marker beside this blob, or this marker beside its blob.
+    let _lock = match FileLock::acquire(&blob_path) {
+        Ok(lock) => lock,
+        Err(err) => {
+            debug!("Not caching the index of '{}': {}", package, err);
+            return Ok(built);
+        }
+    };
     match write_atomically(&blob_path, &built) {
         Ok(()) => {
             if let Err(err) = write_atomically(&etag_path, etag.unwrap_or_default().as_bytes()) {
@@ -472,7 +482,7 @@ pub fn prefetch_binary_indices(packages: &[String], ttl: Option<Duration>) {
 /// in flight instead of being tacked onto the end of them.
 #[tokio::main]
 async fn prefetch_all(todo: &[(String, Option<String>)]) -> Result<(), Box<dyn Error>> {
-    let client = reqwest::Client::new();
+    let client = crate::http::client()?;
     futures::stream::iter(todo.iter().map(|(package, etag)| {
         let client = &client;
         async move {
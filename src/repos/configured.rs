@@ -1,10 +1,16 @@
 use std::env;
 use std::error::Error;
 
+use simple_error::bail;
+
 use crate::common::get_r_version_data_version;
 use crate::common::sc_get_default_or_fail;
+use crate::repos::get_repos_config;
 use crate::repos::r_version_to_bioc_version;
 use crate::repositories::{read_repositories_file, RepoFileEntry};
+use crate::rversion::OsVersion;
+
+use super::setup::catalog_repo_entries;
 
 #[cfg(target_os = "macos")]
 use crate::macos::*;
@@ -78,13 +84,8 @@ pub(crate) fn configured_repos(
             .any(|x| x.url.contains("%v") || x.url.contains("%bm"));
         if has_bioc {
             let ver = get_r_version_data_version(&rver)?;
-            let biocver = r_version_to_bioc_version(&ver)?;
-            let biocmirror = match env::var("R_BIOC_MIRROR") {
-                Ok(v) => v,
-                Err(_) => "https://bioconductor.org".to_string(),
-            };
             for repo in repos.iter_mut() {
-                repo.url = repo.url.replace("%v", &biocver).replace("%bm", &biocmirror);
+                repo.url = resolve_bioc_vars(&repo.url, &ver)?;
             }
             numver = Some(ver);
         }
@@ -96,3 +97,184 @@ pub(crate) fn configured_repos(
         repos,
     })
 }
+
+/// Substitute the Bioconductor version (`%v`) and mirror (`%bm`) of an R
+/// version into a repository URL.
+fn resolve_bioc_vars(url: &str, numver: &str) -> Result<String, Box<dyn Error>> {
+    if !url.contains("%v") && !url.contains("%bm") {
+        return Ok(url.to_string());
+    }
+    let biocver = r_version_to_bioc_version(numver)?;
+    let biocmirror = match env::var("R_BIOC_MIRROR") {
+        Ok(v) => v,
+        Err(_) => "https://bioconductor.org".to_string(),
+    };
+    Ok(url.replace("%v", &biocver).replace("%bm", &biocmirror))
+}
+
+/// A repository to list the packages of.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct PackageRepo {
+    pub name: String,
+    pub url: String,
+}
+
+/// The repositories `rig pkg available` lists the packages of.
+///
+/// `specs` are repository names or URLs. A name is looked up among the
+/// repositories of the R installation `rver`, then in the catalog of rig, for
+/// `platform` and the numeric R version `numver`. Without `specs` these are
+/// the default repositories of `rver`, or CRAN if there is no such
+/// installation.
+pub(crate) fn package_repos(
+    specs: &[String],
+    rver: Option<&str>,
+    platform: &OsVersion,
+    numver: &str,
+) -> Result<Vec<PackageRepo>, Box<dyn Error>> {
+    let installed: Vec<RepoFileEntry> = match rver {
+        Some(rver) => configured_repos(Some(rver), true, false)?.repos,
+        None => vec![],
+    };
+
+    if specs.is_empty() {
+        let mut out = vec![];
+        for entry in installed.iter().filter(|e| e.default) {
+            out.push(PackageRepo {
+                name: entry.name.clone(),
+                url: resolve_bioc_vars(&entry.url, numver)?,
+            });
+        }
+        if out.is_empty() {
+            out.push(PackageRepo {
+                name: "CRAN".to_string(),
+                url: "https://cloud.r-project.org".to_string(),
+            });
+        }
+        return Ok(out);
+    }
+
+    let config = get_repos_config()?;
+    let mut out: Vec<PackageRepo> = vec![];
+    for spec in specs {
+        if spec.contains("://") {
+            out.push(PackageRepo {
+                name: spec.trim_end_matches('/').to_string(),
+                url: spec.trim_end_matches('/').to_string(),
+            });
+            continue;
+        }
+        let needle = spec.to_lowercase();
+        let matching: Vec<&RepoFileEntry> = installed
+            .iter()
+            .filter(|e| e.name.to_lowercase() == needle)
+            .collect();
+        if !matching.is_empty() {
+            for entry in matching {
+                out.push(PackageRepo {
+                    name: entry.name.clone(),
+                    url: resolve_bioc_vars(&entry.url, numver)?,
+                });
+            }
+            continue;
+        }
+        let repo = match config.iter().find(|r| r.name.to_lowercase() == needle) {
+            Some(repo) => repo,
+            None => {
+                let mut names: Vec<&str> = config.iter().map(|r| r.name.as_str()).collect();
+                names.sort_by_key(|n| n.to_lowercase());
+                bail!(
+                    "Unknown repository: {}. Use a URL, a repository of `rig repos list --all`, \
+                     or one of {}",
+                    spec,
+                    names.join(", ")
+                );
+            }
+        };
+        let entries = catalog_repo_entries(repo, platform, numver)?;
+        if entries.is_empty() {
+            bail!(
+                "Repository {} is not available for R {} on this platform",
+                repo.name,
+                numver
+            );
+        }
+        for entry in entries {
+            out.push(PackageRepo {
+                name: entry.name,
+                url: resolve_bioc_vars(&entry.url, numver)?,
+            });
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ubuntu() -> OsVersion {
+        OsVersion {
+            rig_platform: Some("linux-ubuntu-22.04".to_string()),
+            arch: "x86_64".to_string(),
+            vendor: "pc".to_string(),
+            os: "linux-gnu".to_string(),
+            distro: Some("ubuntu".to_string()),
+            version: Some("22.04".to_string()),
+        }
+    }
+
+    fn specs(x: &[&str]) -> Vec<String> {
+        x.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn package_repos_are_urls_or_catalog_names() {
+        let repos = package_repos(
+            &specs(&["https://example.com/cran/", "cran", "P3M"]),
+            None,
+            &ubuntu(),
+            "4.5.1",
+        )
+        .unwrap();
+        let pairs: Vec<(&str, &str)> = repos
+            .iter()
+            .map(|r| (r.name.as_str(), r.url.as_str()))
+            .collect();
+        assert_eq!(
+            pairs[0],
+            ("https://example.com/cran", "https://example.com/cran")
+        );
+        assert_eq!(pairs[1], ("CRAN", "https://cloud.r-project.org"));
+        assert_eq!(pairs[2].0, "P3M");
+        assert!(pairs[2].1.contains("__linux__/jammy"), "{}", pairs[2].1);
+    }
+
+    #[test]
+    fn package_repos_resolve_bioconductor() {
+        let repos = package_repos(&specs(&["bioconductor"]), None, &ubuntu(), "4.5.1").unwrap();
+        assert_eq!(repos.len(), 5);
+        assert!(repos
+            .iter()
+            .all(|r| r.url.starts_with("https://bioconductor.org/packages/3.")));
+    }
+
+    #[test]
+    fn package_repos_default_to_cran() {
+        let repos = package_repos(&[], None, &ubuntu(), "4.5.1").unwrap();
+        assert_eq!(
+            repos,
+            vec![PackageRepo {
+                name: "CRAN".to_string(),
+                url: "https://cloud.r-project.org".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn package_repos_reject_unknown_names() {
+        let err = package_repos(&specs(&["nosuch"]), None, &ubuntu(), "4.5.1").unwrap_err();
+        assert!(err.to_string().contains("Unknown repository: nosuch"));
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fs::File;
 use std::io::{Read, Seek, SeekFrom};
@@ -19,6 +19,7 @@ use zstd::stream::read::Decoder as ZstdDecoder;
 use crate::cache::get_cache_dir;
 use crate::dcf::*;
 use crate::download::download_first_available_;
+use crate::lock::{get_lock_timeout, FileLock};
 use crate::output::OUTPUT;
 use crate::rds::*;
 use crate::solver::PackageVersionLoader;
@@ -172,7 +173,7 @@ impl DbSourcePackageLoader {
 
         let repo_local = repo_local_file(&allpackages_url())?;
         let repo_db = repo_db_file(&repo_local)?;
-        let conn = Connection::open(&repo_db)?;
+        let conn = open_db(&repo_db)?;
 
         let repo_ids = source_repo_ids(&conn, &allpackages_url(), "source")?;
 
@@ -265,7 +266,7 @@ pub fn allpackages_versions(package: &str) -> Result<Vec<AllPackagesVersion>, Bo
 
     let repo_local = repo_local_file(&allpackages_url())?;
     let repo_db = repo_db_file(&repo_local)?;
-    let conn = Connection::open(&repo_db)?;
+    let conn = open_db(&repo_db)?;
     let repo_ids = source_repo_ids(&conn, &allpackages_url(), "source")?;
 
     // Query by name only, for the same reason as `load_versions()` above: it
@@ -299,6 +300,54 @@ pub fn allpackages_versions(package: &str) -> Result<Vec<AllPackagesVersion>, Bo
     Ok(out)
 }
 
+/// The `License` field of package versions in the ALLPACKAGES history, for
+/// the `(package, version)` pairs of `packages`, keyed by package name. A
+/// version that is not in the history has no entry.
+pub fn allpackages_licenses(
+    packages: &[(String, String)],
+) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    ensure_allpackages_fresh()?;
+
+    let repo_local = repo_local_file(&allpackages_url())?;
+    let repo_db = repo_db_file(&repo_local)?;
+    licenses_in_db(&repo_db, &allpackages_url(), packages)
+}
+
+fn licenses_in_db(
+    db_path: &Path,
+    feed_url: &str,
+    packages: &[(String, String)],
+) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let conn = open_db(db_path)?;
+    let repo_ids = source_repo_ids(&conn, feed_url, "source")?;
+
+    // Query by name only, for the same reason as `load_versions()` above.
+    let mut stmt =
+        conn.prepare("SELECT version, license, repo_id FROM packages WHERE name = ?1")?;
+    let mut out: HashMap<String, String> = HashMap::new();
+    for (package, version) in packages {
+        let rows = stmt.query_map(params![package], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                row.get::<_, i64>(2)?,
+            ))
+        })?;
+        for row in rows {
+            let (ver, license, repo_id) = row?;
+            if !repo_ids.contains(&repo_id) || &ver != version {
+                continue;
+            }
+            if let Some(license) = license {
+                out.insert(package.clone(), license);
+                break;
+            }
+        }
+    }
+
+    Ok(out)
+}
+
 /// URL of the CRAN-wide ALLPACKAGES metadata (every version of every package
 /// ever published on CRAN), overridable via the `RIG_ALLPACKAGES_URL` env var.
 fn allpackages_url() -> String {
@@ -329,11 +378,11 @@ pub fn archived_package(package: &str) -> Result<Option<ArchivedPackage>, Box<dy
 /// The `archived_packages` row of `package` for the feed at `feed_url`, without
 /// refreshing anything.
 fn archived_package_in_db(
-    db_path: &PathBuf,
+    db_path: &Path,
     feed_url: &str,
     package: &str,
 ) -> Result<Option<ArchivedPackage>, Box<dyn Error>> {
-    let conn = Connection::open(db_path)?;
+    let conn = open_db(db_path)?;
     let repo_ids = source_repo_ids(&conn, feed_url, "source")?;
 
     let mut stmt =
@@ -353,6 +402,245 @@ fn archived_package_in_db(
     Ok(None)
 }
 
+/// A package found by `rig pkg search`.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SearchResult {
+    pub package: String,
+    /// The latest version of the package.
+    pub version: String,
+    pub title: Option<String>,
+    /// The date CRAN archived the package, if it did.
+    pub archived: Option<String>,
+}
+
+/// Search the titles and descriptions of all packages ever published on
+/// CRAN, best matches first. If the metadata cannot be updated, e.g. there
+/// is no network, the cached metadata is searched.
+pub fn search_packages(query: &str, limit: usize) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+    let repo_local = repo_local_file(&allpackages_url())?;
+    let repo_db = repo_db_file(&repo_local)?;
+
+    if let Err(e) = ensure_allpackages_fresh() {
+        let cached = repo_db.exists()
+            && repo_has_packages(&repo_db, &allpackages_url(), "source", Feed::Cranlike)
+                .unwrap_or(false);
+        if !cached {
+            return Err(e);
+        }
+        OUTPUT.warn(&format!(
+            "Could not update package metadata, searching the cached metadata: {}",
+            e
+        ));
+        info!(
+            "Could not update package metadata, searching the cached metadata: {}",
+            e
+        );
+    }
+
+    search_packages_in_db(
+        &repo_db,
+        &allpackages_url(),
+        &archivedpackages_url(),
+        query,
+        limit,
+    )
+}
+
+fn search_packages_in_db(
+    db_path: &Path,
+    feed_url: &str,
+    archived_url: &str,
+    query: &str,
+    limit: usize,
+) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+    let fts_query = match fts_query(query) {
+        Some(q) => q,
+        None => bail!("Nothing to search for"),
+    };
+
+    let conn = open_db(db_path)?;
+    let repo_ids = source_repo_ids(&conn, feed_url, "source")?;
+
+    // A package whose name is the query comes first, then the best matches,
+    // a match in the name counts most and one in the description the least.
+    let mut stmt = conn.prepare(
+        "SELECT name, version, title, repo_id FROM packages_fts
+         WHERE packages_fts MATCH ?1
+         ORDER BY lower(name) = lower(?2) DESC, bm25(packages_fts, 10.0, 5.0, 1.0)",
+    )?;
+    let rows = stmt.query_map(params![fts_query, query.trim()], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, Option<String>>(2)?,
+            row.get::<_, i64>(3)?,
+        ))
+    })?;
+
+    let mut out: Vec<SearchResult> = vec![];
+    for row in rows {
+        let (package, version, title, repo_id) = row?;
+        if !repo_ids.contains(&repo_id) {
+            continue; // row from a repo we do not search
+        }
+        out.push(SearchResult {
+            package,
+            version,
+            title,
+            archived: None,
+        });
+        if out.len() >= limit {
+            break;
+        }
+    }
+    drop(stmt);
+
+    for result in out.iter_mut() {
+        result.archived =
+            archived_package_in_db(db_path, archived_url, &result.package)?.map(|a| a.archived);
+    }
+
+    Ok(out)
+}
+
+/// An FTS5 query that matches the documents that have all words of `query`.
+/// Every word is quoted, so characters that mean something to FTS5 are
+/// searched for literally.
+fn fts_query(query: &str) -> Option<String> {
+    let words: Vec<String> = query
+        .split_whitespace()
+        .map(|w| format!("\"{}\"", w.replace('"', "\"\"")))
+        .collect();
+    if words.is_empty() {
+        None
+    } else {
+        Some(words.join(" "))
+    }
+}
+
+/// A package that depends on another one, as the reverse dependency index
+/// records it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReverseDep {
+    pub package: String,
+    /// The latest version of the package.
+    pub version: String,
+    /// How the package depends on the other one, e.g. `Imports`.
+    pub types: Vec<RDepType>,
+}
+
+/// The reverse dependencies of packages on CRAN, from the reverse dependency
+/// index of the ALLPACKAGES history, i.e. of the latest version of every
+/// package ever published on CRAN. Packages that CRAN has archived are left
+/// out: they are not on CRAN any more, so nothing on CRAN breaks with them.
+pub struct DbReverseDepLoader {
+    conn: Connection,
+    /// repo ids of the ALLPACKAGES history to search.
+    repo_ids: Vec<i64>,
+    archived: HashSet<String>,
+}
+
+impl DbReverseDepLoader {
+    /// Ensure the metadata is fresh in the database, then open a connection
+    /// ready to serve per-package queries.
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        ensure_allpackages_fresh()?;
+
+        let repo_local = repo_local_file(&allpackages_url())?;
+        let repo_db = repo_db_file(&repo_local)?;
+        DbReverseDepLoader::open(&repo_db, &allpackages_url(), &archivedpackages_url())
+    }
+
+    fn open(db_path: &Path, feed_url: &str, archived_url: &str) -> Result<Self, Box<dyn Error>> {
+        let conn = open_db(db_path)?;
+        let repo_ids = source_repo_ids(&conn, feed_url, "source")?;
+
+        let archived_ids = source_repo_ids(&conn, archived_url, "source")?;
+        let mut archived: HashSet<String> = HashSet::new();
+        {
+            let mut stmt = conn.prepare("SELECT name, repo_id FROM archived_packages")?;
+            let rows = stmt.query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+            })?;
+            for row in rows {
+                let (name, repo_id) = row?;
+                if archived_ids.contains(&repo_id) {
+                    archived.insert(name);
+                }
+            }
+        }
+
+        Ok(DbReverseDepLoader {
+            conn,
+            repo_ids,
+            archived,
+        })
+    }
+
+    /// The packages that depend on `package` directly, in any way, unordered.
+    pub fn reverse_deps(&self, package: &str) -> Result<Vec<ReverseDep>, Box<dyn Error>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT name, version, types, repo_id FROM reverse_deps WHERE dependency = ?1",
+        )?;
+        let rows = stmt.query_map(params![package], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, i64>(3)?,
+            ))
+        })?;
+
+        let mut out: Vec<ReverseDep> = vec![];
+        for row in rows {
+            let (name, version, types, repo_id) = row?;
+            if !self.repo_ids.contains(&repo_id) || self.archived.contains(&name) {
+                continue; // row from a repo we do not use, or not on CRAN
+            }
+            if out.iter().any(|r| r.package == name) {
+                continue;
+            }
+            let types = types
+                .split(',')
+                .filter_map(|t| RDepType::from_str(t).ok())
+                .collect();
+            out.push(ReverseDep {
+                package: name,
+                version,
+                types,
+            });
+        }
+
+        Ok(out)
+    }
+
+    /// The latest version of `package`, if it is on CRAN, or was.
+    pub fn latest_version(&self, package: &str) -> Result<Option<String>, Box<dyn Error>> {
+        // Query by name only, see `DbSourcePackageLoader::load_versions()`.
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT version, repo_id FROM packages WHERE name = ?1")?;
+        let rows = stmt.query_map(params![package], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+        })?;
+
+        let mut latest: Option<RPackageVersion> = None;
+        for row in rows {
+            let (version, repo_id) = row?;
+            if !self.repo_ids.contains(&repo_id) {
+                continue;
+            }
+            if let Ok(version) = RPackageVersion::from_str(&version) {
+                if latest.as_ref().is_none_or(|l| version > *l) {
+                    latest = Some(version);
+                }
+            }
+        }
+
+        Ok(latest.map(|v| v.to_string()))
+    }
+}
+
 /// Which metadata feed is being cached, i.e. how a freshly downloaded file is
 /// stored and which table holds its rows.
 #[derive(Clone, Copy, PartialEq)]
@@ -426,6 +714,10 @@ fn ensure_packages_cached(
     let repo_local = repo_local_file(cache_key)?;
     let repo_db = repo_db_file(&repo_local)?;
 
+    // Another rig process may be updating the same metadata. Waiting for it
+    // means that this one finds the update done, instead of doing it again.
+    let _lock = FileLock::acquire(&repo_local)?;
+
     // Ensure database schema exists early
     ensure_db_schema(&repo_db)?;
 
@@ -526,12 +818,12 @@ fn ensure_packages_cached(
 /// Whether the database holds at least one row for the given repo, in the table
 /// the feed stores its rows in.
 fn repo_has_packages(
-    db_path: &PathBuf,
+    db_path: &Path,
     repo_url: &str,
     pkg_type: &str,
     feed: Feed,
 ) -> Result<bool, Box<dyn Error>> {
-    let conn = Connection::open(db_path)?;
+    let conn = open_db(db_path)?;
     let repo_url = repo_url.trim_end_matches('/');
     let query = match feed {
         Feed::Cranlike => {
@@ -557,7 +849,7 @@ fn repo_has_packages(
 #[allow(clippy::too_many_arguments)]
 fn parse_store_and_cleanup(
     repo_local: &PathBuf,
-    repo_db: &PathBuf,
+    repo_db: &Path,
     repo_url_key: &str,
     r_version: Option<&str>,
     pkg_type: &str,
@@ -791,6 +1083,8 @@ fn parse_packages_from_rds_object(robj: RObject) -> Result<Vec<Package>, Box<dyn
         "DownloadURL",
         "Built",
         "License",
+        "Title",
+        "Description",
         "Platform",
         "Arch",
         "GraphicsAPIVersion",
@@ -838,6 +1132,8 @@ fn parse_packages_from_rds_object(robj: RObject) -> Result<Vec<Package>, Box<dyn
         let download_url = cols.get("DownloadURL").unwrap()[i].clone();
         let built = cols.get("Built").unwrap()[i].clone();
         let license = cols.get("License").unwrap()[i].clone();
+        let title = cols.get("Title").unwrap()[i].clone();
+        let description = cols.get("Description").unwrap()[i].clone();
         let platform = cols.get("Platform").unwrap()[i].clone();
         let arch = cols.get("Arch").unwrap()[i].clone();
         let graphics_api_version = cols.get("GraphicsAPIVersion").unwrap()[i].clone();
@@ -856,6 +1152,8 @@ fn parse_packages_from_rds_object(robj: RObject) -> Result<Vec<Package>, Box<dyn
                 .map(|b| DCFBuilt::from_str(&b))
                 .transpose()?,
             license: na_to_none(&license),
+            title: na_to_none(&title),
+            description: na_to_none(&description),
             platform: na_to_none(&platform),
             arch: na_to_none(&arch),
             graphics_api_version: na_to_none(&graphics_api_version),
@@ -876,8 +1174,18 @@ pub fn parse_packages_from_rds(rds_path: &PathBuf) -> Result<Vec<Package>, Box<d
     parse_packages_from_rds_object(robj)
 }
 
-fn ensure_db_schema(db_path: &PathBuf) -> Result<(), Box<dyn Error>> {
+/// Open the metadata database. Several rig processes may use it at once: the
+/// writes take the lock of the database file, see `FileLock`, and SQLite
+/// itself makes a reader wait out a write that is being committed.
+fn open_db(db_path: &Path) -> Result<Connection, Box<dyn Error>> {
     let conn = Connection::open(db_path)?;
+    conn.busy_timeout(get_lock_timeout()?)?;
+    Ok(conn)
+}
+
+fn ensure_db_schema(db_path: &Path) -> Result<(), Box<dyn Error>> {
+    let _lock = FileLock::acquire(db_path)?;
+    let conn = open_db(db_path)?;
 
     // Create repos table
     conn.execute(
@@ -903,6 +1211,8 @@ fn ensure_db_schema(db_path: &PathBuf) -> Result<(), Box<dyn Error>> {
             path TEXT,
             built TEXT,
             license TEXT,
+            title TEXT,
+            description TEXT,
             platform TEXT,
             arch TEXT,
             graphics_api_version TEXT,
@@ -947,16 +1257,74 @@ fn ensure_db_schema(db_path: &PathBuf) -> Result<(), Box<dyn Error>> {
         [],
     )?;
 
+    add_title_columns(&conn)?;
+
+    // Full text index of the latest version of each package of a repo, for
+    // `rig pkg search`.
+    conn.execute(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS packages_fts USING fts5(
+            name,
+            title,
+            description,
+            version UNINDEXED,
+            repo_id UNINDEXED,
+            tokenize = 'porter unicode61'
+        )",
+        [],
+    )?;
+
+    add_reverse_deps_table(&conn)?;
+
+    Ok(())
+}
+
+/// Databases of older rig versions have no `title` and `description`
+/// columns. Add them, and mark every repo as stale, so that the next lookup
+/// downloads the metadata again and fills them in.
+fn add_title_columns(conn: &Connection) -> Result<(), Box<dyn Error>> {
+    if conn.prepare("SELECT title FROM packages LIMIT 0").is_ok() {
+        return Ok(());
+    }
+    info!("Adding title and description to the metadata database");
+    conn.execute_batch(
+        "ALTER TABLE packages ADD COLUMN title TEXT;
+         ALTER TABLE packages ADD COLUMN description TEXT;
+         UPDATE repos SET etag = NULL, last_updated = '1970-01-01 00:00:00';",
+    )?;
+    Ok(())
+}
+
+/// The reverse dependency index: one row for each package that the latest
+/// version of another package depends on, with the dependency types, for
+/// `rig pkg revdeps`. Databases of older rig versions do not have it, so
+/// create it and mark every repo as stale, the same as in
+/// [`add_title_columns`], to have the next lookup fill it in.
+fn add_reverse_deps_table(conn: &Connection) -> Result<(), Box<dyn Error>> {
+    if conn
+        .prepare("SELECT name FROM reverse_deps LIMIT 0")
+        .is_ok()
+    {
+        return Ok(());
+    }
+    info!("Adding the reverse dependency index to the metadata database");
+    conn.execute_batch(
+        "CREATE TABLE reverse_deps (
+            dependency TEXT NOT NULL,
+            name TEXT NOT NULL,
+            version TEXT NOT NULL,
+            types TEXT NOT NULL,
+            repo_id INTEGER NOT NULL,
+            FOREIGN KEY (repo_id) REFERENCES repos(id)
+         );
+         CREATE INDEX idx_reverse_deps_dependency ON reverse_deps (dependency);
+         UPDATE repos SET etag = NULL, last_updated = '1970-01-01 00:00:00';",
+    )?;
     Ok(())
 }
 
 /// Get the stored etag for a repository from the database
-fn get_repo_etag(
-    db_path: &PathBuf,
-    repo_url: &str,
-    pkg_type: &str,
-) -> Result<String, Box<dyn Error>> {
-    let conn = Connection::open(db_path)?;
+fn get_repo_etag(db_path: &Path, repo_url: &str, pkg_type: &str) -> Result<String, Box<dyn Error>> {
+    let conn = open_db(db_path)?;
 
     // Normalize repo_url by removing trailing slashes
     let repo_url = repo_url.trim_end_matches('/');
@@ -971,11 +1339,11 @@ fn get_repo_etag(
 }
 
 fn is_repo_cache_recent(
-    db_path: &PathBuf,
+    db_path: &Path,
     repo_url: &str,
     pkg_type: &str,
 ) -> Result<bool, Box<dyn Error>> {
-    let conn = Connection::open(db_path)?;
+    let conn = open_db(db_path)?;
 
     // Normalize repo_url by removing trailing slashes
     let repo_url = repo_url.trim_end_matches('/');
@@ -997,11 +1365,11 @@ fn is_repo_cache_recent(
 }
 
 fn load_packages_from_db(
-    db_path: &PathBuf,
+    db_path: &Path,
     repo_url: &str,
     pkg_type: &str,
 ) -> Result<Vec<Package>, Box<dyn Error>> {
-    let conn = Connection::open(db_path)?;
+    let conn = open_db(db_path)?;
 
     // Normalize repo_url by removing trailing slashes
     let repo_url = repo_url.trim_end_matches('/');
@@ -1016,7 +1384,7 @@ fn load_packages_from_db(
     let mut stmt = conn.prepare(
         "SELECT name, version, dependencies, download_url, file, path, built,
                 license, platform, arch, graphics_api_version, internals_id, filesize,
-                sha256sum
+                sha256sum, title, description
          FROM packages WHERE repo_id = ?1",
     )?;
 
@@ -1039,6 +1407,8 @@ fn load_packages_from_db(
                 internals_id: row.get(11)?,
                 filesize: row.get(12)?,
                 sha256sum: row.get(13)?,
+                title: row.get(14)?,
+                description: row.get(15)?,
                 // The `packages` table does not store this: archived packages
                 // live in their own table, see `archived_package()`.
                 archived: None,
@@ -1050,15 +1420,16 @@ fn load_packages_from_db(
 }
 
 fn save_packages_to_db(
-    packages: &Vec<Package>,
-    db_path: &PathBuf,
+    packages: &[Package],
+    db_path: &Path,
     repo_url: &str,
     r_version: Option<&str>,
     pkg_type: &str,
     path: &str,
     etag: Option<&str>,
 ) -> Result<(), Box<dyn Error>> {
-    let mut conn = Connection::open(db_path)?;
+    let _lock = FileLock::acquire(db_path)?;
+    let mut conn = open_db(db_path)?;
 
     // Normalize repo_url by removing trailing slashes
     let repo_url = repo_url.trim_end_matches('/');
@@ -1100,8 +1471,9 @@ fn save_packages_to_db(
         "INSERT INTO packages
          (name, version, dependencies, download_url, file, path, built,
           license, platform, arch, graphics_api_version, internals_id, filesize,
-          sha256sum, repo_id)
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+          sha256sum, repo_id, title, description)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15,
+                 ?16, ?17)",
     )?;
 
     for pkg in packages {
@@ -1127,8 +1499,60 @@ fn save_packages_to_db(
             pkg.filesize,
             &pkg.sha256sum,
             repo_id,
+            &pkg.title,
+            &pkg.description,
         ])?;
     }
+    drop(stmt);
+
+    tx.execute(
+        "DELETE FROM packages_fts WHERE repo_id = ?1",
+        params![repo_id],
+    )?;
+    let mut stmt = tx.prepare(
+        "INSERT INTO packages_fts (name, title, description, version, repo_id)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+    )?;
+    for pkg in latest_versions(packages) {
+        stmt.execute(params![
+            &pkg.name,
+            &pkg.title,
+            &pkg.description,
+            pkg.version.to_string(),
+            repo_id,
+        ])?;
+    }
+
+    drop(stmt);
+
+    tx.execute(
+        "DELETE FROM reverse_deps WHERE repo_id = ?1",
+        params![repo_id],
+    )?;
+    let mut stmt = tx.prepare(
+        "INSERT INTO reverse_deps (dependency, name, version, types, repo_id)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+    )?;
+    for pkg in latest_versions(packages) {
+        for dep in pkg.dependencies.dependencies.iter() {
+            if dep.name == "R" {
+                continue;
+            }
+            let types = dep
+                .types
+                .iter()
+                .map(|t| t.to_string())
+                .collect::<Vec<String>>()
+                .join(",");
+            stmt.execute(params![
+                &dep.name,
+                &pkg.name,
+                pkg.version.to_string(),
+                types,
+                repo_id,
+            ])?;
+        }
+    }
 
     drop(stmt); // Drop statement before committing
     tx.commit()?;
@@ -1136,16 +1560,34 @@ fn save_packages_to_db(
     Ok(())
 }
 
+/// The latest version of each package. ALLPACKAGES has every version of a
+/// package, but only the latest one should be found by a search.
+fn latest_versions(packages: &[Package]) -> Vec<&Package> {
+    let mut latest: HashMap<&str, &Package> = HashMap::new();
+    for pkg in packages {
+        latest
+            .entry(pkg.name.as_str())
+            .and_modify(|p| {
+                if pkg.version > p.version {
+                    *p = pkg;
+                }
+            })
+            .or_insert(pkg);
+    }
+    latest.into_values().collect()
+}
+
 /// Store the ARCHIVEDPACKAGES records in the `archived_packages` table.
 fn save_archived_to_db(
     packages: &[Package],
-    db_path: &PathBuf,
+    db_path: &Path,
     repo_url: &str,
     pkg_type: &str,
     path: &str,
     etag: Option<&str>,
 ) -> Result<(), Box<dyn Error>> {
-    let mut conn = Connection::open(db_path)?;
+    let _lock = FileLock::acquire(db_path)?;
+    let mut conn = open_db(db_path)?;
     let repo_url = repo_url.trim_end_matches('/');
 
     let tx = conn.transaction()?;
@@ -1191,6 +1633,37 @@ fn save_archived_to_db(
         stored += 1;
     }
 
+    drop(stmt);
+
+    tx.execute(
+        "DELETE FROM reverse_deps WHERE repo_id = ?1",
+        params![repo_id],
+    )?;
+    let mut stmt = tx.prepare(
+        "INSERT INTO reverse_deps (dependency, name, version, types, repo_id)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+    )?;
+    for pkg in latest_versions(packages) {
+        for dep in pkg.dependencies.dependencies.iter() {
+            if dep.name == "R" {
+                continue;
+            }
+            let types = dep
+                .types
+                .iter()
+                .map(|t| t.to_string())
+                .collect::<Vec<String>>()
+                .join(",");
+            stmt.execute(params![
+                &dep.name,
+                &pkg.name,
+                pkg.version.to_string(),
+                types,
+                repo_id,
+            ])?;
+        }
+    }
+
     drop(stmt); // Drop statement before committing
     tx.commit()?;
 
@@ -1351,6 +1824,254 @@ Version: 2.1.0
         let _ = std::fs::remove_file(&db);
     }
 
+    fn titled(name: &str, version: &str, title: &str, description: &str) -> Package {
+        let mut pkg = Package::from_crandb(
+            name.to_string(),
+            RPackageVersion::from_str(version).unwrap(),
+            vec![],
+        );
+        pkg.title = Some(title.to_string());
+        pkg.description = Some(description.to_string());
+        pkg
+    }
+
+    #[test]
+    fn test_search_packages_ranks_and_marks_archived() {
+        let url = "https://example.com/ALLPACKAGES.zst";
+        let archived_url = "https://example.com/ARCHIVEDPACKAGES.zst";
+        let mut db = std::env::temp_dir();
+        db.push(format!("rig-test-search-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&db);
+        ensure_db_schema(&db).unwrap();
+
+        let packages = vec![
+            titled(
+                "lme4",
+                "1.1-30",
+                "Linear Mixed-Effects Models",
+                "Fit linear and generalized linear mixed-effects models.",
+            ),
+            titled(
+                "lme4",
+                "1.1-35",
+                "Linear Mixed-Effects Models using 'Eigen' and S4",
+                "Fit linear and generalized linear mixed-effects models.",
+            ),
+            titled(
+                "brms",
+                "2.21.0",
+                "Bayesian Regression Models using 'Stan'",
+                "Fit Bayesian generalized (non-)linear multivariate multilevel \
+                 models, including mixed models.",
+            ),
+            titled(
+                "oldbayes",
+                "0.1",
+                "Simple Bayesian Tools",
+                "Bayesian mixed model helpers.",
+            ),
+            titled(
+                "cli",
+                "3.6.3",
+                "Helpers for Developing CLIs",
+                "Terminal output.",
+            ),
+        ];
+        save_packages_to_db(&packages, &db, url, None, "source", "ALLPACKAGES", None).unwrap();
+        save_archived_to_db(
+            &[archived_record("oldbayes", Some("2019-01-02"))],
+            &db,
+            archived_url,
+            "source",
+            "ARCHIVEDPACKAGES",
+            None,
+        )
+        .unwrap();
+
+        let found =
+            search_packages_in_db(&db, url, archived_url, "bayesian mixed models", 10).unwrap();
+        let names: Vec<&str> = found.iter().map(|r| r.package.as_str()).collect();
+        // A match in the title counts more than one in the description.
+        assert_eq!(names, vec!["brms", "oldbayes"]);
+        assert_eq!(found[1].archived.as_deref(), Some("2019-01-02"));
+        assert_eq!(found[0].archived, None);
+
+        // Only the latest version of a package is found, and stemming finds
+        // `models` for `model`.
+        let found = search_packages_in_db(&db, url, archived_url, "mixed model", 10).unwrap();
+        let lme4 = found.iter().find(|r| r.package == "lme4").unwrap();
+        assert_eq!(lme4.version, "1.1-35");
+        assert_eq!(found.len(), 3);
+
+        // The package of that name comes first.
+        let found = search_packages_in_db(&db, url, archived_url, "cli", 1).unwrap();
+        assert_eq!(found[0].package, "cli");
+
+        // FTS5 syntax is searched for literally.
+        let found = search_packages_in_db(&db, url, archived_url, "models\" OR", 10).unwrap();
+        assert!(found.is_empty());
+        assert!(search_packages_in_db(&db, url, archived_url, "  ", 10).is_err());
+
+        let _ = std::fs::remove_file(&db);
+    }
+
+    fn depending(name: &str, version: &str, deps: &[(&str, &str)]) -> Package {
+        let mut dependencies = PackageDependencies::new();
+        for (dep_type, list) in deps {
+            dependencies.append(&mut PackageDependencies::from_str(list, dep_type).unwrap());
+        }
+        dependencies.simplify();
+        Package::from_crandb(
+            name.to_string(),
+            RPackageVersion::from_str(version).unwrap(),
+            dependencies.dependencies,
+        )
+    }
+
+    #[test]
+    fn test_reverse_deps_index_the_latest_versions() {
+        let url = "https://example.com/ALLPACKAGES.zst";
+        let archived_url = "https://example.com/ARCHIVEDPACKAGES.zst";
+        let mut db = std::env::temp_dir();
+        db.push(format!("rig-test-revdeps-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&db);
+        ensure_db_schema(&db).unwrap();
+
+        let packages = vec![
+            depending("cli", "3.6.3", &[("Depends", "R (>= 3.4)")]),
+            // Only the latest version of a package counts.
+            depending("glue", "1.6.0", &[("Imports", "cli")]),
+            depending("glue", "1.8.0", &[]),
+            depending(
+                "pillar",
+                "1.9.0",
+                &[("Imports", "cli (>= 2.3.0)"), ("LinkingTo", "cli")],
+            ),
+            depending("testthat", "3.2.3", &[("Suggests", "cli")]),
+            depending("oldpkg", "0.1", &[("Imports", "cli")]),
+        ];
+        save_packages_to_db(&packages, &db, url, None, "source", "ALLPACKAGES", None).unwrap();
+        save_archived_to_db(
+            &[archived_record("oldpkg", Some("2019-01-02"))],
+            &db,
+            archived_url,
+            "source",
+            "ARCHIVEDPACKAGES",
+            None,
+        )
+        .unwrap();
+
+        let loader = DbReverseDepLoader::open(&db, url, archived_url).unwrap();
+        let mut revdeps = loader.reverse_deps("cli").unwrap();
+        revdeps.sort_by(|a, b| a.package.cmp(&b.package));
+        assert_eq!(
+            revdeps,
+            vec![
+                ReverseDep {
+                    package: "pillar".to_string(),
+                    version: "1.9.0".to_string(),
+                    types: vec![RDepType::Imports, RDepType::LinkingTo],
+                },
+                ReverseDep {
+                    package: "testthat".to_string(),
+                    version: "3.2.3".to_string(),
+                    types: vec![RDepType::Suggests],
+                },
+            ]
+        );
+        // R is not a package.
+        assert!(loader.reverse_deps("R").unwrap().is_empty());
+        assert!(loader.reverse_deps("nothing").unwrap().is_empty());
+
+        assert_eq!(
+            loader.latest_version("glue").unwrap().as_deref(),
+            Some("1.8.0")
+        );
+        assert_eq!(loader.latest_version("nothing").unwrap(), None);
+
+        let _ = std::fs::remove_file(&db);
+    }
+
+    #[test]
+    fn test_licenses_of_package_versions() {
+        let url = "https://example.com/ALLPACKAGES.zst";
+        let mut db = std::env::temp_dir();
+        db.push(format!("rig-test-licenses-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&db);
+        ensure_db_schema(&db).unwrap();
+
+        let mut old = depending("cli", "3.6.2", &[]);
+        old.license = Some("GPL-3".to_string());
+        let mut new = depending("cli", "3.6.3", &[]);
+        new.license = Some("MIT + file LICENSE".to_string());
+        save_packages_to_db(&[old, new], &db, url, None, "source", "ALLPACKAGES", None).unwrap();
+
+        let want = vec![
+            ("cli".to_string(), "3.6.2".to_string()),
+            ("nothing".to_string(), "1.0.0".to_string()),
+        ];
+        let licenses = licenses_in_db(&db, url, &want).unwrap();
+        assert_eq!(licenses.len(), 1);
+        assert_eq!(licenses["cli"], "GPL-3");
+
+        let _ = std::fs::remove_file(&db);
+    }
+
+    #[test]
+    fn test_old_databases_get_title_columns() {
+        let mut db = std::env::temp_dir();
+        db.push(format!("rig-test-migrate-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&db);
+        {
+            let conn = Connection::open(&db).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE repos (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    url TEXT NOT NULL,
+                    pkg_type TEXT NOT NULL,
+                    r_version TEXT,
+                    path TEXT NOT NULL,
+                    etag TEXT,
+                    last_updated TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+                );
+                CREATE TABLE packages (
+                    name TEXT NOT NULL,
+                    version TEXT NOT NULL,
+                    dependencies TEXT NOT NULL,
+                    download_url TEXT,
+                    file TEXT,
+                    path TEXT,
+                    built TEXT,
+                    license TEXT,
+                    platform TEXT,
+                    arch TEXT,
+                    graphics_api_version TEXT,
+                    internals_id TEXT,
+                    filesize INTEGER,
+                    sha256sum TEXT,
+                    repo_id INTEGER NOT NULL
+                );
+                INSERT INTO repos (url, pkg_type, path, etag)
+                    VALUES ('https://example.com', 'source', 'src/contrib', 'abc');",
+            )
+            .unwrap();
+        }
+
+        ensure_db_schema(&db).unwrap();
+        let conn = Connection::open(&db).unwrap();
+        assert!(conn
+            .prepare("SELECT title, description FROM packages")
+            .is_ok());
+        assert!(conn.prepare("SELECT name FROM reverse_deps").is_ok());
+        // The metadata is downloaded again, to fill in the new columns.
+        assert!(!is_repo_cache_recent(&db, "https://example.com", "source").unwrap());
+        assert!(get_repo_etag(&db, "https://example.com", "source").is_err());
+        // And a second time it is left alone.
+        ensure_db_schema(&db).unwrap();
+
+        let _ = std::fs::remove_file(&db);
+    }
+
     #[test]
     fn test_parse_packages_reads_sha256original() {
         use std::io::Write;
@@ -18,29 +18,33 @@ use crate::linux::*;
 mod config;
 pub use config::{get_repos_config, RepoEntry, Repository};
 mod configured;
+pub(crate) use configured::{package_repos, PackageRepo};
 mod interpret_repos_args;
-pub use interpret_repos_args::interpret_repos_args;
+pub use interpret_repos_args::{interpret_repos_args, ReposSetupArgs};
 mod repos_available;
 use repos_available::sc_repos_available;
+mod repos_edit;
+use repos_edit::{sc_repos_add, sc_repos_disable, sc_repos_enable, sc_repos_reset, sc_repos_rm};
 mod repos_list;
 use repos_list::sc_repos_list;
 mod repos_status;
 use repos_status::sc_repos_status;
+pub(crate) use repos_status::{probe_default_repos, serves_linux_binaries};
 pub mod cranlike_metadata;
 pub use cranlike_metadata::DbSourcePackageLoader;
 pub mod binaries;
 mod setup;
-pub use setup::repos_setup;
+pub use setup::{repos_profile_block, repos_setup, repos_setup_is_current, ReposBlock};
 
 pub fn sc_repos(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn Error>> {
     match args.subcommand() {
-        // Some(("add", s)) => sc_repos_add(s, args, mainargs),
+        Some(("add", s)) => sc_repos_add(s, args, mainargs),
         Some(("available", s)) => sc_repos_available(s, args, mainargs),
-        // Some(("disable", s)) => sc_repos_disable(s, args, mainargs),
-        // Some(("enable", s)) => sc_repos_enable(s, args, mainargs),
+        Some(("disable", s)) => sc_repos_disable(s, args, mainargs),
+        Some(("enable", s)) => sc_repos_enable(s, args, mainargs),
         Some(("list", s)) => sc_repos_list(s, args, mainargs),
-        // Some(("reset", s)) => sc_repos_reset(s, args, mainargs),
-        // Some(("rm", s)) => sc_repos_rm(s, args, mainargs),
+        Some(("reset", s)) => sc_repos_reset(s, args, mainargs),
+        Some(("rm", s)) => sc_repos_rm(s, args, mainargs),
         Some(("setup", s)) => sc_repos_setup(s, args, mainargs),
         Some(("status", s)) => sc_repos_status(s, args, mainargs),
         _ => Ok(()), // unreachable
@@ -66,51 +70,6 @@ pub fn r_version_to_bioc_version(rver: &str) -> Result<String, Box<dyn Error>> {
     }
 }
 
-// pub fn sc_repos_add(
-//     args: &ArgMatches,
-//     _libargs: &ArgMatches,
-//     _mainargs: &ArgMatches,
-// ) -> Result<(), Box<dyn Error>> {
-//     panic!("TODO: implement sc_repos_add");
-//     Ok(())
-// }
-
-// pub fn sc_repos_disable(
-//     args: &ArgMatches,
-//     _libargs: &ArgMatches,
-//     _mainargs: &ArgMatches,
-// ) -> Result<(), Box<dyn Error>> {
-//     panic!("TODO: implement sc_repos_disable");
-//     Ok(())
-// }
-
-// pub fn sc_repos_enable(
-//     args: &ArgMatches,
-//     _libargs: &ArgMatches,
-//     _mainargs: &ArgMatches,
-// ) -> Result<(), Box<dyn Error>> {
-//     panic!("TODO: implement sc_repos_enable");
-//     Ok(())
-// }
-
-// pub fn sc_repos_reset(
-//     args: &ArgMatches,
-//     _libargs: &ArgMatches,
-//     _mainargs: &ArgMatches,
-// ) -> Result<(), Box<dyn Error>> {
-//     panic!("TODO: implement sc_repos_reset");
-//     Ok(())
-// }
-
-// pub fn sc_repos_rm(
-//     args: &ArgMatches,
-//     _libargs: &ArgMatches,
-//     _mainargs: &ArgMatches,
-// ) -> Result<(), Box<dyn Error>> {
-//     panic!("TODO: implement sc_repos_rm");
-//     Ok(())
-// }
-
 fn sc_repos_setup(
     args: &ArgMatches,
     _libargs: &ArgMatches,
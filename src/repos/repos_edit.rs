@@ -0,0 +1,336 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use clap::ArgMatches;
+use log::{debug, info, warn};
+use simple_error::*;
+
+use crate::common::*;
+use crate::escalate::escalate;
+use crate::lock::FileLock;
+use crate::output::OUTPUT;
+use crate::repositories::*;
+use crate::utils::*;
+
+#[cfg(target_os = "macos")]
+use crate::macos::*;
+
+#[cfg(target_os = "windows")]
+use crate::windows::*;
+
+#[cfg(target_os = "linux")]
+use crate::linux::*;
+
+use super::config::{get_repos_config, RepoEntry, Repository};
+use super::interpret_repos_args::ReposSetupArgs;
+use super::setup::{
+    get_r_data, repos_setup, repositories_path, save_repositories_orig, should_activate_repo,
+    update_repos_profile,
+};
+
+pub fn sc_repos_add(
+    args: &ArgMatches,
+    _libargs: &ArgMatches,
+    _mainargs: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    let name = args.get_one::<String>("name").unwrap();
+    let url = args.get_one::<String>("url");
+    let enable = args.get_flag("enable");
+    let config = get_repos_config()?;
+
+    // A URL makes it a new repository, even if the name is in the catalog.
+    let catalog = match url {
+        Some(_) => None,
+        None => match find_catalog_repo(&config, name) {
+            Some(repo) => Some(repo),
+            None => bail!(
+                "Unknown repository: {}. Give its URL as well, or use one of {}",
+                name,
+                catalog_names(&config)
+            ),
+        },
+    };
+
+    edit_repositories(args, |ver, repos| {
+        let entries: Vec<RepoEntry> = match (catalog, url) {
+            (Some(repo), _) => {
+                let rdata = get_r_data(ver)?;
+                let mut entries = vec![];
+                for entry in repo.repos.iter() {
+                    if should_activate_repo(repo, entry, &rdata)? {
+                        entries.push(entry.clone());
+                    }
+                }
+                entries
+            }
+            (None, Some(url)) => vec![RepoEntry {
+                name: name.to_string(),
+                title: None,
+                description: None,
+                url: url.to_string(),
+                platforms: None,
+                archs: None,
+                rversions: None,
+                enabled: None,
+            }],
+            (None, None) => unreachable!(),
+        };
+        if entries.is_empty() {
+            OUTPUT.warn(&format!(
+                "Repository {} is not available for R {}, skipping",
+                name, ver
+            ));
+            warn!(
+                "Repository {} is not available for R {}, skipping",
+                name, ver
+            );
+            return Ok(false);
+        }
+        for entry in entries.iter() {
+            add_rig_repository(repos, entry, enable);
+            let msg = format!(
+                "Added repository {} ({}) to R {}{}",
+                entry.name,
+                entry.url,
+                ver,
+                if enable { ", enabled" } else { "" }
+            );
+            OUTPUT.success(&msg);
+            info!("{}", msg);
+        }
+        Ok(true)
+    })?;
+
+    Ok(())
+}
+
+pub fn sc_repos_rm(
+    args: &ArgMatches,
+    _libargs: &ArgMatches,
+    _mainargs: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    let name = args.get_one::<String>("name").unwrap();
+    let config = get_repos_config()?;
+
+    let changed = edit_repositories(args, |ver, repos| {
+        let names = resolve_repo_names(repos, &config, name);
+        for rname in names.iter() {
+            comment_out_repository(repos, rname);
+            OUTPUT.success(&format!("Removed repository {} from R {}", rname, ver));
+            info!("Removed repository {} from R {}", rname, ver);
+        }
+        Ok(!names.is_empty())
+    })?;
+
+    if changed == 0 {
+        bail!("No repository named {}", name);
+    }
+    Ok(())
+}
+
+pub fn sc_repos_enable(
+    args: &ArgMatches,
+    _libargs: &ArgMatches,
+    _mainargs: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    set_repos_default(args, true)
+}
+
+pub fn sc_repos_disable(
+    args: &ArgMatches,
+    _libargs: &ArgMatches,
+    _mainargs: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    set_repos_default(args, false)
+}
+
+pub fn sc_repos_reset(
+    args: &ArgMatches,
+    _libargs: &ArgMatches,
+    _mainargs: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    if !args.get_flag("r-default") {
+        let vers = target_versions(args)?;
+        escalate_for_repos()?;
+        repos_setup(
+            Some(vers),
+            ReposSetupArgs::Default {
+                whitelist: Vec::new(),
+                blacklist: Vec::new(),
+            },
+        )?;
+        OUTPUT.success("Reset repositories to the rig defaults");
+        info!("Reset repositories to the rig defaults");
+        return Ok(());
+    }
+
+    edit_repositories(args, |ver, repos| {
+        let orig = repositories_path(ver)? + ".orig";
+        *repos = read_repositories_file(&orig)?;
+        OUTPUT.success(&format!(
+            "Reset repositories of R {} to the R defaults",
+            ver
+        ));
+        info!("Reset repositories of R {} to the R defaults", ver);
+        Ok(true)
+    })?;
+
+    Ok(())
+}
+
+fn set_repos_default(args: &ArgMatches, default: bool) -> Result<(), Box<dyn Error>> {
+    let name = args.get_one::<String>("name").unwrap();
+    let config = get_repos_config()?;
+    let what = if default { "Enabled" } else { "Disabled" };
+
+    let changed = edit_repositories(args, |ver, repos| {
+        let names = resolve_repo_names(repos, &config, name);
+        for rname in names.iter() {
+            set_repository_default(repos, rname, default);
+            OUTPUT.success(&format!("{} repository {} for R {}", what, rname, ver));
+            info!("{} repository {} for R {}", what, rname, ver);
+        }
+        Ok(!names.is_empty())
+    })?;
+
+    if changed == 0 {
+        bail!(
+            "No repository named {}, use `rig repos add` to add it",
+            name
+        );
+    }
+    Ok(())
+}
+
+fn target_versions(args: &ArgMatches) -> Result<Vec<String>, Box<dyn Error>> {
+    match args.get_one::<String>("r-version") {
+        Some(ver) => Ok(vec![check_installed(ver)?]),
+        None => sc_get_list(),
+    }
+}
+
+// The repositories files belong to root in admin mode.
+fn escalate_for_repos() -> Result<(), Box<dyn Error>> {
+    if get_mode()? == Mode::Admin {
+        escalate("updating package repositories")?;
+    }
+    Ok(())
+}
+
+/// Edit the `repositories` file of the R versions of `args`: the one of
+/// `--r-version`, or all of them. `edit` returns whether it changed the
+/// file, only changed files are written. Returns the number of them.
+fn edit_repositories<F>(args: &ArgMatches, mut edit: F) -> Result<usize, Box<dyn Error>>
+where
+    F: FnMut(&str, &mut RepositoriesContents) -> Result<bool, Box<dyn Error>>,
+{
+    let vers = target_versions(args)?;
+    escalate_for_repos()?;
+
+    let mut changed = 0;
+    for ver in vers {
+        let repositories = repositories_path(&ver)?;
+        // The same as for `rig repos setup`, very old R versions might not
+        // have one.
+        if !PathBuf::from(&repositories).exists() {
+            debug!(
+                "repositories file does not exist at {}, skipping",
+                repositories
+            );
+            continue;
+        }
+
+        let _lock = FileLock::acquire(Path::new(&repositories))?;
+        save_repositories_orig(&repositories)?;
+        let mut repos = read_repositories_file(&repositories)?;
+        if edit(&ver, &mut repos)? {
+            debug!("Updating repositories file at {}", repositories);
+            write_repositories_file(repos, &repositories)?;
+            update_repos_profile(&ver)?;
+            changed += 1;
+        }
+    }
+
+    Ok(changed)
+}
+
+/// Look up a repository of the catalog by name, case insensitively.
+fn find_catalog_repo<'a>(config: &'a [Repository], name: &str) -> Option<&'a Repository> {
+    let needle = name.to_lowercase();
+    config.iter().find(|r| r.name.to_lowercase() == needle)
+}
+
+fn catalog_names(config: &[Repository]) -> String {
+    let mut names: Vec<&str> = config.iter().map(|r| r.name.as_str()).collect();
+    names.sort_by_key(|n| n.to_lowercase());
+    names.join(", ")
+}
+
+/// The names of the entries of a `repositories` file that `name` refers to.
+/// It is the name of an entry, or else the name of a repository of the
+/// catalog, that might have several entries, e.g. Bioconductor. Names are
+/// matched case insensitively, unless there is an exact match.
+fn resolve_repo_names(
+    repos: &RepositoriesContents,
+    config: &[Repository],
+    name: &str,
+) -> Vec<String> {
+    if repos.data.iter().any(|e| e.name == name) {
+        return vec![name.to_string()];
+    }
+    let needle = name.to_lowercase();
+    let mut names: Vec<String> = repos
+        .data
+        .iter()
+        .filter(|e| e.name.to_lowercase() == needle)
+        .map(|e| e.name.clone())
+        .collect();
+    if names.is_empty() {
+        if let Some(repo) = find_catalog_repo(config, name) {
+            names = repos
+                .data
+                .iter()
+                .filter(|e| repo.repos.iter().any(|r| r.name == e.name))
+                .map(|e| e.name.clone())
+                .collect();
+        }
+    }
+    names.dedup();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_entry(name: &str) -> RepoFileEntry {
+        RepoFileEntry {
+            name: name.to_string(),
+            description: name.to_string(),
+            url: format!("https://example.com/{}", name),
+            default: true,
+            source: true,
+            win_binary: true,
+            mac_binary: true,
+        }
+    }
+
+    #[test]
+    fn repo_names_resolve_to_entries_or_catalog() {
+        let repos = RepositoriesContents {
+            data: ["CRAN", "BioCsoft", "BioCann", "P3M"]
+                .iter()
+                .map(|n| file_entry(n))
+                .collect(),
+            comments: vec![],
+        };
+        let config = get_repos_config().unwrap();
+        assert_eq!(resolve_repo_names(&repos, &config, "CRAN"), vec!["CRAN"]);
+        assert_eq!(resolve_repo_names(&repos, &config, "p3m"), vec!["P3M"]);
+        assert_eq!(
+            resolve_repo_names(&repos, &config, "bioconductor"),
+            vec!["BioCsoft", "BioCann"]
+        );
+        assert!(resolve_repo_names(&repos, &config, "RHUB").is_empty());
+    }
+}
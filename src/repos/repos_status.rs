@@ -4,7 +4,7 @@ use std::io::IsTerminal;
 use clap::ArgMatches;
 use tabular::*;
 
-use crate::download::probe_urls_;
+use crate::download::{probe_urls_, UrlProbe};
 use crate::platform::{detect_platform, platform_to_pkg_type};
 use crate::repos::configured::configured_repos;
 use crate::repos::cranlike_metadata::{cranlike_urls, minor_r_version, package_type_to_path};
@@ -151,6 +151,22 @@ pub fn sc_repos_status(
     Ok(())
 }
 
+/// Probe the source package index of the default repositories of an R
+/// version, for `rig doctor`. Every repository has a source index, so a
+/// failure here means the repository is not reachable, whatever the platform.
+pub(crate) fn probe_default_repos(rver: &str) -> Result<Vec<(String, UrlProbe)>, Box<dyn Error>> {
+    let cfg = configured_repos(Some(rver), false, true)?;
+    let minor = minor_r_version(&cfg.numeric_version()?).unwrap_or_default();
+    let path = package_type_to_path("source", &minor)?;
+    let urls: Vec<String> = cfg
+        .repos
+        .iter()
+        .map(|r| cranlike_urls(&r.url, &path)[0].clone())
+        .collect();
+    let probes = probe_urls_(&urls);
+    Ok(cfg.repos.into_iter().map(|r| r.name).zip(probes).collect())
+}
+
 /// The package types the `repositories` file declares for a repository.
 fn declared_types(entry: &RepoFileEntry) -> Vec<&'static str> {
     let mut types = vec![];
@@ -189,7 +205,7 @@ fn install_arch(rver: &str) -> Option<&'static str> {
 /// source packages from a distro-specific URL, either `__linux__/<distro>` or
 /// the portable `manylinux` one. There is no way to tell from the `PACKAGES`
 /// index alone without downloading it, but the URL says so.
-fn serves_linux_binaries(url: &str) -> bool {
+pub(crate) fn serves_linux_binaries(url: &str) -> bool {
     url.contains("/__linux__/") || url.contains("manylinux")
 }
 
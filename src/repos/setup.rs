@@ -11,6 +11,7 @@ use crate::dcf::*;
 use crate::hardcoded::*;
 use crate::output::OUTPUT;
 use crate::repositories::*;
+use crate::rversion::OsVersion;
 use crate::utils::*;
 
 use super::{
@@ -31,7 +32,7 @@ use crate::linux::*;
 use crate::platform::*;
 
 #[derive(Debug)]
-struct RData {
+pub(super) struct RData {
     pub platform: String,
     pub arch: String,    // x86_64, aarch64
     pub version: String, // 4.5.2, etc.
@@ -39,41 +40,36 @@ struct RData {
     pub release: Option<String>,
 }
 
-fn validate_repos_in_setup(
-    config: &[Repository],
-    setup: &ReposSetupArgs,
-) -> Result<(), Box<dyn Error>> {
-    let mut valid_repo_names: Vec<String> = config
-        .iter()
-        .map(|r| r.name.to_lowercase())
-        .collect::<Vec<_>>();
-    valid_repo_names.sort();
-
-    let mut invalid_repos: Vec<String> = Vec::new();
-
-    match setup {
+/// The repository names of `setup` that are not in the catalog, lower case,
+/// sorted.
+fn invalid_repos_in_setup(config: &[Repository], setup: &ReposSetupArgs) -> Vec<String> {
+    let valid_repo_names: Vec<String> = config.iter().map(|r| r.name.to_lowercase()).collect();
+    let named: Vec<&String> = match setup {
         ReposSetupArgs::Default {
             whitelist,
             blacklist,
-        } => {
-            for repo in whitelist.iter().chain(blacklist.iter()) {
-                if !valid_repo_names.contains(repo) {
-                    invalid_repos.push(repo.clone());
-                }
-            }
-        }
-        ReposSetupArgs::Empty { whitelist } => {
-            for repo in whitelist.iter() {
-                if !valid_repo_names.contains(repo) {
-                    invalid_repos.push(repo.clone());
-                }
-            }
-        }
-    }
+        } => whitelist.iter().chain(blacklist.iter()).collect(),
+        ReposSetupArgs::Empty { whitelist } => whitelist.iter().collect(),
+    };
+    let mut invalid_repos: Vec<String> = named
+        .into_iter()
+        .filter(|r| !valid_repo_names.contains(r))
+        .cloned()
+        .collect();
+    invalid_repos.sort();
+    invalid_repos.dedup();
+    invalid_repos
+}
 
+fn validate_repos_in_setup(
+    config: &[Repository],
+    setup: &ReposSetupArgs,
+) -> Result<(), Box<dyn Error>> {
+    let invalid_repos = invalid_repos_in_setup(config, setup);
     if !invalid_repos.is_empty() {
-        invalid_repos.sort();
-        invalid_repos.dedup();
+        let mut valid_repo_names: Vec<String> =
+            config.iter().map(|r| r.name.to_lowercase()).collect();
+        valid_repo_names.sort();
         let inv = invalid_repos.join(", ");
         let val = valid_repo_names.join(", ");
         let msg = format!(
@@ -100,11 +96,7 @@ pub fn repos_setup(vers: Option<Vec<String>>, setup: ReposSetupArgs) -> Result<(
 
     for ver in vers {
         let ver = check_installed(&ver.to_string())?;
-        let root: String = get_r_root_for(&ver)?;
-        let repositories = root.clone()
-            + "/"
-            + &get_r_etc_path()?.replace("{}", &version_dir_key(&ver))
-            + "/repositories";
+        let repositories = repositories_path(&ver)?;
 
         // if no 'repositories' file, skip. Maybe this happens for very old R versions?
         if !PathBuf::from(&repositories).exists() {
@@ -115,111 +107,217 @@ pub fn repos_setup(vers: Option<Vec<String>>, setup: ReposSetupArgs) -> Result<(
             continue;
         }
 
-        // save a copy of the original file, so we can restore later if needed.
-        let orig: String = repositories.clone() + ".orig";
-        if !PathBuf::from(&orig).exists() {
-            debug!(
-                "Original repositories file does not exist at {}, copying from {}",
-                orig, repositories
-            );
-            std::fs::copy(&repositories, &orig)?;
-        }
+        let orig = save_repositories_orig(&repositories)?;
 
         debug!("Updating repositories file at {}", repositories);
-        let mut repos = read_repositories_file(&orig)?;
-
-        let rdata = get_r_data(&ver)?;
-        debug!("Detected architecture {:?}", rdata);
-
-        let rdata_platform = rdata_platform_string(&rdata);
-
-        add_repositories_comment(&mut repos, "start added by rig");
-        for repo in config.iter() {
-            // In `Empty` mode nothing is enabled by default; only the explicitly
-            // whitelisted repos are activated.
-            let empty_mode = matches!(setup, ReposSetupArgs::Empty { .. });
-            let in_whitelist = match &setup {
-                ReposSetupArgs::Default {
-                    whitelist,
-                    blacklist,
-                } => {
-                    whitelist.contains(&repo.name.to_lowercase())
-                        && !blacklist.contains(&repo.name.to_lowercase())
-                }
-                ReposSetupArgs::Empty { whitelist } => {
-                    whitelist.contains(&repo.name.to_lowercase())
-                }
-            };
+        let repos = set_up_repositories(&ver, &orig, &config, &setup)?;
+        write_repositories_file(repos, &repositories)?;
+
+        update_repos_profile(&ver)?;
+    }
 
-            for entry in repo.repos.iter() {
-                // An entry's `enabled` (if present) overrides the repo's. Whether
-                // a repo is enabled by default can depend on the installation's
-                // platform (e.g. P3M-manylinux is a default only on manylinux).
-                let enabled_default = if empty_mode {
-                    false
-                } else {
-                    let enabled = entry.enabled.as_ref().unwrap_or(&repo.enabled);
-                    enabled_by_default(enabled, &rdata_platform, &repo.name)
-                };
-                if !enabled_default && !in_whitelist {
-                    continue;
-                }
-                if !should_activate_repo(repo, entry, &rdata)? {
-                    continue;
-                }
-                add_repository(&mut repos, entry);
+    Ok(())
+}
+
+/// The `repositories` file `setup` makes for an installed R version, from its
+/// original `repositories` file `orig`.
+fn set_up_repositories(
+    ver: &str,
+    orig: &str,
+    config: &[Repository],
+    setup: &ReposSetupArgs,
+) -> Result<RepositoriesContents, Box<dyn Error>> {
+    let mut repos = read_repositories_file(orig)?;
+    let rdata = get_r_data(ver)?;
+    debug!("Detected architecture {:?}", rdata);
+
+    let rdata_platform = rdata_platform_string(&rdata);
+
+    add_repositories_comment(&mut repos, "start added by rig");
+    for repo in config.iter() {
+        // In `Empty` mode nothing is enabled by default; only the explicitly
+        // whitelisted repos are activated.
+        let empty_mode = matches!(setup, ReposSetupArgs::Empty { .. });
+        let in_whitelist = match setup {
+            ReposSetupArgs::Default {
+                whitelist,
+                blacklist,
+            } => {
+                whitelist.contains(&repo.name.to_lowercase())
+                    && !blacklist.contains(&repo.name.to_lowercase())
             }
+            ReposSetupArgs::Empty { whitelist } => whitelist.contains(&repo.name.to_lowercase()),
+        };
+
+        for entry in repo.repos.iter() {
+            // An entry's `enabled` (if present) overrides the repo's. Whether
+            // a repo is enabled by default can depend on the installation's
+            // platform (e.g. P3M-manylinux is a default only on manylinux).
+            let enabled_default = if empty_mode {
+                false
+            } else {
+                let enabled = entry.enabled.as_ref().unwrap_or(&repo.enabled);
+                enabled_by_default(enabled, &rdata_platform, &repo.name)
+            };
+            if !enabled_default && !in_whitelist {
+                continue;
+            }
+            if !should_activate_repo(repo, entry, &rdata)? {
+                continue;
+            }
+            add_repository(&mut repos, entry);
         }
-        add_repositories_comment(&mut repos, "end added by rig");
+    }
+    add_repositories_comment(&mut repos, "end added by rig");
 
-        write_repositories_file(repos, &repositories)?;
+    Ok(repos)
+}
 
-        let profile =
-            root.clone() + "/" + &get_r_base_profile()?.replace("{}", &version_dir_key(&ver));
-        debug!("Updating R profile at {}", profile);
-        let mut profile_lines = read_lines(Path::new(&profile))?;
-
-        // maybe already current?
-        if !grep_lines(
-            &Regex::new(&HC_PROFILE_REPOS_MARKERS.current_start.to_string())?,
-            &profile_lines,
-        )
-        .is_empty()
-        {
-            continue;
+/// Whether the repositories of an installed R version are set up the way
+/// `setup` sets them up, so setting them up again would change nothing.
+pub fn repos_setup_is_current(ver: &str, setup: &ReposSetupArgs) -> Result<bool, Box<dyn Error>> {
+    let repositories = repositories_path(ver)?;
+    if !PathBuf::from(&repositories).exists() {
+        // `repos_setup` skips these, too.
+        return Ok(true);
+    }
+    if repos_profile_block(ver)? != ReposBlock::Current {
+        return Ok(false);
+    }
+    let config = get_repos_config()?;
+    if !invalid_repos_in_setup(&config, setup).is_empty() {
+        // Let the setup report these.
+        return Ok(false);
+    }
+    let orig = repositories.clone() + ".orig";
+    let orig = if PathBuf::from(&orig).exists() {
+        orig
+    } else {
+        repositories.clone()
+    };
+    let want = set_up_repositories(ver, &orig, &config, setup)?;
+    Ok(read_repositories_file(&repositories)?.data == want.data)
+}
+
+/// The entries of a repository of the catalog that rig would set up for an
+/// R version on a platform, without an installation of it.
+pub(super) fn catalog_repo_entries(
+    repo: &Repository,
+    platform: &OsVersion,
+    r_version: &str,
+) -> Result<Vec<RepoEntry>, Box<dyn Error>> {
+    let rdata = RData {
+        platform: format!("{}-{}-{}", platform.arch, platform.vendor, platform.os),
+        arch: platform.arch.clone(),
+        version: r_version.to_string(),
+        distro: platform.distro.clone(),
+        release: platform.version.clone(),
+    };
+    let mut entries = vec![];
+    for entry in repo.repos.iter() {
+        if should_activate_repo(repo, entry, &rdata)? {
+            entries.push(entry.clone());
         }
+    }
+    Ok(entries)
+}
 
-        // maybe from another version of rig?
-        let start = grep_lines(
-            &Regex::new(&HC_PROFILE_REPOS_MARKERS.generic_start.to_string())?,
-            &profile_lines,
-        );
-        let end = grep_lines(
-            &Regex::new(&HC_PROFILE_REPOS_MARKERS.end.to_string())?,
-            &profile_lines,
+/// The `repositories` file of an installed R version.
+pub(super) fn repositories_path(ver: &str) -> Result<String, Box<dyn Error>> {
+    Ok(get_r_root_for(ver)?
+        + "/"
+        + &get_r_etc_path()?.replace("{}", &version_dir_key(ver))
+        + "/repositories")
+}
+
+/// Save a copy of the original `repositories` file of R, so we can restore it
+/// later if needed. Returns the path of the copy.
+pub(super) fn save_repositories_orig(repositories: &str) -> Result<String, Box<dyn Error>> {
+    let orig: String = repositories.to_string() + ".orig";
+    if !PathBuf::from(&orig).exists() {
+        debug!(
+            "Original repositories file does not exist at {}, copying from {}",
+            orig, repositories
         );
+        std::fs::copy(repositories, &orig)?;
+    }
+    Ok(orig)
+}
 
-        if start.len() == 1 && end.len() == 1 {
-            // remove old version
-            profile_lines.drain(start[0]..=end[0]);
-        } else if start.is_empty() && end.is_empty() {
-            // nothing there, nothing to remove
-        } else {
+/// Add the repository setup block of this version of rig to the profile of
+/// an installed R version, replacing the block of another rig version.
+pub(super) fn update_repos_profile(ver: &str) -> Result<(), Box<dyn Error>> {
+    let profile =
+        get_r_root_for(ver)? + "/" + &get_r_base_profile()?.replace("{}", &version_dir_key(ver));
+    debug!("Updating R profile at {}", profile);
+    let mut profile_lines = read_lines(Path::new(&profile))?;
+
+    match profile_repos_block(&profile_lines)? {
+        ReposBlock::Current => return Ok(()),
+        ReposBlock::Old(start, end) => {
+            // from another version of rig, remove it
+            profile_lines.drain(start..=end);
+        }
+        ReposBlock::Missing => {}
+        ReposBlock::Corrupt => {
             OUTPUT.warn(&format!(
                 "Corrupt R profile at {}, try reinstalling R. If the issue perists, report it to rig developers.",
                 profile
             ));
             warn!("Corrupt R profile at {}, try reinstalling R. If the issue perists, report it to rig developers.", profile);
-            continue;
+            return Ok(());
         }
-
-        profile_lines.push(HC_PROFILE_REPOS.to_string());
-        std::fs::write(&profile, profile_lines.join("\n"))?;
     }
 
+    profile_lines.push(HC_PROFILE_REPOS.to_string());
+    std::fs::write(&profile, profile_lines.join("\n"))?;
     Ok(())
 }
 
+/// The repository setup block of rig in an R profile.
+#[derive(Debug, PartialEq)]
+pub enum ReposBlock {
+    /// Written by this version of rig.
+    Current,
+    /// Written by another version of rig, the first and last line.
+    Old(usize, usize),
+    Missing,
+    /// Start or end markers that do not pair up.
+    Corrupt,
+}
+
+fn profile_repos_block(lines: &[String]) -> Result<ReposBlock, Box<dyn Error>> {
+    let current = grep_lines(
+        &Regex::new(&HC_PROFILE_REPOS_MARKERS.current_start.to_string())?,
+        lines,
+    );
+    if !current.is_empty() {
+        return Ok(ReposBlock::Current);
+    }
+    let start = grep_lines(
+        &Regex::new(&HC_PROFILE_REPOS_MARKERS.generic_start.to_string())?,
+        lines,
+    );
+    let end = grep_lines(
+        &Regex::new(&HC_PROFILE_REPOS_MARKERS.end.to_string())?,
+        lines,
+    );
+    if start.len() == 1 && end.len() == 1 && start[0] < end[0] {
+        Ok(ReposBlock::Old(start[0], end[0]))
+    } else if start.is_empty() && end.is_empty() {
+        Ok(ReposBlock::Missing)
+    } else {
+        Ok(ReposBlock::Corrupt)
+    }
+}
+
+/// The repository setup block in the profile of an installed R version.
+pub fn repos_profile_block(rver: &str) -> Result<ReposBlock, Box<dyn Error>> {
+    let profile =
+        get_r_root_for(rver)? + "/" + &get_r_base_profile()?.replace("{}", &version_dir_key(rver));
+    profile_repos_block(&read_lines(Path::new(&profile))?)
+}
+
 // Compose the full platform string that platform globs are matched against,
 // e.g. "x86_64-pc-linux-gnu-ubuntu-22.04" or "x86_64-pc-linux-gnu-manylinux-2.34".
 fn rdata_platform_string(rdata: &RData) -> String {
@@ -270,7 +368,7 @@ fn enabled_by_default(enabled: &Enabled, rdata_platform: &str, ctx: &str) -> boo
     }
 }
 
-fn should_activate_repo(
+pub(super) fn should_activate_repo(
     repo: &Repository,
     entry: &RepoEntry,
     rdata: &RData,
@@ -335,7 +433,7 @@ fn should_activate_repo(
 }
 
 #[cfg(target_os = "macos")]
-fn get_r_data(ver: &str) -> Result<RData, Box<dyn Error>> {
+pub(super) fn get_r_data(ver: &str) -> Result<RData, Box<dyn Error>> {
     get_r_data_common(ver)
 }
 
@@ -422,7 +520,7 @@ fn get_r_data_common(ver: &str) -> Result<RData, Box<dyn Error>> {
 }
 
 #[cfg(target_os = "linux")]
-fn get_r_data(ver: &str) -> Result<RData, Box<dyn Error>> {
+pub(super) fn get_r_data(ver: &str) -> Result<RData, Box<dyn Error>> {
     let mut data = get_r_data_common(ver)?;
 
     let install_dir = PathBuf::from(get_r_root_for(ver)?).join(version_dir_key(ver));
@@ -449,7 +547,7 @@ fn get_r_data(ver: &str) -> Result<RData, Box<dyn Error>> {
 }
 
 #[cfg(target_os = "windows")]
-fn get_r_data(ver: &str) -> Result<RData, Box<dyn Error>> {
+pub(super) fn get_r_data(ver: &str) -> Result<RData, Box<dyn Error>> {
     // TODO: this arch does not work on Windows, because of an R bug:
     // https://bugs.r-project.org/show_bug.cgi?id=19003
     // We need to look for "^BINPREF" in a a Makeconf file, in
@@ -461,9 +559,10 @@ fn get_r_data(ver: &str) -> Result<RData, Box<dyn Error>> {
 #[cfg(test)]
 mod tests {
     use super::{
-        enabled_by_default, rdata_platform_string, should_activate_repo, validate_repos_in_setup,
-        RData,
+        enabled_by_default, profile_repos_block, rdata_platform_string, should_activate_repo,
+        validate_repos_in_setup, RData, ReposBlock,
     };
+    use crate::hardcoded::HC_PROFILE_REPOS_MARKERS;
     use crate::repos::config::{Enabled, RepoEntry, Repository};
     use crate::repos::interpret_repos_args::ReposSetupArgs;
 
@@ -730,4 +829,30 @@ mod tests {
             "P3M-manylinux"
         ));
     }
+
+    fn lines(x: &[&str]) -> Vec<String> {
+        x.iter().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn profile_repos_block_tells_old_from_current() {
+        let markers = &*HC_PROFILE_REPOS_MARKERS;
+        let current = lines(&[
+            "x <- 1",
+            &markers.generic_start,
+            &markers.current_start,
+            &markers.end,
+        ]);
+        assert_eq!(profile_repos_block(&current).unwrap(), ReposBlock::Current);
+
+        let old = lines(&["x <- 1", &markers.generic_start, "options()", &markers.end]);
+        assert_eq!(profile_repos_block(&old).unwrap(), ReposBlock::Old(1, 3));
+
+        assert_eq!(
+            profile_repos_block(&lines(&["x <- 1"])).unwrap(),
+            ReposBlock::Missing
+        );
+        let corrupt = lines(&["x <- 1", &markers.generic_start]);
+        assert_eq!(profile_repos_block(&corrupt).unwrap(), ReposBlock::Corrupt);
+    }
 }
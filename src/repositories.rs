@@ -7,7 +7,7 @@ use serde::Serialize;
 
 use crate::repos::*;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, PartialEq, Serialize)]
 pub struct RepoFileEntry {
     pub name: String,
     pub description: String,
@@ -152,6 +152,52 @@ pub fn add_repository(repos: &mut RepositoriesContents, entry: &RepoEntry) {
     repos.data.push(new_entry);
 }
 
+/// Add a repository to the block of repositories that rig added, replacing
+/// a repository of the same name. Starts the block if there is none.
+pub fn add_rig_repository(repos: &mut RepositoriesContents, entry: &RepoEntry, default: bool) {
+    comment_out_repository(repos, &entry.name);
+    let end = "## end added by rig";
+    match repos.comments.iter().position(|(_, c)| c == end) {
+        Some(idx) => {
+            // The end marker moves below the new entry, so the lines after
+            // it move up by one.
+            let (line, _) = repos.comments.remove(idx);
+            for (num, _) in repos.comments.iter_mut() {
+                if *num > line {
+                    *num -= 1;
+                }
+            }
+        }
+        None => add_repositories_comment(repos, "start added by rig"),
+    }
+    repos.data.push(RepoFileEntry {
+        name: entry.name.clone(),
+        description: entry.name.clone(),
+        url: entry.url.clone(),
+        default,
+        source: true,
+        win_binary: true,
+        mac_binary: true,
+    });
+    add_repositories_comment(repos, "end added by rig");
+}
+
+/// Set whether a repository is enabled by default. Returns `false` if there
+/// is no repository with this name.
+pub fn set_repository_default(
+    repos: &mut RepositoriesContents,
+    repo_name: &str,
+    default: bool,
+) -> bool {
+    match repos.data.iter_mut().find(|entry| entry.name == repo_name) {
+        Some(entry) => {
+            entry.default = default;
+            true
+        }
+        None => false,
+    }
+}
+
 pub fn add_repositories_comment(repos: &mut RepositoriesContents, comment: &str) {
     let total_lines = repos.comments.len() + repos.data.len();
     repos
@@ -284,3 +330,108 @@ fn write_tsv(
     writer.flush()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, url: &str) -> RepoEntry {
+        RepoEntry {
+            name: name.to_string(),
+            title: None,
+            description: None,
+            url: url.to_string(),
+            platforms: None,
+            archs: None,
+            rversions: None,
+            enabled: None,
+        }
+    }
+
+    fn roundtrip(contents: &str, edit: impl FnOnce(&mut RepositoriesContents)) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("repositories");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, contents).unwrap();
+        let mut repos = read_repositories_file(path).unwrap();
+        edit(&mut repos);
+        write_repositories_file(repos, path).unwrap();
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    const REPOSITORIES: &str = "\
+## comment
+menu_name\tURL\tdefault\tsource\twin.binary\tmac.binary
+CRAN\tCRAN\t@CRAN@\tTRUE\tTRUE\tTRUE\tTRUE
+## start added by rig
+P3M\tP3M\thttps://p3m.dev/cran/latest\tTRUE\tTRUE\tTRUE\tTRUE
+## end added by rig
+";
+
+    #[test]
+    fn added_repositories_go_in_the_rig_block() {
+        let out = roundtrip(REPOSITORIES, |repos| {
+            add_rig_repository(repos, &entry("extra", "https://example.com"), false);
+        });
+        assert_eq!(
+            out,
+            "\
+## comment
+menu_name\tURL\tdefault\tsource\twin.binary\tmac.binary
+CRAN\tCRAN\t@CRAN@\tTRUE\tTRUE\tTRUE\tTRUE
+## start added by rig
+P3M\tP3M\thttps://p3m.dev/cran/latest\tTRUE\tTRUE\tTRUE\tTRUE
+extra\textra\thttps://example.com\tFALSE\tTRUE\tTRUE\tTRUE
+## end added by rig
+"
+        );
+    }
+
+    #[test]
+    fn added_repositories_replace_the_same_name() {
+        let out = roundtrip(REPOSITORIES, |repos| {
+            add_rig_repository(repos, &entry("CRAN", "https://cran.example.com"), true);
+        });
+        assert_eq!(
+            out,
+            "\
+## comment
+menu_name\tURL\tdefault\tsource\twin.binary\tmac.binary
+## CRAN\tCRAN\t@CRAN@\tTRUE\tTRUE\tTRUE\tTRUE
+## start added by rig
+P3M\tP3M\thttps://p3m.dev/cran/latest\tTRUE\tTRUE\tTRUE\tTRUE
+CRAN\tCRAN\thttps://cran.example.com\tTRUE\tTRUE\tTRUE\tTRUE
+## end added by rig
+"
+        );
+    }
+
+    #[test]
+    fn added_repositories_start_a_rig_block() {
+        let plain = "menu_name\tURL\tdefault\tsource\twin.binary\tmac.binary\n\
+                     CRAN\tCRAN\t@CRAN@\tTRUE\tTRUE\tTRUE\tTRUE\n";
+        let out = roundtrip(plain, |repos| {
+            add_rig_repository(repos, &entry("extra", "https://example.com"), true);
+        });
+        assert_eq!(
+            out,
+            "\
+menu_name\tURL\tdefault\tsource\twin.binary\tmac.binary
+CRAN\tCRAN\t@CRAN@\tTRUE\tTRUE\tTRUE\tTRUE
+## start added by rig
+extra\textra\thttps://example.com\tTRUE\tTRUE\tTRUE\tTRUE
+## end added by rig
+"
+        );
+    }
+
+    #[test]
+    fn repositories_are_enabled_and_disabled() {
+        let out = roundtrip(REPOSITORIES, |repos| {
+            assert!(set_repository_default(repos, "CRAN", false));
+            assert!(!set_repository_default(repos, "nope", false));
+        });
+        assert!(out.contains("CRAN\tCRAN\t@CRAN@\tFALSE\tTRUE"));
+        assert!(out.contains("P3M\tP3M\thttps://p3m.dev/cran/latest\tTRUE"));
+    }
+}
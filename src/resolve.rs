@@ -1,12 +1,12 @@
 use futures::future;
 use std::error::Error;
+use std::path::{Path, PathBuf};
 
 use clap::ArgMatches;
-use log::error;
+use log::{debug, error, warn};
 #[cfg(target_os = "windows")]
-use log::warn;
-#[cfg(target_os = "windows")]
-use serde_json::{Map, Value};
+use serde_json::Map;
+use serde_json::Value;
 use simple_error::bail;
 #[cfg(target_os = "windows")]
 use std::sync::{LazyLock, RwLock};
@@ -19,9 +19,229 @@ use crate::output::OUTPUT;
 use crate::rversion::*;
 use crate::utils::*;
 
-const API_URI: &str = "https://api.r-hub.io/rversions/resolve/";
-#[cfg(target_os = "windows")]
-const API_ROOT: &str = "https://api.r-hub.io/rversions/"; // must end with '/'
+const DEFAULT_API_ROOT: &str = "https://api.r-hub.io/rversions/";
+
+/// The root of the R versions API, from `RIG_RVERSIONS_API` or the
+/// `rversions-api` config entry, e.g. an internal mirror of it. It always
+/// ends with a `/`, so endpoints can be appended to it.
+pub fn rversions_api() -> String {
+    let root = match std::env::var("RIG_RVERSIONS_API") {
+        Ok(val) if !val.is_empty() => val,
+        _ => match crate::config::get_global_config_value("rversions-api") {
+            Ok(Some(val)) if !val.is_empty() => val,
+            _ => DEFAULT_API_ROOT.to_string(),
+        },
+    };
+    format!("{}/", root.trim_end_matches('/'))
+}
+
+/// The user-supplied version table, from `RIG_RVERSIONS_TABLE` or the
+/// `rversions-table` config entry. With one rig resolves R versions from it,
+/// without the R versions API.
+pub fn rversions_table() -> Option<PathBuf> {
+    match std::env::var("RIG_RVERSIONS_TABLE") {
+        Ok(val) if !val.is_empty() => Some(PathBuf::from(val)),
+        _ => match crate::config::get_global_config_value("rversions-table") {
+            Ok(Some(val)) if !val.is_empty() => Some(PathBuf::from(val)),
+            _ => None,
+        },
+    }
+}
+
+/// Where rig keeps the versions it learned from the API, for resolving when
+/// the API is not reachable. `rig available` and every successful resolve
+/// update it. The `default` arch is the native one, as for `rig available`.
+fn cached_table_path(platform: &str, arch: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let arch = if arch == "default" {
+        arch_name(platform, std::env::consts::ARCH.to_string())
+    } else {
+        arch.to_string()
+    };
+    Ok(crate::cache::get_cache_dir()?
+        .join("rversions")
+        .join(format!("available-{}-{}.json", platform, arch)))
+}
+
+/// Add `entries` to the cached version table at `path`. An entry replaces
+/// the cached one of the same version, or of the same name for `devel` and
+/// `next`, but keeps the fields that only the cached one has: the `available`
+/// endpoint does not tell about P3M binaries, the `resolve` endpoint does.
+fn update_cached_table(path: &Path, entries: &[Value]) {
+    let key = |v: &Value| -> Option<String> {
+        match v["name"].as_str() {
+            Some(name @ ("devel" | "next")) => Some(name.to_string()),
+            _ => v["version"].as_str().map(|v| v.to_string()),
+        }
+    };
+    let mut table: Vec<Value> = std::fs::read_to_string(path)
+        .ok()
+        .and_then(|x| serde_json::from_str::<Value>(&x).ok())
+        .and_then(|x| x.as_array().cloned())
+        .unwrap_or_default();
+    for entry in entries {
+        let Some(k) = key(entry) else { continue };
+        match table.iter_mut().find(|v| key(v).as_ref() == Some(&k)) {
+            Some(old) => {
+                let same_version = old["version"] == entry["version"];
+                if let (Some(old), Some(new)) = (old.as_object_mut(), entry.as_object()) {
+                    if !same_version {
+                        old.clear();
+                    }
+                    for (field, value) in new {
+                        old.insert(field.clone(), value.clone());
+                    }
+                }
+            }
+            None => table.push(entry.clone()),
+        }
+    }
+    let write = serde_json::to_string_pretty(&table)
+        .map_err(|e| e.into())
+        .and_then(|json| crate::utils::write_atomically(path, json.as_bytes()));
+    if let Err(err) = write {
+        debug!(
+            "Cannot cache R version table at {}: {}",
+            path.display(),
+            err
+        );
+    }
+}
+
+/// The R versions available for `platform` and `arch`: from the user's
+/// version table, if there is one, otherwise from the API, and from the copy
+/// of the API's answer cached the last time, if the API is not reachable.
+pub fn get_available_table(platform: &str, arch: &str) -> Result<Vec<Value>, Box<dyn Error>> {
+    if let Some(path) = rversions_table() {
+        debug!("Using R version table at {}", path.display());
+        return read_version_table(&path, platform, arch);
+    }
+
+    let url = rversions_api() + "available/" + platform + "/" + arch;
+    let cached = cached_table_path(platform, arch)?;
+    match crate::download::download_json_sync(vec![url]) {
+        Ok(resp) => match resp[0].as_array() {
+            Some(table) => {
+                update_cached_table(&cached, table);
+                Ok(table.clone())
+            }
+            None => bail!("Invalid response from the R versions API, not an array"),
+        },
+        Err(err) if cached.exists() => {
+            OUTPUT.warn(&format!(
+                "Cannot reach the R versions API, using the versions cached at {}",
+                cached.display()
+            ));
+            warn!(
+                "Cannot reach the R versions API ({}), using {}",
+                err,
+                cached.display()
+            );
+            read_version_table(&cached, platform, arch)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Read a version table: the answer of the API's `available` endpoint, an
+/// array of `{ name, version, date, type, url }` objects, or an object of
+/// those, keyed by `<platform>/<arch>` or `<platform>`.
+pub fn read_version_table(
+    path: &Path,
+    platform: &str,
+    arch: &str,
+) -> Result<Vec<Value>, Box<dyn Error>> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("Cannot read R version table {}: {}", path.display(), e))?;
+    let table: Value = serde_json::from_str(&contents)
+        .map_err(|e| format!("Invalid R version table {}: {}", path.display(), e))?;
+    let table = match &table {
+        Value::Object(map) => map
+            .get(&format!("{}/{}", platform, arch))
+            .or_else(|| map.get(platform)),
+        _ => Some(&table),
+    };
+    match table.and_then(|t| t.as_array()) {
+        Some(table) => Ok(table.clone()),
+        None => bail!(
+            "No R versions for {}/{} in R version table {}",
+            platform,
+            arch,
+            path.display()
+        ),
+    }
+}
+
+/// Resolve `ver` in a version table, the way the API does: `devel` and
+/// `next` by name, `release` is the newest version, `oldrel/n` (`oldrel` is
+/// `oldrel/1`) the newest of the `n`th previous minor branch, and `4`, `4.4`
+/// and `4.4.1` the newest version they are a prefix of.
+pub fn resolve_in_table(
+    table: &[Value],
+    ver: &str,
+    arch: &str,
+) -> Result<Rversion, Box<dyn Error>> {
+    let entry = |v: &Value| -> Option<(String, semver::Version)> {
+        let version = v["version"].as_str()?;
+        Some((version.to_string(), semver::Version::parse(version).ok()?))
+    };
+    let releases: Vec<(&Value, semver::Version)> = table
+        .iter()
+        .filter(|v| !matches!(v["name"].as_str(), Some("devel") | Some("next")))
+        .filter_map(|v| entry(v).map(|(_, sv)| (v, sv)))
+        .collect();
+
+    let newest = |pred: &dyn Fn(&semver::Version) -> bool| {
+        releases
+            .iter()
+            .filter(|(_, sv)| pred(sv))
+            .max_by(|a, b| a.1.cmp(&b.1))
+            .map(|(v, _)| *v)
+    };
+
+    let found: Option<&Value> = match ver {
+        "devel" | "next" => table.iter().find(|v| v["name"].as_str() == Some(ver)),
+        "release" => newest(&|_| true),
+        _ if ver == "oldrel" || ver.starts_with("oldrel/") => {
+            let n: usize = match ver.strip_prefix("oldrel/") {
+                None => 1,
+                Some(n) => n
+                    .parse()
+                    .map_err(|_| format!("Invalid R version: {}", ver))?,
+            };
+            let mut minors: Vec<(u64, u64)> = releases
+                .iter()
+                .map(|(_, sv)| (sv.major, sv.minor))
+                .collect();
+            minors.sort();
+            minors.dedup();
+            minors.reverse();
+            match minors.get(n) {
+                Some(&(major, minor)) => newest(&|sv| sv.major == major && sv.minor == minor),
+                None => None,
+            }
+        }
+        _ => match crate::shim::parse_partial(ver) {
+            Some((major, minor, patch)) => newest(&|sv| {
+                sv.major == major
+                    && minor.is_none_or(|m| sv.minor == m)
+                    && patch.is_none_or(|p| sv.patch == p)
+            }),
+            None => bail!("Invalid R version: {}", ver),
+        },
+    };
+
+    let Some(found) = found else {
+        bail!("No R version {} in the R version table", ver);
+    };
+    Ok(Rversion {
+        version: found["version"].as_str().map(|v| v.to_string()),
+        url: found["url"].as_str().map(|v| v.to_string()),
+        arch: Some(arch.to_string()),
+        ppm: found["ppm-binaries"].as_bool().unwrap_or_default(),
+        ppmurl: found["ppm-binary-url"].as_str().map(|v| v.to_string()),
+        sha256: found["sha256"].as_str().map(|v| v.to_string()),
+    })
+}
 
 pub fn get_resolve(args: &ArgMatches) -> Result<Rversion, Box<dyn Error>> {
     let platform = get_platform(args)?;
@@ -30,7 +250,7 @@ pub fn get_resolve(args: &ArgMatches) -> Result<Rversion, Box<dyn Error>> {
 
 pub fn get_resolve_for(args: &ArgMatches, platform: &str) -> Result<Rversion, Box<dyn Error>> {
     let arch = get_arch(platform, args);
-    let str: &String = args.get_one("str").unwrap();
+    let str = &crate::shim::add_version(args);
     let eps = vec![str.to_string()];
 
     if str.len() > 8 && (&str[..7] == "http://" || &str[..8] == "https://") {
@@ -40,6 +260,7 @@ pub fn get_resolve_for(args: &ArgMatches, platform: &str) -> Result<Rversion, Bo
             arch: None,
             ppm: false,
             ppmurl: None,
+            sha256: None,
         })
     } else {
         Ok(resolve_versions(eps, platform, &arch)?[0].to_owned())
@@ -52,7 +273,7 @@ pub async fn resolve_versions(
     platform: &str,
     arch: &str,
 ) -> Result<Vec<Rversion>, Box<dyn Error>> {
-    let client = reqwest::Client::new();
+    let client = crate::http::client()?;
     let client = &client;
     let out: Vec<Result<Rversion, Box<dyn Error>>> = future::join_all(
         vers.into_iter()
@@ -82,28 +303,90 @@ async fn resolve_version(
     platform: &str,
     arch: &str,
 ) -> Result<Rversion, Box<dyn Error>> {
-    let mut url = API_URI.to_string() + ver + "/" + platform;
+    if let Some(path) = rversions_table() {
+        debug!(
+            "Resolving R {} from the R version table at {}",
+            ver,
+            path.display()
+        );
+        let table = read_version_table(&path, platform, arch)?;
+        return resolve_in_table(&table, ver, arch);
+    }
+
+    let mut url = rversions_api() + "resolve/" + ver + "/" + platform;
 
     if arch != "default" {
         url = url + "/" + arch;
     }
 
-    let resp = download_json(client, vec![url]).await?;
+    let resp = match download_json(client, vec![url]).await {
+        Ok(resp) => resp,
+        Err(err) => {
+            let cached = cached_table_path(platform, arch)?;
+            if !cached.exists() {
+                return Err(err);
+            }
+            OUTPUT.warn(&format!(
+                "Cannot reach the R versions API, resolving R {} from the versions cached at {}",
+                ver,
+                cached.display()
+            ));
+            warn!(
+                "Cannot reach the R versions API ({}), using {}",
+                err,
+                cached.display()
+            );
+            let table = read_version_table(&cached, platform, arch)?;
+            return resolve_in_table(&table, ver, arch);
+        }
+    };
     let resp = &resp[0];
+    remember_resolved(platform, arch, ver, resp);
 
     let version: String = unquote(&resp["version"].to_string());
     let dlurl = Some(unquote(&resp["url"].to_string()));
     let ppm = resp["ppm-binaries"].as_bool().unwrap_or_default();
     let ppmurl = resp["ppm-binary-url"].as_str().map(|v| v.to_string());
+    let sha256 = resp["sha256"].as_str().map(|v| v.to_string());
     Ok(Rversion {
         version: Some(version),
         url: dlurl,
         arch: Some(arch.to_string()),
         ppm,
         ppmurl,
+        sha256,
     })
 }
 
+/// Add the answer of the `resolve` endpoint to the cached version table, so
+/// that the same version resolves offline, with its P3M binaries.
+fn remember_resolved(platform: &str, arch: &str, ver: &str, resp: &Value) {
+    let Some(version) = resp["version"].as_str() else {
+        return;
+    };
+    let mut entry = resp.clone();
+    if let Some(obj) = entry.as_object_mut() {
+        // A `devel` or `next` entry has to say so, or it counts as a release.
+        let name = match ver {
+            "devel" | "next" => ver,
+            _ => resp["name"].as_str().unwrap_or(version),
+        };
+        obj.insert("name".to_string(), Value::from(name));
+        if !obj.contains_key("type") {
+            let kind = if ver == "devel" || ver == "next" {
+                ver
+            } else {
+                "release"
+            };
+            obj.insert("type".to_string(), Value::from(kind));
+        }
+    }
+    match cached_table_path(platform, arch) {
+        Ok(path) => update_cached_table(&path, &[entry]),
+        Err(err) => debug!("Cannot cache resolved R {}: {}", ver, err),
+    }
+}
+
 #[cfg(target_os = "windows")]
 static API_CACHE: LazyLock<RwLock<Map<String, Value>>> = LazyLock::new(|| RwLock::new(Map::new()));
 
@@ -125,7 +408,7 @@ pub fn get_available_rtools_versions(arch: &str) -> serde_json::Value {
     let value = match cache_get_value(&cache_key) {
         Some(cached) => cached,
         None => {
-            let url = API_ROOT.to_string() + "rtools-versions/" + arch;
+            let url = rversions_api() + "rtools-versions/" + arch;
             let val = match download_json_sync(vec![url]) {
                 Ok(dl) => dl[0].clone(),
                 Err(err) => {
@@ -184,3 +467,117 @@ pub fn get_rtools_version(version: &str, arch: &str) -> Result<RtoolsVersion, Bo
     error!("{}", msg);
     bail!(msg)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> Vec<Value> {
+        serde_json::from_str(
+            r#"[
+              {"name": "4.2.3", "version": "4.2.3", "type": "release", "url": "https://m/R-4.2.3.tar.gz"},
+              {"name": "4.3.2", "version": "4.3.2", "type": "release", "url": "https://m/R-4.3.2.tar.gz"},
+              {"name": "4.3.3", "version": "4.3.3", "type": "release", "url": "https://m/R-4.3.3.tar.gz"},
+              {"name": "4.4.0", "version": "4.4.0", "type": "release", "url": "https://m/R-4.4.0.tar.gz"},
+              {"name": "4.4.1", "version": "4.4.1", "type": "release", "url": "https://m/R-4.4.1.tar.gz",
+               "sha256": "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"},
+              {"name": "next", "version": "4.4.2", "type": "next", "url": "https://m/R-patched.tar.gz"},
+              {"name": "devel", "version": "4.5.0", "type": "devel", "url": "https://m/R-devel.tar.gz"}
+            ]"#,
+        )
+        .unwrap()
+    }
+
+    fn resolve(ver: &str) -> Option<String> {
+        resolve_in_table(&table(), ver, "x86_64")
+            .ok()
+            .and_then(|v| v.version)
+    }
+
+    #[test]
+    fn symbolic_versions_resolve_in_a_table() {
+        assert_eq!(resolve("release").as_deref(), Some("4.4.1"));
+        assert_eq!(resolve("oldrel").as_deref(), Some("4.3.3"));
+        assert_eq!(resolve("oldrel/1").as_deref(), Some("4.3.3"));
+        assert_eq!(resolve("oldrel/2").as_deref(), Some("4.2.3"));
+        assert_eq!(resolve("oldrel/3"), None);
+        assert_eq!(resolve("devel").as_deref(), Some("4.5.0"));
+        assert_eq!(resolve("next").as_deref(), Some("4.4.2"));
+    }
+
+    #[test]
+    fn version_prefixes_resolve_in_a_table() {
+        assert_eq!(resolve("4.3").as_deref(), Some("4.3.3"));
+        assert_eq!(resolve("4.3.2").as_deref(), Some("4.3.2"));
+        assert_eq!(resolve("4").as_deref(), Some("4.4.1"));
+        assert_eq!(resolve("4.1"), None);
+        assert_eq!(resolve("foo"), None);
+
+        let v = resolve_in_table(&table(), "4.4", "x86_64").unwrap();
+        assert_eq!(v.url.as_deref(), Some("https://m/R-4.4.1.tar.gz"));
+        assert_eq!(v.arch.as_deref(), Some("x86_64"));
+        assert!(v.sha256.is_some());
+    }
+
+    #[test]
+    fn a_table_can_be_keyed_by_platform() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("rversions.json");
+        let entries = serde_json::to_string(&table()).unwrap();
+        std::fs::write(
+            &path,
+            format!(
+                r#"{{"linux-ubuntu-24.04/x86_64": {0}, "macos": {0}}}"#,
+                entries
+            ),
+        )
+        .unwrap();
+
+        let t = read_version_table(&path, "linux-ubuntu-24.04", "x86_64").unwrap();
+        assert_eq!(t.len(), 7);
+        let t = read_version_table(&path, "macos", "arm64").unwrap();
+        assert_eq!(t.len(), 7);
+        assert!(read_version_table(&path, "linux-ubuntu-24.04", "aarch64").is_err());
+
+        std::fs::write(&path, entries).unwrap();
+        let t = read_version_table(&path, "windows", "x86_64").unwrap();
+        assert_eq!(t.len(), 7);
+    }
+
+    #[test]
+    fn resolving_keeps_the_cached_p3m_binaries() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("available-linux-ubuntu-24.04-x86_64.json");
+
+        // What `rig available` caches, and then what a resolve adds.
+        update_cached_table(&path, &table());
+        let resolved: Value = serde_json::from_str(
+            r#"{"version": "4.4.1", "url": "https://m/R-4.4.1.tar.gz",
+                "ppm-binaries": true, "ppm-binary-url": "https://p3m/noble"}"#,
+        )
+        .unwrap();
+        update_cached_table(&path, &[resolved]);
+        // A later `rig available` does not know about P3M.
+        update_cached_table(&path, &table());
+
+        let cached = read_version_table(&path, "linux-ubuntu-24.04", "x86_64").unwrap();
+        assert_eq!(cached.len(), 7);
+        let v = resolve_in_table(&cached, "release", "x86_64").unwrap();
+        assert_eq!(v.version.as_deref(), Some("4.4.1"));
+        assert!(v.ppm);
+        assert_eq!(v.ppmurl.as_deref(), Some("https://p3m/noble"));
+        assert!(v.sha256.is_some());
+        assert!(!resolve_in_table(&cached, "4.3", "x86_64").unwrap().ppm);
+
+        // A new devel version replaces the old one, fields and all.
+        let devel: Value = serde_json::from_str(
+            r#"{"name": "devel", "version": "4.6.0", "url": "https://m/R-devel.tar.gz"}"#,
+        )
+        .unwrap();
+        update_cached_table(&path, &[devel]);
+        let cached = read_version_table(&path, "linux-ubuntu-24.04", "x86_64").unwrap();
+        assert_eq!(cached.len(), 7);
+        let v = resolve_in_table(&cached, "devel", "x86_64").unwrap();
+        assert_eq!(v.version.as_deref(), Some("4.6.0"));
+    }
+}
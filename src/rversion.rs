@@ -17,6 +17,9 @@ pub struct Rversion {
     pub ppm: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ppmurl: Option<String>,
+    /// The sha256 of the installer at `url`, if the API has it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
 }
 
 #[cfg(target_os = "macos")]
@@ -0,0 +1,823 @@
+// `rig cache`: what rig keeps in its cache and download directories, and
+// getting rid of it.
+//
+// rig never deletes anything from these directories on its own, so without
+// this command they only grow. What is in them, by category:
+//
+// * `packages`: the package files `rig pkg install` and `rig proj deploy`
+//   download, under `<cache>/packages`, named by `PakLockfilePackage::target`.
+// * `binaries`: the per-package P3M binary indexes, `<cache>/binaries`.
+// * `metadata`: everything else in the cache directory: the SQLite
+//   `packages.db`, the downloaded `PACKAGES` files and their ETags, the P3M
+//   status document and the package manifests. All of it is re-downloaded on
+//   demand.
+// * `installers`: the R (and on Windows the Rtools) installers in the download
+//   directory, see `get_download_dir()`. If the user set the download
+//   directory, e.g. to `~/Downloads`, it has other files as well, and then
+//   only the ones that rig names the way it names its downloads belong to this
+//   category, see `is_rig_download()`.
+//
+// The log files are in the cache directory on Linux and Windows, but they are
+// not a cache, so none of the categories include them. Neither do the lock
+// files in `<cache>/locks`: removing a lock that another rig process holds
+// would let a third one in.
+//
+// This is a command module, and deliberately not part of src/cache.rs, which
+// src/lib.rs (the macOS menu bar app) compiles as well.
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use clap::ArgMatches;
+use log::{debug, info, warn};
+use simple_error::bail;
+use tabular::{row, Table};
+
+use crate::cache::{download_dir_is_overridden, get_cache_dir, get_download_dir};
+use crate::output::OUTPUT;
+use crate::pak::{artifact_cache_key, PakLockfile, ARCHIVE_SUFFIXES};
+use crate::pkg::list::{read_installed, InstalledPackage};
+use crate::textfmt::format_bytes;
+
+#[cfg(target_os = "linux")]
+use crate::linux::sc_get_list;
+#[cfg(target_os = "macos")]
+use crate::macos::sc_get_list;
+#[cfg(target_os = "windows")]
+use crate::windows::sc_get_list;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Category {
+    Packages,
+    Binaries,
+    Metadata,
+    Installers,
+}
+
+const CATEGORIES: [Category; 4] = [
+    Category::Packages,
+    Category::Binaries,
+    Category::Metadata,
+    Category::Installers,
+];
+
+// Subdirectories of the cache directory that are not `metadata`.
+const PACKAGES_DIR: &str = "packages";
+const BINARIES_DIR: &str = "binaries";
+const LOGS_DIR: &str = "logs";
+const LOCKS_DIR: &str = "locks";
+
+impl Category {
+    fn name(&self) -> &'static str {
+        match self {
+            Category::Packages => "packages",
+            Category::Binaries => "binaries",
+            Category::Metadata => "metadata",
+            Category::Installers => "installers",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Category> {
+        CATEGORIES.into_iter().find(|c| c.name() == name)
+    }
+}
+
+/// The two directories the categories live in. Passed around rather than
+/// looked up, so that the tests can point them at a temporary directory.
+struct CacheRoots {
+    cache: PathBuf,
+    download: PathBuf,
+    /// Whether the user set the download directory, so it is not only ours.
+    download_overridden: bool,
+}
+
+impl CacheRoots {
+    fn current() -> Result<CacheRoots, Box<dyn Error>> {
+        Ok(CacheRoots {
+            cache: get_cache_dir()?,
+            download: get_download_dir()?,
+            download_overridden: download_dir_is_overridden()?,
+        })
+    }
+
+    /// The directory to report for a category.
+    fn path(&self, cat: Category) -> PathBuf {
+        match cat {
+            Category::Packages => self.cache.join(PACKAGES_DIR),
+            Category::Binaries => self.cache.join(BINARIES_DIR),
+            Category::Metadata => self.cache.clone(),
+            Category::Installers => self.download.clone(),
+        }
+    }
+
+    /// The top level files and directories that make up a category. Missing
+    /// directories are not an error, they are just empty.
+    fn entries(&self, cat: Category) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+        match cat {
+            Category::Packages | Category::Binaries => {
+                let dir = self.path(cat);
+                Ok(if dir.exists() { vec![dir] } else { vec![] })
+            }
+            Category::Metadata => Ok(list_dir(&self.cache)?
+                .into_iter()
+                .filter(|p| {
+                    let name = p.file_name().and_then(|x| x.to_str()).unwrap_or("");
+                    name != PACKAGES_DIR
+                        && name != BINARIES_DIR
+                        && name != LOGS_DIR
+                        && name != LOCKS_DIR
+                })
+                .collect()),
+            Category::Installers if self.download_overridden => Ok(list_dir(&self.download)?
+                .into_iter()
+                .filter(|p| {
+                    let name = p.file_name().and_then(|x| x.to_str()).unwrap_or("");
+                    is_rig_download(name)
+                })
+                .collect()),
+            Category::Installers => list_dir(&self.download),
+        }
+    }
+}
+
+/// Whether a file in the download directory is one that rig downloaded or
+/// created: an R, Rtools or gfortran installer, the fallback fonts, a partial
+/// download and its validator, or an R source build directory. R installers are named after
+/// their URL, possibly with an arch or hash prefix: `R-4.5.1-arm64.pkg`,
+/// `x64-R-4.5.1-win.exe`, `r-4.5.1_1_amd64.deb`.
+fn is_rig_download(name: &str) -> bool {
+    const INSTALLER_EXTS: [&str; 9] = [
+        ".pkg", ".exe", ".deb", ".rpm", ".tar.gz", ".tgz", ".tar.xz", ".tar.bz2", ".zip",
+    ];
+    let lower = name.to_lowercase();
+    if lower.ends_with(".tmp")
+        || lower.ends_with(".tmp.etag")
+        || lower.starts_with("rig-build-")
+        || lower.starts_with("rig-fonts")
+        || lower == "r.entitlements"
+    {
+        return true;
+    }
+    let installer = INSTALLER_EXTS.iter().any(|ext| lower.ends_with(ext));
+    let r = lower.starts_with("r-") || lower.contains("-r-");
+    installer && (r || lower.starts_with("rtools") || lower.starts_with("gfortran"))
+}
+
+fn list_dir(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut out = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        out.push(entry?.path());
+    }
+    out.sort();
+    Ok(out)
+}
+
+/// A regular file in the cache.
+#[derive(Debug, Clone)]
+struct CacheFile {
+    category: Category,
+    path: PathBuf,
+    bytes: u64,
+    modified: SystemTime,
+}
+
+/// Every regular file under `path`, which may be a file itself. Symbolic links
+/// are listed, not followed, and count as zero bytes: rig does not create
+/// them, and whatever they point to is not ours to measure or delete.
+fn walk_files(
+    category: Category,
+    path: &Path,
+    out: &mut Vec<CacheFile>,
+) -> Result<(), Box<dyn Error>> {
+    let meta = std::fs::symlink_metadata(path)?;
+    if meta.is_dir() {
+        for entry in list_dir(path)? {
+            walk_files(category, &entry, out)?;
+        }
+    } else {
+        out.push(CacheFile {
+            category,
+            path: path.to_path_buf(),
+            bytes: if meta.is_file() { meta.len() } else { 0 },
+            modified: meta.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+        });
+    }
+    Ok(())
+}
+
+fn category_files(roots: &CacheRoots, cat: Category) -> Result<Vec<CacheFile>, Box<dyn Error>> {
+    let mut files = vec![];
+    for entry in roots.entries(cat)? {
+        walk_files(cat, &entry, &mut files)?;
+    }
+    Ok(files)
+}
+
+/// The categories named on the command line, or all of them.
+fn selected_categories(args: &ArgMatches) -> Result<Vec<Category>, Box<dyn Error>> {
+    match args.get_many::<String>("category") {
+        None => Ok(CATEGORIES.to_vec()),
+        Some(names) => {
+            let mut cats = vec![];
+            for name in names {
+                match Category::from_name(name) {
+                    Some(cat) if !cats.contains(&cat) => cats.push(cat),
+                    Some(_) => {}
+                    // clap already checks the values, this is a safety net.
+                    None => bail!("Unknown cache category: {}", name),
+                }
+            }
+            Ok(cats)
+        }
+    }
+}
+
+// ------------------------------------------------------------------------
+
+pub fn sc_cache(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    match args.subcommand() {
+        Some(("info", s)) => sc_cache_info(s, args, mainargs),
+        Some(("prune", s)) => sc_cache_prune(s, args, mainargs),
+        Some(("clean", s)) => sc_cache_clean(s, args, mainargs),
+        Some((name, _)) => bail!("Internal error: unknown `rig cache` subcommand: {}", name),
+        None => Ok(()),
+    }
+}
+
+fn json_flag(args: &ArgMatches, cacheargs: &ArgMatches, mainargs: &ArgMatches) -> bool {
+    args.get_flag("json") || cacheargs.get_flag("json") || mainargs.get_flag("json")
+}
+
+// -- rig cache info ------------------------------------------------------
+
+#[derive(serde::Serialize)]
+struct CategoryInfo {
+    category: &'static str,
+    path: String,
+    files: usize,
+    bytes: u64,
+}
+
+fn cache_info(roots: &CacheRoots, cats: &[Category]) -> Result<Vec<CategoryInfo>, Box<dyn Error>> {
+    let mut out = vec![];
+    for cat in cats {
+        let files = category_files(roots, *cat)?;
+        out.push(CategoryInfo {
+            category: cat.name(),
+            path: roots.path(*cat).display().to_string(),
+            files: files.len(),
+            bytes: files.iter().map(|f| f.bytes).sum(),
+        });
+    }
+    Ok(out)
+}
+
+fn sc_cache_info(
+    args: &ArgMatches,
+    cacheargs: &ArgMatches,
+    mainargs: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    let roots = CacheRoots::current()?;
+    let info = cache_info(&roots, &selected_categories(args)?)?;
+
+    if json_flag(args, cacheargs, mainargs) {
+        println!("{}", serde_json::to_string_pretty(&info)?);
+        return Ok(());
+    }
+
+    let mut tab = Table::new("{:<}  {:>}  {:>}  {:<}");
+    tab.add_row(row!["category", "files", "size", "path"]);
+    tab.add_heading("------------------------------------------------------------");
+    for cat in &info {
+        tab.add_row(row!(
+            cat.category,
+            cat.files,
+            format_bytes(cat.bytes),
+            &cat.path
+        ));
+    }
+    let total: u64 = info.iter().map(|c| c.bytes).sum();
+    tab.add_heading("------------------------------------------------------------");
+    tab.add_row(row!(
+        "total",
+        info.iter().map(|c| c.files).sum::<usize>(),
+        format_bytes(total),
+        ""
+    ));
+    print!("{}", tab);
+
+    Ok(())
+}
+
+// -- rig cache prune -----------------------------------------------------
+
+/// Parse an age like `90d`: a whole number and a unit, one of `s`, `m`
+/// (minutes), `h`, `d` and `w`.
+fn parse_age(age: &str) -> Result<Duration, Box<dyn Error>> {
+    let age = age.trim();
+    let split = age.find(|c: char| !c.is_ascii_digit()).unwrap_or(age.len());
+    let (num, unit) = age.split_at(split);
+    let num: u64 = match num.parse() {
+        Ok(x) => x,
+        Err(_) => bail!("Invalid age: '{}', use e.g. '90d' or '12h'", age),
+    };
+    let secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => bail!(
+            "Invalid age unit in '{}', use one of s, m, h, d or w, e.g. '90d'",
+            age
+        ),
+    };
+    Ok(Duration::from_secs(num * secs))
+}
+
+/// What the cached package files can still be used for: the artifacts that a
+/// known lockfile points at, and the ones a package in a known library was
+/// installed from.
+#[derive(Debug, Default)]
+struct References {
+    /// `target`s of lockfile entries, relative to `<cache>/packages`.
+    targets: HashSet<String>,
+    /// File names without their archive suffix, e.g. `cli_3.6.3-1a2b3c4d`.
+    stems: HashSet<String>,
+}
+
+impl References {
+    fn add_lockfile(&mut self, lockfile: &PakLockfile) {
+        for pkg in &lockfile.packages {
+            self.targets.insert(pkg.target.clone());
+        }
+    }
+
+    /// An installed package references the artifact it was installed from.
+    ///
+    /// The cache file name of an artifact has its [`artifact_cache_key`] in it,
+    /// computed from the same `RemoteHash` and `RemoteLinkingToHashes` that the
+    /// installed package records, so the name can be reconstructed. It is not
+    /// known whether the package was installed from a binary, which is keyed on
+    /// both, or from a source tarball, which is keyed on the hash alone, so
+    /// both names count. A package without a `RemoteHash` was installed from an
+    /// artifact without a key.
+    fn add_installed(&mut self, pkg: &InstalledPackage) {
+        let base = format!("{}_{}", pkg.package, pkg.version);
+        match &pkg.hash {
+            None => {
+                self.stems.insert(base);
+            }
+            Some(hash) => {
+                let linkingto = crate::install::format_linkingto(&pkg.linkingto);
+                let keys = [
+                    artifact_cache_key(Some(hash), Some(&linkingto)),
+                    artifact_cache_key(Some(hash), None),
+                ];
+                for key in keys.into_iter().flatten() {
+                    self.stems.insert(format!("{}-{}", base, key));
+                }
+            }
+        }
+    }
+
+    fn uses(&self, packages_dir: &Path, file: &Path) -> bool {
+        if let Ok(rel) = file.strip_prefix(packages_dir) {
+            let rel: Vec<String> = rel
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            if self.targets.contains(&rel.join("/")) {
+                return true;
+            }
+        }
+        let name = match file.file_name().and_then(|x| x.to_str()) {
+            Some(x) => x,
+            None => return false,
+        };
+        let stem = match ARCHIVE_SUFFIXES.iter().find(|s| name.ends_with(**s)) {
+            Some(suffix) => &name[..name.len() - suffix.len()],
+            None => name,
+        };
+        self.stems.contains(stem)
+    }
+}
+
+/// Collect the references from the lockfiles and libraries rig knows about:
+/// the `--lockfile`s, or `pkg.lock` in the current directory, the libraries of
+/// every installed R version, and the `--library` directories.
+fn known_references(args: &ArgMatches) -> Result<References, Box<dyn Error>> {
+    let mut refs = References::default();
+
+    let lockfiles: Vec<PathBuf> = match args.get_many::<String>("lockfile") {
+        Some(x) => x.map(PathBuf::from).collect(),
+        None => {
+            let default = PathBuf::from("pkg.lock");
+            if default.exists() {
+                vec![default]
+            } else {
+                vec![]
+            }
+        }
+    };
+    for path in lockfiles {
+        debug!("Reading lockfile {}", path.display());
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(x) => x,
+            Err(err) => bail!("Cannot read lockfile {}: {}", path.display(), err),
+        };
+        let lockfile: PakLockfile = match serde_json::from_str(&contents) {
+            Ok(x) => x,
+            Err(err) => bail!("Cannot parse lockfile {}: {}", path.display(), err),
+        };
+        refs.add_lockfile(&lockfile);
+    }
+
+    let mut libraries: Vec<PathBuf> = vec![];
+    for rver in sc_get_list()? {
+        // A broken R installation should not stop the pruning, but then its
+        // packages do not protect anything, so say so.
+        match crate::library::sc_library_get_list(Some(rver.clone()), true) {
+            Ok(libs) => libraries.extend(libs.into_iter().map(|l| l.path)),
+            Err(err) => warn!("Cannot list the libraries of R {}: {}", rver, err),
+        }
+    }
+    if let Some(libs) = args.get_many::<String>("library") {
+        libraries.extend(libs.map(PathBuf::from));
+    }
+    for lib in libraries {
+        match read_installed(&lib) {
+            Ok(pkgs) => pkgs.iter().for_each(|p| refs.add_installed(p)),
+            Err(err) => debug!("Skipping library {}: {}", lib.display(), err),
+        }
+    }
+
+    Ok(refs)
+}
+
+/// The files `rig cache prune` removes: the ones older than `older_than`, if
+/// given, and, with `unused`, the package files nothing references. With both,
+/// a file has to be both.
+fn prune_candidates(
+    roots: &CacheRoots,
+    cats: &[Category],
+    older_than: Option<Duration>,
+    unused: Option<&References>,
+    now: SystemTime,
+) -> Result<Vec<CacheFile>, Box<dyn Error>> {
+    let packages_dir = roots.path(Category::Packages);
+    let mut out = vec![];
+    for cat in cats {
+        // Only package files are referenced by anything.
+        if unused.is_some() && *cat != Category::Packages {
+            continue;
+        }
+        for file in category_files(roots, *cat)? {
+            if let Some(age) = older_than {
+                let file_age = now.duration_since(file.modified).unwrap_or_default();
+                if file_age < age {
+                    continue;
+                }
+            }
+            if let Some(refs) = unused {
+                if refs.uses(&packages_dir, &file.path) {
+                    continue;
+                }
+            }
+            out.push(file);
+        }
+    }
+    Ok(out)
+}
+
+#[derive(serde::Serialize)]
+struct RemovedFile {
+    category: &'static str,
+    path: String,
+    bytes: u64,
+}
+
+#[derive(serde::Serialize)]
+struct RemovalReport {
+    dry_run: bool,
+    files: usize,
+    bytes: u64,
+    removed: Vec<RemovedFile>,
+}
+
+impl RemovalReport {
+    fn new(files: &[CacheFile], dry_run: bool) -> RemovalReport {
+        RemovalReport {
+            dry_run,
+            files: files.len(),
+            bytes: files.iter().map(|f| f.bytes).sum(),
+            removed: files
+                .iter()
+                .map(|f| RemovedFile {
+                    category: f.category.name(),
+                    path: f.path.display().to_string(),
+                    bytes: f.bytes,
+                })
+                .collect(),
+        }
+    }
+
+    fn print(&self, json: bool) -> Result<(), Box<dyn Error>> {
+        if json {
+            println!("{}", serde_json::to_string_pretty(self)?);
+            return Ok(());
+        }
+        if self.dry_run {
+            for file in &self.removed {
+                println!("{}  {}", format_bytes(file.bytes), file.path);
+            }
+        }
+        let word = if self.files == 1 { "file" } else { "files" };
+        let msg = if self.dry_run {
+            format!(
+                "Would remove {} {}, {}",
+                self.files,
+                word,
+                format_bytes(self.bytes)
+            )
+        } else {
+            format!(
+                "Removed {} {}, {}",
+                self.files,
+                word,
+                format_bytes(self.bytes)
+            )
+        };
+        OUTPUT.success(&msg);
+        info!("{}", msg);
+        Ok(())
+    }
+}
+
+/// Remove the files, then the directories the removal left empty, up to (but
+/// not including) the roots of the categories.
+fn remove_files(roots: &CacheRoots, files: &[CacheFile]) -> Result<(), Box<dyn Error>> {
+    let stop = [roots.cache.clone(), roots.download.clone()];
+    for file in files {
+        debug!("Removing {}", file.path.display());
+        if let Err(err) = std::fs::remove_file(&file.path) {
+            bail!("Cannot remove {}: {}", file.path.display(), err);
+        }
+        let mut dir = file.path.parent();
+        while let Some(d) = dir {
+            if stop.iter().any(|s| s == d) || std::fs::remove_dir(d).is_err() {
+                break;
+            }
+            dir = d.parent();
+        }
+    }
+    Ok(())
+}
+
+fn sc_cache_prune(
+    args: &ArgMatches,
+    cacheargs: &ArgMatches,
+    mainargs: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    let json = json_flag(args, cacheargs, mainargs);
+    let dry_run = args.get_flag("dry-run");
+    let older_than = match args.get_one::<String>("older-than") {
+        Some(x) => Some(parse_age(x)?),
+        None => None,
+    };
+    let refs = if args.get_flag("unused") {
+        Some(known_references(args)?)
+    } else {
+        None
+    };
+
+    let roots = CacheRoots::current()?;
+    let files = prune_candidates(
+        &roots,
+        &selected_categories(args)?,
+        older_than,
+        refs.as_ref(),
+        SystemTime::now(),
+    )?;
+    if !dry_run {
+        remove_files(&roots, &files)?;
+    }
+
+    RemovalReport::new(&files, dry_run).print(json)
+}
+
+// -- rig cache clean -----------------------------------------------------
+
+fn sc_cache_clean(
+    args: &ArgMatches,
+    cacheargs: &ArgMatches,
+    mainargs: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    let json = json_flag(args, cacheargs, mainargs);
+    let dry_run = args.get_flag("dry-run");
+
+    let roots = CacheRoots::current()?;
+    let mut files = vec![];
+    for cat in selected_categories(args)? {
+        files.extend(category_files(&roots, cat)?);
+    }
+    if !dry_run {
+        remove_files(&roots, &files)?;
+    }
+
+    RemovalReport::new(&files, dry_run).print(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn roots(tmp: &Path) -> CacheRoots {
+        CacheRoots {
+            cache: tmp.join("cache"),
+            download: tmp.join("download"),
+            download_overridden: false,
+        }
+    }
+
+    fn touch(path: &Path, bytes: usize) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, vec![b'x'; bytes]).unwrap();
+    }
+
+    #[test]
+    fn parse_age_units() {
+        assert_eq!(parse_age("90d").unwrap(), Duration::from_secs(90 * 86400));
+        assert_eq!(parse_age("12h").unwrap(), Duration::from_secs(12 * 3600));
+        assert_eq!(parse_age("2w").unwrap(), Duration::from_secs(14 * 86400));
+        assert_eq!(parse_age("30m").unwrap(), Duration::from_secs(1800));
+        assert_eq!(parse_age("5s").unwrap(), Duration::from_secs(5));
+        assert!(parse_age("90").is_err());
+        assert!(parse_age("d").is_err());
+        assert!(parse_age("3y").is_err());
+    }
+
+    #[test]
+    fn categories_split_the_cache_directory() {
+        let tmp = tempfile::tempdir().unwrap();
+        let roots = roots(tmp.path());
+        touch(&roots.cache.join("packages/src/cli_3.6.3.tar.gz"), 10);
+        touch(&roots.cache.join("binaries/cli.v1.rbi"), 20);
+        touch(&roots.cache.join("packages.db"), 30);
+        touch(&roots.cache.join("package-metadata/manifest-cli.json"), 40);
+        touch(&roots.cache.join("logs/rig.log"), 1000);
+        touch(
+            &roots.cache.join("locks/packages.db-0123456789abcdef.lock"),
+            5,
+        );
+        touch(&roots.download.join("R-4.5.1.pkg"), 50);
+
+        let info = cache_info(&roots, &CATEGORIES).unwrap();
+        let sizes: Vec<(&str, usize, u64)> = info
+            .iter()
+            .map(|c| (c.category, c.files, c.bytes))
+            .collect();
+        assert_eq!(
+            sizes,
+            vec![
+                ("packages", 1, 10),
+                ("binaries", 1, 20),
+                ("metadata", 2, 70),
+                ("installers", 1, 50),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_user_set_download_dir_only_loses_rig_downloads() {
+        let tmp = tempfile::tempdir().unwrap();
+        let roots = CacheRoots {
+            download_overridden: true,
+            ..roots(tmp.path())
+        };
+        let ours = [
+            "R-4.5.1-arm64.pkg",
+            "x64-R-4.5.1-win.exe",
+            "r-4.5.1_1_amd64.deb",
+            "rtools-45-x86_64.exe",
+            "rig-fonts-1.tar.gz",
+            "R-4.4.1.tar.gz.tmp",
+            "R-4.4.1.tar.gz.tmp.etag",
+        ];
+        for f in ours {
+            touch(&roots.download.join(f), 1);
+        }
+        touch(&roots.download.join("rig-build-123/R-4.4.1/configure"), 1);
+        let theirs = roots.download.join("taxes-2025.pdf");
+        let their_tarball = roots.download.join("project.tar.gz");
+        touch(&theirs, 1);
+        touch(&their_tarball, 1);
+
+        let files = prune_candidates(
+            &roots,
+            &[Category::Installers],
+            None,
+            None,
+            SystemTime::now(),
+        )
+        .unwrap();
+        assert_eq!(files.len(), ours.len() + 1);
+        remove_files(&roots, &files).unwrap();
+        assert!(theirs.exists());
+        assert!(their_tarball.exists());
+        assert!(!roots.download.join("R-4.5.1-arm64.pkg").exists());
+        assert!(!roots.download.join("rig-build-123").exists());
+    }
+
+    #[test]
+    fn missing_directories_are_empty() {
+        let tmp = tempfile::tempdir().unwrap();
+        let info = cache_info(&roots(tmp.path()), &CATEGORIES).unwrap();
+        assert!(info.iter().all(|c| c.files == 0 && c.bytes == 0));
+    }
+
+    #[test]
+    fn prune_by_age() {
+        let tmp = tempfile::tempdir().unwrap();
+        let roots = roots(tmp.path());
+        let old = roots.cache.join("binaries/old.v1.rbi");
+        let new = roots.cache.join("binaries/new.v1.rbi");
+        touch(&old, 1);
+        touch(&new, 1);
+        let now = SystemTime::now();
+        let long_ago = now - Duration::from_secs(100 * 86400);
+        filetime::set_file_mtime(&old, filetime::FileTime::from_system_time(long_ago)).unwrap();
+
+        let files = prune_candidates(
+            &roots,
+            &CATEGORIES,
+            Some(Duration::from_secs(90 * 86400)),
+            None,
+            now,
+        )
+        .unwrap();
+        let paths: Vec<&PathBuf> = files.iter().map(|f| &f.path).collect();
+        assert_eq!(paths, vec![&old]);
+
+        remove_files(&roots, &files).unwrap();
+        assert!(!old.exists());
+        assert!(new.exists());
+        assert!(roots.cache.join("binaries").exists());
+    }
+
+    #[test]
+    fn prune_unused_keeps_referenced_packages() {
+        let tmp = tempfile::tempdir().unwrap();
+        let roots = roots(tmp.path());
+        let pkgs = roots.cache.join("packages");
+
+        let sha = "7b1fc90750fbb46483423da6721832c545d37b157f4f3355784a65e50fada8c2";
+        let key = artifact_cache_key(Some(sha), None).unwrap();
+        let installed = pkgs.join(format!("src/dplyr_0.7.4-{}.tar.gz", key));
+        let locked = pkgs.join("bin/macosx/big-sur-arm64/4.5/pak_0.9.5.tgz");
+        let plain = pkgs.join("src/glue_1.8.0.tar.gz");
+        let unused = pkgs.join("src/cli_3.6.3.tar.gz");
+        for f in [&installed, &locked, &plain, &unused] {
+            touch(f, 5);
+        }
+        touch(&roots.cache.join("binaries/cli.v1.rbi"), 5);
+
+        let mut refs = References::default();
+        refs.targets
+            .insert("bin/macosx/big-sur-arm64/4.5/pak_0.9.5.tgz".to_string());
+        refs.add_installed(&InstalledPackage::for_test(
+            "dplyr",
+            "0.7.4",
+            Some(sha),
+            vec![],
+        ));
+        refs.add_installed(&InstalledPackage::for_test("glue", "1.8.0", None, vec![]));
+
+        let files =
+            prune_candidates(&roots, &CATEGORIES, None, Some(&refs), SystemTime::now()).unwrap();
+        let paths: Vec<&PathBuf> = files.iter().map(|f| &f.path).collect();
+        assert_eq!(paths, vec![&unused]);
+
+        remove_files(&roots, &files).unwrap();
+        assert!(!unused.exists());
+        assert!(installed.exists());
+    }
+
+    #[test]
+    fn remove_files_cleans_up_empty_directories() {
+        let tmp = tempfile::tempdir().unwrap();
+        let roots = roots(tmp.path());
+        let file = roots.cache.join("packages/bin/windows/4.5/cli_3.6.3.zip");
+        touch(&file, 1);
+        let files = category_files(&roots, Category::Packages).unwrap();
+        remove_files(&roots, &files).unwrap();
+        assert!(!roots.cache.join("packages").exists());
+        assert!(roots.cache.exists());
+    }
+}
@@ -0,0 +1,555 @@
+//! `R` and `Rscript` shims: per-directory R version selection.
+//!
+//! In user mode the `R` and `Rscript` links in the binary directory point at
+//! rig itself, and rig, started under one of those names, works as a shim:
+//! it picks an R version for the working directory and runs that version's
+//! `R` or `Rscript`, with the same arguments. It goes up from the working
+//! directory, and stops at the first directory that has one of these, in this
+//! order:
+//!
+//! * `.R-version`, with the name of an installed R version, an alias, or a
+//!   version number, e.g. `4.4`,
+//! * `renv.lock`, the R version renv recorded, or the newest patch version
+//!   of its minor version,
+//! * `pkg.lock`, the R version `rig proj solve` solved for, the same way,
+//! * `DESCRIPTION`, with a `Depends: R (>= x)` constraint.
+//!
+//! The `RIG_R_VERSION` environment variable overrides all of these, and
+//! without any of them the default R version is used.
+
+use std::error::Error;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::ArgMatches;
+use log::{debug, warn};
+use regex::Regex;
+use simple_error::*;
+
+#[cfg(target_os = "macos")]
+use crate::macos::*;
+
+#[cfg(target_os = "windows")]
+use crate::windows::*;
+
+#[cfg(target_os = "linux")]
+use crate::linux::*;
+
+use crate::common::sc_get_list_details;
+use crate::output::OUTPUT;
+use crate::renv;
+use crate::rversion::InstalledVersion;
+
+/// The programs rig works as a shim for.
+pub const SHIM_PROGRAMS: [&str; 2] = ["R", "Rscript"];
+
+/// The environment variable that selects the R version of the shims.
+pub const VERSION_ENV: &str = "RIG_R_VERSION";
+
+/// The file `rig pin` writes and the shims read first.
+pub const VERSION_FILE: &str = ".R-version";
+
+/// What a directory asks for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Request {
+    /// The name of an installed version or alias, or a version number, that
+    /// may be partial: `4.4` is any `4.4.x`.
+    Version(String),
+    /// The version a lockfile recorded. Any patch version of the same minor
+    /// version will do, like for `rig rstudio`, see `renv::match_r_version()`.
+    Locked(String),
+    /// At least this version, from a `DESCRIPTION`.
+    AtLeast(semver::Version),
+}
+
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Request::Version(v) | Request::Locked(v) => write!(f, "{}", v),
+            Request::AtLeast(v) => write!(f, ">= {}", v),
+        }
+    }
+}
+
+/// Where the selected version came from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    Env,
+    File(PathBuf),
+    Default,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Env => write!(f, "{}", VERSION_ENV),
+            Source::File(path) => write!(f, "{}", path.display()),
+            Source::Default => write!(f, "default"),
+        }
+    }
+}
+
+/// The R version selected for a directory.
+#[derive(Debug, Clone)]
+pub struct Selection {
+    pub name: String,
+    pub binary: PathBuf,
+    pub source: Source,
+}
+
+/// The shim rig runs as, if it was started as `R` or `Rscript`.
+pub fn shim_program() -> Option<&'static str> {
+    let argv0 = std::env::args_os().next()?;
+    let name = Path::new(&argv0).file_name()?.to_str()?;
+    SHIM_PROGRAMS.iter().find(|p| **p == name).copied()
+}
+
+/// Run the selected R version's `prog` with rig's arguments, and return the
+/// exit status, if rig is still running then.
+pub fn run_shim(prog: &str) -> i32 {
+    let selection = match std::env::current_dir()
+        .map_err(|e| e.into())
+        .and_then(|dir| select_r_version(&dir))
+    {
+        Ok(s) => s,
+        Err(err) => {
+            eprintln!("rig: cannot select an R version for {}: {}", prog, err);
+            return 1;
+        }
+    };
+    let target = selection.binary.with_file_name(prog);
+    debug!(
+        "{} shim: R {} from {}, running {}",
+        prog,
+        selection.name,
+        selection.source,
+        target.display()
+    );
+    let args: Vec<OsString> = std::env::args_os().skip(1).collect();
+    exec(&target, &args)
+}
+
+#[cfg(unix)]
+fn exec(target: &Path, args: &[OsString]) -> i32 {
+    use std::os::unix::process::CommandExt;
+    // Only returns on failure.
+    let err = std::process::Command::new(target).args(args).exec();
+    eprintln!("rig: cannot run {}: {}", target.display(), err);
+    126
+}
+
+#[cfg(not(unix))]
+fn exec(target: &Path, args: &[OsString]) -> i32 {
+    match std::process::Command::new(target).args(args).status() {
+        Ok(status) => status.code().unwrap_or(1),
+        Err(err) => {
+            eprintln!("rig: cannot run {}: {}", target.display(), err);
+            126
+        }
+    }
+}
+
+/// The R version the shims use in `dir`.
+pub fn select_r_version(dir: &Path) -> Result<Selection, Box<dyn Error>> {
+    let (request, source) = match find_version_request(dir)? {
+        Some((request, source)) => (Some(request), source),
+        None => (None, Source::Default),
+    };
+
+    let default = sc_get_default()?;
+    let installed = sc_get_list_details()?;
+    let picked = match &request {
+        Some(request) => match pick_version(request, &installed, default.as_deref()) {
+            Some(v) => v,
+            None => bail!(
+                "R {} is not installed (asked for by {}), install it with `rig add`",
+                request,
+                source
+            ),
+        },
+        None => match default
+            .as_ref()
+            .and_then(|d| installed.iter().find(|v| &v.name == d))
+        {
+            Some(v) => v,
+            None => bail!("No default R version, set one with `rig default`"),
+        },
+    };
+
+    Ok(Selection {
+        name: picked.name.clone(),
+        binary: match &picked.binary {
+            Some(b) => PathBuf::from(b),
+            None => get_r_binary(&picked.name)?,
+        },
+        source,
+    })
+}
+
+/// What `RIG_R_VERSION`, or else the files in and above `dir`, ask for.
+pub fn find_version_request(dir: &Path) -> Result<Option<(Request, Source)>, Box<dyn Error>> {
+    match std::env::var(VERSION_ENV) {
+        Ok(v) if !v.trim().is_empty() => {
+            Ok(Some((Request::Version(v.trim().to_string()), Source::Env)))
+        }
+        _ => Ok(find_request(dir)?.map(|(request, path)| (request, Source::File(path)))),
+    }
+}
+
+/// The version `rig add` installs: the one on its command line, otherwise
+/// the one the working directory asks for, and the latest release without
+/// either. A minimum version asks for the latest release, too.
+pub fn add_version(args: &ArgMatches) -> String {
+    if let Some(v) = args.get_one::<String>("str") {
+        return v.to_string();
+    }
+    let request = std::env::current_dir()
+        .ok()
+        .and_then(|dir| find_version_request(&dir).ok().flatten());
+    match request {
+        Some((Request::Version(v), _)) | Some((Request::Locked(v), _)) => v,
+        _ => "release".to_string(),
+    }
+}
+
+/// The first request on the way up from `dir`, and the file it is in.
+///
+/// A file that cannot be read or parsed is skipped with a warning: one broken
+/// lockfile somewhere up the tree must not stop `R` from starting.
+pub fn find_request(dir: &Path) -> Result<Option<(Request, PathBuf)>, Box<dyn Error>> {
+    for dir in dir.ancestors() {
+        let file = dir.join(VERSION_FILE);
+        if file.is_file() {
+            if let Some(v) = readable(&file, read_version_file(&file)).flatten() {
+                return Ok(Some((Request::Version(v), file)));
+            }
+        }
+
+        let file = dir.join("renv.lock");
+        if file.is_file() {
+            if let Some(v) = readable(&file, renv::parse_r_version(file.clone())) {
+                return Ok(Some((Request::Locked(v), file)));
+            }
+        }
+
+        let file = dir.join("pkg.lock");
+        if file.is_file() {
+            let lockfile = fs::read_to_string(&file)
+                .map_err(|e| e.into())
+                .and_then(|x| Ok(serde_json::from_str::<serde_json::Value>(&x)?));
+            if let Some(lockfile) = readable(&file, lockfile) {
+                if let Some(v) = lockfile["r_version"].as_str().filter(|v| !v.is_empty()) {
+                    return Ok(Some((Request::Locked(v.to_string()), file)));
+                }
+            }
+        }
+
+        // A package without an R constraint does not ask for anything, so
+        // keep looking further up.
+        let file = dir.join("DESCRIPTION");
+        if file.is_file() {
+            let desc = readable(&file, fs::read_to_string(&file).map_err(|e| e.into()));
+            if let Some(v) = desc.and_then(|d| description_min_r(&d)) {
+                return Ok(Some((Request::AtLeast(v), file)));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// The contents of a version file, or `None`, with a warning, if it is broken.
+fn readable<T>(file: &Path, x: Result<T, Box<dyn Error>>) -> Option<T> {
+    match x {
+        Ok(x) => Some(x),
+        Err(err) => {
+            OUTPUT.warn(&format!("Ignoring {}: {}", file.display(), err));
+            warn!("Ignoring {}: {}", file.display(), err);
+            None
+        }
+    }
+}
+
+/// The version in a `.R-version` file: its first line that is not empty or a
+/// `#` comment.
+pub fn read_version_file(path: &Path) -> Result<Option<String>, Box<dyn Error>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(|l| l.trim())
+        .find(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| l.to_string()))
+}
+
+/// Write `version` into the `.R-version` file of `dir`, and return its path.
+pub fn write_version_file(dir: &Path, version: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let path = dir.join(VERSION_FILE);
+    fs::write(&path, format!("{}\n", version))?;
+    Ok(path)
+}
+
+/// The minimum R version of a `DESCRIPTION`, from `Depends: R (>= x)`.
+fn description_min_r(desc: &str) -> Option<semver::Version> {
+    // The field can span several lines, up to the next field.
+    let re = Regex::new(r"(?ms)^Depends:(.*?)(^\S|\z)").unwrap();
+    let depends = re.captures(desc)?.get(1)?.as_str();
+    let re = Regex::new(r"(?:^|,)\s*R\s*\(\s*>=?\s*([0-9]+(?:[.-][0-9]+)*)\s*\)").unwrap();
+    let ver = re.captures(depends)?.get(1)?.as_str();
+    parse_partial(ver).map(|(major, minor, patch)| {
+        semver::Version::new(major, minor.unwrap_or(0), patch.unwrap_or(0))
+    })
+}
+
+/// `4`, `4.4` or `4.4.1` (or `4.4-1`), as its components.
+pub fn parse_partial(ver: &str) -> Option<(u64, Option<u64>, Option<u64>)> {
+    let mut parts = ver.split(['.', '-']);
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next().map(|p| p.parse()).transpose().ok()?;
+    let patch = parts.next().map(|p| p.parse()).transpose().ok()?;
+    if parts.next().is_some() {
+        return None;
+    }
+    Some((major, minor, patch))
+}
+
+/// The installed version that satisfies `request`.
+///
+/// A name or an alias wins outright. A version number matches the installed
+/// versions it is a prefix of, and the newest of those is picked, except that
+/// an exact match of a full version wins. A lockfile version matches the same
+/// minor version, and picks the exact patch version if it is installed, and
+/// the newest patch version otherwise. For a minimum version the default
+/// is picked if it is new enough, and the newest version otherwise. On equal
+/// versions an `-arm64` build is preferred, as for `renv.lock` files.
+pub fn pick_version<'a>(
+    request: &Request,
+    installed: &'a [InstalledVersion],
+    default: Option<&str>,
+) -> Option<&'a InstalledVersion> {
+    let versioned = installed.iter().filter_map(|v| {
+        let sv = semver::Version::parse(v.version.as_ref()?).ok()?;
+        Some((sv, v))
+    });
+
+    let candidates: Vec<(semver::Version, &InstalledVersion)> = match request {
+        Request::Version(want) => {
+            if let Some(v) = installed
+                .iter()
+                .find(|v| &v.name == want || v.aliases.contains(want))
+            {
+                return Some(v);
+            }
+            let (major, minor, patch) = parse_partial(want)?;
+            let matching: Vec<_> = versioned
+                .filter(|(sv, _)| {
+                    sv.major == major
+                        && minor.is_none_or(|m| sv.minor == m)
+                        && patch.is_none_or(|p| sv.patch == p)
+                })
+                .collect();
+            matching
+        }
+        Request::Locked(want) => {
+            let (major, minor, patch) = parse_partial(want)?;
+            let same_minor: Vec<_> = versioned
+                .filter(|(sv, _)| sv.major == major && minor.is_none_or(|m| sv.minor == m))
+                .collect();
+            let exact: Vec<_> = same_minor
+                .iter()
+                .filter(|(sv, _)| patch.is_some_and(|p| sv.patch == p))
+                .cloned()
+                .collect();
+            if exact.is_empty() {
+                same_minor
+            } else {
+                exact
+            }
+        }
+        Request::AtLeast(min) => {
+            let matching: Vec<_> = versioned.filter(|(sv, _)| sv >= min).collect();
+            if let Some((_, v)) = matching
+                .iter()
+                .find(|(_, v)| Some(v.name.as_str()) == default)
+            {
+                return Some(v);
+            }
+            matching
+        }
+    };
+
+    candidates
+        .into_iter()
+        .max_by(|(a, va), (b, vb)| {
+            a.cmp(b).then_with(|| {
+                va.name
+                    .ends_with("-arm64")
+                    .cmp(&vb.name.ends_with("-arm64"))
+            })
+        })
+        .map(|(_, v)| v)
+}
+
+/// Point the `R` and `Rscript` links in `binary_dir` at rig, so that they
+/// work as shims. Returns `false`, and leaves the links alone, if the running
+/// program is not rig itself, e.g. the macOS menu bar app.
+#[cfg(unix)]
+pub fn make_shims(binary_dir: &Path) -> Result<bool, Box<dyn Error>> {
+    let rig = std::env::current_exe()?;
+    if rig.file_stem().and_then(|s| s.to_str()) != Some("rig") {
+        debug!("Not running as rig ({}), not making shims", rig.display());
+        return Ok(false);
+    }
+    for prog in SHIM_PROGRAMS {
+        let link = binary_dir.join(prog);
+        if fs::read_link(&link).is_ok_and(|t| t == rig) {
+            continue;
+        }
+        debug!("Adding shim {} -> {}", link.display(), rig.display());
+        fs::remove_file(&link).ok();
+        std::os::unix::fs::symlink(&rig, &link)?;
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inst(name: &str, version: &str, aliases: &[&str]) -> InstalledVersion {
+        InstalledVersion {
+            name: name.to_string(),
+            version: Some(version.to_string()),
+            path: None,
+            binary: None,
+            aliases: aliases.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+
+    fn installed() -> Vec<InstalledVersion> {
+        vec![
+            inst("4.2.3", "4.2.3", &[]),
+            inst("4.4.0", "4.4.0", &[]),
+            inst("4.4.2", "4.4.2", &["release"]),
+            inst("devel", "4.6.0", &[]),
+        ]
+    }
+
+    fn pick(request: Request, default: Option<&str>) -> Option<String> {
+        pick_version(&request, &installed(), default).map(|v| v.name.clone())
+    }
+
+    fn ver(v: &str) -> Request {
+        Request::Version(v.to_string())
+    }
+
+    #[test]
+    fn a_name_or_alias_is_picked_as_is() {
+        assert_eq!(pick(ver("devel"), None).as_deref(), Some("devel"));
+        assert_eq!(pick(ver("release"), None).as_deref(), Some("4.4.2"));
+    }
+
+    #[test]
+    fn a_version_number_picks_the_newest_match() {
+        assert_eq!(pick(ver("4.4"), None).as_deref(), Some("4.4.2"));
+        assert_eq!(pick(ver("4.4.0"), None).as_deref(), Some("4.4.0"));
+        assert_eq!(pick(ver("4"), None).as_deref(), Some("devel"));
+        assert_eq!(pick(ver("4.3"), None), None);
+        assert_eq!(pick(ver("4.4.1"), None), None);
+        assert_eq!(pick(ver("oldrel"), None), None);
+    }
+
+    #[test]
+    fn a_minimum_version_prefers_the_default() {
+        let min = |v| Request::AtLeast(semver::Version::parse(v).unwrap());
+        assert_eq!(pick(min("4.1.0"), Some("4.2.3")).as_deref(), Some("4.2.3"));
+        assert_eq!(pick(min("4.3.0"), Some("4.2.3")).as_deref(), Some("devel"));
+        assert_eq!(pick(min("5.0.0"), Some("4.2.3")), None);
+    }
+
+    #[test]
+    fn a_lockfile_version_falls_back_to_the_same_minor_version() {
+        let locked = |v: &str| Request::Locked(v.to_string());
+        assert_eq!(pick(locked("4.4.0"), None).as_deref(), Some("4.4.0"));
+        assert_eq!(pick(locked("4.4.1"), None).as_deref(), Some("4.4.2"));
+        assert_eq!(pick(locked("4.4"), None).as_deref(), Some("4.4.2"));
+        assert_eq!(pick(locked("4.3.1"), None), None);
+        // Not a name or alias, and not a version either.
+        assert_eq!(pick(locked("release"), None), None);
+    }
+
+    #[test]
+    fn an_arm64_build_wins_a_tie() {
+        let installed = vec![
+            inst("4.4-x86_64", "4.4.1", &[]),
+            inst("4.4-arm64", "4.4.1", &[]),
+        ];
+        let v = pick_version(&ver("4.4"), &installed, None).unwrap();
+        assert_eq!(v.name, "4.4-arm64");
+    }
+
+    #[test]
+    fn the_minimum_r_version_comes_from_depends() {
+        let desc = "Package: foo\nDepends:\n    methods,\n    R (>= 4.1)\nImports: cli\n";
+        assert_eq!(description_min_r(desc), Some(semver::Version::new(4, 1, 0)));
+        let desc = "Package: foo\nDepends: R(>=3.5.0), utils\n";
+        assert_eq!(description_min_r(desc), Some(semver::Version::new(3, 5, 0)));
+        // An `Rcpp` dependency is not an R dependency.
+        let desc = "Package: foo\nDepends: Rcpp (>= 1.0)\nImports: R6\n";
+        assert_eq!(description_min_r(desc), None);
+        assert_eq!(description_min_r("Package: foo\n"), None);
+    }
+
+    #[test]
+    fn a_pin_is_read_back() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = write_version_file(tmp.path(), "4.4").unwrap();
+        assert_eq!(path, tmp.path().join(VERSION_FILE));
+        assert_eq!(read_version_file(&path).unwrap().as_deref(), Some("4.4"));
+        let (request, file) = find_request(tmp.path()).unwrap().unwrap();
+        assert_eq!(request, ver("4.4"));
+        assert_eq!(file, path);
+    }
+
+    #[test]
+    fn the_nearest_project_file_wins() {
+        let tmp = tempfile::tempdir().unwrap();
+        let top = tmp.path();
+        let sub = top.join("a/b");
+        fs::create_dir_all(&sub).unwrap();
+        fs::write(top.join(VERSION_FILE), "# pinned\n\n4.4\n").unwrap();
+        fs::write(top.join("a/DESCRIPTION"), "Package: a\nImports: cli\n").unwrap();
+
+        // A DESCRIPTION without an R dependency does not stop the search.
+        let (request, file) = find_request(&sub).unwrap().unwrap();
+        assert_eq!(request, ver("4.4"));
+        assert_eq!(file, top.join(VERSION_FILE));
+
+        fs::write(
+            top.join("a/pkg.lock"),
+            r#"{"lockfile_version": 1, "r_version": "4.2.3", "packages": []}"#,
+        )
+        .unwrap();
+        let (request, file) = find_request(&sub).unwrap().unwrap();
+        assert_eq!(request, Request::Locked("4.2.3".to_string()));
+        assert_eq!(file, top.join("a/pkg.lock"));
+
+        fs::write(sub.join("DESCRIPTION"), "Package: b\nDepends: R (>= 4.3)\n").unwrap();
+        let (request, _) = find_request(&sub).unwrap().unwrap();
+        assert_eq!(request, Request::AtLeast(semver::Version::new(4, 3, 0)));
+    }
+
+    #[test]
+    fn a_broken_lockfile_is_skipped() {
+        let tmp = tempfile::tempdir().unwrap();
+        let top = tmp.path();
+        let sub = top.join("a");
+        fs::create_dir_all(&sub).unwrap();
+        fs::write(top.join(VERSION_FILE), "4.4\n").unwrap();
+        fs::write(sub.join("renv.lock"), "{ not json").unwrap();
+        fs::write(sub.join("pkg.lock"), "{ not json either").unwrap();
+
+        let (request, file) = find_request(&sub).unwrap().unwrap();
+        assert_eq!(request, ver("4.4"));
+        assert_eq!(file, top.join(VERSION_FILE));
+    }
+}
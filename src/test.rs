@@ -68,7 +68,7 @@ fn sc_test_download_lockfile(
     _subargs: &ArgMatches,
     _mainargs: &ArgMatches,
 ) -> Result<(), Box<dyn Error>> {
-    crate::proj::proj_download()?;
+    crate::proj::proj_download(None)?;
     Ok(())
 }
 
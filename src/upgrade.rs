@@ -0,0 +1,397 @@
+//! `rig upgrade`: move to another R version, and take the package library
+//! along.
+//!
+//! A library cannot be copied from one R minor version to the next, packages
+//! with compiled code have to be rebuilt, or replaced by binaries built for the
+//! new R. So the upgrade reads which packages the old library holds, and asks
+//! the solver for the same package set for the new R version, the way
+//! `rig pkg install` would, with binaries preferred. The versions are whatever
+//! the solve picks, the old library only says *which* packages. The solve is
+//! `sc_proj_solve_deps`, called by `install_into`, which then installs the
+//! solution, so the upgrade, `rig pkg install` and `rig proj solve` cannot
+//! pick different versions for the same packages.
+//!
+//! Adding R, switching the default and removing the old version are the
+//! `rig add`, `rig default` and `rig rm` commands, run as child processes. In
+//! admin mode those escalate to `sudo`, which re-runs the whole process as
+//! root, and the packages must go into the user's library, not root's.
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::process::Command;
+
+use clap::ArgMatches;
+use log::{debug, info, warn};
+use simple_error::*;
+use tabular::*;
+
+use crate::common::{
+    check_installed, get_arch, get_platform, sc_get_default_or_fail, sc_get_list_details,
+};
+use crate::install::default_jobs;
+use crate::library::sc_library_get_default;
+use crate::output::OUTPUT;
+use crate::pkg::install::{install_into, requested_deps, InstallOptions};
+use crate::pkg::list::{read_installed, InstalledPackage, ResolvedLibrary};
+use crate::pkg::repo_package_names;
+use crate::proj::BASE_PKGS;
+use crate::resolve::resolve_versions;
+use crate::shim::{pick_version, Request};
+
+/// A package of the old library that did not make it into the new one.
+#[derive(Debug, PartialEq, serde::Serialize)]
+struct NotCarried {
+    package: String,
+    reason: String,
+}
+
+#[derive(serde::Serialize)]
+struct UpgradeReport<'a> {
+    from: &'a str,
+    to: &'a str,
+    dry_run: bool,
+    packages: Vec<String>,
+    not_carried: &'a [NotCarried],
+    removed: bool,
+}
+
+pub fn sc_upgrade(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let json = args.get_flag("json") || mainargs.get_flag("json");
+    let dry_run = args.get_flag("dry-run");
+
+    let from = match args.get_one::<String>("from") {
+        Some(from) => check_installed(from)?,
+        None => sc_get_default_or_fail()?,
+    };
+    let to = args.get_one::<String>("to").unwrap();
+
+    // Which packages: the ones in the old version's default library, less the
+    // ones nothing can be done about.
+    let oldlib = sc_library_get_default(&from)?;
+    let old = if oldlib.path.exists() {
+        read_installed(&oldlib.path)?
+    } else {
+        debug!("Library {} does not exist", oldlib.path.display());
+        vec![]
+    };
+    let exclude: Vec<String> = args
+        .get_many::<String>("exclude")
+        .map(|x| x.map(|s| s.to_string()).collect())
+        .unwrap_or_default();
+    // Which R version.
+    let installed = sc_get_list_details()?;
+    let want = target_version(to, args)?;
+    let target =
+        pick_version(&Request::Version(want.clone()), &installed, None).map(|v| v.name.clone());
+    if target.as_deref() == Some(from.as_str()) {
+        bail!("Nothing to upgrade, R {} is {}", from, to);
+    }
+
+    let target = match target {
+        Some(target) => target,
+        None if dry_run => {
+            // Nothing to solve against without the new R, so this is all a dry
+            // run can tell. The old R's repositories stand in for the new
+            // one's.
+            let (names, not_carried) = carried_packages(&old, &exclude, &from, &from)?;
+            OUTPUT.info(&format!("Would install R {}", want));
+            report(&from, &want, true, &names, &not_carried, false, json)?;
+            return Ok(());
+        }
+        None => add_r_version(to)?,
+    };
+    let (names, mut not_carried) = carried_packages(&old, &exclude, &from, &target)?;
+
+    if !names.is_empty() {
+        let newlib = sc_library_get_default(&target)?;
+        let lib = ResolvedLibrary {
+            name: Some(newlib.name),
+            path: newlib.path,
+            rversion: Some(target.clone()),
+        };
+        let deps = requested_deps(&names)?;
+        let options = InstallOptions {
+            platform: None,
+            prefer_binary: args.get_one::<usize>("prefer-binary").copied(),
+            reinstall: false,
+            rebuild: &[],
+            dry_run,
+            jobs: args
+                .get_one::<usize>("jobs")
+                .copied()
+                .unwrap_or_else(default_jobs),
+            json,
+            print_plan: !json,
+        };
+        // install_into() solves `deps` with sc_proj_solve_deps() for the new
+        // R, and installs the solution, or with `dry_run` only prints it.
+        let result = install_into(&lib, &target, &deps, &options, None);
+        if dry_run {
+            result?;
+            report(&from, &target, true, &names, &not_carried, false, json)?;
+            return Ok(());
+        }
+
+        // The installer goes on after a failed package, so some of the
+        // library may have made it. What did is what is in the library now.
+        let now: HashSet<String> = if lib.path.exists() {
+            read_installed(&lib.path)?
+                .into_iter()
+                .map(|p| p.package)
+                .collect()
+        } else {
+            HashSet::new()
+        };
+        let failed: Vec<&String> = names.iter().filter(|n| !now.contains(*n)).collect();
+        if let Err(err) = result {
+            if failed.len() == names.len() {
+                OUTPUT.info(&format!(
+                    "R {} is installed, but no package could be carried over to it. \
+                     Use --exclude to leave out the packages that fail.",
+                    target
+                ));
+                return Err(err);
+            }
+            warn!("Some packages failed to install: {}", err);
+        }
+        not_carried.extend(failed.into_iter().map(|n| NotCarried {
+            package: n.clone(),
+            reason: "failed to install".to_string(),
+        }));
+    } else if dry_run {
+        report(&from, &target, true, &names, &not_carried, false, json)?;
+        return Ok(());
+    }
+
+    run_rig(&["default", &target], "set the default R version")?;
+    OUTPUT.success(&format!("Default R version is now {}", target));
+    info!("Default R version is now {}", target);
+
+    let mut removed = false;
+    if args.get_flag("remove-old") {
+        // Packages left out on purpose do not keep the old version around.
+        if not_carried.iter().all(|nc| nc.reason == "excluded") {
+            run_rig(&["rm", &from], "remove the old R version")?;
+            removed = true;
+        } else {
+            OUTPUT.warn(&format!(
+                "Not removing R {}, some of its packages were not carried over",
+                from
+            ));
+            warn!("Not removing R {}, packages left behind", from);
+        }
+    }
+
+    let carried: Vec<String> = names
+        .into_iter()
+        .filter(|n| !not_carried.iter().any(|nc| &nc.package == n))
+        .collect();
+    report(&from, &target, false, &carried, &not_carried, removed, json)
+}
+
+/// The packages of the old library to carry over to R `target`, checked
+/// against the repositories `target` is set up with, so packages from
+/// Bioconductor or r-universe are not left behind.
+fn carried_packages(
+    old: &[InstalledPackage],
+    exclude: &[String],
+    from: &str,
+    target: &str,
+) -> Result<(Vec<String>, Vec<NotCarried>), Box<dyn Error>> {
+    let available = repo_package_names(target)?;
+    let (names, not_carried) =
+        carry_over(old.iter().map(|p| p.package.as_str()), exclude, |name| {
+            Ok(available.contains(name))
+        })?;
+    info!(
+        "Carrying over {} packages from R {}, {} left behind",
+        names.len(),
+        from,
+        not_carried.len()
+    );
+    Ok((names, not_carried))
+}
+
+/// Split the packages of the old library into the ones to carry over, and the
+/// ones that cannot be: the excluded ones and the ones no repository has.
+/// `available` tells whether a package is in the repositories at all.
+///
+/// Base packages are part of R, and come with the new R version.
+fn carry_over<'a>(
+    packages: impl Iterator<Item = &'a str>,
+    exclude: &[String],
+    available: impl Fn(&str) -> Result<bool, Box<dyn Error>>,
+) -> Result<(Vec<String>, Vec<NotCarried>), Box<dyn Error>> {
+    let mut names: Vec<String> = vec![];
+    let mut not_carried: Vec<NotCarried> = vec![];
+    for name in packages {
+        if BASE_PKGS.contains(&name) || names.iter().any(|n| n == name) {
+            continue;
+        }
+        let reason = if exclude.iter().any(|e| e == name) {
+            "excluded"
+        } else if !available(name)? {
+            "not in the repositories"
+        } else {
+            names.push(name.to_string());
+            continue;
+        };
+        debug!("Not carrying over {}: {}", name, reason);
+        not_carried.push(NotCarried {
+            package: name.to_string(),
+            reason: reason.to_string(),
+        });
+    }
+    names.sort_by_key(|n| n.to_lowercase());
+    not_carried.sort_by_key(|nc| nc.package.to_lowercase());
+    Ok((names, not_carried))
+}
+
+/// The version `rig upgrade` upgrades to: `release` and `oldrel` are resolved
+/// to a version number, so that an installed R is found by its version.
+fn target_version(to: &str, args: &ArgMatches) -> Result<String, Box<dyn Error>> {
+    if to != "release" && to != "oldrel" && !to.starts_with("oldrel/") {
+        return Ok(to.to_string());
+    }
+    let platform = get_platform(args)?;
+    let arch = get_arch(&platform, args);
+    match &resolve_versions(vec![to.to_string()], &platform, &arch)?[0].version {
+        Some(v) => {
+            debug!("Resolved {} to R {}", to, v);
+            Ok(v.to_string())
+        }
+        None => bail!("Cannot resolve R version {}", to),
+    }
+}
+
+/// `rig add` the new version, and find out what it is called.
+fn add_r_version(to: &str) -> Result<String, Box<dyn Error>> {
+    OUTPUT.status(&format!("Installing R {}", to));
+    info!("Installing R {}", to);
+    let before: Vec<String> = sc_get_list_details()?.into_iter().map(|v| v.name).collect();
+    run_rig(&["add", to], "install R")?;
+    let after = sc_get_list_details()?;
+    match after.iter().find(|v| !before.contains(&v.name)) {
+        Some(v) => Ok(v.name.clone()),
+        None => match pick_version(&Request::Version(to.to_string()), &after, None) {
+            Some(v) => Ok(v.name.clone()),
+            None => bail!("Cannot find the R version `rig add {}` installed", to),
+        },
+    }
+}
+
+fn run_rig(args: &[&str], what: &str) -> Result<(), Box<dyn Error>> {
+    let exe = std::env::current_exe()?;
+    debug!("Running rig {}", args.join(" "));
+    let status = Command::new(&exe).args(args).status()?;
+    if !status.success() {
+        bail!("Failed to {}, `rig {}` failed", what, args.join(" "));
+    }
+    Ok(())
+}
+
+fn report(
+    from: &str,
+    to: &str,
+    dry_run: bool,
+    packages: &[String],
+    not_carried: &[NotCarried],
+    removed: bool,
+    json: bool,
+) -> Result<(), Box<dyn Error>> {
+    if json {
+        let report = UpgradeReport {
+            from,
+            to,
+            dry_run,
+            packages: packages.to_vec(),
+            not_carried,
+            removed,
+        };
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    let n = packages.len();
+    let word = if n == 1 { "package" } else { "packages" };
+    if dry_run {
+        OUTPUT.success(&format!(
+            "Would upgrade from R {} to R {}, carrying over {} {}",
+            from, to, n, word
+        ));
+    } else {
+        OUTPUT.success(&format!(
+            "Upgraded from R {} to R {}, carried over {} {}",
+            from, to, n, word
+        ));
+    }
+
+    if !not_carried.is_empty() {
+        OUTPUT.warn(&format!(
+            "{} {} could not be carried over",
+            not_carried.len(),
+            if not_carried.len() == 1 {
+                "package"
+            } else {
+                "packages"
+            }
+        ));
+        let mut tab = Table::new("{:<}  {:<}");
+        tab.add_row(row!("Package", "Reason"));
+        for nc in not_carried {
+            tab.add_row(row!(&nc.package, &nc.reason));
+        }
+        println!("{}", tab);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(
+        packages: &[&str],
+        exclude: &[&str],
+        missing: &[&str],
+    ) -> (Vec<String>, Vec<NotCarried>) {
+        let exclude: Vec<String> = exclude.iter().map(|s| s.to_string()).collect();
+        carry_over(packages.iter().copied(), &exclude, |name| {
+            Ok(!missing.contains(&name))
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn available_packages_are_carried_over() {
+        let (names, not_carried) = split(&["glue", "cli"], &[], &[]);
+        assert_eq!(names, vec!["cli", "glue"]);
+        assert!(not_carried.is_empty());
+    }
+
+    #[test]
+    fn excluded_and_unknown_packages_are_left_behind() {
+        let (names, not_carried) = split(&["mypkg", "cli", "rlang"], &["rlang"], &["mypkg"]);
+        assert_eq!(names, vec!["cli"]);
+        assert_eq!(
+            not_carried,
+            vec![
+                NotCarried {
+                    package: "mypkg".to_string(),
+                    reason: "not in the repositories".to_string()
+                },
+                NotCarried {
+                    package: "rlang".to_string(),
+                    reason: "excluded".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn base_packages_are_not_carried_over() {
+        let (names, not_carried) = split(&["stats", "cli", "cli"], &[], &["stats"]);
+        assert_eq!(names, vec!["cli"]);
+        assert!(not_carried.is_empty());
+    }
+}
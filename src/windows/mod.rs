@@ -251,10 +251,16 @@ fn user_install_name(install_dir: &Path, arch: &str) -> Result<String, Box<dyn E
 
 #[warn(unused_variables)]
 pub fn sc_add(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    if args.get_flag("from-source") {
+        OUTPUT.error("`--from-source` is only supported on Linux.");
+        error!("`--from-source` is only supported on Linux");
+        bail!("`--from-source` is only supported on Linux");
+    }
     escalate("adding new R version")?;
     let alias = get_alias(args);
     sc_clean_registry()?;
-    let str = args.get_one::<String>("str").unwrap();
+    // Without a version on the command line, the pinned one, or the release.
+    let str = &crate::shim::add_version(args);
     if str.len() >= 6 && &str[0..6] == "rtools" {
         // For bare "rtools" (install all needed), only honour --arch when the user
         // explicitly passed it; the flag's native-arch default should not filter out
@@ -679,7 +685,7 @@ fn add_rtools(version: String, arch: Option<String>) -> Result<(), Box<dyn Error
             arch: a,
         }]
     };
-    let client = &reqwest::Client::new();
+    let client = &crate::http::client()?;
     for item in needed {
         let instdirpath = rtools_install_path(&item.version, &item.arch)?;
         if instdirpath.exists() {